@@ -2,9 +2,13 @@ pub mod font;
 pub mod frame;
 pub mod map;
 pub mod message;
+pub mod movie;
 pub mod palette;
 pub mod proto;
+pub mod save;
 pub mod script;
+pub mod sound;
+pub mod worldmap;
 
 use enumflags2_derive::EnumFlags;
 use enum_map_derive::Enum;
@@ -1,12 +1,13 @@
+pub mod convert;
 mod db;
 pub mod id;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use enum_map::EnumMap;
-use std::io::{self, prelude::*};
+use std::io::{self, Error, ErrorKind, prelude::*};
 
 pub use id::FrameId;
-pub use db::FrameDb;
+pub use db::{critter_anim_codes, FrameDb};
 
 use crate::graphics::Point;
 use crate::graphics::geometry::hex::Direction;
@@ -95,4 +96,184 @@ pub fn read_frm(rd: &mut impl Read, texture_factory: &TextureFactory) -> io::Res
         action_frame,
         frame_lists: EnumMap::from(|k| frame_lists[k].take().unwrap()),
     })
+}
+
+/// Writes `frame_set` in FRM format. Directions that share frames (as produced by `read_frm()` for
+/// directions with the same offset) are written once.
+pub fn write_frm(w: &mut impl Write, frame_set: &FrameSet, texture_factory: &TextureFactory)
+    -> io::Result<()>
+{
+    let frames_per_direction = frame_set.frame_lists[Direction::NE].frames.len();
+
+    let mut data = Vec::new();
+    let mut offsets: EnumMap<Direction, u32> = EnumMap::new();
+    for dir in Direction::iter() {
+        let frame_list = &frame_set.frame_lists[dir];
+        if frame_list.frames.len() != frames_per_direction || frames_per_direction == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                "all directions must have the same non-zero number of frames"));
+        }
+        let same_as = Direction::iter()
+            .take_while(|&d| d != dir)
+            .find(|&d| same_frames(&frame_set.frame_lists[d], frame_list));
+        if let Some(same_as) = same_as {
+            offsets[dir] = offsets[same_as];
+            continue;
+        }
+
+        offsets[dir] = data.len() as u32;
+        for frame in &frame_list.frames {
+            let pixels = texture_factory.texture_data(&frame.texture);
+            if pixels.len() != (frame.width * frame.height) as usize {
+                return Err(Error::new(ErrorKind::InvalidInput, "frame size doesn't match texture"));
+            }
+            data.write_i16::<BigEndian>(to_i16(frame.width)?)?;
+            data.write_i16::<BigEndian>(to_i16(frame.height)?)?;
+            data.write_u32::<BigEndian>(pixels.len() as u32)?;
+            data.write_i16::<BigEndian>(to_i16(frame.shift.x)?)?;
+            data.write_i16::<BigEndian>(to_i16(frame.shift.y)?)?;
+            data.write_all(&pixels)?;
+        }
+    }
+
+    w.write_u32::<BigEndian>(4)?;
+    w.write_u16::<BigEndian>(frame_set.fps)?;
+    w.write_u16::<BigEndian>(frame_set.action_frame)?;
+    w.write_u16::<BigEndian>(frames_per_direction as u16)?;
+    for dir in Direction::iter() {
+        w.write_i16::<BigEndian>(to_i16(frame_set.frame_lists[dir].center.x)?)?;
+    }
+    for dir in Direction::iter() {
+        w.write_i16::<BigEndian>(to_i16(frame_set.frame_lists[dir].center.y)?)?;
+    }
+    for dir in Direction::iter() {
+        w.write_u32::<BigEndian>(offsets[dir])?;
+    }
+    w.write_u32::<BigEndian>(data.len() as u32)?;
+    w.write_all(&data)
+}
+
+fn to_i16(v: i32) -> io::Result<i16> {
+    if v >= i16::min_value() as i32 && v <= i16::max_value() as i32 {
+        Ok(v as i16)
+    } else {
+        Err(Error::new(ErrorKind::InvalidInput, format!("value is out of FRM range: {}", v)))
+    }
+}
+
+/// Whether the frame lists have the same center and frames with the same textures and shifts.
+fn same_frames(a: &FrameList, b: &FrameList) -> bool {
+    a.center == b.center
+        && a.frames.len() == b.frames.len()
+        && a.frames.iter().zip(&b.frames).all(|(a, b)| a.texture == b.texture && a.shift == b.shift)
+}
+
+/// Encodes `frame_set` into in-memory FRM image.
+pub fn encode_frm(frame_set: &FrameSet, texture_factory: &TextureFactory) -> io::Result<Vec<u8>> {
+    let mut r = Vec::new();
+    write_frm(&mut r, frame_set, texture_factory)?;
+    Ok(r)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    pub fn assert_frame_sets_eq(a: &FrameSet, b: &FrameSet, texture_factory: &TextureFactory) {
+        assert_eq!(a.fps, b.fps);
+        assert_eq!(a.action_frame, b.action_frame);
+        for dir in Direction::iter() {
+            let (a, b) = (&a.frame_lists[dir], &b.frame_lists[dir]);
+            assert_eq!(a.center, b.center, "{:?}", dir);
+            assert_eq!(a.frames.len(), b.frames.len(), "{:?}", dir);
+            for (a, b) in a.frames.iter().zip(&b.frames) {
+                assert_eq!((a.shift, a.width, a.height), (b.shift, b.width, b.height), "{:?}", dir);
+                assert_eq!(texture_factory.texture_data(&a.texture),
+                    texture_factory.texture_data(&b.texture), "{:?}", dir);
+            }
+        }
+    }
+
+    fn random_frame_list(rng: &mut StdRng, frame_count: usize, tf: &TextureFactory) -> FrameList {
+        let mut frames = Vec::with_capacity(frame_count);
+        for _ in 0..frame_count {
+            let width = rng.gen_range(1, 20);
+            let height = rng.gen_range(1, 20);
+            let pixels: Box<[u8]> = (0..width * height).map(|_| rng.gen()).collect();
+            frames.push(Frame {
+                shift: Point::new(rng.gen_range(-100, 100), rng.gen_range(-100, 100)),
+                width,
+                height,
+                mask: Mask::new(width, &pixels),
+                texture: tf.new_texture(width, height, pixels),
+            });
+        }
+        FrameList {
+            center: Point::new(rng.gen_range(-100, 100), rng.gen_range(-100, 100)),
+            frames,
+        }
+    }
+
+    #[test]
+    fn write_read_roundtrip() {
+        let tf = &TextureFactory::new_in_memory();
+        let rng = &mut StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let frame_count = rng.gen_range(1, 5);
+            let shared = random_frame_list(rng, frame_count, tf);
+            let frame_set = FrameSet {
+                fps: rng.gen_range(1, 30),
+                action_frame: rng.gen_range(0, frame_count as u16),
+                frame_lists: EnumMap::from(|_| if rng.gen() {
+                    shared.clone()
+                } else {
+                    random_frame_list(rng, frame_count, tf)
+                }),
+            };
+
+            let data = encode_frm(&frame_set, tf).unwrap();
+            let actual = read_frm(&mut &data[..], tf).unwrap();
+            assert_frame_sets_eq(&frame_set, &actual, tf);
+
+            for d1 in Direction::iter() {
+                for d2 in Direction::iter() {
+                    assert_eq!(
+                        same_frames(&frame_set.frame_lists[d1], &frame_set.frame_lists[d2]),
+                        same_frames(&actual.frame_lists[d1], &actual.frame_lists[d2]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn write_shared_directions_once() {
+        let tf = &TextureFactory::new_in_memory();
+        let rng = &mut StdRng::seed_from_u64(0);
+        let shared = random_frame_list(rng, 2, tf);
+        let frame_set = FrameSet {
+            fps: 10,
+            action_frame: 0,
+            frame_lists: EnumMap::from(|_| shared.clone()),
+        };
+        let data = encode_frm(&frame_set, tf).unwrap();
+        let frame_data_len: usize = shared.frames.iter()
+            .map(|f| 12 + (f.width * f.height) as usize)
+            .sum();
+        assert_eq!(data.len(), 62 + frame_data_len);
+    }
+
+    #[test]
+    fn write_bad_frame_count() {
+        let tf = &TextureFactory::new_in_memory();
+        let rng = &mut StdRng::seed_from_u64(0);
+        let frame_set = FrameSet {
+            fps: 10,
+            action_frame: 0,
+            frame_lists: EnumMap::from(|d| random_frame_list(rng,
+                if d == Direction::W { 1 } else { 2 }, tf)),
+        };
+        assert_eq!(encode_frm(&frame_set, tf).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,368 @@
+//! Conversion of frame sets to and from PNG images.
+//!
+//! A frame set is exported as one indexed PNG per frame plus a sidecar text file describing
+//! the frame set:
+//!
+//! ```text
+//! fps = 10
+//! action_frame = 0
+//!
+//! [ne]
+//! center = 0 -2
+//! ; frame = <shift x> <shift y> <PNG file or `-` for empty frame>
+//! frame = 0 0 hmjmpsaa_ne_0.png
+//! frame = 1 -1 hmjmpsaa_ne_1.png
+//!
+//! [e]
+//! ; Direction that shares frames with another direction.
+//! same_as = ne
+//! ```
+
+use enum_map::EnumMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind};
+use std::path::Path;
+
+use super::same_frames;
+use crate::graphics::Point;
+use crate::graphics::color::{Color8, Rgb24};
+use crate::graphics::color::palette::Palette;
+use crate::graphics::geometry::hex::Direction;
+use crate::graphics::render::TextureFactory;
+use crate::graphics::sprite::{Frame, FrameList, FrameSet, Mask};
+use crate::util::EnumExt;
+
+/// Exports `frame_set` into `out_dir` as `<name>.txt` sidecar and `<name>_<direction>_<frame>.png`
+/// images.
+pub fn export_png(frame_set: &FrameSet, texture_factory: &TextureFactory, palette: &Palette,
+    out_dir: &Path, name: &str) -> io::Result<()>
+{
+    fs::create_dir_all(out_dir)?;
+
+    let mut sidecar = String::new();
+    sidecar.push_str(&format!("fps = {}\n", frame_set.fps));
+    sidecar.push_str(&format!("action_frame = {}\n", frame_set.action_frame));
+
+    for dir in Direction::iter() {
+        let dir_name = direction_name(dir);
+        sidecar.push_str(&format!("\n[{}]\n", dir_name));
+
+        let frame_list = &frame_set.frame_lists[dir];
+        let same_as = Direction::iter()
+            .take_while(|&d| d != dir)
+            .find(|&d| same_frames(&frame_set.frame_lists[d], frame_list));
+        if let Some(same_as) = same_as {
+            sidecar.push_str(&format!("same_as = {}\n", direction_name(same_as)));
+            continue;
+        }
+
+        sidecar.push_str(&format!("center = {} {}\n", frame_list.center.x, frame_list.center.y));
+        for (i, frame) in frame_list.frames.iter().enumerate() {
+            let file = if frame.width > 0 && frame.height > 0 {
+                let file = format!("{}_{}_{}.png", name, dir_name, i);
+                let pixels = texture_factory.texture_data(&frame.texture);
+                write_png(&out_dir.join(&file), frame.width, frame.height, &pixels, palette)?;
+                file
+            } else {
+                "-".into()
+            };
+            sidecar.push_str(&format!("frame = {} {} {}\n", frame.shift.x, frame.shift.y, file));
+        }
+    }
+
+    fs::write(out_dir.join(format!("{}.txt", name)), sidecar)
+}
+
+/// Imports frame set described by the sidecar file at `path`. Image paths are relative to the
+/// sidecar directory. Pixels are quantized to `palette`. Pixels with alpha below 128 become
+/// transparent.
+pub fn import_png(path: &Path, palette: &Palette, texture_factory: &TextureFactory)
+    -> io::Result<FrameSet>
+{
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let sidecar = fs::read_to_string(path)?;
+
+    let err = |line: usize, msg: &str|
+        Error::new(ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), line + 1, msg));
+
+    let mut fps = None;
+    let mut action_frame = 0;
+    let mut dir = None;
+    let mut frame_lists: EnumMap<Direction, Option<FrameList>> = EnumMap::new();
+    let mut same_as: EnumMap<Direction, Option<Direction>> = EnumMap::new();
+
+    for (line_num, line) in sidecar.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            let d = parse_direction(&line[1..line.len() - 1])
+                .ok_or_else(|| err(line_num, "unknown direction"))?;
+            frame_lists[d] = Some(FrameList {
+                center: Point::new(0, 0),
+                frames: Vec::new(),
+            });
+            dir = Some(d);
+            continue;
+        }
+
+        let mut kv = line.splitn(2, '=');
+        let key = kv.next().unwrap().trim();
+        let value = kv.next().ok_or_else(|| err(line_num, "expected `key = value`"))?.trim();
+        let parse_num = |s: &str| s.parse::<i32>().map_err(|_| err(line_num, "malformed number"));
+
+        match (key, dir) {
+            ("fps", None) => fps = Some(parse_num(value)? as u16),
+            ("action_frame", None) => action_frame = parse_num(value)? as u16,
+            ("same_as", Some(d)) => {
+                same_as[d] = Some(parse_direction(value)
+                    .ok_or_else(|| err(line_num, "unknown direction"))?);
+            }
+            ("center", Some(d)) => {
+                let v: Vec<_> = value.split_whitespace().collect();
+                if v.len() != 2 {
+                    return Err(err(line_num, "expected `center = <x> <y>`"));
+                }
+                frame_lists[d].as_mut().unwrap().center = Point::new(parse_num(v[0])?, parse_num(v[1])?);
+            }
+            ("frame", Some(d)) => {
+                let v: Vec<_> = value.splitn(3, ' ').map(|s| s.trim()).collect();
+                if v.len() != 3 {
+                    return Err(err(line_num, "expected `frame = <shift x> <shift y> <file>`"));
+                }
+                let shift = Point::new(parse_num(v[0])?, parse_num(v[1])?);
+                let (width, height, pixels) = if v[2] == "-" {
+                    (0, 0, Vec::new().into_boxed_slice())
+                } else {
+                    read_png(&base_dir.join(v[2]), palette)
+                        .map_err(|e| err(line_num, &format!("error reading {}: {}", v[2], e)))?
+                };
+                let mask = Mask::new(width.max(1), &pixels);
+                let texture = texture_factory.new_texture(width, height, pixels);
+                frame_lists[d].as_mut().unwrap().frames.push(Frame {
+                    shift,
+                    width,
+                    height,
+                    texture,
+                    mask,
+                });
+            }
+            _ => return Err(err(line_num, &format!("unexpected key: {}", key))),
+        }
+    }
+
+    for d in Direction::iter() {
+        if let Some(src) = same_as[d] {
+            if same_as[src].is_some() {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("{}: chained same_as in direction {}", path.display(), direction_name(d))));
+            }
+            frame_lists[d] = frame_lists[src].clone();
+        }
+    }
+
+    let mut frames_per_direction = None;
+    for d in Direction::iter() {
+        let frame_list = frame_lists[d].as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("{}: missing direction {}", path.display(), direction_name(d))))?;
+        let len = frame_list.frames.len();
+        if len == 0 || frames_per_direction.is_some() && frames_per_direction != Some(len) {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("{}: all directions must have the same non-zero number of frames",
+                    path.display())));
+        }
+        frames_per_direction = Some(len);
+    }
+
+    Ok(FrameSet {
+        fps: fps.unwrap_or(10),
+        action_frame,
+        frame_lists: EnumMap::from(|k| frame_lists[k].take().unwrap()),
+    })
+}
+
+fn direction_name(dir: Direction) -> String {
+    format!("{:?}", dir).to_ascii_lowercase()
+}
+
+fn parse_direction(s: &str) -> Option<Direction> {
+    Direction::iter().find(|&d| direction_name(d).eq_ignore_ascii_case(s.trim()))
+}
+
+/// Palette as 8-bit RGB triples.
+fn rgb24_palette(palette: &Palette) -> Vec<u8> {
+    let mut r = Vec::with_capacity(256 * 3);
+    for i in 0..=255 {
+        let c = palette.rgb::<Color8>(i);
+        r.extend_from_slice(&[c.r(), c.g(), c.b()]);
+    }
+    r
+}
+
+fn write_png(path: &Path, width: i32, height: i32, pixels: &[u8], palette: &Palette)
+    -> io::Result<()>
+{
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?),
+        width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(rgb24_palette(palette));
+    // Color index 0 is transparent.
+    encoder.set_trns(vec![0]);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    Ok(())
+}
+
+fn png_err(e: png::DecodingError) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+/// Reads PNG at `path` into color indices of `palette`. Indexed images with the palette
+/// identical to `palette` (such as the exported ones) are read as is without quantization.
+fn read_png(path: &Path, palette: &Palette) -> io::Result<(i32, i32, Box<[u8]>)> {
+    let data = fs::read(path)?;
+
+    let mut decoder = png::Decoder::new(&data[..]);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info().map_err(png_err)?;
+    if info.color_type == png::ColorType::Indexed && info.bit_depth == png::BitDepth::Eight
+        && reader.info().palette.as_ref().map(|p| &p[..]) == Some(&rgb24_palette(palette)[..])
+    {
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).map_err(png_err)?;
+        return Ok((info.width as i32, info.height as i32, pixels.into_boxed_slice()));
+    }
+
+    let mut decoder = png::Decoder::new(&data[..]);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().map_err(png_err)?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).map_err(png_err)?;
+
+    let (color_type, _) = reader.output_color_type();
+    let channels = color_type.samples();
+    let mut pixels = Vec::with_capacity((info.width * info.height) as usize);
+    for px in buf.chunks(channels) {
+        let (rgb, alpha) = match color_type {
+            png::ColorType::Grayscale => ((px[0], px[0], px[0]), 255),
+            png::ColorType::GrayscaleAlpha => ((px[0], px[0], px[0]), px[1]),
+            png::ColorType::RGB => ((px[0], px[1], px[2]), 255),
+            png::ColorType::RGBA => ((px[0], px[1], px[2]), px[3]),
+            png::ColorType::Indexed => unreachable!(),
+        };
+        pixels.push(if alpha < 128 {
+            0
+        } else {
+            quantize(palette, Rgb24::new(rgb.0, rgb.1, rgb.2))
+        });
+    }
+    Ok((info.width as i32, info.height as i32, pixels.into_boxed_slice()))
+}
+
+/// Finds color index for `rgb` that is never the transparent index 0.
+fn quantize(palette: &Palette, rgb: Rgb24) -> u8 {
+    let idx = palette.color_idx(rgb);
+    if idx != 0 {
+        return idx;
+    }
+    // Find the nearest opaque color.
+    let dist = |i: u8| {
+        let c = palette.rgb::<Color8>(i);
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(c.r(), rgb.r()) + d(c.g(), rgb.g()) + d(c.b(), rgb.b())
+    };
+    (1..=255).min_by_key(|&i| dist(i)).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asset::frame::test::assert_frame_sets_eq;
+    use crate::util::test::{TempDir, ungz};
+
+    fn palette() -> Palette {
+        let data = ungz(include_bytes!("../../graphics/color/color.pal.gz"));
+        crate::asset::palette::read_palette(&mut std::io::Cursor::new(&data[..])).unwrap()
+    }
+
+    fn frame(tf: &TextureFactory, width: i32, height: i32, shift: Point, pixels: &[u8]) -> Frame {
+        Frame {
+            shift,
+            width,
+            height,
+            texture: tf.new_texture(width, height, pixels.into()),
+            mask: Mask::new(width.max(1), pixels),
+        }
+    }
+
+    #[test]
+    fn export_import_roundtrip() {
+        let tf = TextureFactory::new_in_memory();
+        let pal = palette();
+
+        let shared = FrameList {
+            center: Point::new(1, -2),
+            frames: vec![
+                frame(&tf, 2, 2, Point::new(0, 0), &[0, 1, 2, 255]),
+                frame(&tf, 0, 0, Point::new(3, 4), &[]),
+            ],
+        };
+        let frame_set = FrameSet {
+            fps: 12,
+            action_frame: 1,
+            frame_lists: EnumMap::from(|d| if d == Direction::SW {
+                FrameList {
+                    center: Point::new(5, 6),
+                    frames: vec![
+                        frame(&tf, 3, 1, Point::new(-1, 1), &[100, 229, 0]),
+                        frame(&tf, 1, 1, Point::new(0, 0), &[7]),
+                    ],
+                }
+            } else {
+                shared.clone()
+            }),
+        };
+
+        let tmp = TempDir::new("frm-png");
+        export_png(&frame_set, &tf, &pal, tmp.path(), "test").unwrap();
+        let sidecar = fs::read_to_string(tmp.path().join("test.txt")).unwrap();
+        assert!(sidecar.contains("[e]\nsame_as = ne\n"));
+        assert!(sidecar.contains("frame = 3 4 -\n"));
+
+        let imported = import_png(&tmp.path().join("test.txt"), &pal, &tf).unwrap();
+        assert_frame_sets_eq(&frame_set, &imported, &tf);
+        assert_eq!(imported.frame_lists[Direction::NE].frames[0].texture,
+            imported.frame_lists[Direction::NW].frames[0].texture);
+    }
+
+    #[test]
+    fn import_rgba() {
+        let tf = TextureFactory::new_in_memory();
+        let pal = palette();
+        let tmp = TempDir::new("frm-png-rgba");
+
+        let mut encoder = png::Encoder::new(File::create(tmp.path().join("a.png")).unwrap(), 3, 1);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(&[
+            0, 0, 0, 0,
+            0, 0, 0, 255,
+            255, 255, 255, 255,
+        ]).unwrap();
+        let mut sidecar = "[ne]\nframe = 0 0 a.png\n".to_owned();
+        for d in &["e", "se", "sw", "w", "nw"] {
+            sidecar.push_str(&format!("[{}]\nsame_as = ne\n", d));
+        }
+        fs::write(tmp.path().join("a.txt"), sidecar).unwrap();
+
+        let frame_set = import_png(&tmp.path().join("a.txt"), &pal, &tf).unwrap();
+        assert_eq!(frame_set.fps, 10);
+        let pixels = tf.texture_data(&frame_set.first().texture);
+        assert_eq!(pixels[0], 0);
+        assert_ne!(pixels[1], 0);
+        assert_eq!(pal.rgb::<Color8>(pixels[2]), pal.quantize(Rgb24::new(255, 255, 255)));
+    }
+}
\ No newline at end of file
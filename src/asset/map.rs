@@ -1,18 +1,19 @@
 pub mod db;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use enumflags2::BitFlags;
 use enumflags2_derive::EnumFlags;
 use log::*;
 use measure_time::*;
 use num_traits::FromPrimitive;
 use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::{TryFrom, TryInto};
 use std::io::{self, Error, ErrorKind, prelude::*};
 
 use crate::asset::*;
 use crate::asset::frame::{FrameId, FrameDb};
-use crate::asset::proto::{MapExit, ProtoId, ProtoDb, SubItem, TargetMap};
+use crate::asset::proto::{MapExit, Proto, ProtoId, ProtoDb, SubItem, SubProto, SubScenery, TargetMap};
 use crate::asset::script::ProgramId;
 use crate::game::object::{self, *};
 use crate::game::script::*;
@@ -24,6 +25,12 @@ use crate::util::array2d::Array2d;
 
 pub const ELEVATION_COUNT: u32 = 3;
 
+/// Map format version of Fallout 2. Fallout 1 maps have version 19.
+pub const VERSION_F2: u32 = 20;
+
+const NAME_LEN: usize = 16;
+const SCRIPT_NODE_LEN: usize = 16;
+
 struct ScriptInfo {
     sid: ScriptIid,
     program_id: ProgramId,
@@ -51,6 +58,8 @@ pub type MapId = u32;
 
 pub struct Map {
     pub id: MapId,
+    /// Map file name as stored in the header, e.g. `ARTEMPLE.MAP`.
+    pub name: String,
     pub savegame: bool,
     pub entrance: EPoint,
     pub entrance_direction: Direction,
@@ -73,8 +82,10 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
 
         let version = self.reader.read_u32::<BigEndian>()?;
 
-        let mut name = [0; 16];
-        self.reader.read_exact(&mut name[..]).unwrap();
+        let mut name = [0; NAME_LEN];
+        self.reader.read_exact(&mut name[..])?;
+        let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        let name = String::from_utf8_lossy(&name[..name_len]).into_owned();
 
         let entrance_pos_lin = self.reader.read_i32::<BigEndian>()?;
         let entrance_pos = TileGrid::default().from_linear_inv(entrance_pos_lin as u32);
@@ -85,7 +96,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
             .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid entrance direction"))?;
         let local_var_count = cmp::max(self.reader.read_i32::<BigEndian>()?, 0) as usize;
 
-        let program_id = self.read_program_id(0)?;
+        let program_id = read_program_id(self.reader, 0)?;
         debug!("map program_id: {:?}", program_id);
 
         let flags = self.reader.read_u32::<BigEndian>()?;
@@ -114,16 +125,24 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         }
 
         let sqr_tiles = self.read_sqr_tiles(flags)?;
-        self.read_scripts(&local_vars, savegame)?;
+        let local_vars_end = self.read_scripts(&local_vars, savegame)?;
 
         if let Some(program_id) = program_id {
-            self.make_map_script(program_id)?;
+            // Map script local variables follow the ones of the other scripts.
+            let local_vars = &local_vars[local_vars_end.min(local_vars.len())..];
+            let local_vars = if savegame && !local_vars.is_empty() {
+                Some(local_vars.into())
+            } else {
+                None
+            };
+            self.make_map_script(program_id, local_vars)?;
         }
 
         self.read_objects(version)?;
 
         Ok(Map {
             id,
+            name,
             savegame,
             entrance: EPoint {
                 elevation: entrance_elevation,
@@ -135,20 +154,22 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         })
     }
 
-    fn read_scripts(&mut self, local_vars: &[i32], savegame: bool) -> io::Result<()> {
+    /// Returns the end of the local variables used by the scripts.
+    fn read_scripts(&mut self, local_vars: &[i32], savegame: bool) -> io::Result<usize> {
+        let mut local_vars_end = 0;
         for script_kind in ScriptKind::iter() {
             debug!("reading {:?} scripts", script_kind);
             let script_count = self.reader.read_i32::<BigEndian>()?;
             debug!("script_count: {}", script_count);
             if script_count > 0 {
                 let script_count = script_count as usize;
-                const NODE_LEN: usize = 16;
-                let node_count = script_count / NODE_LEN + (script_count % NODE_LEN != 0) as usize;
+                let node_count = script_count / SCRIPT_NODE_LEN
+                    + (script_count % SCRIPT_NODE_LEN != 0) as usize;
                 debug!("node_count: {}", node_count);
                 let mut scripts = Vec::new();
                 for _ in 0..node_count {
                     scripts.clear();
-                    for _ in 0..NODE_LEN {
+                    for _ in 0..SCRIPT_NODE_LEN {
                         if let Some(script) = self.read_script()? {
                             scripts.push(script);
                         }
@@ -161,8 +182,9 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                     scripts.truncate(node_script_count as usize);
 
                     for script in &scripts {
+                        let end = script.local_var_offset + script.local_var_count;
+                        local_vars_end = local_vars_end.max(end);
                         let local_vars = if savegame && script.local_var_count > 0 {
-                            let end = script.local_var_offset + script.local_var_count;
                             Some(local_vars[script.local_var_offset..end].into())
                         } else {
                             None
@@ -172,7 +194,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                 }
             }
         }
-        Ok(())
+        Ok(local_vars_end)
     }
 
     fn read_script(&mut self) -> io::Result<Option<ScriptInfo>> {
@@ -205,7 +227,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
 
         let _flags = self.reader.read_i32::<BigEndian>()?;
 
-        let program_id = self.read_program_id(1)?;
+        let program_id = read_program_id(self.reader, 1)?;
         trace!("program_id: {:?}", program_id);
 
         let _ = self.reader.read_i32::<BigEndian>()?;
@@ -242,7 +264,12 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
             debug!("object count at elevation {}: {}", elev, obj_count);
 
             for _ in 0..obj_count {
-                let obj = self.read_object(version != 19)?;
+                let obj = ObjectReader {
+                    reader: self.reader,
+                    objects: self.objects,
+                    proto_db: self.proto_db,
+                    frm_db: self.frm_db,
+                }.read_object(version != 19)?;
                 let script = obj.script;
                 let objh = self.objects.insert(obj);
                 if let Some((sid, _)) = script {
@@ -253,7 +280,53 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         Ok(())
     }
 
-    fn read_object(&mut self, f2: bool) -> io::Result<Object> {
+    fn make_map_script(&mut self, program_id: ProgramId, local_vars: Option<Box<[i32]>>)
+        -> io::Result<()>
+    {
+        let sid = self.scripts.instantiate_map_script(program_id, local_vars)?;
+        let mut obj = Object::new(FrameId::MAPMK, None, Some(Default::default()), SubObject::None);
+        obj.flags = BitFlags::from(Flag::LightThru)
+            | Flag::WalkThru
+            | Flag::TurnedOff;
+        let objh = self.objects.insert(obj);
+        self.scripts.attach_to_object(sid, objh);
+        Ok(())
+    }
+
+    fn read_sqr_tiles(&mut self, flags: u32) -> io::Result<SqrTiles> {
+        let mut sqr_tiles: Vec<Option<_>> = Vec::with_capacity(ELEVATION_COUNT as usize);
+        for i in 0..ELEVATION_COUNT {
+            if flags & (1 << (i as u32 + 1)) != 0 {
+                debug!("no {} elevation", i);
+                sqr_tiles.push(None);
+                continue;
+            }
+            let mut tiles = Array2d::with_default(100, 100);
+            for y in 0..tiles.height() {
+                for x in (0..tiles.width()).rev() {
+                    let roof_id = self.reader.read_u16::<BigEndian>()?;
+                    let floor_id = self.reader.read_u16::<BigEndian>()?;
+                    *tiles.get_mut(x, y).unwrap() = (floor_id, roof_id);
+                }
+            }
+            sqr_tiles.push(Some(tiles));
+        }
+        Ok(sqr_tiles)
+    }
+}
+
+
+pub struct ObjectReader<'a, R: 'a> {
+    pub reader: &'a mut R,
+    pub objects: &'a mut Objects,
+    pub proto_db: &'a ProtoDb,
+    pub frm_db: &'a FrameDb,
+}
+
+impl<'a, R: 'a + Read> ObjectReader<'a, R> {
+    /// Reads object in Fallout 1 or Fallout 2 (`f2`) format. Inventory items are inserted into
+    /// `objects` while the object itself is returned to the caller.
+    pub fn read_object(&mut self, f2: bool) -> io::Result<Object> {
         let id = self.reader.read_u32::<BigEndian>()?;
 
         trace!("object ID {}", id);
@@ -349,11 +422,13 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
                     let proto = proto.borrow();
                     match proto.sub.as_item().unwrap().sub {
                         SubItem::Weapon(ref proto) => {
-                            let _ammo_count = self.reader.read_i32::<BigEndian>()?;
+                            let ammo_count = self.reader.read_i32::<BigEndian>()?;
                             let ammo_proto_id = ProtoId::from_packed(self.reader.read_u32::<BigEndian>()?);
 
                             // object_fix_weapon_ammo()
-                            let ammo_count = proto.max_ammo_count;
+                            let ammo_count = ammo_count.try_into().ok()
+                                .filter(|&c| c <= proto.max_ammo_count)
+                                .unwrap_or(proto.max_ammo_count);
                             let ammo_proto_id = ammo_proto_id.or(proto.ammo_proto_id);
                             let ammo_proto = if let Some(pid) = ammo_proto_id {
                                 Some(self.proto_db.proto(pid)?)
@@ -461,7 +536,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
 
         let mut inventory = Inventory {
             capacity: inventory_capacity,
-            items: Vec::with_capacity(inventory_capacity),
+            items: Vec::new(),
         };
         for i in 0..inventory_len {
             trace!("loading inventory item {}/{}", i, inventory_len);
@@ -495,7 +570,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         let sid = ScriptIid::read_opt(self.reader)?;
         trace!("sid: {:?}", sid);
 
-        let program_id = self.read_program_id(1)?;
+        let program_id = read_program_id(self.reader, 1)?;
         trace!("program_id: {:?}", program_id);
 
         if sid.is_some() != program_id.is_some() {
@@ -523,7 +598,7 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
         }
 
         let translucent = take_bit(flags, OutlineFlag::Translucent);
-        let disabled = take_bit(flags, OutlineFlag::Translucent);
+        let disabled = take_bit(flags, OutlineFlag::Disabled);
 
         let style =
             if take_bit(flags, OutlineFlag::GlowingRed) { OutlineStyle::GlowingRed }
@@ -548,43 +623,1032 @@ impl<'a, R: 'a + Read> MapReader<'a, R> {
             disabled,
         }))
     }
+}
+
+fn read_program_id(rd: &mut impl Read, offset: i32) -> io::Result<Option<ProgramId>> {
+    Ok(rd.read_i32::<BigEndian>()?
+        .checked_add(offset)
+        .and_then(|v| v.try_into().ok())
+        .and_then(ProgramId::new))
+}
+
+
+pub struct MapWriter<'a, W: 'a> {
+    pub writer: &'a mut W,
+    pub map: &'a Map,
+    pub objects: &'a Objects,
+    pub scripts: &'a Scripts,
+}
+
+impl<'a, W: 'a + Write> MapWriter<'a, W> {
+    /// Writes the map in Fallout 2 format. Header, map variables and square tiles are taken from
+    /// `map`. If `map.savegame` is set, script local variables are restored when the map is read.
+    ///
+    /// Objects without proto (like the map script object), the dude and objects not placed on
+    /// the map are skipped. Object IDs are assigned anew. `Scripts` doesn't track spatial and time
+    /// script triggers so these are written empty.
+    pub fn write(&mut self) -> io::Result<()> {
+        debug_time!("MapWriter::write()");
+
+        if self.map.sqr_tiles.len() != ELEVATION_COUNT as usize {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("expected {} elevations but found {}",
+                    ELEVATION_COUNT, self.map.sqr_tiles.len())));
+        }
+
+        let objects = self.collect_objects()?;
+        let mut obj_writer = ObjectWriter::new(self.objects);
+        for &obj in objects.iter().flatten() {
+            obj_writer.add(obj);
+        }
+
+        let map_sid = self.scripts.map_sid();
+        let mut scripts: Vec<_> = self.scripts.iter()
+            .filter(|&(sid, _)| Some(sid) != map_sid)
+            .collect();
+        scripts.sort_by_key(|&(sid, _)| (sid.kind(), sid.id()));
+
+        let mut local_vars = Vec::new();
+        let mut local_var_offsets = Vec::with_capacity(scripts.len());
+        for &(_, script) in &scripts {
+            local_var_offsets.push(local_vars.len());
+            local_vars.extend_from_slice(&script.local_vars);
+        }
+        if let Some(script) = map_sid.and_then(|sid| self.scripts.get(sid)) {
+            local_vars.extend_from_slice(&script.local_vars);
+        }
+
+        // header
+
+        self.writer.write_u32::<BigEndian>(VERSION_F2)?;
+
+        let name = self.map.name.as_bytes();
+        if name.len() >= NAME_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("map name is too long: {}", self.map.name)));
+        }
+        let mut name_buf = [0; NAME_LEN];
+        name_buf[..name.len()].copy_from_slice(name);
+        self.writer.write_all(&name_buf)?;
+
+        let entrance_pos = TileGrid::default().to_linear_inv(self.map.entrance.point)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                format!("invalid entrance position: {:?}", self.map.entrance)))?;
+        self.writer.write_i32::<BigEndian>(entrance_pos as i32)?;
+        self.writer.write_u32::<BigEndian>(self.map.entrance.elevation)?;
+        self.writer.write_u32::<BigEndian>(self.map.entrance_direction as u32)?;
+        self.writer.write_i32::<BigEndian>(to_i32(local_vars.len())?)?;
+
+        let map_program_id = map_sid
+            .and_then(|sid| self.scripts.get(sid))
+            .map(|script| script.program_id);
+        write_program_id(self.writer, map_program_id, 0)?;
+
+        let mut flags = self.map.savegame as u32;
+        for (i, tiles) in self.map.sqr_tiles.iter().enumerate() {
+            if tiles.is_none() {
+                flags |= 1 << (i as u32 + 1);
+            }
+        }
+        self.writer.write_u32::<BigEndian>(flags)?;
+
+        self.writer.write_i32::<BigEndian>(0)?;
+        self.writer.write_i32::<BigEndian>(to_i32(self.map.map_vars.len())?)?;
+        self.writer.write_i32::<BigEndian>(to_i32(self.map.id as usize)?)?;
+        // time
+        self.writer.write_u32::<BigEndian>(0)?;
+
+        self.writer.write_all(&[0; 44 * 4][..])?;
+
+        // map global vars
+
+        for &v in self.map.map_vars.iter() {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        // map local vars
+
+        for v in local_vars {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        self.write_sqr_tiles()?;
+        self.write_scripts(&scripts, &local_var_offsets, &obj_writer)?;
+        self.write_objects(&objects, &obj_writer)?;
 
-    fn make_map_script(&mut self, program_id: ProgramId) -> io::Result<()> {
-        let sid = self.scripts.instantiate_map_script(program_id)?;
-        let mut obj = Object::new(FrameId::MAPMK, None, Some(Default::default()), SubObject::None);
-        obj.flags = BitFlags::from(Flag::LightThru)
-            | Flag::WalkThru
-            | Flag::TurnedOff;
-        let objh = self.objects.insert(obj);
-        self.scripts.attach_to_object(sid, objh);
         Ok(())
     }
 
-    fn read_program_id(&mut self, offset: i32) -> io::Result<Option<ProgramId>> {
-        Ok(self.reader.read_i32::<BigEndian>()?
-            .checked_add(offset)
-            .and_then(|v| v.try_into().ok())
-            .and_then(ProgramId::new))
-    }
+    fn collect_objects(&self) -> io::Result<Vec<Vec<object::Handle>>> {
+        let mut in_inventory = BTreeSet::new();
+        for h in self.objects.iter() {
+            in_inventory.extend(self.objects.get(h).inventory.items.iter().map(|item| item.object));
+        }
 
-    fn read_sqr_tiles(&mut self, flags: u32) -> io::Result<SqrTiles> {
-        let mut sqr_tiles: Vec<Option<_>> = Vec::with_capacity(ELEVATION_COUNT as usize);
-        for i in 0..ELEVATION_COUNT {
-            if flags & (1 << (i as u32 + 1)) != 0 {
-                debug!("no {} elevation", i);
-                sqr_tiles.push(None);
+        let mut r = vec![Vec::new(); ELEVATION_COUNT as usize];
+        for h in self.objects.iter() {
+            if in_inventory.contains(&h) {
                 continue;
             }
-            let mut tiles = Array2d::with_default(100, 100);
+            let obj = self.objects.get(h);
+            match obj.proto_id() {
+                Some(pid) if !pid.is_dude() => {}
+                _ => continue,
+            }
+            if let Some(pos) = obj.pos {
+                r.get_mut(pos.elevation as usize)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                        format!("invalid object elevation: {}", pos.elevation)))?
+                    .push(h);
+            }
+        }
+        Ok(r)
+    }
+
+    fn write_sqr_tiles(&mut self) -> io::Result<()> {
+        let map = self.map;
+        for tiles in map.sqr_tiles.iter().flatten() {
+            if tiles.width() != 100 || tiles.height() != 100 {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    format!("invalid square tiles size: {}x{}", tiles.width(), tiles.height())));
+            }
             for y in 0..tiles.height() {
                 for x in (0..tiles.width()).rev() {
-                    let roof_id = self.reader.read_u16::<BigEndian>()?;
-                    let floor_id = self.reader.read_u16::<BigEndian>()?;
-                    *tiles.get_mut(x, y).unwrap() = (floor_id, roof_id);
+                    let &(floor_id, roof_id) = tiles.get(x, y).unwrap();
+                    self.writer.write_u16::<BigEndian>(roof_id)?;
+                    self.writer.write_u16::<BigEndian>(floor_id)?;
                 }
             }
-            sqr_tiles.push(Some(tiles));
         }
-        Ok(sqr_tiles)
+        Ok(())
+    }
+
+    fn write_scripts(&mut self,
+        scripts: &[(ScriptIid, &Script)],
+        local_var_offsets: &[usize],
+        obj_writer: &ObjectWriter,
+    ) -> io::Result<()> {
+        for script_kind in ScriptKind::iter() {
+            let scripts: Vec<_> = scripts.iter()
+                .zip(local_var_offsets)
+                .filter(|((sid, _), _)| sid.kind() == script_kind)
+                .collect();
+            self.writer.write_i32::<BigEndian>(to_i32(scripts.len())?)?;
+            for node in scripts.chunks(SCRIPT_NODE_LEN) {
+                for &(&(sid, script), &local_var_offset) in node {
+                    let self_obj_id = script.object
+                        .and_then(|obj| obj_writer.obj_id(obj))
+                        .map(|id| id as i32)
+                        .unwrap_or(-1);
+                    self.write_script(sid, script, local_var_offset, self_obj_id)?;
+                }
+                for _ in node.len()..SCRIPT_NODE_LEN {
+                    // Unused slots contain garbage in the original maps.
+                    self.writer.write_all(&[0xcc; 16 * 4][..])?;
+                }
+                self.writer.write_i32::<BigEndian>(node.len() as i32)?;
+                self.writer.write_i32::<BigEndian>(0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_script(&mut self, sid: ScriptIid, script: &Script, local_var_offset: usize,
+        self_obj_id: i32) -> io::Result<()>
+    {
+        self.writer.write_u32::<BigEndian>(sid.pack())?;
+
+        self.writer.write_i32::<BigEndian>(0)?;
+
+        match sid.kind() {
+            ScriptKind::Spatial => {
+                // elevation_and_tile, spatial_radius
+                self.writer.write_i32::<BigEndian>(0)?;
+                self.writer.write_i32::<BigEndian>(0)?;
+            }
+            ScriptKind::Time => {
+                // elevation_and_tile
+                self.writer.write_i32::<BigEndian>(0)?;
+            }
+            _ => {}
+        }
+
+        // flags
+        self.writer.write_i32::<BigEndian>(0)?;
+
+        write_program_id(self.writer, Some(script.program_id), 1)?;
+
+        self.writer.write_i32::<BigEndian>(0)?;
+        self.writer.write_i32::<BigEndian>(self_obj_id)?;
+        self.writer.write_i32::<BigEndian>(to_i32(local_var_offset)?)?;
+        self.writer.write_i32::<BigEndian>(to_i32(script.local_vars.len())?)?;
+
+        // return_value, action, ext_param, action_num, script_overrides, unk1, how_much, unk2
+        self.writer.write_all(&[0; 8 * 4][..])?;
+
+        Ok(())
+    }
+
+    fn write_objects(&mut self, objects: &[Vec<object::Handle>], obj_writer: &ObjectWriter)
+        -> io::Result<()>
+    {
+        let total_obj_count = objects.iter().map(|v| v.len()).sum();
+        self.writer.write_i32::<BigEndian>(to_i32(total_obj_count)?)?;
+        for elev_objects in objects {
+            self.writer.write_u32::<BigEndian>(elev_objects.len() as u32)?;
+            for &obj in elev_objects {
+                obj_writer.write(self.writer, obj)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes objects along with their inventory in the map file format.
+pub struct ObjectWriter<'a> {
+    objects: &'a Objects,
+    obj_ids: BTreeMap<object::Handle, u32>,
+}
+
+impl<'a> ObjectWriter<'a> {
+    pub fn new(objects: &'a Objects) -> Self {
+        Self {
+            objects,
+            obj_ids: BTreeMap::new(),
+        }
+    }
+
+    /// Assigns sequential IDs to the object and its inventory items. Objects must be added before
+    /// they're written.
+    pub fn add(&mut self, obj: object::Handle) {
+        let id = self.obj_ids.len() as u32 + 1;
+        self.obj_ids.insert(obj, id);
+        for item in &self.objects.get(obj).inventory.items {
+            self.add(item.object);
+        }
+    }
+
+    pub fn obj_id(&self, obj: object::Handle) -> Option<u32> {
+        self.obj_ids.get(&obj).cloned()
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W, objh: object::Handle) -> io::Result<()> {
+        let obj = self.objects.get(objh);
+        let proto = obj.proto()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "can't write object without proto"))?;
+
+        let id = self.obj_id(objh)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "object wasn't added to writer"))?;
+        writer.write_u32::<BigEndian>(id)?;
+
+        let pos_lin = if let Some(pos) = obj.pos {
+            TileGrid::default().to_linear_inv(pos.point)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                    format!("invalid object position: {:?}", pos)))? as i32
+        } else {
+            -1
+        };
+        writer.write_i32::<BigEndian>(pos_lin)?;
+
+        writer.write_i32::<BigEndian>(obj.screen_shift.x)?;
+        writer.write_i32::<BigEndian>(obj.screen_shift.y)?;
+        writer.write_i32::<BigEndian>(obj.screen_pos.x)?;
+        writer.write_i32::<BigEndian>(obj.screen_pos.y)?;
+        writer.write_i32::<BigEndian>(to_i32(obj.frame_idx)?)?;
+        writer.write_u32::<BigEndian>(obj.direction as u32)?;
+        writer.write_u32::<BigEndian>(obj.fid.packed())?;
+        writer.write_u32::<BigEndian>(obj.flags.bits())?;
+        writer.write_u32::<BigEndian>(obj.pos.map(|p| p.elevation).unwrap_or(0))?;
+        writer.write_u32::<BigEndian>(proto.id().pack())?;
+        // cid
+        writer.write_i32::<BigEndian>(-1)?;
+        writer.write_i32::<BigEndian>(obj.light_emitter.radius as i32)?;
+        writer.write_i32::<BigEndian>(obj.light_emitter.intensity as i32)?;
+        writer.write_u32::<BigEndian>(outline_flags(obj.outline).bits())?;
+
+        let (sid, program_id) = if let Some((sid, program_id)) = obj.script {
+            (sid.pack() as i32, Some(program_id))
+        } else {
+            (-1, None)
+        };
+        writer.write_i32::<BigEndian>(sid)?;
+        write_program_id(writer, program_id, 1)?;
+
+        // proto update data
+
+        writer.write_i32::<BigEndian>(to_i32(obj.inventory.items.len())?)?;
+        writer.write_i32::<BigEndian>(to_i32(obj.inventory.capacity)?)?;
+        writer.write_u32::<BigEndian>(0)?;
+        writer.write_u32::<BigEndian>(obj.updated_flags.bits())?;
+
+        Self::write_sub_object(writer, &obj, &proto)?;
+
+        // inventory
+
+        for item in &obj.inventory.items {
+            writer.write_i32::<BigEndian>(to_i32(item.count as usize)?)?;
+            self.write(writer, item.object)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_sub_object<W: Write>(writer: &mut W, obj: &Object, proto: &Proto) -> io::Result<()> {
+        let pid = proto.id();
+        let bad_sub = || Error::new(ErrorKind::InvalidInput,
+            format!("object data doesn't match its proto {:?}: {:?}", pid, obj.sub));
+        match proto.sub {
+            SubProto::Critter(_) => {
+                let critter = obj.sub.as_critter().ok_or_else(bad_sub)?;
+
+                // damage_last_turn, combat_state, action_points
+                writer.write_all(&[0; 3 * 4][..])?;
+
+                writer.write_u32::<BigEndian>(critter.combat.damage_flags.bits())?;
+                writer.write_i32::<BigEndian>(critter.combat.ai_packet)?;
+                writer.write_i32::<BigEndian>(critter.combat.team_id)?;
+                writer.write_i32::<BigEndian>(critter.combat.who_hit_me)?;
+
+                writer.write_i32::<BigEndian>(critter.hit_points)?;
+                writer.write_i32::<BigEndian>(critter.radiation)?;
+                writer.write_i32::<BigEndian>(critter.poison)?;
+            }
+            SubProto::Item(ref proto) => match proto.sub {
+                SubItem::Weapon(_) => {
+                    let item = obj.sub.as_item().ok_or_else(bad_sub)?;
+                    writer.write_i32::<BigEndian>(to_i32(item.ammo_count as usize)?)?;
+                    let ammo_pid = item.ammo_proto.as_ref()
+                        .map(|p| p.borrow().id().pack() as i32)
+                        .unwrap_or(-1);
+                    writer.write_i32::<BigEndian>(ammo_pid)?;
+                }
+                SubItem::Ammo(_) | SubItem::Misc(_) => {
+                    let item = obj.sub.as_item().ok_or_else(bad_sub)?;
+                    writer.write_i32::<BigEndian>(to_i32(item.ammo_count as usize)?)?;
+                }
+                SubItem::Key(ref proto) => {
+                    writer.write_i32::<BigEndian>(proto.id)?;
+                }
+                _ => {}
+            }
+            SubProto::Scenery(ref proto) => {
+                let scenery = || obj.sub.as_scenery().ok_or_else(bad_sub);
+                match proto.sub {
+                    SubScenery::Door(_) => {
+                        let door = scenery()?.as_door().ok_or_else(bad_sub)?;
+                        writer.write_u32::<BigEndian>(door.flags.bits())?;
+                    }
+                    SubScenery::Stairs(_) => {
+                        let exit = scenery()?.as_stairs().ok_or_else(bad_sub)?;
+                        let (map, location) = encode_map_exit(exit)?;
+                        writer.write_u32::<BigEndian>(location)?;
+                        writer.write_i32::<BigEndian>(map)?;
+                    }
+                    SubScenery::Elevator(_) => {
+                        let elevator = scenery()?.as_elevator().ok_or_else(bad_sub)?;
+                        writer.write_u32::<BigEndian>(elevator.kind)?;
+                        writer.write_u32::<BigEndian>(elevator.level)?;
+                    }
+                    SubScenery::Ladder(_) => {
+                        let exit = scenery()?.as_ladder().ok_or_else(bad_sub)?;
+                        let (map, location) = encode_map_exit(exit)?;
+                        writer.write_i32::<BigEndian>(map)?;
+                        writer.write_u32::<BigEndian>(location)?;
+                    }
+                    SubScenery::Misc => {}
+                }
+            }
+            SubProto::Misc if pid.is_exit_area() => {
+                let exit = obj.sub.as_map_exit().ok_or_else(bad_sub)?;
+                writer.write_i32::<BigEndian>(exit.map.encode())?;
+                let pos = TileGrid::default().to_linear_inv(exit.pos.point)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                        format!("invalid map exit: {:?}", exit)))?;
+                writer.write_u32::<BigEndian>(pos)?;
+                writer.write_u32::<BigEndian>(exit.pos.elevation)?;
+                writer.write_u32::<BigEndian>(exit.direction as u32)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+}
+
+fn write_program_id<W: Write>(writer: &mut W, program_id: Option<ProgramId>, offset: i32)
+    -> io::Result<()>
+{
+    let v = program_id
+        .map(|v| v.val() as i32 - offset)
+        .unwrap_or(-1);
+    writer.write_i32::<BigEndian>(v)
+}
+
+fn encode_map_exit(exit: &MapExit) -> io::Result<(i32, u32)> {
+    exit.encode()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+            format!("invalid map exit: {:?}", exit)))
+}
+
+fn outline_flags(outline: Option<Outline>) -> BitFlags<OutlineFlag> {
+    let outline = if let Some(v) = outline {
+        v
+    } else {
+        return BitFlags::empty();
+    };
+    let mut r = BitFlags::from(match outline.style {
+        OutlineStyle::GlowingRed => OutlineFlag::GlowingRed,
+        OutlineStyle::Red => OutlineFlag::Red,
+        OutlineStyle::Gray => OutlineFlag::Gray,
+        OutlineStyle::GlowingGreen => OutlineFlag::GlowingGreen,
+        OutlineStyle::Yellow => OutlineFlag::Yellow,
+        OutlineStyle::Brown => OutlineFlag::Brown,
+        // Purple marks bad outline flags and has no flag of its own.
+        OutlineStyle::Purple => return BitFlags::empty(),
+    });
+    if outline.translucent {
+        r |= OutlineFlag::Translucent;
+    }
+    if outline.disabled {
+        r |= OutlineFlag::Disabled;
+    }
+    r
+}
+
+fn to_i32(v: usize) -> io::Result<i32> {
+    i32::try_from(v)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("value is too big: {}", v)))
+}
+
+/// Minimal game data used by tests that need to read or write maps.
+#[cfg(test)]
+pub(crate) mod test {
+    use byteorder::{BigEndian, WriteBytesExt};
+    use enum_map::EnumMap;
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::asset::proto::{ProtoRef, WorldMapKind};
+    use crate::asset::script::db::ScriptDb;
+    use crate::fs::FileSystem;
+    use crate::game::rpg::Rpg;
+    use crate::graphics::render::TextureFactory;
+    use crate::util::test::TempDir;
+    use crate::vm::Vm;
+
+    pub const PID_KEY: u32 = 1;
+    pub const PID_MISC_ITEM: u32 = 2;
+    pub const PID_WEAPON: u32 = 3;
+    pub const PID_AMMO: u32 = 4;
+    pub const PID_CONTAINER: u32 = 5;
+    pub const PID_CRITTER: u32 = 0x1000001;
+    pub const PID_DOOR: u32 = 0x2000001;
+    pub const PID_STAIRS: u32 = 0x2000002;
+    pub const PID_ELEVATOR: u32 = 0x2000003;
+    pub const PID_LADDER: u32 = 0x2000004;
+    pub const PID_SCENERY: u32 = 0x2000005;
+    pub const PID_WALL: u32 = 0x3000001;
+
+    /// Program with `local_vars=2`.
+    pub const PROGRAM_MAP: u32 = 1;
+
+    /// Program with `local_vars=3`.
+    pub const PROGRAM_OBJ: u32 = 2;
+
+    /// Exit grid FIDs must be at least 33 to target world map.
+    pub const FID_ID_EXIT_GRID: u16 = 33;
+
+    pub struct Fixture {
+        dir: TempDir,
+        pub fs: Rc<FileSystem>,
+        pub proto_db: Rc<ProtoDb>,
+        pub frm_db: Rc<FrameDb>,
+    }
+
+    impl Fixture {
+        pub fn new(name: &str) -> Self {
+            let dir = TempDir::new(name);
+            write_game_data(dir.path());
+
+            let mut fs = FileSystem::new();
+            fs.register_provider("fixture", crate::fs::std::new_provider(dir.path()).unwrap());
+            let fs = Rc::new(fs);
+
+            let proto_db = Rc::new(ProtoDb::new(fs.clone(), "english").unwrap());
+            let frm_db = Rc::new(FrameDb::new(fs.clone(), "english",
+                TextureFactory::new_in_memory()).unwrap());
+
+            Self {
+                dir,
+                fs,
+                proto_db,
+                frm_db,
+            }
+        }
+
+        pub fn new_objects(&self) -> Objects {
+            Objects::new(TileGrid::default(), ELEVATION_COUNT, self.frm_db.clone())
+        }
+
+        pub fn new_scripts(&self) -> Scripts {
+            Scripts::new(self.proto_db.clone(),
+                ScriptDb::new(self.fs.clone(), "english").unwrap(),
+                Vm::default())
+        }
+
+        pub fn new_rpg(&self) -> Rpg {
+            Rpg::new(&self.fs, "english").unwrap()
+        }
+
+        pub fn proto(&self, pid: u32) -> ProtoRef {
+            self.proto_db.proto(ProtoId::from_packed(pid).unwrap()).unwrap()
+        }
+
+        /// Adds or replaces file in the game data.
+        pub fn write_file(&self, path: &str, content: &[u8]) {
+            write_file(self.dir.path(), path, content);
+            self.fs.invalidate();
+        }
+    }
+
+    fn write_file(root: &Path, path: &str, content: &[u8]) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn write_game_data(root: &Path) {
+        for kind in EntityKind::iter() {
+            let lst = match kind {
+                EntityKind::Item => "item.frm\n".into(),
+                EntityKind::Critter => "hmjmps\n".into(),
+                EntityKind::Scenery => "scen.frm\n".into(),
+                EntityKind::Wall => "wall.frm\n".into(),
+                EntityKind::SqrTile => "tile.frm\n".into(),
+                EntityKind::Misc => "misc.frm\n".repeat(FID_ID_EXIT_GRID as usize + 1),
+                // Includes MAPMK.
+                EntityKind::Interface => "intrface.frm\n".repeat(13),
+                _ => String::new(),
+            };
+            write_file(root, &format!("art/{0}/{0}.lst", kind.dir()), lst.as_bytes());
+        }
+        for path in &[
+            "art/items/item.frm",
+            "art/critters/hmjmpsaa.frm",
+            "art/scenery/scen.frm",
+            "art/walls/wall.frm",
+            "art/misc/misc.frm",
+            "art/intrface/intrface.frm",
+        ] {
+            write_file(root, path, &frm());
+        }
+
+        for msg in &["proto", "pro_item", "pro_crit", "pro_scen", "pro_wall", "pro_tile", "pro_misc",
+                "stat", "skill", "perk"] {
+            write_file(root, &format!("text/english/game/{}.msg", msg), b"");
+        }
+
+        let item_fid = FrameId::new_generic(EntityKind::Item, 0).unwrap();
+        let scenery_fid = FrameId::new_generic(EntityKind::Scenery, 0).unwrap();
+        let protos: &[(u32, &str, FrameId, WriteSub)] = &[
+            (PID_KEY, "key", item_fid, Box::new(|w| {
+                item(w, ItemKind::Key);
+                // id
+                w.write_i32::<BigEndian>(42).unwrap();
+            })),
+            (PID_MISC_ITEM, "misc", item_fid, Box::new(|w| {
+                item(w, ItemKind::Misc);
+                // ammo_proto_id, ammo_kind, max_ammo_count
+                write_i32s(w, &[-1, 0, 10]);
+            })),
+            (PID_WEAPON, "weapon", item_fid, Box::new(|w| {
+                item(w, ItemKind::Weapon);
+                // animation_code, damage, damage_kind, max_range, projectile_pid, min_strength,
+                // ap_cost, crit_failure_table, perk, burst_bullet_count, caliber
+                write_i32s(w, &[0, 1, 2, 0, 1, 1, -1, 0, 1, 1, 0, -1, 0, 0]);
+                // ammo_proto_id, max_ammo_count
+                write_i32s(w, &[PID_AMMO as i32, 6]);
+                // sound_id
+                w.push(0);
+            })),
+            (PID_AMMO, "ammo", item_fid, Box::new(|w| {
+                item(w, ItemKind::Ammo);
+                // caliber, max_ammo_count, ac_modifier, dr_modifier, damage_mult, damage_div
+                write_i32s(w, &[0, 20, 0, 0, 1, 1]);
+            })),
+            (PID_CONTAINER, "box", item_fid, Box::new(|w| {
+                item(w, ItemKind::Container);
+                // capacity, flags
+                write_i32s(w, &[100, 0]);
+            })),
+            (PID_CRITTER, "critter",
+                FrameId::new_critter(None, CritterAnim::Stand, WeaponKind::Unarmed, 0).unwrap(),
+                Box::new(|w| {
+                    // head_fid, ai_packet, team_id, flags
+                    write_i32s(w, &[-1, 0, 0, 0]);
+                    // base_stats, bonus_stats, skills
+                    write_i32s(w, &[0; 35 + 35 + 18]);
+                    // body_kind, experience, kill_kind, damage_kind
+                    write_i32s(w, &[0; 4]);
+                })),
+            (PID_DOOR, "door", scenery_fid, Box::new(|w| {
+                scenery(w, SceneryKind::Door);
+                // flags, key_id
+                write_i32s(w, &[0, 0]);
+            })),
+            (PID_STAIRS, "stairs", scenery_fid, Box::new(|w| {
+                scenery(w, SceneryKind::Stairs);
+                // location, map
+                write_i32s(w, &[-1, -1]);
+            })),
+            (PID_ELEVATOR, "elevator", scenery_fid, Box::new(|w| {
+                scenery(w, SceneryKind::Elevator);
+                // kind, level
+                write_i32s(w, &[0, 0]);
+            })),
+            (PID_LADDER, "ladder", scenery_fid, Box::new(|w| {
+                scenery(w, SceneryKind::LadderDown);
+                // location
+                write_i32s(w, &[-1]);
+            })),
+            (PID_SCENERY, "scenery", scenery_fid, Box::new(|w| {
+                scenery(w, SceneryKind::Misc);
+                write_i32s(w, &[0]);
+            })),
+            (PID_WALL, "wall", FrameId::new_generic(EntityKind::Wall, 0).unwrap(), Box::new(|w| {
+                // material
+                write_i32s(w, &[0]);
+            })),
+            (ProtoId::EXIT_AREA_FIRST.pack(), "exitgrid",
+                FrameId::new_generic(EntityKind::Misc, FID_ID_EXIT_GRID).unwrap(),
+                Box::new(|_| {})),
+        ];
+        let mut lsts: EnumMap<EntityKind, Vec<String>> = EnumMap::new();
+        for (pid, name, fid, write_sub) in protos {
+            let pid = ProtoId::from_packed(*pid).unwrap();
+            let file_name = format!("{}.pro", name);
+
+            let lst = &mut lsts[pid.kind()];
+            if lst.len() < pid.id() as usize {
+                lst.resize(pid.id() as usize, "none.pro".into());
+            }
+            lst[pid.id() as usize - 1] = file_name.clone();
+
+            let mut w = Vec::new();
+            w.write_u32::<BigEndian>(pid.pack()).unwrap();
+            // message_id
+            w.write_i32::<BigEndian>(0).unwrap();
+            w.write_u32::<BigEndian>(fid.packed()).unwrap();
+            // light_radius, light_intensity, flags, flags_ext
+            write_i32s(&mut w, &[0; 4]);
+            if pid.kind() != EntityKind::Misc {
+                // script
+                w.write_i32::<BigEndian>(-1).unwrap();
+            }
+            write_sub(&mut w);
+            write_file(root, &format!("proto/{}/{}", pid.kind().dir(), file_name), &w);
+        }
+        for kind in crate::asset::proto::proto_entity_kinds() {
+            let mut lst = lsts[kind].join("\n");
+            lst.push('\n');
+            write_file(root, &format!("proto/{0}/{0}.lst", kind.dir()), lst.as_bytes());
+        }
+
+        write_file(root, "scripts/scripts.lst",
+            b"map.int ; Map # local_vars=2\nobj.int ; Object # local_vars=3\n");
+        for name in &["map", "obj"] {
+            write_file(root, &format!("scripts/{}.int", name), &program());
+        }
+    }
+
+    /// Writes kind specific part of proto file.
+    type WriteSub = Box<dyn Fn(&mut Vec<u8>)>;
+
+    fn write_i32s(w: &mut Vec<u8>, values: &[i32]) {
+        for &v in values {
+            w.write_i32::<BigEndian>(v).unwrap();
+        }
+    }
+
+    fn item(w: &mut Vec<u8>, kind: ItemKind) {
+        // kind, material, size, weight, price, inventory_fid
+        write_i32s(w, &[kind as i32, 0, 1, 1, 1, -1]);
+        // sound_id
+        w.push(0);
+    }
+
+    fn scenery(w: &mut Vec<u8>, kind: SceneryKind) {
+        // kind, material
+        write_i32s(w, &[kind as i32, 0]);
+        // sound_id
+        w.push(0);
+    }
+
+    /// FRM with single 1x1 frame shared by all directions.
+    fn frm() -> Vec<u8> {
+        let mut w = Vec::new();
+        // version, fps, action_frame, frame count
+        w.write_u32::<BigEndian>(4).unwrap();
+        w.write_u16::<BigEndian>(10).unwrap();
+        w.write_u16::<BigEndian>(0).unwrap();
+        w.write_u16::<BigEndian>(1).unwrap();
+        // centers x, centers y, frame offsets, data len
+        w.extend_from_slice(&[0; 6 * 2 + 6 * 2 + 6 * 4 + 4]);
+        // width, height, len, shift x, shift y, pixels
+        write_i16s(&mut w, &[1, 1, 0, 1, 0, 0]);
+        w.push(1);
+        w
+    }
+
+    fn write_i16s(w: &mut Vec<u8>, values: &[i16]) {
+        for &v in values {
+            w.write_i16::<BigEndian>(v).unwrap();
+        }
+    }
+
+    /// Program without procedures, names and strings.
+    fn program() -> Vec<u8> {
+        let mut w = vec![0; 42];
+        // procedure count
+        w.write_u32::<BigEndian>(0).unwrap();
+        // name table, string table
+        w.write_i32::<BigEndian>(-1).unwrap();
+        w.write_i32::<BigEndian>(-1).unwrap();
+        w
+    }
+
+    fn sqr_tiles() -> SqrTiles {
+        let mut r = Vec::new();
+        for elev in 0..2 {
+            let mut tiles = Array2d::with_default(100, 100);
+            for y in 0..tiles.height() {
+                for x in 0..tiles.width() {
+                    *tiles.get_mut(x, y).unwrap() = ((x + elev) as u16, (y * 2) as u16);
+                }
+            }
+            r.push(Some(tiles));
+        }
+        r.push(None);
+        r
+    }
+
+    fn tiles(sqr_tiles: &SqrTiles) -> Vec<Option<&[(u16, u16)]>> {
+        sqr_tiles.iter().map(|t| t.as_ref().map(|t| t.as_slice())).collect()
+    }
+
+    struct World {
+        objects: Objects,
+        scripts: Scripts,
+    }
+
+    fn new_world(fx: &Fixture, savegame: bool) -> World {
+        let mut objects = fx.new_objects();
+        let mut scripts = fx.new_scripts();
+
+        let local_vars = |vars: &[i32]| if savegame { Some(vars.into()) } else { None };
+        let obj_program_id = ProgramId::new(PROGRAM_OBJ).unwrap();
+
+        let map_sid = scripts.instantiate_map_script(ProgramId::new(PROGRAM_MAP).unwrap(),
+            local_vars(&[10, 11])).unwrap();
+        let map_obj = objects.insert(Object::new(FrameId::MAPMK, None, Some(Default::default()),
+            SubObject::None));
+        scripts.attach_to_object(map_sid, map_obj);
+
+        let spatial_sid = ScriptIid::new(ScriptKind::Spatial, 0);
+        scripts.instantiate(spatial_sid, obj_program_id, local_vars(&[1, 2, 3])).unwrap();
+        let time_sid = ScriptIid::new(ScriptKind::Time, 3);
+        scripts.instantiate(time_sid, obj_program_id, None).unwrap();
+
+        let new_obj = |pid: u32, pos: Option<EPoint>, sub: SubObject| {
+            let proto = fx.proto(pid);
+            let fid = proto.borrow().fid;
+            Object::new(fid, Some(proto), pos, sub)
+        };
+
+        // Critter with inventory.
+
+        let weapon = new_obj(PID_WEAPON, None, SubObject::Item(object::Item {
+            ammo_count: 4,
+            ammo_proto: Some(fx.proto(PID_AMMO)),
+        }));
+        let weapon = objects.insert(weapon);
+        let key = objects.insert(new_obj(PID_KEY, None, SubObject::None));
+
+        let mut critter = new_obj(PID_CRITTER, Some(EPoint::new(0, Point::new(10, 20))),
+            SubObject::Critter(object::Critter {
+                hit_points: 25,
+                radiation: 3,
+                poison: 4,
+                combat: CritterCombat {
+                    damage_flags: DamageFlag::CripArmLeft | DamageFlag::Blind,
+                    ai_packet: 5,
+                    team_id: 6,
+                    who_hit_me: -1,
+                },
+            }));
+        critter.fid = critter.fid.with_direction(Some(Direction::SW)).unwrap();
+        critter.direction = Direction::SW;
+        critter.screen_shift = Point::new(-3, 4);
+        critter.screen_pos = Point::new(100, -200);
+        critter.flags = Flag::Flat | Flag::ShootThru;
+        critter.light_emitter = LightEmitter { radius: 4, intensity: 0x10000 };
+        critter.outline = Some(Outline {
+            style: OutlineStyle::Red,
+            translucent: true,
+            disabled: true,
+        });
+        critter.inventory = Inventory {
+            capacity: 10,
+            items: vec![
+                InventoryItem { object: weapon, count: 1 },
+                InventoryItem { object: key, count: 2 },
+            ],
+        };
+        let critter_sid = ScriptIid::new(ScriptKind::Critter, 7);
+        critter.script = Some((critter_sid, obj_program_id));
+        let critter = objects.insert(critter);
+        scripts.instantiate(critter_sid, obj_program_id, local_vars(&[4, 5, 6])).unwrap();
+        scripts.attach_to_object(critter_sid, critter);
+
+        // Container with inventory.
+
+        let misc_item = objects.insert(new_obj(PID_MISC_ITEM, None, SubObject::Item(object::Item {
+            ammo_count: 5,
+            ammo_proto: None,
+        })));
+        let mut container = new_obj(PID_CONTAINER, Some(EPoint::new(0, Point::new(11, 20))), SubObject::None);
+        container.inventory = Inventory {
+            capacity: 1,
+            items: vec![InventoryItem { object: misc_item, count: 3 }],
+        };
+        let item_sid = ScriptIid::new(ScriptKind::Item, 1);
+        container.script = Some((item_sid, obj_program_id));
+        let container = objects.insert(container);
+        scripts.instantiate(item_sid, obj_program_id, local_vars(&[7, 8, 9])).unwrap();
+        scripts.attach_to_object(item_sid, container);
+
+        objects.insert(new_obj(PID_AMMO, Some(EPoint::new(0, Point::new(12, 20))),
+            SubObject::Item(object::Item {
+                ammo_count: 15,
+                ammo_proto: None,
+            })));
+
+        // Scenery.
+
+        let mut door = new_obj(PID_DOOR, Some(EPoint::new(0, Point::new(0, 0))),
+            SubObject::Scenery(object::Scenery::Door(object::Door {
+                flags: DoorFlag::Open.into(),
+            })));
+        door.updated_flags = UpdatedFlag::Locked | UpdatedFlag::Jammed;
+        objects.insert(door);
+        objects.insert(new_obj(PID_STAIRS, Some(EPoint::new(0, Point::new(99, 99))),
+            SubObject::Scenery(object::Scenery::Stairs(MapExit {
+                map: TargetMap::Map { map_id: 12 },
+                pos: EPoint::new(1, Point::new(50, 60)),
+                direction: Direction::E,
+            }))));
+        objects.insert(new_obj(PID_ELEVATOR, Some(EPoint::new(1, Point::new(1, 2))),
+            SubObject::Scenery(object::Scenery::Elevator(object::Elevator {
+                kind: 3,
+                level: 1,
+            }))));
+        objects.insert(new_obj(PID_LADDER, Some(EPoint::new(1, Point::new(1, 3))),
+            SubObject::Scenery(object::Scenery::Ladder(MapExit {
+                map: TargetMap::CurrentMap,
+                pos: EPoint::new(0, Point::new(30, 40)),
+                direction: Direction::NW,
+            }))));
+        objects.insert(new_obj(PID_SCENERY, Some(EPoint::new(1, Point::new(1, 4))), SubObject::None));
+        objects.insert(new_obj(PID_WALL, Some(EPoint::new(2, Point::new(1, 5))), SubObject::None));
+        objects.insert(new_obj(ProtoId::EXIT_AREA_FIRST.pack(), Some(EPoint::new(0, Point::new(5, 5))),
+            SubObject::MapExit(MapExit {
+                map: TargetMap::WorldMap(WorldMapKind::World),
+                pos: EPoint::new(0, Point::new(0, 0)),
+                direction: Direction::NE,
+            })));
+
+        // Dude isn't part of the map.
+        let dude = Object::new(FrameId::BLANK, Some(fx.proto_db.dude()),
+            Some(EPoint::new(0, Point::new(50, 50))), SubObject::Critter(Default::default()));
+        objects.insert(dude);
+
+        World {
+            objects,
+            scripts,
+        }
+    }
+
+    fn new_map(savegame: bool) -> Map {
+        Map {
+            id: 42,
+            name: "TEST.MAP".into(),
+            savegame,
+            entrance: EPoint::new(1, Point::new(33, 44)),
+            entrance_direction: Direction::SE,
+            sqr_tiles: sqr_tiles(),
+            map_vars: vec![7, -8].into(),
+        }
+    }
+
+    fn write(map: &Map, world: &World) -> Vec<u8> {
+        let mut r = Vec::new();
+        MapWriter {
+            writer: &mut r,
+            map,
+            objects: &world.objects,
+            scripts: &world.scripts,
+        }.write().unwrap();
+        r
+    }
+
+    fn read(fx: &Fixture, data: &[u8]) -> (Map, World) {
+        let mut objects = fx.new_objects();
+        let mut scripts = fx.new_scripts();
+        let map = MapReader {
+            reader: &mut &data[..],
+            objects: &mut objects,
+            proto_db: &fx.proto_db,
+            frm_db: &fx.frm_db,
+            scripts: &mut scripts,
+        }.read().unwrap();
+        (map, World { objects, scripts })
+    }
+
+    fn roundtrip(savegame: bool) {
+        let fx = &Fixture::new(&format!("map-roundtrip-{}", savegame));
+        let map = new_map(savegame);
+        let world = new_world(fx, savegame);
+        let data = write(&map, &world);
+
+        let (act_map, act_world) = read(fx, &data);
+        assert_eq!(act_map.id, map.id);
+        assert_eq!(act_map.name, map.name);
+        assert_eq!(act_map.savegame, savegame);
+        assert_eq!(act_map.entrance, map.entrance);
+        assert_eq!(act_map.entrance_direction, map.entrance_direction);
+        assert_eq!(tiles(&act_map.sqr_tiles), tiles(&map.sqr_tiles));
+        assert_eq!(act_map.map_vars, map.map_vars);
+
+        // Map script object + 10 top-level objects + 3 inventory items.
+        assert_eq!(act_world.objects.iter().count(), 14);
+        let critter = act_world.objects.iter()
+            .find(|&h| act_world.objects.get(h).proto_id().map(|p| p.pack()) == Some(PID_CRITTER))
+            .unwrap();
+        {
+            let critter = act_world.objects.get(critter);
+            assert_eq!(critter.pos, Some(EPoint::new(0, Point::new(10, 20))));
+            assert_eq!(critter.outline, Some(Outline {
+                style: OutlineStyle::Red,
+                translucent: true,
+                disabled: true,
+            }));
+            assert_eq!(critter.inventory.items.len(), 2);
+            assert_eq!(critter.inventory.items[1].count, 2);
+        }
+
+        let local_vars = &act_world.scripts.get(ScriptIid::new(ScriptKind::Critter, 7)).unwrap()
+            .local_vars[..];
+        assert_eq!(local_vars, if savegame { &[4, 5, 6] } else { &[0, 0, 0] });
+        assert_eq!(act_world.scripts.get(ScriptIid::new(ScriptKind::Critter, 7)).unwrap().object,
+            Some(critter));
+        assert!(act_world.scripts.get(ScriptIid::new(ScriptKind::Spatial, 0)).is_some());
+        assert!(act_world.scripts.get(ScriptIid::new(ScriptKind::Time, 3)).is_some());
+        let map_script = act_world.scripts.get(act_world.scripts.map_sid().unwrap()).unwrap();
+        assert_eq!(map_script.program_id.val(), PROGRAM_MAP);
+        assert_eq!(&map_script.local_vars[..], if savegame { &[10, 11] } else { &[0, 0] });
+
+        assert_eq!(write(&act_map, &act_world), data);
+    }
+
+    #[test]
+    fn roundtrip_map() {
+        roundtrip(false);
+    }
+
+    #[test]
+    fn roundtrip_savegame() {
+        roundtrip(true);
+    }
+
+    #[test]
+    fn write_no_objects() {
+        let fx = &Fixture::new("map-write-no-objects");
+        let mut map = new_map(false);
+        map.sqr_tiles = vec![None, Some(Array2d::with_default(100, 100)), None];
+        let world = World {
+            objects: fx.new_objects(),
+            scripts: fx.new_scripts(),
+        };
+        let data = write(&map, &world);
+
+        // header + map vars + tiles + script counts + object counts
+        assert_eq!(data.len(), 236 + 2 * 4 + 100 * 100 * 4 + 5 * 4 + 4 * 4);
+
+        let (act_map, act_world) = read(fx, &data);
+        assert_eq!(tiles(&act_map.sqr_tiles), tiles(&map.sqr_tiles));
+        assert_eq!(act_world.objects.iter().count(), 0);
+        assert_eq!(act_world.scripts.map_sid(), None);
     }
 }
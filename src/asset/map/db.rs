@@ -138,6 +138,13 @@ impl MapDb {
     pub fn get(&self, id: u32) -> Option<&MapDef> {
         self.maps.get(id as usize)
     }
+
+    /// Finds map ID by `lookup_name`.
+    pub fn find(&self, lookup_name: &str) -> Option<u32> {
+        self.maps.iter()
+            .position(|m| m.lookup_name.eq_ignore_ascii_case(lookup_name))
+            .map(|i| i as u32)
+    }
 }
 
 #[cfg(test)]
@@ -201,7 +208,9 @@ map_name=desert2";
             },
         ];
 
-        let act = MapDb::read(&mut BufReader::new(Cursor::new(inp))).unwrap().maps;
-        assert_eq!(act, exp);
+        let db = MapDb::read(&mut BufReader::new(Cursor::new(inp))).unwrap();
+        assert_eq!(db.find("desert encounter 2"), Some(1));
+        assert_eq!(db.find("Desert Encounter 3"), None);
+        assert_eq!(db.maps, exp);
     }
 }
\ No newline at end of file
@@ -0,0 +1,817 @@
+//! Interplay MVE movie decoder.
+//!
+//! The file is a sequence of chunks made of opcodes. Video is decoded into two frame buffers
+//! that are swapped between frames. Each 8x8 block of the frame is encoded with one of 16
+//! methods selected by the decoding map, most methods either copy a block from the current or
+//! previous frame or fill it using a few colors from the stream. Only the 8-bit palettized video
+//! used by Fallout is supported.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{self, prelude::*, Cursor, Error, ErrorKind};
+use std::time::Duration;
+
+use crate::graphics::color::{Rgb, Rgb18};
+
+const SIGNATURE: &[u8] = b"Interplay MVE File\x1a\0";
+const MAGIC: [u16; 3] = [0x001a, 0x0100, 0x1133];
+
+const OP_END_OF_STREAM: u8 = 0x00;
+const OP_END_OF_CHUNK: u8 = 0x01;
+const OP_CREATE_TIMER: u8 = 0x02;
+const OP_INIT_AUDIO: u8 = 0x03;
+const OP_INIT_VIDEO: u8 = 0x05;
+const OP_SHOW_FRAME: u8 = 0x07;
+const OP_AUDIO_FRAME: u8 = 0x08;
+const OP_SILENCE_FRAME: u8 = 0x09;
+const OP_SET_PALETTE: u8 = 0x0c;
+const OP_SET_PALETTE_COMPRESSED: u8 = 0x0d;
+const OP_SET_DECODING_MAP: u8 = 0x0f;
+const OP_VIDEO_DATA: u8 = 0x11;
+
+const AUDIO_STEREO: u16 = 0x1;
+const AUDIO_16BIT: u16 = 0x2;
+const AUDIO_COMPRESSED: u16 = 0x4;
+
+/// Upper bound of the frame size in 8x8 blocks.
+const MAX_BLOCKS: usize = 256;
+
+/// Sample deltas of the compressed audio stream.
+static AUDIO_DELTAS: [i16; 256] = [
+         0,      1,      2,      3,      4,      5,      6,      7,
+         8,      9,     10,     11,     12,     13,     14,     15,
+        16,     17,     18,     19,     20,     21,     22,     23,
+        24,     25,     26,     27,     28,     29,     30,     31,
+        32,     33,     34,     35,     36,     37,     38,     39,
+        40,     41,     42,     43,     47,     51,     56,     61,
+        66,     72,     79,     86,     94,    102,    112,    122,
+       133,    145,    158,    173,    189,    206,    225,    245,
+       267,    292,    318,    348,    379,    414,    452,    493,
+       538,    587,    640,    699,    763,    832,    908,    991,
+      1081,   1180,   1288,   1405,   1534,   1673,   1826,   1993,
+      2175,   2373,   2590,   2826,   3084,   3365,   3672,   4008,
+      4373,   4772,   5208,   5683,   6202,   6767,   7385,   8059,
+      8794,   9597,  10472,  11428,  12471,  13609,  14851,  16206,
+     17685,  19298,  21060,  22981,  25078,  27367,  29864,  32589,
+    -29973, -26728, -23186, -19322, -15105, -10503,  -5481,     -1,
+         1,      1,   5481,  10503,  15105,  19322,  23186,  26728,
+     29973, -32589, -29864, -27367, -25078, -22981, -21060, -19298,
+    -17685, -16206, -14851, -13609, -12471, -11428, -10472,  -9597,
+     -8794,  -8059,  -7385,  -6767,  -6202,  -5683,  -5208,  -4772,
+     -4373,  -4008,  -3672,  -3365,  -3084,  -2826,  -2590,  -2373,
+     -2175,  -1993,  -1826,  -1673,  -1534,  -1405,  -1288,  -1180,
+     -1081,   -991,   -908,   -832,   -763,   -699,   -640,   -587,
+      -538,   -493,   -452,   -414,   -379,   -348,   -318,   -292,
+      -267,   -245,   -225,   -206,   -189,   -173,   -158,   -145,
+      -133,   -122,   -112,   -102,    -94,    -86,    -79,    -72,
+       -66,    -61,    -56,    -51,    -47,    -43,    -42,    -41,
+       -40,    -39,    -38,    -37,    -36,    -35,    -34,    -33,
+       -32,    -31,    -30,    -29,    -28,    -27,    -26,    -25,
+       -24,    -23,    -22,    -21,    -20,    -19,    -18,    -17,
+       -16,    -15,    -14,    -13,    -12,    -11,    -10,     -9,
+        -8,     -7,     -6,     -5,     -4,     -3,     -2,     -1,
+];
+
+fn bad_data(msg: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AudioInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AudioFormat {
+    info: AudioInfo,
+    is_16bit: bool,
+    compressed: bool,
+}
+
+/// Streaming MVE decoder. Call `next_frame()` to decode up to the next frame to display.
+pub struct MveReader<R> {
+    rd: R,
+    chunk: Vec<u8>,
+    chunk_pos: usize,
+    ended: bool,
+    frame_duration: Duration,
+    audio: Option<AudioFormat>,
+    samples: Vec<i16>,
+    width: usize,
+    height: usize,
+    /// Frame being decoded and displayed is `frames[cur]`, the previous frame is the other one.
+    frames: [Box<[u8]>; 2],
+    cur: usize,
+    decoding_map: Vec<u8>,
+    palette: Box<[Rgb18; 256]>,
+}
+
+impl<R: Read> MveReader<R> {
+    pub fn new(mut rd: R) -> io::Result<Self> {
+        let mut sig = [0; 20];
+        rd.read_exact(&mut sig)?;
+        if sig != SIGNATURE {
+            return Err(bad_data("not an MVE file"));
+        }
+        for &v in &MAGIC {
+            if rd.read_u16::<LittleEndian>()? != v {
+                return Err(bad_data("bad MVE header"));
+            }
+        }
+        Ok(Self {
+            rd,
+            chunk: Vec::new(),
+            chunk_pos: 0,
+            ended: false,
+            frame_duration: Duration::from_millis(66),
+            audio: None,
+            samples: Vec::new(),
+            width: 0,
+            height: 0,
+            frames: [Vec::new().into(), Vec::new().into()],
+            cur: 0,
+            decoding_map: Vec::new(),
+            palette: Box::new([Rgb::black(); 256]),
+        })
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width as i32
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height as i32
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    /// Returns format of the audio stream. Only known after the first `next_frame()` call.
+    pub fn audio_info(&self) -> Option<AudioInfo> {
+        self.audio.map(|a| a.info)
+    }
+
+    /// Pixels of the current frame as indices into `palette()`.
+    pub fn pixels(&self) -> &[u8] {
+        &self.frames[self.cur]
+    }
+
+    pub fn palette(&self) -> &[Rgb18; 256] {
+        &self.palette
+    }
+
+    /// Moves interleaved audio samples decoded so far into `out`.
+    pub fn drain_audio(&mut self, out: &mut Vec<i16>) {
+        out.append(&mut self.samples);
+    }
+
+    /// Decodes the stream up to the next frame to display. Returns `false` at the end of the
+    /// stream.
+    pub fn next_frame(&mut self) -> io::Result<bool> {
+        while !self.ended {
+            if self.chunk_pos == self.chunk.len() && !self.read_chunk()? {
+                self.ended = true;
+                break;
+            }
+            if self.next_opcode()? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn read_chunk(&mut self) -> io::Result<bool> {
+        let len = match self.rd.read_u16::<LittleEndian>() {
+            Ok(v) => v as usize,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let _kind = self.rd.read_u16::<LittleEndian>()?;
+        self.chunk.resize(len, 0);
+        self.rd.read_exact(&mut self.chunk)?;
+        self.chunk_pos = 0;
+        Ok(true)
+    }
+
+    /// Handles the next opcode of the current chunk. Returns `true` if a frame is ready.
+    fn next_opcode(&mut self) -> io::Result<bool> {
+        let (kind, version, data) = {
+            let mut hdr = Cursor::new(&self.chunk[self.chunk_pos..]);
+            let len = hdr.read_u16::<LittleEndian>()? as usize;
+            let kind = hdr.read_u8()?;
+            let version = hdr.read_u8()?;
+            let start = self.chunk_pos + 4;
+            let end = start + len;
+            if end > self.chunk.len() {
+                return Err(bad_data("MVE opcode crosses chunk boundary"));
+            }
+            self.chunk_pos = end;
+            (kind, version, start..end)
+        };
+        if kind == OP_END_OF_CHUNK {
+            self.chunk_pos = self.chunk.len();
+            return Ok(false);
+        }
+        let chunk = std::mem::take(&mut self.chunk);
+        let r = self.handle_opcode(kind, version, &chunk[data]);
+        self.chunk = chunk;
+        r
+    }
+
+    fn handle_opcode(&mut self, kind: u8, version: u8, data: &[u8]) -> io::Result<bool> {
+        let mut rd = Cursor::new(data);
+        match kind {
+            OP_END_OF_STREAM => self.ended = true,
+            OP_CREATE_TIMER => {
+                let rate = rd.read_u32::<LittleEndian>()?;
+                let subdivision = rd.read_u16::<LittleEndian>()?;
+                self.frame_duration = Duration::from_micros(rate as u64 * subdivision as u64);
+            }
+            OP_INIT_AUDIO => {
+                let _ = rd.read_u16::<LittleEndian>()?;
+                let flags = rd.read_u16::<LittleEndian>()?;
+                let sample_rate = rd.read_u16::<LittleEndian>()? as u32;
+                self.audio = Some(AudioFormat {
+                    info: AudioInfo {
+                        channels: if flags & AUDIO_STEREO != 0 { 2 } else { 1 },
+                        sample_rate,
+                    },
+                    is_16bit: flags & AUDIO_16BIT != 0,
+                    compressed: version > 0 && flags & AUDIO_COMPRESSED != 0,
+                });
+            }
+            OP_INIT_VIDEO => {
+                let width = rd.read_u16::<LittleEndian>()? as usize;
+                let height = rd.read_u16::<LittleEndian>()? as usize;
+                if version >= 2 {
+                    let _count = rd.read_u16::<LittleEndian>()?;
+                    if rd.read_u16::<LittleEndian>()? != 0 {
+                        return Err(Error::new(ErrorKind::InvalidData,
+                            "true color MVE video is not supported"));
+                    }
+                }
+                if width == 0 || height == 0 || width > MAX_BLOCKS || height > MAX_BLOCKS {
+                    return Err(bad_data(format!("bad MVE video size: {}x{} blocks",
+                        width, height)));
+                }
+                self.width = width * 8;
+                self.height = height * 8;
+                let len = self.width * self.height;
+                self.frames = [vec![0; len].into(), vec![0; len].into()];
+            }
+            OP_SHOW_FRAME => return Ok(true),
+            OP_AUDIO_FRAME | OP_SILENCE_FRAME => {
+                let _seq = rd.read_u16::<LittleEndian>()?;
+                let streams = rd.read_u16::<LittleEndian>()?;
+                let len = rd.read_u16::<LittleEndian>()? as usize;
+                if streams & 1 != 0 {
+                    if let Some(format) = self.audio {
+                        if kind == OP_AUDIO_FRAME {
+                            decode_audio(format, len, &mut rd, &mut self.samples)?;
+                        } else {
+                            let count = if format.is_16bit { len / 2 } else { len };
+                            self.samples.resize(self.samples.len() + count, 0);
+                        }
+                    }
+                }
+            }
+            OP_SET_PALETTE => {
+                let start = rd.read_u16::<LittleEndian>()? as usize;
+                let count = rd.read_u16::<LittleEndian>()? as usize;
+                if start + count > 256 {
+                    return Err(bad_data("bad MVE palette range"));
+                }
+                for c in &mut self.palette[start..start + count] {
+                    *c = read_rgb(&mut rd)?;
+                }
+            }
+            OP_SET_PALETTE_COMPRESSED => {
+                for group in self.palette.chunks_mut(8) {
+                    let mask = rd.read_u8()?;
+                    for (i, c) in group.iter_mut().enumerate() {
+                        if mask & (1 << i) != 0 {
+                            *c = read_rgb(&mut rd)?;
+                        }
+                    }
+                }
+            }
+            OP_SET_DECODING_MAP => {
+                self.decoding_map.clear();
+                self.decoding_map.extend_from_slice(data);
+            }
+            OP_VIDEO_DATA => {
+                if data.len() < 14 {
+                    return Err(bad_data("MVE video data is too short"));
+                }
+                let flags = (&data[12..14]).read_u16::<LittleEndian>()?;
+                if flags & 1 != 0 {
+                    self.cur ^= 1;
+                }
+                self.decode_video(&data[14..])?;
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn decode_video(&mut self, data: &[u8]) -> io::Result<()> {
+        let blocks_x = self.width / 8;
+        let block_count = blocks_x * (self.height / 8);
+        if self.decoding_map.len() * 2 < block_count {
+            return Err(bad_data("MVE decoding map is too short"));
+        }
+        let mut rd = Cursor::new(data);
+        let (a, b) = self.frames.split_at_mut(1);
+        let (cur, prev) = if self.cur == 0 {
+            (&mut a[0], &b[0])
+        } else {
+            (&mut b[0], &a[0])
+        };
+        let mut frame = Frame {
+            cur,
+            prev,
+            width: self.width,
+            height: self.height,
+            x: 0,
+            y: 0,
+        };
+        for i in 0..block_count {
+            let op = (self.decoding_map[i / 2] >> ((i & 1) * 4)) & 0xf;
+            frame.x = (i % blocks_x) * 8;
+            frame.y = (i / blocks_x) * 8;
+            frame.decode_block(op, &mut rd)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_rgb(rd: &mut impl Read) -> io::Result<Rgb18> {
+    let r = rd.read_u8()? & 0x3f;
+    let g = rd.read_u8()? & 0x3f;
+    let b = rd.read_u8()? & 0x3f;
+    Ok(Rgb::new(r, g, b))
+}
+
+fn decode_audio(format: AudioFormat, len: usize, rd: &mut impl Read, out: &mut Vec<i16>)
+    -> io::Result<()>
+{
+    if format.compressed {
+        let channels = format.info.channels as usize;
+        let count = len / 2;
+        let mut predictors = [0i32; 2];
+        for p in &mut predictors[..channels] {
+            *p = rd.read_i16::<LittleEndian>()? as i32;
+            out.push(*p as i16);
+        }
+        for i in channels..count {
+            let p = &mut predictors[i % channels];
+            *p = (*p + AUDIO_DELTAS[rd.read_u8()? as usize] as i32)
+                .clamp(i16::MIN as i32, i16::MAX as i32);
+            out.push(*p as i16);
+        }
+    } else if format.is_16bit {
+        for _ in 0..len / 2 {
+            out.push(rd.read_i16::<LittleEndian>()?);
+        }
+    } else {
+        for _ in 0..len {
+            out.push(((rd.read_u8()? as i16) - 0x80) << 8);
+        }
+    }
+    Ok(())
+}
+
+/// Decoding target positioned at the top-left corner of the current block.
+struct Frame<'a> {
+    cur: &'a mut [u8],
+    prev: &'a [u8],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+}
+
+impl Frame<'_> {
+    fn set(&mut self, x: usize, y: usize, v: u8) {
+        self.cur[(self.y + y) * self.width + self.x + x] = v;
+    }
+
+    fn fill_2x2(&mut self, x: usize, y: usize, v: u8) {
+        self.set(x, y, v);
+        self.set(x + 1, y, v);
+        self.set(x, y + 1, v);
+        self.set(x + 1, y + 1, v);
+    }
+
+    /// Offset of the block at `(dx, dy)` relative to the current block.
+    fn offset(&self, dx: i32, dy: i32) -> io::Result<usize> {
+        let x = self.x as i32 + dx;
+        let y = self.y as i32 + dy;
+        if x < 0 || y < 0 || x as usize + 8 > self.width || y as usize + 8 > self.height {
+            return Err(bad_data(format!("MVE motion vector out of frame: {}, {}", dx, dy)));
+        }
+        Ok(y as usize * self.width + x as usize)
+    }
+
+    fn copy_from_prev(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        let src = self.offset(dx, dy)?;
+        let dst = self.offset(0, 0)?;
+        for row in 0..8 {
+            let (s, d) = (src + row * self.width, dst + row * self.width);
+            self.cur[d..d + 8].copy_from_slice(&self.prev[s..s + 8]);
+        }
+        Ok(())
+    }
+
+    fn copy_from_cur(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        let src = self.offset(dx, dy)?;
+        let dst = self.offset(0, 0)?;
+        for row in 0..8 {
+            let s = src + row * self.width;
+            self.cur.copy_within(s..s + 8, dst + row * self.width);
+        }
+        Ok(())
+    }
+
+    fn decode_block(&mut self, op: u8, rd: &mut Cursor<&[u8]>) -> io::Result<()> {
+        match op {
+            // Unchanged from the previous frame.
+            0x0 => self.copy_from_prev(0, 0)?,
+            // Unchanged from two frames ago which is what the current buffer holds.
+            0x1 => {}
+            // Copy from a block of two frames ago that is below or to the right.
+            0x2 => {
+                let b = rd.read_u8()? as i32;
+                let (dx, dy) = if b < 56 {
+                    (8 + b % 7, b / 7)
+                } else {
+                    (-14 + (b - 56) % 29, 8 + (b - 56) / 29)
+                };
+                self.copy_from_cur(dx, dy)?;
+            }
+            // Copy from an already decoded block above or to the left.
+            0x3 => {
+                let b = rd.read_u8()? as i32;
+                let (dx, dy) = if b < 56 {
+                    (-(8 + b % 7), -(b / 7))
+                } else {
+                    (14 - (b - 56) % 29, -(8 + (b - 56) / 29))
+                };
+                self.copy_from_cur(dx, dy)?;
+            }
+            0x4 => {
+                let b = rd.read_u8()? as i32;
+                self.copy_from_prev(-8 + (b & 0xf), -8 + (b >> 4))?;
+            }
+            0x5 => {
+                let dx = rd.read_i8()? as i32;
+                let dy = rd.read_i8()? as i32;
+                self.copy_from_prev(dx, dy)?;
+            }
+            // Unused.
+            0x6 => {}
+            0x7 => self.two_colors(rd)?,
+            0x8 => self.two_colors_split(rd)?,
+            0x9 => self.four_colors(rd)?,
+            0xa => self.four_colors_split(rd)?,
+            // Raw pixels.
+            0xb => {
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let c = rd.read_u8()?;
+                        self.set(x, y, c);
+                    }
+                }
+            }
+            // Raw 2x2 blocks.
+            0xc => {
+                for y in (0..8).step_by(2) {
+                    for x in (0..8).step_by(2) {
+                        let c = rd.read_u8()?;
+                        self.fill_2x2(x, y, c);
+                    }
+                }
+            }
+            // Solid 4x4 quadrants.
+            0xd => {
+                let mut p = [0; 2];
+                for y in 0..8 {
+                    if y % 4 == 0 {
+                        rd.read_exact(&mut p)?;
+                    }
+                    for x in 0..8 {
+                        self.set(x, y, p[x / 4]);
+                    }
+                }
+            }
+            // Solid block.
+            0xe => {
+                let c = rd.read_u8()?;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        self.set(x, y, c);
+                    }
+                }
+            }
+            // Checkerboard.
+            0xf => {
+                let mut p = [0; 2];
+                rd.read_exact(&mut p)?;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        self.set(x, y, p[(x ^ y) & 1]);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Pixels or 2x2 blocks of one of two colors.
+    fn two_colors(&mut self, rd: &mut Cursor<&[u8]>) -> io::Result<()> {
+        let mut p = [0; 2];
+        rd.read_exact(&mut p)?;
+        if p[0] <= p[1] {
+            for y in 0..8 {
+                let flags = rd.read_u8()?;
+                for x in 0..8 {
+                    self.set(x, y, p[(flags >> x) as usize & 1]);
+                }
+            }
+        } else {
+            let mut flags = rd.read_u16::<LittleEndian>()?;
+            for y in (0..8).step_by(2) {
+                for x in (0..8).step_by(2) {
+                    self.fill_2x2(x, y, p[flags as usize & 1]);
+                    flags >>= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Two colors per quadrant or per half of the block.
+    fn two_colors_split(&mut self, rd: &mut Cursor<&[u8]>) -> io::Result<()> {
+        let mut p = [0; 2];
+        rd.read_exact(&mut p)?;
+        if p[0] <= p[1] {
+            // Quadrants in order: top-left, bottom-left, top-right, bottom-right.
+            for q in 0..4 {
+                if q > 0 {
+                    rd.read_exact(&mut p)?;
+                }
+                let mut flags = rd.read_u16::<LittleEndian>()?;
+                let (qx, qy) = ((q / 2) * 4, (q % 2) * 4);
+                for y in 0..4 {
+                    for x in 0..4 {
+                        self.set(qx + x, qy + y, p[flags as usize & 1]);
+                        flags >>= 1;
+                    }
+                }
+            }
+        } else {
+            let mut flags = rd.read_u32::<LittleEndian>()?;
+            let mut p2 = [0; 2];
+            rd.read_exact(&mut p2)?;
+            let vertical = p2[0] <= p2[1];
+            for half in 0..2 {
+                if half == 1 {
+                    p = p2;
+                    flags = rd.read_u32::<LittleEndian>()?;
+                }
+                let (w, h) = if vertical { (4, 8) } else { (8, 4) };
+                let (hx, hy) = if vertical { (half * 4, 0) } else { (0, half * 4) };
+                for y in 0..h {
+                    for x in 0..w {
+                        self.set(hx + x, hy + y, p[flags as usize & 1]);
+                        flags >>= 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pixels, 2x2, 2x1 or 1x2 blocks of one of four colors.
+    fn four_colors(&mut self, rd: &mut Cursor<&[u8]>) -> io::Result<()> {
+        let mut p = [0; 4];
+        rd.read_exact(&mut p)?;
+        if p[0] <= p[1] {
+            if p[2] <= p[3] {
+                for y in 0..8 {
+                    let mut flags = rd.read_u16::<LittleEndian>()?;
+                    for x in 0..8 {
+                        self.set(x, y, p[flags as usize & 3]);
+                        flags >>= 2;
+                    }
+                }
+            } else {
+                let mut flags = rd.read_u32::<LittleEndian>()?;
+                for y in (0..8).step_by(2) {
+                    for x in (0..8).step_by(2) {
+                        self.fill_2x2(x, y, p[flags as usize & 3]);
+                        flags >>= 2;
+                    }
+                }
+            }
+        } else {
+            let mut flags = rd.read_u64::<LittleEndian>()?;
+            if p[2] <= p[3] {
+                for y in 0..8 {
+                    for x in (0..8).step_by(2) {
+                        let c = p[flags as usize & 3];
+                        self.set(x, y, c);
+                        self.set(x + 1, y, c);
+                        flags >>= 2;
+                    }
+                }
+            } else {
+                for y in (0..8).step_by(2) {
+                    for x in 0..8 {
+                        let c = p[flags as usize & 3];
+                        self.set(x, y, c);
+                        self.set(x, y + 1, c);
+                        flags >>= 2;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Four colors per quadrant or per half of the block.
+    fn four_colors_split(&mut self, rd: &mut Cursor<&[u8]>) -> io::Result<()> {
+        let mut p = [0; 4];
+        rd.read_exact(&mut p)?;
+        if p[0] <= p[1] {
+            // Quadrants in order: top-left, bottom-left, top-right, bottom-right.
+            for q in 0..4 {
+                if q > 0 {
+                    rd.read_exact(&mut p)?;
+                }
+                let mut flags = rd.read_u32::<LittleEndian>()?;
+                let (qx, qy) = ((q / 2) * 4, (q % 2) * 4);
+                for y in 0..4 {
+                    for x in 0..4 {
+                        self.set(qx + x, qy + y, p[flags as usize & 3]);
+                        flags >>= 2;
+                    }
+                }
+            }
+        } else {
+            let mut flags = rd.read_u64::<LittleEndian>()?;
+            let mut p2 = [0; 4];
+            rd.read_exact(&mut p2)?;
+            let vertical = p2[0] <= p2[1];
+            for half in 0..2 {
+                if half == 1 {
+                    p = p2;
+                    flags = rd.read_u64::<LittleEndian>()?;
+                }
+                let (w, h) = if vertical { (4, 8) } else { (8, 4) };
+                let (hx, hy) = if vertical { (half * 4, 0) } else { (0, half * 4) };
+                for y in 0..h {
+                    for x in 0..w {
+                        self.set(hx + x, hy + y, p[flags as usize & 3]);
+                        flags >>= 2;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    #[derive(Default)]
+    struct Builder {
+        data: Vec<u8>,
+        chunk: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new() -> Self {
+            let mut data = SIGNATURE.to_vec();
+            for &v in &MAGIC {
+                data.write_u16::<LittleEndian>(v).unwrap();
+            }
+            Self {
+                data,
+                chunk: Vec::new(),
+            }
+        }
+
+        fn op(mut self, kind: u8, version: u8, data: &[u8]) -> Self {
+            self.chunk.write_u16::<LittleEndian>(data.len() as u16).unwrap();
+            self.chunk.push(kind);
+            self.chunk.push(version);
+            self.chunk.extend_from_slice(data);
+            self
+        }
+
+        fn video(self, map: u8, data: &[u8]) -> Self {
+            let mut v = vec![0; 12];
+            v.extend_from_slice(&[1, 0]);
+            v.extend_from_slice(data);
+            self.op(OP_SET_DECODING_MAP, 0, &[map])
+                .op(OP_VIDEO_DATA, 0, &v)
+                .op(OP_SHOW_FRAME, 0, &[0, 0, 0, 0])
+        }
+
+        fn chunk(mut self) -> Self {
+            self = self.op(OP_END_OF_CHUNK, 0, &[]);
+            self.data.write_u16::<LittleEndian>(self.chunk.len() as u16).unwrap();
+            self.data.write_u16::<LittleEndian>(0).unwrap();
+            self.data.append(&mut self.chunk);
+            self
+        }
+    }
+
+    fn fixture() -> Vec<u8> {
+        Builder::new()
+            .op(OP_CREATE_TIMER, 0, &[0xe8, 0x03, 0, 0, 66, 0])
+            .op(OP_INIT_AUDIO, 1, &[0, 0, 7, 0, 0x22, 0x56, 0, 0, 0, 0])
+            .op(OP_INIT_VIDEO, 0, &[2, 0, 1, 0])
+            .op(OP_SET_PALETTE, 0, &[1, 0, 2, 0, 63, 0, 0, 0, 63, 0])
+            .op(OP_AUDIO_FRAME, 0, &[0, 0, 1, 0, 8, 0, 100, 0, 0x9c, 0xff, 1, 255])
+            // Solid and checkerboard.
+            .video(0xfe, &[1, 1, 2])
+            .chunk()
+            .op(OP_SILENCE_FRAME, 0, &[1, 0, 1, 0, 4, 0])
+            // Unchanged and two colors.
+            .video(0x70, &[1, 2, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f])
+            .chunk()
+            // Quadrants and copy from the left.
+            .video(0x3d, &[3, 4, 5, 6, 0])
+            .chunk()
+            .op(OP_END_OF_STREAM, 0, &[])
+            .chunk()
+            .data
+    }
+
+    fn row(mve: &MveReader<impl Read>, y: usize) -> &[u8] {
+        &mve.pixels()[y * 16..(y + 1) * 16]
+    }
+
+    #[test]
+    fn decode() {
+        let mut mve = MveReader::new(Cursor::new(fixture())).unwrap();
+
+        assert!(mve.next_frame().unwrap());
+        assert_eq!((mve.width(), mve.height()), (16, 8));
+        assert_eq!(mve.frame_duration(), Duration::from_millis(66));
+        assert_eq!(mve.audio_info(), Some(AudioInfo { channels: 2, sample_rate: 22050 }));
+        assert_eq!(mve.palette()[2], Rgb::new(0, 63, 0));
+        assert_eq!(row(&mve, 0), &[1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 1, 2, 1, 2, 1, 2]);
+        assert_eq!(row(&mve, 1), &[1, 1, 1, 1, 1, 1, 1, 1, 2, 1, 2, 1, 2, 1, 2, 1]);
+        let audio = &mut Vec::new();
+        mve.drain_audio(audio);
+        assert_eq!(audio, &[100, -100, 101, -101]);
+
+        assert!(mve.next_frame().unwrap());
+        for y in 0..8 {
+            assert_eq!(row(&mve, y), &[1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 1, 1, 1, 1]);
+        }
+        mve.drain_audio(audio);
+        assert_eq!(audio, &[100, -100, 101, -101, 0, 0]);
+
+        assert!(mve.next_frame().unwrap());
+        assert_eq!(row(&mve, 0), &[3, 3, 3, 3, 4, 4, 4, 4, 3, 3, 3, 3, 4, 4, 4, 4]);
+        assert_eq!(row(&mve, 7), &[5, 5, 5, 5, 6, 6, 6, 6, 5, 5, 5, 5, 6, 6, 6, 6]);
+
+        assert!(!mve.next_frame().unwrap());
+        assert!(!mve.next_frame().unwrap());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut data = fixture();
+        data.truncate(100);
+        let mut mve = MveReader::new(Cursor::new(data)).unwrap();
+        assert!(mve.next_frame().is_err());
+    }
+
+    #[test]
+    fn bad_motion_vector() {
+        let data = Builder::new()
+            .op(OP_INIT_VIDEO, 0, &[1, 0, 1, 0])
+            .video(0x04, &[0])
+            .chunk()
+            .data;
+        let mut mve = MveReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(mve.next_frame().err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut data = fixture();
+        data[0] = b'X';
+        assert!(MveReader::new(Cursor::new(data)).is_err());
+    }
+}
@@ -24,6 +24,9 @@ pub const MSG_DOORWAY_SEEMS_TO_BE_BLOCKED: MessageId = 597;
 /// "You see: %s."
 pub const MSG_YOU_SEE_X: MessageId = 480;
 
+/// "This container is locked."
+pub const MSG_CONTAINER_LOCKED: MessageId = 487;
+
 pub type ProtoRef = std::rc::Rc<std::cell::RefCell<Proto>>;
 
 #[derive(Debug)]
@@ -273,7 +276,7 @@ pub struct Critter {
 #[derive(Clone, Copy, Debug, EnumFlags, Eq, PartialEq)]
 #[repr(u32)]
 pub enum CritterFlag {
-    NoBarter        = 0x00000002, // Can barter with.
+    Barter          = 0x00000002, // Can barter with.
     NoSteal         = 0x00000020, // Can't steal from.
     NoDrop          = 0x00000040, // Doesn't drop items.
     NoLoseLimbs     = 0x00000080, // Can't shoot off limbs.
@@ -400,8 +403,8 @@ impl MapExit {
         } else {
             TargetMap::CurrentMap
         };
-        let elevation = location & 0x3ffffff;
-        let pos = TileGrid::default().from_linear_inv((location & 0xE0000000) >> 29)
+        let elevation = (location & 0xE0000000) >> 29;
+        let pos = TileGrid::default().from_linear_inv(location & 0x3ffffff)
             .elevated(elevation);
         let direction = Direction::from_u32((location & 0x1C000000) >> 26)?;
         Some(MapExit {
@@ -410,6 +413,19 @@ impl MapExit {
             direction,
         })
     }
+
+    /// Inverse of `decode()`. Returns `(map, location)` pair or `None` if the position can't
+    /// be encoded.
+    pub fn encode(&self) -> Option<(i32, u32)> {
+        let tile_num = TileGrid::default().to_linear_inv(self.pos.point)?;
+        if self.pos.elevation > 7 {
+            return None;
+        }
+        let location = tile_num
+            | (self.direction as u32) << 26
+            | self.pos.elevation << 29;
+        Some((self.map.encode(), location))
+    }
 }
 
 #[derive(Clone, Copy, Eq, Debug, PartialEq)]
@@ -432,6 +448,16 @@ impl TargetMap {
             _ => return None,
         })
     }
+
+    /// Inverse of `decode()`. `CurrentMap` is encoded as `0` which is what `MapExit` expects.
+    pub fn encode(self) -> i32 {
+        match self {
+            TargetMap::Map { map_id } => map_id as i32,
+            TargetMap::CurrentMap => 0,
+            TargetMap::WorldMap(WorldMapKind::Town) => -1,
+            TargetMap::WorldMap(WorldMapKind::World) => -2,
+        }
+    }
 }
 
 // Subset that has prototypes.
@@ -68,6 +68,7 @@ impl ProtoId {
     pub const HARDENED_POWER_ARMOR: Self = unsafe { Self::from_packed_unchecked(0xE8) };
     pub const ADVANCED_POWER_ARMOR: Self = unsafe { Self::from_packed_unchecked(0x15C) };
     pub const ADVANCED_POWER_ARMOR_MK2: Self = unsafe { Self::from_packed_unchecked(0x15D) };
+    pub const BOTTLE_CAPS: Self = unsafe { Self::from_packed_unchecked(0x29) };
     pub const MIRRORED_SHADES: Self = unsafe { Self::from_packed_unchecked(0x1B1) };
     pub const EXIT_AREA_FIRST: Self = unsafe { Self::from_packed_unchecked(0x5000010) };
     pub const EXIT_AREA_LAST: Self = unsafe { Self::from_packed_unchecked(0x5000017) };
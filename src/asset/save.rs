@@ -0,0 +1,612 @@
+use bstring::{bstr, BString};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use enum_map::EnumMap;
+use log::*;
+use measure_time::*;
+use num_traits::FromPrimitive;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Error, ErrorKind, prelude::*};
+use std::path::Path;
+
+use crate::asset::{Skill, Stat};
+use crate::asset::frame::FrameDb;
+use crate::asset::map::{MapId, ObjectReader, ObjectWriter};
+use crate::asset::proto::{self, ProtoDb};
+use crate::asset::worldmap::WorldMapDb;
+use crate::game::GameTime;
+use crate::game::encounter::EncounterCounters;
+use crate::game::object::{self, Object, Objects};
+use crate::game::rpg::{Rpg, RpgState};
+use crate::game::timer::TimerEvents;
+use crate::game::worldmap::{WorldMap, WorldMapSave};
+
+/// Name of the file with the game session state inside a slot directory.
+pub const SAVE_FILE_NAME: &str = "SAVE.DAT";
+
+const SIGNATURE: &[u8] = b"FALLOUT SAVE FILE";
+const SIGNATURE_LEN: usize = 24;
+const VERSION: (u16, u16, u8) = (1, 2, b'R');
+const PLAYER_NAME_LEN: usize = 32;
+const SAVE_NAME_LEN: usize = 30;
+const MAP_FILE_NAME_LEN: usize = 16;
+const THUMBNAIL_LEN: usize = 224 * 133;
+const HEADER_PADDING_LEN: usize = 128;
+const STAT_COUNT: usize = 35;
+const SKILL_COUNT: usize = 18;
+
+/// Returns name of the slot directory, e.g. `SLOT01`. Slots are numbered from 1.
+pub fn slot_dir_name(slot: u32) -> String {
+    format!("SLOT{:02}", slot)
+}
+
+/// Returns name of the map state file, e.g. `ARTEMPLE.SAV` for `artemple`.
+pub fn map_file_name(map_name: &str) -> String {
+    format!("{}.SAV", map_name.to_ascii_uppercase())
+}
+
+/// Inverse of `map_file_name()`.
+pub fn map_name(map_file_name: &str) -> Option<String> {
+    let i = map_file_name.len().checked_sub(4)?;
+    if !map_file_name.is_char_boundary(i) || !map_file_name[i..].eq_ignore_ascii_case(".sav") {
+        return None;
+    }
+    Some(map_file_name[..i].to_ascii_lowercase())
+}
+
+/// Moves the directory `src` to `dst` replacing the existing `dst`. The old `dst` is restored if
+/// `src` can't be moved.
+pub fn replace_dir(src: &Path, dst: &Path) -> io::Result<()> {
+    let old = dst.with_extension("old");
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    let has_old = dst.exists();
+    if has_old {
+        fs::rename(dst, &old)?;
+    }
+    if let Err(e) = fs::rename(src, dst) {
+        if has_old {
+            fs::rename(&old, dst)?;
+        }
+        return Err(e);
+    }
+    if has_old {
+        if let Err(e) = fs::remove_dir_all(&old) {
+            warn!("couldn't remove {}: {}", old.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// `SAVE.DAT` header. Has the same layout as in the original game so the slot can be listed
+/// without loading the rest of the file. Real-world save date and thumbnail are not supported and
+/// are written zeroed.
+pub struct Header {
+    pub player_name: BString,
+    pub save_name: BString,
+    pub game_time: GameTime,
+    pub map_id: MapId,
+    pub elevation: u32,
+}
+
+impl Header {
+    pub fn read(rd: &mut impl Read) -> io::Result<Self> {
+        let signature = read_str(rd, SIGNATURE_LEN)?;
+        if signature.as_bytes() != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid savegame signature"));
+        }
+        let version = (rd.read_u16::<BigEndian>()?, rd.read_u16::<BigEndian>()?, rd.read_u8()?);
+        if version != VERSION {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("unsupported savegame version: {:?}", version)));
+        }
+
+        let player_name = read_str(rd, PLAYER_NAME_LEN)?;
+        let save_name = read_str(rd, SAVE_NAME_LEN)?;
+
+        // Real-world day, month, year and time.
+        rd.read_exact(&mut [0; 3 * 2 + 4][..])?;
+
+        // Game month, day and year.
+        rd.read_exact(&mut [0; 3 * 2][..])?;
+        let game_time = GameTime::from_decis(rd.read_u32::<BigEndian>()?);
+
+        let elevation = rd.read_u16::<BigEndian>()?.into();
+        let map_id = rd.read_u16::<BigEndian>()?.into();
+        let map_file_name = read_str(rd, MAP_FILE_NAME_LEN)?;
+        debug!("savegame map: {} {}", map_id, map_file_name.display());
+
+        io::copy(&mut rd.take((THUMBNAIL_LEN + HEADER_PADDING_LEN) as u64), &mut io::sink())?;
+
+        Ok(Self {
+            player_name,
+            save_name,
+            game_time,
+            map_id,
+            elevation,
+        })
+    }
+
+    /// `map_name` is the name of the current map as in `maps.txt`.
+    pub fn write(&self, wr: &mut impl Write, map_name: &str) -> io::Result<()> {
+        write_str(wr, SIGNATURE.into(), SIGNATURE_LEN)?;
+        wr.write_u16::<BigEndian>(VERSION.0)?;
+        wr.write_u16::<BigEndian>(VERSION.1)?;
+        wr.write_u8(VERSION.2)?;
+
+        write_str(wr, &self.player_name, PLAYER_NAME_LEN)?;
+        write_str(wr, &self.save_name, SAVE_NAME_LEN)?;
+
+        // Real-world day, month, year and time.
+        wr.write_all(&[0; 3 * 2 + 4][..])?;
+
+        wr.write_u16::<BigEndian>(self.game_time.month().into())?;
+        wr.write_u16::<BigEndian>(self.game_time.day().into())?;
+        wr.write_u16::<BigEndian>(self.game_time.year())?;
+        wr.write_u32::<BigEndian>(self.game_time.as_decis())?;
+
+        wr.write_u16::<BigEndian>(to_u16(self.elevation)?)?;
+        wr.write_u16::<BigEndian>(to_u16(self.map_id)?)?;
+        write_str(wr, map_file_name(map_name).as_bytes().into(), MAP_FILE_NAME_LEN)?;
+
+        wr.write_all(&vec![0; THUMBNAIL_LEN + HEADER_PADDING_LEN])?;
+
+        Ok(())
+    }
+}
+
+pub struct SaveGame {
+    pub header: Header,
+    pub global_vars: Box<[i32]>,
+    /// Names of the map state files in the slot directory.
+    pub map_files: Vec<String>,
+    /// Dude object. Its inventory items are inserted into `SaveReader::objects`.
+    pub dude_obj: Object,
+    pub dude_stats: DudeStats,
+    pub rpg_state: RpgState,
+    pub timer_events: TimerEvents,
+    pub encounter_counters: EncounterCounters,
+    pub world_map: WorldMapSave,
+}
+
+/// Dude critter proto fields that change during the game.
+pub struct DudeStats {
+    base_stats: EnumMap<Stat, i32>,
+    bonus_stats: EnumMap<Stat, i32>,
+    skills: EnumMap<Skill, i32>,
+    experience: i32,
+}
+
+impl DudeStats {
+    fn read(rd: &mut impl Read) -> io::Result<Self> {
+        let mut base_stats = EnumMap::default();
+        for stat in 0..STAT_COUNT {
+            base_stats[Stat::from_usize(stat).unwrap()] = rd.read_i32::<BigEndian>()?;
+        }
+        let mut bonus_stats = EnumMap::default();
+        for stat in 0..STAT_COUNT {
+            bonus_stats[Stat::from_usize(stat).unwrap()] = rd.read_i32::<BigEndian>()?;
+        }
+        let mut skills = EnumMap::default();
+        for skill in 0..SKILL_COUNT {
+            skills[Skill::from_usize(skill).unwrap()] = rd.read_i32::<BigEndian>()?;
+        }
+        let experience = rd.read_i32::<BigEndian>()?;
+        Ok(Self {
+            base_stats,
+            bonus_stats,
+            skills,
+            experience,
+        })
+    }
+
+    pub fn apply(&self, critter: &mut proto::Critter) {
+        critter.base_stats = self.base_stats;
+        critter.bonus_stats = self.bonus_stats;
+        critter.skills = self.skills;
+        critter.experience = self.experience;
+    }
+}
+
+pub struct SaveReader<'a, R: 'a> {
+    pub reader: &'a mut R,
+    pub objects: &'a mut Objects,
+    pub proto_db: &'a ProtoDb,
+    pub frm_db: &'a FrameDb,
+    pub world_map_db: &'a WorldMapDb,
+}
+
+impl<'a, R: 'a + Read> SaveReader<'a, R> {
+    /// Reads `SAVE.DAT`. Nothing but `objects` is changed so the caller can apply the savegame
+    /// only after it has been read successfully.
+    pub fn read(&mut self) -> io::Result<SaveGame> {
+        debug_time!("SaveReader::read()");
+
+        let header = Header::read(self.reader)?;
+
+        let global_var_count = self.reader.read_i32::<BigEndian>()?;
+        // Counts come from the file so nothing is preallocated.
+        let mut global_vars = Vec::new();
+        for _ in 0..global_var_count {
+            global_vars.push(self.reader.read_i32::<BigEndian>()?);
+        }
+
+        let map_file_count = self.reader.read_i32::<BigEndian>()?;
+        let mut map_files = Vec::new();
+        for _ in 0..map_file_count {
+            let name = read_str(self.reader, MAP_FILE_NAME_LEN)?;
+            map_files.push(name.into_string()
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid map file name"))?);
+        }
+
+        let dude_obj = ObjectReader {
+            reader: self.reader,
+            objects: self.objects,
+            proto_db: self.proto_db,
+            frm_db: self.frm_db,
+        }.read_object(true)?;
+        if dude_obj.proto_id().map(|pid| pid.is_dude()) != Some(true) {
+            return Err(Error::new(ErrorKind::InvalidData, "savegame object is not the dude"));
+        }
+
+        let dude_stats = DudeStats::read(self.reader)?;
+        let rpg_state = RpgState::read(self.reader)?;
+
+        let timer_events = TimerEvents::read(self.reader)?;
+        let encounter_counters = EncounterCounters::read(self.reader)?;
+        let world_map = WorldMapSave::read(self.reader, self.world_map_db)?;
+
+        Ok(SaveGame {
+            header,
+            global_vars: global_vars.into(),
+            map_files,
+            dude_obj,
+            dude_stats,
+            rpg_state,
+            timer_events,
+            encounter_counters,
+            world_map,
+        })
+    }
+}
+
+pub struct SaveWriter<'a, W: 'a> {
+    pub writer: &'a mut W,
+    pub header: &'a Header,
+    /// Name of the current map as in `maps.txt`.
+    pub map_name: &'a str,
+    pub global_vars: &'a [i32],
+    /// Names of the map state files in the slot directory.
+    pub map_files: &'a [String],
+    pub objects: &'a Objects,
+    pub dude_obj: object::Handle,
+    pub rpg: &'a Rpg,
+    pub timer_events: &'a TimerEvents,
+    pub encounter_counters: &'a EncounterCounters,
+    pub world_map: &'a WorldMap,
+}
+
+impl<'a, W: 'a + Write> SaveWriter<'a, W> {
+    /// Writes `SAVE.DAT`. Only the header follows the original layout, the rest of the file holds
+    /// global variables, list of map state files, the dude with inventory, the dude critter proto,
+    /// `Rpg` state, timer events, counters of the random encounters and the world map state.
+    pub fn write(&mut self) -> io::Result<()> {
+        debug_time!("SaveWriter::write()");
+
+        self.header.write(self.writer, self.map_name)?;
+
+        self.writer.write_i32::<BigEndian>(to_i32(self.global_vars.len())?)?;
+        for &v in self.global_vars {
+            self.writer.write_i32::<BigEndian>(v)?;
+        }
+
+        self.writer.write_i32::<BigEndian>(to_i32(self.map_files.len())?)?;
+        for name in self.map_files {
+            write_str(self.writer, name.as_bytes().into(), MAP_FILE_NAME_LEN)?;
+        }
+
+        let mut obj_writer = ObjectWriter::new(self.objects);
+        obj_writer.add(self.dude_obj);
+        obj_writer.write(self.writer, self.dude_obj)?;
+
+        {
+            let obj = self.objects.get(self.dude_obj);
+            let proto = obj.proto()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "dude has no proto"))?;
+            let critter = proto.sub.as_critter()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "dude proto is not a critter"))?;
+            self.write_dude_proto(critter)?;
+        }
+
+        self.rpg.write_state(self.writer)?;
+
+        self.timer_events.write(self.writer)?;
+
+        self.encounter_counters.write(self.writer)?;
+
+        self.world_map.write_state(self.writer)?;
+
+        Ok(())
+    }
+
+    fn write_dude_proto(&mut self, critter: &proto::Critter) -> io::Result<()> {
+        for stat in 0..STAT_COUNT {
+            self.writer.write_i32::<BigEndian>(critter.base_stats[Stat::from_usize(stat).unwrap()])?;
+        }
+        for stat in 0..STAT_COUNT {
+            self.writer.write_i32::<BigEndian>(critter.bonus_stats[Stat::from_usize(stat).unwrap()])?;
+        }
+        for skill in 0..SKILL_COUNT {
+            self.writer.write_i32::<BigEndian>(critter.skills[Skill::from_usize(skill).unwrap()])?;
+        }
+        self.writer.write_i32::<BigEndian>(critter.experience)?;
+        Ok(())
+    }
+}
+
+fn read_str(rd: &mut impl Read, len: usize) -> io::Result<BString> {
+    let mut buf = vec![0; len];
+    rd.read_exact(&mut buf)?;
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(len);
+    buf.truncate(len);
+    Ok(buf.into())
+}
+
+/// Writes nul-terminated string padded with zeros to `len` bytes.
+fn write_str(wr: &mut impl Write, s: &bstr, len: usize) -> io::Result<()> {
+    if s.len() >= len {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("string is too long: {}", s.display())));
+    }
+    wr.write_all(s.as_bytes())?;
+    wr.write_all(&vec![0; len - s.len()])
+}
+
+fn to_i32(v: usize) -> io::Result<i32> {
+    i32::try_from(v)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("value is too big: {}", v)))
+}
+
+fn to_u16(v: u32) -> io::Result<u16> {
+    u16::try_from(v)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("value is too big: {}", v)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::asset::{Perk, Trait};
+    use crate::asset::map::test::*;
+    use crate::asset::proto::ProtoId;
+    use crate::asset::worldmap;
+    use crate::game::object::{InventoryItem, SubObject};
+    use crate::game::script::{ScriptIid, ScriptKind};
+    use crate::game::timer::TimerEvent;
+    use crate::graphics::{EPoint, Point};
+    use crate::util::EnumExt;
+    use crate::util::test::TempDir;
+
+    fn rpg_state() -> Vec<u8> {
+        let mut r = Vec::new();
+        // Tagged skills.
+        for &v in &[Skill::Doctor as i32, Skill::Steal as i32, -1, -1] {
+            r.write_i32::<BigEndian>(v).unwrap();
+        }
+        // Traits.
+        for &v in &[Trait::Gifted as i32, -1] {
+            r.write_i32::<BigEndian>(v).unwrap();
+        }
+        // Perks.
+        r.write_i32::<BigEndian>(1).unwrap();
+        r.write_u32::<BigEndian>(ProtoId::DUDE.pack()).unwrap();
+        for perk in Perk::iter() {
+            r.write_i32::<BigEndian>((perk == Perk::BonusHthDamage) as i32).unwrap();
+        }
+        r
+    }
+
+    #[test]
+    fn map_file_name_() {
+        assert_eq!(map_file_name("artemple"), "ARTEMPLE.SAV");
+        assert_eq!(map_name("ARTEMPLE.SAV").as_ref().map(|s| &s[..]), Some("artemple"));
+        assert_eq!(map_name("artemple.sav").as_ref().map(|s| &s[..]), Some("artemple"));
+        assert_eq!(map_name("ARTEMPLE.MAP"), None);
+        assert_eq!(map_name("SAV"), None);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let fx = &Fixture::new("save-roundtrip");
+
+        let mut objects = fx.new_objects();
+        let mut rpg = fx.new_rpg();
+        rpg.read_state(&mut &rpg_state()[..]).unwrap();
+
+        fx.proto_db.dude().borrow_mut().sub.as_critter_mut().unwrap().base_stats[Stat::Luck] = 7;
+        fx.proto_db.dude().borrow_mut().sub.as_critter_mut().unwrap().experience = 123;
+
+        let new_obj = |pid: u32, sub: SubObject| {
+            let proto = fx.proto(pid);
+            let fid = proto.borrow().fid;
+            Object::new(fid, Some(proto), None, sub)
+        };
+        let key = objects.insert(new_obj(PID_KEY, SubObject::None));
+        let misc = objects.insert(new_obj(PID_MISC_ITEM, SubObject::Item(object::Item {
+            ammo_count: 2,
+            ammo_proto: None,
+        })));
+        let mut dude = Object::new(fx.proto(PID_CRITTER).borrow().fid, Some(fx.proto_db.dude()),
+            Some(EPoint::new(1, Point::new(12, 34))), SubObject::Critter(Default::default()));
+        dude.inventory.items.push(InventoryItem { object: key, count: 1 });
+        dude.inventory.items.push(InventoryItem { object: misc, count: 5 });
+        let dude = objects.insert(dude);
+
+        let header = Header {
+            player_name: "Narg".into(),
+            save_name: "Temple".into(),
+            game_time: GameTime::from_decis(302412),
+            map_id: 42,
+            elevation: 1,
+        };
+        let map_files = vec!["ARTEMPLE.SAV".to_owned(), "ARVILLAG.SAV".to_owned()];
+        let mut timer_events = TimerEvents::new();
+        timer_events.add(TimerEvent {
+            time: GameTime::from_decis(302500),
+            map_id: 42,
+            sid: ScriptIid::new(ScriptKind::Critter, 3),
+            fixed_param: 9,
+        });
+        // Table 1, entry 2 has 0 encounters left.
+        let encounter_counters = EncounterCounters::read(
+            &mut &[0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0][..]).unwrap();
+        let mut world_map = WorldMap::new(Rc::new(worldmap::test::new_db()));
+        world_map.set_pos(Point::new(10, 10));
+
+        let mut data = Vec::new();
+        SaveWriter {
+            writer: &mut data,
+            header: &header,
+            map_name: "artemple",
+            global_vars: &[1, -2, 3],
+            map_files: &map_files,
+            objects: &objects,
+            dude_obj: dude,
+            rpg: &rpg,
+            timer_events: &timer_events,
+            encounter_counters: &encounter_counters,
+            world_map: &world_map,
+        }.write().unwrap();
+
+        {
+            let proto = fx.proto_db.dude();
+            let mut proto = proto.borrow_mut();
+            let critter = proto.sub.as_critter_mut().unwrap();
+            critter.base_stats[Stat::Luck] = 0;
+            critter.experience = 0;
+        }
+
+        // Truncated at the end, after the dude proto and rpg state.
+        assert!(SaveReader {
+            reader: &mut &data[..data.len() - 2],
+            objects: &mut fx.new_objects(),
+            proto_db: &fx.proto_db,
+            frm_db: &fx.frm_db,
+            world_map_db: world_map.db(),
+        }.read().is_err());
+
+        let mut act_objects = fx.new_objects();
+        let mut act_rpg = fx.new_rpg();
+        let save = SaveReader {
+            reader: &mut &data[..],
+            objects: &mut act_objects,
+            proto_db: &fx.proto_db,
+            frm_db: &fx.frm_db,
+            world_map_db: world_map.db(),
+        }.read().unwrap();
+        assert_eq!(fx.proto_db.dude().borrow().sub.as_critter().unwrap().experience, 0);
+        save.dude_stats.apply(fx.proto_db.dude().borrow_mut().sub.as_critter_mut().unwrap());
+        act_rpg.set_state(save.rpg_state);
+
+        assert_eq!(save.header.player_name, header.player_name);
+        assert_eq!(save.header.save_name, header.save_name);
+        assert_eq!(save.header.game_time.as_decis(), 302412);
+        assert_eq!(save.header.map_id, 42);
+        assert_eq!(save.header.elevation, 1);
+        assert_eq!(&save.global_vars[..], &[1, -2, 3]);
+        assert_eq!(save.map_files, map_files);
+        assert_eq!(save.timer_events.iter().collect::<Vec<_>>(),
+            timer_events.iter().collect::<Vec<_>>());
+        assert_eq!(save.encounter_counters, encounter_counters);
+        world_map.set_state(save.world_map);
+        assert_eq!(world_map.pos(), Point::new(10, 10));
+
+        assert_eq!(save.dude_obj.pos, Some(EPoint::new(1, Point::new(12, 34))));
+        let items: Vec<_> = save.dude_obj.inventory.items.iter()
+            .map(|i| (act_objects.get(i.object).proto_id().unwrap().pack(), i.count))
+            .collect();
+        assert_eq!(items, &[(PID_KEY, 1), (PID_MISC_ITEM, 5)]);
+
+        {
+            let proto = fx.proto_db.dude();
+            let proto = proto.borrow();
+            let critter = proto.sub.as_critter().unwrap();
+            assert_eq!(critter.base_stats[Stat::Luck], 7);
+            assert_eq!(critter.experience, 123);
+        }
+
+        assert!(act_rpg.is_tagged(Skill::Doctor));
+        assert!(act_rpg.is_tagged(Skill::Steal));
+        assert!(!act_rpg.is_tagged(Skill::Barter));
+        assert!(act_rpg.has_trait(Trait::Gifted));
+        assert!(!act_rpg.has_trait(Trait::Bruiser));
+        assert!(act_rpg.has_perk(Perk::BonusHthDamage, ProtoId::DUDE));
+        assert!(!act_rpg.has_perk(Perk::BonusMove, ProtoId::DUDE));
+
+        let mut rpg_data = Vec::new();
+        act_rpg.write_state(&mut rpg_data).unwrap();
+        assert_eq!(rpg_data, rpg_state());
+    }
+
+    #[test]
+    fn replace_dir_() {
+        let tmp = TempDir::new("save-replace-dir");
+        let src = tmp.path().join("slot01.tmp");
+        let dst = tmp.path().join("slot01");
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(dst.join("OLD.SAV"), b"old").unwrap();
+
+        // Missing source leaves the slot intact.
+        assert!(replace_dir(&src, &dst).is_err());
+        assert_eq!(fs::read(dst.join("OLD.SAV")).unwrap(), b"old");
+
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("SAVE.DAT"), b"new").unwrap();
+        replace_dir(&src, &dst).unwrap();
+        assert!(!src.exists());
+        assert!(!dst.join("OLD.SAV").exists());
+        assert_eq!(fs::read(dst.join("SAVE.DAT")).unwrap(), b"new");
+        assert!(!dst.with_extension("old").exists());
+    }
+
+    #[test]
+    fn bad_counts() {
+        let fx = &Fixture::new("save-bad-counts");
+        let header = Header {
+            player_name: "".into(),
+            save_name: "".into(),
+            game_time: GameTime::from_decis(0),
+            map_id: 0,
+            elevation: 0,
+        };
+        let mut data = Vec::new();
+        header.write(&mut data, "artemple").unwrap();
+        // global_var_count
+        data.write_i32::<BigEndian>(i32::MAX).unwrap();
+        assert!(SaveReader {
+            reader: &mut &data[..],
+            objects: &mut fx.new_objects(),
+            proto_db: &fx.proto_db,
+            frm_db: &fx.frm_db,
+            world_map_db: &worldmap::test::new_db(),
+        }.read().is_err());
+    }
+
+    #[test]
+    fn header_layout() {
+        let header = Header {
+            player_name: "".into(),
+            save_name: "".into(),
+            game_time: GameTime::from_decis(0),
+            map_id: 0,
+            elevation: 0,
+        };
+        let mut data = Vec::new();
+        header.write(&mut data, "artemple").unwrap();
+        assert_eq!(data.len(), 0x7563);
+        assert_eq!(&data[..18], b"FALLOUT SAVE FILE\0");
+        assert_eq!(&data[0x73..0x80], b"ARTEMPLE.SAV\0");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,534 @@
+//! Interplay ACM audio decoder.
+//!
+//! The stream is a header followed by an LSB-first bit stream of blocks. Each block holds
+//! `rows` x `cols` packed values that are expanded through the per block amplitude table and
+//! then run through the inverse subband filter (`juggle_block()`).
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp;
+use std::io::{self, prelude::*, Error, ErrorKind};
+
+const SIGNATURE: u32 = 0x01032897;
+
+/// Upper bound of the block size. Real files don't go above a few thousands values per block.
+const MAX_BLOCK_LEN: usize = 1 << 20;
+
+const MAP_1BIT: [i32; 2] = [-1, 1];
+const MAP_2BIT_NEAR: [i32; 4] = [-2, -1, 1, 2];
+const MAP_2BIT_FAR: [i32; 4] = [-3, -2, 2, 3];
+const MAP_3BIT: [i32; 8] = [-4, -3, -2, -1, 1, 2, 3, 4];
+
+/// Offset of the zero amplitude in `AcmReader::amp`.
+const AMP_MID: i32 = 0x8000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AcmInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Total number of samples in all channels.
+    pub sample_count: u32,
+}
+
+struct BitReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    buf_pos: usize,
+    buf_len: usize,
+    bits: u32,
+    bit_count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: vec![0; 0x1000].into(),
+            buf_pos: 0,
+            buf_len: 0,
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Reads `count` bits, `count` must not exceed 24. Zero bits are returned past the end of
+    /// the stream since some of the game files are truncated.
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        debug_assert!(count <= 24);
+        while self.bit_count < count {
+            let byte = self.read_byte()?;
+            self.bits |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let r = self.bits & ((1 << count) - 1);
+        self.bits >>= count;
+        self.bit_count -= count;
+        Ok(r)
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        if self.buf_pos == self.buf_len {
+            self.buf_len = loop {
+                match self.inner.read(&mut self.buf) {
+                    Ok(n) => break n,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            };
+            self.buf_pos = 0;
+            if self.buf_len == 0 {
+                return Ok(0);
+            }
+        }
+        let r = self.buf[self.buf_pos];
+        self.buf_pos += 1;
+        Ok(r)
+    }
+}
+
+/// Streaming ACM decoder producing interleaved 16-bit PCM samples.
+pub struct AcmReader<R> {
+    bits: BitReader<R>,
+    info: AcmInfo,
+    level: u32,
+    cols: usize,
+    rows: usize,
+    block: Box<[i32]>,
+    block_pos: usize,
+    wrap_buf: Box<[i32]>,
+    amp: Box<[i32]>,
+    remaining: u32,
+}
+
+impl<R: Read> AcmReader<R> {
+    pub fn new(mut rd: R) -> io::Result<Self> {
+        if rd.read_u32::<LittleEndian>()? != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "not an ACM file"));
+        }
+        let sample_count = rd.read_u32::<LittleEndian>()?;
+        let channels = rd.read_u16::<LittleEndian>()?;
+        let sample_rate = rd.read_u16::<LittleEndian>()? as u32;
+        let v = rd.read_u16::<LittleEndian>()?;
+        let level = (v & 0xf) as u32;
+        let rows = (v >> 4) as usize;
+        let cols = 1 << level;
+        if channels == 0 || sample_rate == 0 || rows == 0 || cols * rows > MAX_BLOCK_LEN {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("invalid ACM header: channels={} sample_rate={} level={} rows={}",
+                    channels, sample_rate, level, rows)));
+        }
+        Ok(Self {
+            bits: BitReader::new(rd),
+            info: AcmInfo {
+                channels,
+                sample_rate,
+                sample_count,
+            },
+            level,
+            cols,
+            rows,
+            block: vec![0; cols * rows].into(),
+            block_pos: cols * rows,
+            wrap_buf: vec![0; 2 * cols - 2].into(),
+            amp: vec![0; 0x10000].into(),
+            remaining: sample_count,
+        })
+    }
+
+    pub fn info(&self) -> AcmInfo {
+        self.info
+    }
+
+    /// Number of samples left to decode in all channels.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Decodes samples into `buf`. Returns number of samples decoded which is `0` only at the
+    /// end of the stream.
+    pub fn read_samples(&mut self, buf: &mut [i16]) -> io::Result<usize> {
+        let mut done = 0;
+        while done < buf.len() && self.remaining > 0 {
+            if self.block_pos == self.block.len() {
+                self.decode_block()?;
+            }
+            let n = cmp::min(cmp::min(buf.len() - done, self.block.len() - self.block_pos),
+                self.remaining as usize);
+            for (dst, &src) in buf[done..done + n].iter_mut()
+                .zip(&self.block[self.block_pos..self.block_pos + n])
+            {
+                *dst = (src >> self.level) as i16;
+            }
+            done += n;
+            self.block_pos += n;
+            self.remaining -= n as u32;
+        }
+        Ok(done)
+    }
+
+    /// Overrides the channel count from the header. Music files are stereo while their headers
+    /// may say otherwise.
+    pub fn set_channels(&mut self, channels: u16) {
+        assert!(channels > 0);
+        self.info.channels = channels;
+    }
+
+    /// Decodes the whole remaining stream.
+    pub fn read_to_end(&mut self) -> io::Result<Vec<i16>> {
+        let mut r = vec![0; self.remaining as usize];
+        let n = self.read_samples(&mut r)?;
+        r.truncate(n);
+        Ok(r)
+    }
+
+    fn decode_block(&mut self) -> io::Result<()> {
+        let pwr = self.bits.read(4)?;
+        let val = self.bits.read(16)? as i32;
+        let count = 1 << pwr;
+        let mut x = 0i32;
+        for i in 0..count {
+            self.amp[(AMP_MID + i) as usize] = x;
+            x = x.wrapping_add(val);
+        }
+        let mut x = val.wrapping_neg();
+        for i in 1..=count {
+            self.amp[(AMP_MID - i) as usize] = x;
+            x = x.wrapping_sub(val);
+        }
+
+        for col in 0..self.cols {
+            let kind = self.bits.read(5)?;
+            self.fill_column(kind, col)?;
+        }
+
+        self.juggle_block();
+        self.block_pos = 0;
+        Ok(())
+    }
+
+    fn set(&mut self, row: usize, col: usize, amp_idx: i32) {
+        self.block[(row << self.level) + col] = self.amp[(AMP_MID + amp_idx) as usize];
+    }
+
+    fn fill_column(&mut self, kind: u32, col: usize) -> io::Result<()> {
+        match kind {
+            0 => {
+                for row in 0..self.rows {
+                    self.set(row, col, 0);
+                }
+            }
+            3..=16 => {
+                let middle = 1 << (kind - 1);
+                for row in 0..self.rows {
+                    let v = self.bits.read(kind)? as i32;
+                    self.set(row, col, v - middle);
+                }
+            }
+            17 => self.fill_k(col, true, |b| Ok(MAP_1BIT[b.read(1)? as usize]))?,
+            18 => self.fill_k(col, false, |b| Ok(MAP_1BIT[b.read(1)? as usize]))?,
+            19 => self.fill_packed(col, 5, 3, 3)?,
+            20 => self.fill_k(col, true, |b| Ok(MAP_2BIT_NEAR[b.read(2)? as usize]))?,
+            21 => self.fill_k(col, false, |b| Ok(MAP_2BIT_NEAR[b.read(2)? as usize]))?,
+            22 => self.fill_packed(col, 7, 5, 3)?,
+            23 => self.fill_k(col, true, Self::read_far)?,
+            24 => self.fill_k(col, false, Self::read_far)?,
+            26 => self.fill_k(col, true, |b| Ok(MAP_3BIT[b.read(3)? as usize]))?,
+            27 => self.fill_k(col, false, |b| Ok(MAP_3BIT[b.read(3)? as usize]))?,
+            29 => self.fill_packed(col, 7, 11, 2)?,
+            _ => return Err(Error::new(ErrorKind::InvalidData,
+                format!("bad ACM column kind: {}", kind))),
+        }
+        Ok(())
+    }
+
+    fn read_far(bits: &mut BitReader<R>) -> io::Result<i32> {
+        Ok(if bits.read(1)? == 0 {
+            MAP_1BIT[bits.read(1)? as usize]
+        } else {
+            MAP_2BIT_FAR[bits.read(2)? as usize]
+        })
+    }
+
+    /// Reads column of variable length codes. Zero bit means zero value (two zero values if
+    /// `double_zero` is set). With `double_zero` the one bit is followed by another bit which
+    /// if zero means a single zero value. Otherwise the value is read with `value`.
+    fn fill_k(&mut self, col: usize, double_zero: bool,
+        value: impl Fn(&mut BitReader<R>) -> io::Result<i32>) -> io::Result<()>
+    {
+        let mut row = 0;
+        while row < self.rows {
+            if self.bits.read(1)? == 0 {
+                self.set(row, col, 0);
+                if double_zero && row + 1 < self.rows {
+                    row += 1;
+                    self.set(row, col, 0);
+                }
+            } else if double_zero && self.bits.read(1)? == 0 {
+                self.set(row, col, 0);
+            } else {
+                let v = value(&mut self.bits)?;
+                self.set(row, col, v);
+            }
+            row += 1;
+        }
+        Ok(())
+    }
+
+    /// Reads column where each `bit_count` code packs `values_per_code` values in base `base`.
+    fn fill_packed(&mut self, col: usize, bit_count: u32, base: i32, values_per_code: usize)
+        -> io::Result<()>
+    {
+        let mut row = 0;
+        while row < self.rows {
+            let mut code = self.bits.read(bit_count)? as i32;
+            for _ in 0..values_per_code {
+                if row == self.rows {
+                    break;
+                }
+                self.set(row, col, code % base - base / 2);
+                code /= base;
+                row += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Inverse subband filter applied to the unpacked block.
+    fn juggle_block(&mut self) {
+        if self.level == 0 {
+            return;
+        }
+        let step_subcount = if self.level > 9 {
+            1
+        } else {
+            (2048 >> self.level) - 2
+        };
+
+        let mut todo_rows = self.rows;
+        let mut block_pos = 0;
+        loop {
+            let mut wrap_pos = 0;
+            let mut sub_len = self.cols / 2;
+            let mut sub_count = cmp::min(step_subcount, todo_rows) * 2;
+
+            juggle(&mut self.wrap_buf[wrap_pos..], &mut self.block[block_pos..],
+                sub_len, sub_count);
+            wrap_pos += sub_len * 2;
+
+            for i in 0..sub_count {
+                let v = &mut self.block[block_pos + i * sub_len];
+                *v = v.wrapping_add(1);
+            }
+
+            while sub_len > 1 {
+                sub_len /= 2;
+                sub_count *= 2;
+                juggle(&mut self.wrap_buf[wrap_pos..], &mut self.block[block_pos..],
+                    sub_len, sub_count);
+                wrap_pos += sub_len * 2;
+            }
+
+            if todo_rows <= step_subcount {
+                break;
+            }
+            todo_rows -= step_subcount;
+            block_pos += step_subcount << self.level;
+        }
+    }
+}
+
+/// Decodes the remaining stream of `acm` into a 16-bit PCM WAV file.
+pub fn write_wav<R: Read>(acm: &mut AcmReader<R>, w: &mut impl Write) -> io::Result<()> {
+    let info = acm.info();
+    let data_len = acm.remaining().checked_mul(2)
+        .filter(|&v| v <= u32::MAX - 36)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "too many samples for WAV"))?;
+    let block_align = info.channels * 2;
+
+    w.write_all(b"RIFF")?;
+    w.write_u32::<LittleEndian>(36 + data_len)?;
+    w.write_all(b"WAVEfmt ")?;
+    w.write_u32::<LittleEndian>(16)?;
+    w.write_u16::<LittleEndian>(1)?; // PCM
+    w.write_u16::<LittleEndian>(info.channels)?;
+    w.write_u32::<LittleEndian>(info.sample_rate)?;
+    w.write_u32::<LittleEndian>(info.sample_rate * block_align as u32)?;
+    w.write_u16::<LittleEndian>(block_align)?;
+    w.write_u16::<LittleEndian>(16)?;
+    w.write_all(b"data")?;
+    w.write_u32::<LittleEndian>(data_len)?;
+
+    let mut buf = vec![0; 0x4000];
+    loop {
+        let n = acm.read_samples(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for &v in &buf[..n] {
+            w.write_i16::<LittleEndian>(v)?;
+        }
+    }
+    Ok(())
+}
+
+fn juggle(wrap: &mut [i32], block: &mut [i32], sub_len: usize, sub_count: usize) {
+    for i in 0..sub_len {
+        let mut p = i;
+        let mut r0 = wrap[i * 2];
+        let mut r1 = wrap[i * 2 + 1];
+        for _ in 0..sub_count / 2 {
+            let r2 = block[p];
+            block[p] = r1.wrapping_mul(2).wrapping_add(r0.wrapping_add(r2));
+            p += sub_len;
+            let r3 = block[p];
+            block[p] = r2.wrapping_mul(2).wrapping_sub(r1.wrapping_add(r3));
+            p += sub_len;
+            r0 = r2;
+            r1 = r3;
+        }
+        wrap[i * 2] = r0;
+        wrap[i * 2 + 1] = r1;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[derive(Default)]
+    struct BitWriter {
+        buf: Vec<u8>,
+        bit_count: u32,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, count: u32, v: u32) -> &mut Self {
+            for i in 0..count {
+                if self.bit_count & 7 == 0 {
+                    self.buf.push(0);
+                }
+                *self.buf.last_mut().unwrap() |= (((v >> i) & 1) as u8) << (self.bit_count & 7);
+                self.bit_count += 1;
+            }
+            self
+        }
+
+        fn block_header(&mut self, pwr: u32, val: u32) -> &mut Self {
+            self.write(4, pwr).write(16, val)
+        }
+    }
+
+    fn acm(sample_count: u32, channels: u16, level: u16, rows: u16, bits: &BitWriter) -> Vec<u8> {
+        let mut r = Vec::new();
+        r.write_u32::<LittleEndian>(SIGNATURE).unwrap();
+        r.write_u32::<LittleEndian>(sample_count).unwrap();
+        r.write_u16::<LittleEndian>(channels).unwrap();
+        r.write_u16::<LittleEndian>(22050).unwrap();
+        r.write_u16::<LittleEndian>(rows << 4 | level).unwrap();
+        r.extend_from_slice(&bits.buf);
+        r
+    }
+
+    /// Three single column blocks of 4 rows: linear, variable length and packed.
+    pub fn fixture() -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.block_header(4, 100).write(5, 4);
+        for &code in &[8, 9, 7, 15] {
+            bits.write(4, code);
+        }
+
+        bits.block_header(1, 10).write(5, 17)
+            .write(1, 0)
+            .write(1, 1).write(1, 1).write(1, 1)
+            .write(1, 1).write(1, 0);
+
+        bits.block_header(1, 10).write(5, 19)
+            .write(5, 2 + 9)
+            .write(5, 0);
+
+        acm(12, 1, 0, 4, &bits)
+    }
+
+    pub const FIXTURE_SAMPLES: &[i16] = &[0, 100, -100, 700, 0, 0, 10, 0, 10, -10, 0, -10];
+
+    #[test]
+    fn decode() {
+        let mut rd = AcmReader::new(Cursor::new(fixture())).unwrap();
+        assert_eq!(rd.info(), AcmInfo { channels: 1, sample_rate: 22050, sample_count: 12 });
+        assert_eq!(rd.read_to_end().unwrap(), FIXTURE_SAMPLES);
+        assert_eq!(rd.remaining(), 0);
+        assert_eq!(rd.read_samples(&mut [0; 4]).unwrap(), 0);
+    }
+
+    #[test]
+    fn decode_chunked() {
+        let mut rd = AcmReader::new(Cursor::new(fixture())).unwrap();
+        let mut actual = Vec::new();
+        let buf = &mut [0; 5];
+        loop {
+            let n = rd.read_samples(buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            actual.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(actual, FIXTURE_SAMPLES);
+    }
+
+    #[test]
+    fn decode_filtered() {
+        let mut bits = BitWriter::default();
+        bits.block_header(4, 100)
+            .write(5, 4).write(4, 8 + 2)
+            .write(5, 4).write(4, 8 + 1);
+        let mut rd = AcmReader::new(Cursor::new(acm(2, 2, 1, 1, &bits))).unwrap();
+        assert_eq!(rd.read_to_end().unwrap(), &[100, 150]);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut data = fixture();
+        data.truncate(data.len() - 2);
+        let mut rd = AcmReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(rd.read_to_end().unwrap().len(), 12);
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut data = fixture();
+        data[0] = 0;
+        assert_eq!(AcmReader::new(Cursor::new(data)).err().unwrap().kind(),
+            ErrorKind::InvalidData);
+        let data = acm(1, 0, 0, 4, &BitWriter::default());
+        assert_eq!(AcmReader::new(Cursor::new(data)).err().unwrap().kind(),
+            ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_column_kind() {
+        let mut bits = BitWriter::default();
+        bits.block_header(0, 0).write(5, 1);
+        let mut rd = AcmReader::new(Cursor::new(acm(4, 1, 0, 4, &bits))).unwrap();
+        assert_eq!(rd.read_to_end().err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn wav() {
+        let mut rd = AcmReader::new(Cursor::new(fixture())).unwrap();
+        rd.set_channels(2);
+        let mut wav = Vec::new();
+        write_wav(&mut rd, &mut wav).unwrap();
+
+        assert_eq!(wav.len(), 44 + 24);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[4..8], &(36u32 + 24).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[22..24], &2u16.to_le_bytes());
+        assert_eq!(&wav[24..28], &22050u32.to_le_bytes());
+        assert_eq!(&wav[28..32], &(22050u32 * 4).to_le_bytes());
+        assert_eq!(&wav[32..34], &4u16.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(&wav[40..44], &24u32.to_le_bytes());
+        assert_eq!(&wav[44..48], &[0, 0, 100, 0]);
+    }
+}
@@ -0,0 +1,522 @@
+//! World map layout from `data/worldmap.txt` and town areas from `data/city.txt`.
+
+use enum_map::EnumMap;
+use enum_map_derive::Enum;
+use num_traits::FromPrimitive;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Error, ErrorKind};
+
+use crate::asset::EntityKind;
+use crate::asset::frame::FrameId;
+use crate::asset::map::MapId;
+use crate::fs::FileSystem;
+use crate::graphics::{EPoint, Point};
+use crate::graphics::geometry::hex::{Direction, TileGrid};
+
+pub const TILE_WIDTH: i32 = 350;
+pub const TILE_HEIGHT: i32 = 300;
+pub const SUBTILE_SIZE: i32 = 50;
+
+/// Number of subtile columns and rows in a tile.
+pub const SUBTILE_COLS: i32 = TILE_WIDTH / SUBTILE_SIZE;
+pub const SUBTILE_ROWS: i32 = TILE_HEIGHT / SUBTILE_SIZE;
+
+/// Part of the day affecting the random encounter chance.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum DayPart {
+    Morning,
+    Afternoon,
+    Night,
+}
+
+impl DayPart {
+    pub fn from_hour(hour: u8) -> Self {
+        match hour {
+            6..=11 => DayPart::Morning,
+            12..=17 => DayPart::Afternoon,
+            _ => DayPart::Night,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Terrain {
+    pub name: String,
+    /// Travel time multiplier.
+    pub difficulty: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subtile {
+    /// Index into `WorldMapDb::terrains()`.
+    pub terrain: usize,
+    /// Random encounter chance in percent.
+    pub encounter_chance: EnumMap<DayPart, u32>,
+    /// `lookup_name` of the encounter table.
+    pub encounter_table: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub fid: FrameId,
+    pub encounter_difficulty: i32,
+    /// `SUBTILE_COLS` x `SUBTILE_ROWS` subtiles in row-major order.
+    pub subtiles: Vec<Subtile>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AreaSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl AreaSize {
+    /// Radius of the town circle on the world map.
+    pub fn radius(self) -> i32 {
+        match self {
+            AreaSize::Small => 5,
+            AreaSize::Medium => 10,
+            AreaSize::Large => 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entrance {
+    pub known: bool,
+    /// Position of the hotspot on the town map.
+    pub pos: Point,
+    /// `lookup_name` of the map as in `maps.txt`.
+    pub map: String,
+    /// Where the dude is placed on the map. `None` means the map's own entrance.
+    pub location: Option<(EPoint, Direction)>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Area {
+    pub name: String,
+    pub pos: Point,
+    pub known: bool,
+    pub locked: bool,
+    pub size: AreaSize,
+    pub town_map_fid: Option<FrameId>,
+    pub town_map_label_fid: Option<FrameId>,
+    pub entrances: Vec<Entrance>,
+}
+
+pub struct WorldMapDb {
+    terrains: Vec<Terrain>,
+    tiles: Vec<Tile>,
+    tile_cols: i32,
+    areas: Vec<Area>,
+    map_areas: HashMap<MapId, usize>,
+}
+
+impl WorldMapDb {
+    pub fn new(fs: &FileSystem) -> io::Result<Self> {
+        Self::read(&mut fs.reader("data/worldmap.txt")?, &mut fs.reader("data/city.txt")?)
+    }
+
+    pub fn read(worldmap: &mut impl BufRead, city: &mut impl BufRead) -> io::Result<Self> {
+        let ini = crate::asset::read_ini(worldmap)?;
+
+        let data = section(&ini, "Data")?;
+        let terrains = parse_list(get(data, "terrain_types")?)
+            .map(|(name, v)| Ok(Terrain {
+                name: name.into(),
+                difficulty: parse_int(v)?,
+            }))
+            .collect::<io::Result<Vec<_>>>()?;
+        let frequencies = parse_list(get(data, "encounter_frequency")?)
+            .map(|(name, v)| Ok((name.to_ascii_lowercase(), parse_int(v.trim_end_matches('%'))?)))
+            .collect::<io::Result<HashMap<_, u32>>>()?;
+
+        let tile_cols = if let Some(v) = ini.get("Tile Data")
+            .and_then(|s| s.get("num_horizontal_tiles"))
+        {
+            parse_int(v)?
+        } else {
+            4
+        };
+
+        let mut tiles = Vec::new();
+        while let Some(sec) = ini.get(&format!("Tile {}", tiles.len())) {
+            let art_idx = parse_int(get(sec, "art_idx")?)?;
+            let fid = FrameId::new_generic(EntityKind::Interface, art_idx)
+                .ok_or_else(|| invalid_data(format!("bad tile art_idx: {}", art_idx)))?;
+            let encounter_difficulty = sec.get("encounter_difficulty")
+                .map(|v| parse_int(v))
+                .transpose()?
+                .unwrap_or(0);
+            let mut subtiles = Vec::with_capacity((SUBTILE_COLS * SUBTILE_ROWS) as usize);
+            for y in 0..SUBTILE_ROWS {
+                for x in 0..SUBTILE_COLS {
+                    let v = get(sec, &format!("{}_{}", x, y))?;
+                    subtiles.push(parse_subtile(v, &terrains, &frequencies)?);
+                }
+            }
+            tiles.push(Tile {
+                fid,
+                encounter_difficulty,
+                subtiles,
+            });
+        }
+        if tile_cols <= 0 || tiles.is_empty() {
+            return Err(invalid_data("no world map tiles".into()));
+        }
+
+        let areas = Self::read_areas(city)?;
+
+        Ok(Self {
+            terrains,
+            tiles,
+            tile_cols,
+            areas,
+            map_areas: HashMap::new(),
+        })
+    }
+
+    fn read_areas(rd: &mut impl BufRead) -> io::Result<Vec<Area>> {
+        let ini = crate::asset::read_ini(rd)?;
+        let mut areas = Vec::new();
+        while let Some(sec) = ini.get(&format!("Area {:02}", areas.len())) {
+            let mut pos = get(sec, "world_pos")?.split(',');
+            let pos = Point::new(
+                parse_int(pos.next().unwrap_or(""))?,
+                parse_int(pos.next().unwrap_or(""))?);
+            let known = parse_on_off(get(sec, "start_state")?)?;
+            let locked = sec.get("lock_state").map(|v| parse_on_off(v)).transpose()?
+                .unwrap_or(false);
+            let size = match get(sec, "size")?.to_ascii_lowercase().as_str() {
+                "small" => AreaSize::Small,
+                "medium" => AreaSize::Medium,
+                "large" => AreaSize::Large,
+                s => return Err(invalid_data(format!("bad area size: {}", s))),
+            };
+            let art = |key| -> io::Result<Option<FrameId>> {
+                Ok(match sec.get(key) {
+                    Some(v) if parse_int::<i32>(v)? >= 0 =>
+                        FrameId::new_generic(EntityKind::Interface, parse_int(v)?),
+                    _ => None,
+                })
+            };
+            let town_map_fid = art("townmap_art_idx")?;
+            let town_map_label_fid = art("townmap_label_art_idx")?;
+
+            let mut entrances = Vec::new();
+            while let Some(v) = sec.get(&format!("entrance_{}", entrances.len())) {
+                entrances.push(parse_entrance(v)?);
+            }
+
+            areas.push(Area {
+                name: get(sec, "area_name")?.into(),
+                pos,
+                known,
+                locked,
+                size,
+                town_map_fid,
+                town_map_label_fid,
+                entrances,
+            });
+        }
+        Ok(areas)
+    }
+
+    pub fn terrains(&self) -> &[Terrain] {
+        &self.terrains
+    }
+
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    /// Position of the top left corner of the tile at `idx`.
+    pub fn tile_pos(&self, idx: usize) -> Point {
+        let idx = idx as i32;
+        Point::new(idx % self.tile_cols * TILE_WIDTH, idx / self.tile_cols * TILE_HEIGHT)
+    }
+
+    /// Size of the whole world map in pixels.
+    pub fn size(&self) -> Point {
+        let rows = (self.tiles.len() as i32 + self.tile_cols - 1) / self.tile_cols;
+        Point::new(self.tile_cols * TILE_WIDTH, rows * TILE_HEIGHT)
+    }
+
+    /// Size of the world map in subtiles.
+    pub fn subtile_grid_size(&self) -> Point {
+        self.size() / SUBTILE_SIZE
+    }
+
+    /// Converts world map position to subtile grid position.
+    pub fn subtile_pos(&self, pos: Point) -> Option<Point> {
+        let size = self.size();
+        if pos.x < 0 || pos.y < 0 || pos.x >= size.x || pos.y >= size.y {
+            return None;
+        }
+        Some(pos / SUBTILE_SIZE)
+    }
+
+    /// Returns the tile and subtile containing the world map position `pos`.
+    pub fn subtile_at(&self, pos: Point) -> Option<(&Tile, &Subtile)> {
+        let spos = self.subtile_pos(pos)?;
+        let tile = self.tiles.get((spos.y / SUBTILE_ROWS * self.tile_cols
+            + spos.x / SUBTILE_COLS) as usize)?;
+        let subtile = &tile.subtiles[(spos.y % SUBTILE_ROWS * SUBTILE_COLS
+            + spos.x % SUBTILE_COLS) as usize];
+        Some((tile, subtile))
+    }
+
+    pub fn areas(&self) -> &[Area] {
+        &self.areas
+    }
+
+    /// Finds the area having an entrance to the map with `lookup_name`.
+    pub fn area_for_map(&self, lookup_name: &str) -> Option<usize> {
+        self.areas.iter().position(|a|
+            a.entrances.iter().any(|e| e.map.eq_ignore_ascii_case(lookup_name)))
+    }
+
+    /// Resolves entrance map names to map IDs with `find` so areas can be looked up by map ID.
+    pub fn resolve_map_ids(&mut self, find: impl Fn(&str) -> Option<MapId>) {
+        self.map_areas.clear();
+        for (i, area) in self.areas.iter().enumerate() {
+            for e in &area.entrances {
+                if let Some(map_id) = find(&e.map) {
+                    self.map_areas.entry(map_id).or_insert(i);
+                }
+            }
+        }
+    }
+
+    /// Finds the area having an entrance to the map with `map_id`.
+    /// Requires `resolve_map_ids()` to be called first.
+    pub fn area_for_map_id(&self, map_id: MapId) -> Option<usize> {
+        self.map_areas.get(&map_id).copied()
+    }
+}
+
+pub(crate) fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn section<'a>(ini: &'a HashMap<String, HashMap<String, String>>, name: &str)
+    -> io::Result<&'a HashMap<String, String>>
+{
+    ini.get(name).ok_or_else(|| invalid_data(format!("missing section: {}", name)))
+}
+
+fn get<'a>(section: &'a HashMap<String, String>, key: &str) -> io::Result<&'a str> {
+    section.get(key)
+        .map(|s| s.as_str())
+        .ok_or_else(|| invalid_data(format!("missing key: {}", key)))
+}
+
+pub(crate) fn parse_int<T: std::str::FromStr>(s: &str) -> io::Result<T> {
+    s.trim().parse().map_err(|_| invalid_data(format!("couldn't parse number: `{}`", s)))
+}
+
+fn parse_on_off(s: &str) -> io::Result<bool> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(invalid_data(format!("expected On/Off but found: {}", s))),
+    }
+}
+
+/// Parses list like `Desert:1, Mountain:2`.
+pub(crate) fn parse_list(s: &str) -> impl Iterator<Item=(&str, &str)> {
+    s.split(',')
+        .map(|s| {
+            let mut parts = s.splitn(2, ':');
+            (parts.next().unwrap().trim(), parts.next().unwrap_or("").trim())
+        })
+}
+
+/// Parses subtile like `Mountain, No_Fill, Rare, Rare, Uncommon, Arroyo_M`.
+fn parse_subtile(s: &str, terrains: &[Terrain], frequencies: &HashMap<String, u32>)
+    -> io::Result<Subtile>
+{
+    let mut parts = s.split(',').map(|s| s.trim());
+    let mut next = || parts.next()
+        .ok_or_else(|| invalid_data(format!("bad subtile: {}", s)));
+
+    let terrain = next()?;
+    let terrain = terrains.iter().position(|t| t.name.eq_ignore_ascii_case(terrain))
+        .ok_or_else(|| invalid_data(format!("unknown terrain: {}", terrain)))?;
+    // Fill of the fog of war edge.
+    next()?;
+    let mut encounter_chance = EnumMap::new();
+    for part in &[DayPart::Morning, DayPart::Afternoon, DayPart::Night] {
+        let freq = next()?;
+        encounter_chance[*part] = *frequencies.get(&freq.to_ascii_lowercase())
+            .ok_or_else(|| invalid_data(format!("unknown encounter frequency: {}", freq)))?;
+    }
+    let encounter_table = next().ok()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned());
+
+    Ok(Subtile {
+        terrain,
+        encounter_chance,
+        encounter_table,
+    })
+}
+
+/// Parses entrance like `On, 307, 236, Arroyo Village, 0, 20096, 0`. Negative elevation or tile
+/// number means the map's own entrance.
+fn parse_entrance(s: &str) -> io::Result<Entrance> {
+    let parts: Vec<_> = s.split(',').map(|s| s.trim()).collect();
+    if parts.len() != 7 {
+        return Err(invalid_data(format!("bad entrance: {}", s)));
+    }
+    let elevation: i32 = parse_int(parts[4])?;
+    let tile_num: i32 = parse_int(parts[5])?;
+    let direction: u32 = parse_int(parts[6])?;
+    let location = if elevation >= 0 && tile_num >= 0 {
+        let direction = Direction::from_u32(direction)
+            .ok_or_else(|| invalid_data(format!("bad entrance direction: {}", direction)))?;
+        let point = TileGrid::default().from_linear_inv(tile_num as u32);
+        Some((EPoint::new(elevation as u32, point), direction))
+    } else {
+        None
+    };
+    Ok(Entrance {
+        known: parse_on_off(parts[0])?,
+        pos: Point::new(parse_int(parts[1])?, parse_int(parts[2])?),
+        map: parts[3].into(),
+        location,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::{BufReader, Cursor};
+
+    use super::*;
+
+    /// Two tiles wide world map of Desert and Mountain tiles with two areas.
+    pub fn worldmap_txt() -> String {
+        let mut s = "
+[Data]
+terrain_types=Desert:1, Mountain:2
+encounter_frequency=None:0%, Rare:5%, Common:20%, Forced:100%
+
+[Tile Data]
+num_horizontal_tiles=2
+".to_owned();
+        for (i, terrain) in ["Desert", "Mountain"].iter().enumerate() {
+            s += &format!("\n[Tile {}]\nart_idx={}\nencounter_difficulty={}\n", i, 339 + i, i);
+            for y in 0..SUBTILE_ROWS {
+                for x in 0..SUBTILE_COLS {
+                    s += &format!("{}_{}={}, No_Fill, Rare, Common, Forced, {}_Table\n",
+                        x, y, terrain, terrain);
+                }
+            }
+        }
+        s
+    }
+
+    pub const CITY_TXT: &str = "
+[Area 00]
+area_name=Arroyo
+world_pos=173,122
+start_state=On
+size=Large
+townmap_art_idx=156
+townmap_label_art_idx=-1
+entrance_0=On,307,236,Arroyo Village,-1,-1,0
+entrance_1=Off,100,50,Arroyo Temple Area,1,20100,2
+
+[Area 01]
+area_name=Klamath
+world_pos=460,40
+start_state=Off
+lock_state=On
+size=Small
+townmap_art_idx=-1
+townmap_label_art_idx=-1
+";
+
+    pub fn new_db() -> WorldMapDb {
+        WorldMapDb::read(
+            &mut BufReader::new(Cursor::new(worldmap_txt())),
+            &mut BufReader::new(Cursor::new(CITY_TXT))).unwrap()
+    }
+
+    #[test]
+    fn read() {
+        let db = new_db();
+
+        assert_eq!(db.terrains(), &[
+            Terrain { name: "Desert".into(), difficulty: 1 },
+            Terrain { name: "Mountain".into(), difficulty: 2 },
+        ]);
+        assert_eq!(db.size(), Point::new(700, 300));
+        assert_eq!(db.subtile_grid_size(), Point::new(14, 6));
+
+        let (tile, subtile) = db.subtile_at(Point::new(360, 299)).unwrap();
+        assert_eq!(tile.fid, FrameId::WRLDMP01);
+        assert_eq!(tile.encounter_difficulty, 1);
+        assert_eq!(subtile.terrain, 1);
+        assert_eq!(subtile.encounter_chance[DayPart::Morning], 5);
+        assert_eq!(subtile.encounter_chance[DayPart::Afternoon], 20);
+        assert_eq!(subtile.encounter_chance[DayPart::Night], 100);
+        assert_eq!(subtile.encounter_table.as_ref().unwrap(), "Mountain_Table");
+        assert!(db.subtile_at(Point::new(700, 0)).is_none());
+        assert!(db.subtile_at(Point::new(-1, 0)).is_none());
+
+        assert_eq!(db.areas(), &[
+            Area {
+                name: "Arroyo".into(),
+                pos: Point::new(173, 122),
+                known: true,
+                locked: false,
+                size: AreaSize::Large,
+                town_map_fid: FrameId::new_generic(EntityKind::Interface, 156),
+                town_map_label_fid: None,
+                entrances: vec![
+                    Entrance {
+                        known: true,
+                        pos: Point::new(307, 236),
+                        map: "Arroyo Village".into(),
+                        location: None,
+                    },
+                    Entrance {
+                        known: false,
+                        pos: Point::new(100, 50),
+                        map: "Arroyo Temple Area".into(),
+                        location: Some((
+                            EPoint::new(1, TileGrid::default().from_linear_inv(20100)),
+                            Direction::SE)),
+                    },
+                ],
+            },
+            Area {
+                name: "Klamath".into(),
+                pos: Point::new(460, 40),
+                known: false,
+                locked: true,
+                size: AreaSize::Small,
+                town_map_fid: None,
+                town_map_label_fid: None,
+                entrances: vec![],
+            },
+        ]);
+        assert_eq!(db.area_for_map("arroyo temple area"), Some(0));
+        assert_eq!(db.area_for_map("Klamath Downtown"), None);
+    }
+
+    #[test]
+    fn resolve_map_ids() {
+        let mut db = new_db();
+        assert_eq!(db.area_for_map_id(2), None);
+
+        db.resolve_map_ids(|name| match name {
+            "Arroyo Temple Area" => Some(2),
+            _ => None,
+        });
+        assert_eq!(db.area_for_map_id(2), Some(0));
+        assert_eq!(db.area_for_map_id(0), None);
+    }
+}
@@ -0,0 +1,374 @@
+//! Sound effects, music and speech playback.
+//!
+//! Sounds are mixed by the `Mixer` and pushed to the `Output` from `Audio::update()` which must
+//! be called every frame.
+
+pub mod mixer;
+pub mod output;
+pub mod sfx;
+
+use log::*;
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::asset::map::MapId;
+use crate::asset::sound::AcmReader;
+use crate::fs::FileSystem;
+use crate::util::random::random;
+use mixer::{Buffer, Channel, Mixer, SoundHandle, Source};
+use output::Output;
+
+/// Sample rate of the game sounds.
+pub const SAMPLE_RATE: u32 = 22050;
+
+/// Range of delay in seconds between ambient sound effects.
+const AMBIENT_DELAY: (i32, i32) = (10, 30);
+
+/// ACM file streamed from the file system.
+struct AcmStream {
+    fs: Rc<FileSystem>,
+    path: String,
+    channels: Option<u16>,
+    reader: AcmReader<Box<dyn BufRead + Send>>,
+}
+
+impl AcmStream {
+    /// If `channels` is given it overrides the channel count from the file header.
+    fn open(fs: Rc<FileSystem>, path: String, channels: Option<u16>) -> io::Result<Self> {
+        let reader = Self::open_reader(&fs, &path, channels)?;
+        Ok(Self {
+            fs,
+            path,
+            channels,
+            reader,
+        })
+    }
+
+    fn open_reader(fs: &FileSystem, path: &str, channels: Option<u16>)
+        -> io::Result<AcmReader<Box<dyn BufRead + Send>>>
+    {
+        let mut reader = AcmReader::new(fs.reader(path)?)?;
+        if let Some(channels) = channels {
+            reader.set_channels(channels);
+        }
+        Ok(reader)
+    }
+}
+
+impl Source for AcmStream {
+    fn channels(&self) -> u16 {
+        self.reader.info().channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.reader.info().sample_rate
+    }
+
+    fn read(&mut self, buf: &mut [i16]) -> usize {
+        match self.reader.read_samples(buf) {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("error reading {}: {}", self.path, e);
+                0
+            }
+        }
+    }
+
+    fn rewind(&mut self) -> bool {
+        match Self::open_reader(&self.fs, &self.path, self.channels) {
+            Ok(reader) => {
+                self.reader = reader;
+                true
+            }
+            Err(e) => {
+                warn!("error reopening {}: {}", self.path, e);
+                false
+            }
+        }
+    }
+}
+
+struct Music {
+    name: String,
+    sound: SoundHandle,
+}
+
+pub struct Audio {
+    fs: Rc<FileSystem>,
+    mixer: Mixer,
+    output: Box<dyn Output>,
+    mix_buf: Vec<i16>,
+    /// Decoded sound effects keyed by file path. `None` if the file couldn't be read.
+    sfx: HashMap<String, Option<Buffer>>,
+    music: Option<Music>,
+    /// Music set by scripts that overrides the one from `maps.txt`.
+    map_music: HashMap<MapId, String>,
+    /// Ambient sound effect names and their weights.
+    ambient_sfx: Vec<(String, u32)>,
+    ambient_delay: Duration,
+    /// Sounds started by scripts keyed by the ID returned to the script.
+    script_sounds: HashMap<i32, SoundHandle>,
+    last_script_sound_id: i32,
+}
+
+impl Audio {
+    pub fn new(fs: Rc<FileSystem>, output: Box<dyn Output>) -> Self {
+        let mixer = Mixer::new(output.sample_rate());
+        Self {
+            fs,
+            mixer,
+            output,
+            mix_buf: Vec::new(),
+            sfx: HashMap::new(),
+            music: None,
+            map_music: HashMap::new(),
+            ambient_sfx: Vec::new(),
+            ambient_delay: Duration::from_secs(0),
+            script_sounds: HashMap::new(),
+            last_script_sound_id: 0,
+        }
+    }
+
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    pub fn mixer_mut(&mut self) -> &mut Mixer {
+        &mut self.mixer
+    }
+
+    /// Plays sound effect by name like `IB1P1XX1` from the `sound/sfx` directory. Spaces in the
+    /// name are replaced with underscores as the `sfx` module builds names with padding.
+    pub fn play_sfx(&mut self, name: &str) -> Option<SoundHandle> {
+        let path = format!("sound/sfx/{}.acm", name.replace(' ', "_"));
+        let buf = self.load_sfx(path)?;
+        Some(self.mixer.play(Box::new(buf), Channel::Sfx, false))
+    }
+
+    /// Plays looping music by name like `07desert` from the `sound/music` directory. Does
+    /// nothing if the same music is already playing.
+    pub fn play_music(&mut self, name: &str) {
+        if let Some(music) = &self.music {
+            if music.name.eq_ignore_ascii_case(name) && self.mixer.is_playing(music.sound) {
+                return;
+            }
+        }
+        self.stop_music();
+        let path = format!("sound/music/{}.acm", name);
+        // Music files are stereo regardless of what their headers say.
+        match AcmStream::open(self.fs.clone(), path, Some(2)) {
+            Ok(stream) => {
+                let sound = self.mixer.play(Box::new(stream), Channel::Music, true);
+                self.music = Some(Music {
+                    name: name.into(),
+                    sound,
+                });
+            }
+            Err(e) => warn!("couldn't play music `{}`: {}", name, e),
+        }
+    }
+
+    pub fn stop_music(&mut self) {
+        if let Some(music) = self.music.take() {
+            self.mixer.stop(music.sound);
+        }
+    }
+
+    /// Pauses or resumes the music, for example while a movie is playing.
+    pub fn set_music_paused(&mut self, paused: bool) {
+        if let Some(music) = &self.music {
+            self.mixer.set_paused(music.sound, paused);
+        }
+    }
+
+    pub fn music(&self) -> Option<&str> {
+        self.music.as_ref()
+            .filter(|m| self.mixer.is_playing(m.sound))
+            .map(|m| &m.name[..])
+    }
+
+    /// Plays speech file by path relative to the `sound/speech` directory.
+    pub fn play_speech(&mut self, path: &str) -> Option<SoundHandle> {
+        let path = format!("sound/speech/{}.acm", path);
+        match AcmStream::open(self.fs.clone(), path, None) {
+            Ok(stream) => Some(self.mixer.play(Box::new(stream), Channel::Speech, false)),
+            Err(e) => {
+                warn!("couldn't play speech: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Overrides music of the map. If the map is the `current_map` the music is started right
+    /// away.
+    pub fn set_map_music(&mut self, map_id: MapId, name: String, current_map: Option<MapId>) {
+        if current_map == Some(map_id) {
+            self.play_music(&name);
+        }
+        self.map_music.insert(map_id, name);
+    }
+
+    /// Starts music and ambient sound effects of the map. `music` and `ambient_sfx` come from
+    /// the map definition in `maps.txt`.
+    pub fn start_map(&mut self, map_id: MapId, music: Option<&str>, ambient_sfx: &[(String, u32)]) {
+        if let Some(music) = self.map_music.get(&map_id).map(|s| &s[..]).or(music) {
+            let music = music.to_owned();
+            self.play_music(&music);
+        } else {
+            self.stop_music();
+        }
+        self.ambient_sfx = ambient_sfx.into();
+        self.reset_ambient_delay();
+    }
+
+    /// Plays sound by path as requested by the `soundplay` script instruction. The `.acm`
+    /// extension is optional. Returns ID the sound can be referred to by.
+    pub fn play_script_sound(&mut self, path: &str, looping: bool) -> Option<i32> {
+        let path = if path.to_ascii_lowercase().ends_with(".acm") {
+            path.to_owned()
+        } else {
+            format!("{}.acm", path)
+        };
+        let stream = match AcmStream::open(self.fs.clone(), path, None) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("couldn't play script sound: {}", e);
+                return None;
+            }
+        };
+        let sound = self.mixer.play(Box::new(stream), Channel::Sfx, looping);
+        self.last_script_sound_id += 1;
+        self.script_sounds.insert(self.last_script_sound_id, sound);
+        Some(self.last_script_sound_id)
+    }
+
+    pub fn script_sound(&self, id: i32) -> Option<SoundHandle> {
+        self.script_sounds.get(&id).cloned()
+    }
+
+    pub fn delete_script_sound(&mut self, id: i32) -> bool {
+        if let Some(sound) = self.script_sounds.remove(&id) {
+            self.mixer.stop(sound);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stops all sounds started by scripts.
+    pub fn clear_script_sounds(&mut self) {
+        for (_, sound) in self.script_sounds.drain() {
+            self.mixer.stop(sound);
+        }
+    }
+
+    pub fn update(&mut self, elapsed: Duration) {
+        self.update_ambient(elapsed);
+
+        let mixer = &self.mixer;
+        self.script_sounds.retain(|_, &mut s| mixer.is_playing(s));
+
+        let frames = self.output.frames_wanted(elapsed);
+        if frames > 0 {
+            self.mix_buf.resize(frames * 2, 0);
+            self.mixer.mix(&mut self.mix_buf);
+            self.output.write(&self.mix_buf);
+        }
+    }
+
+    fn update_ambient(&mut self, elapsed: Duration) {
+        if self.ambient_sfx.is_empty() {
+            return;
+        }
+        if self.ambient_delay > elapsed {
+            self.ambient_delay -= elapsed;
+            return;
+        }
+        self.reset_ambient_delay();
+
+        let total: u32 = self.ambient_sfx.iter().map(|&(_, w)| w).sum();
+        if total == 0 {
+            return;
+        }
+        let mut roll = random(1, total as i32) as u32;
+        let name = self.ambient_sfx.iter()
+            .find(|&&(_, w)| {
+                if roll <= w {
+                    true
+                } else {
+                    roll -= w;
+                    false
+                }
+            })
+            .map(|(n, _)| n.clone())
+            .unwrap();
+        self.play_sfx(&sfx::ambient_name(&name));
+    }
+
+    fn reset_ambient_delay(&mut self) {
+        self.ambient_delay = Duration::from_secs(random(AMBIENT_DELAY.0, AMBIENT_DELAY.1) as u64);
+    }
+
+    fn load_sfx(&mut self, path: String) -> Option<Buffer> {
+        if let Some(buf) = self.sfx.get(&path) {
+            return buf.clone();
+        }
+        let buf = AcmReader::new(self.fs.reader(&path).ok()?)
+            .and_then(|mut rd| {
+                let info = rd.info();
+                let samples = rd.read_to_end()?;
+                Ok(Buffer::new(info.channels, info.sample_rate, samples.into()))
+            })
+            .map_err(|e| warn!("couldn't load sound effect {}: {}", path, e))
+            .ok();
+        self.sfx.insert(path, buf.clone());
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::asset::sound::test::{fixture, FIXTURE_SAMPLES};
+    use crate::util::test::TempDir;
+
+    fn read_all(src: &mut impl Source) -> Vec<i16> {
+        let mut r = Vec::new();
+        let mut buf = [0; 5];
+        loop {
+            let n = src.read(&mut buf);
+            if n == 0 {
+                break r;
+            }
+            r.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn acm_stream() {
+        let tmp = TempDir::new("acm-stream");
+        fs::write(tmp.path().join("a.acm"), fixture()).unwrap();
+        let mut file_system = FileSystem::new();
+        file_system.register_provider("test", crate::fs::std::new_provider(tmp.path()).unwrap());
+        let file_system = Rc::new(file_system);
+
+        let mut s = AcmStream::open(file_system.clone(), "A.ACM".into(), None).unwrap();
+        assert_eq!(s.channels(), 1);
+        assert_eq!(s.sample_rate(), 22050);
+        assert_eq!(read_all(&mut s), FIXTURE_SAMPLES);
+
+        // Channel override survives rewinding.
+        let mut s = AcmStream::open(file_system.clone(), "a.acm".into(), Some(2)).unwrap();
+        assert_eq!(s.channels(), 2);
+        assert_eq!(read_all(&mut s), FIXTURE_SAMPLES);
+        assert!(s.rewind());
+        assert_eq!(s.channels(), 2);
+        assert_eq!(read_all(&mut s), FIXTURE_SAMPLES);
+
+        assert!(AcmStream::open(file_system, "none.acm".into(), None).is_err());
+    }
+}
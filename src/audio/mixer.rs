@@ -0,0 +1,422 @@
+use enum_map::{enum_map, Enum, EnumMap};
+use slotmap::{SecondaryMap, SlotMap};
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Source of interleaved 16-bit PCM samples.
+pub trait Source {
+    fn channels(&self) -> u16;
+    fn sample_rate(&self) -> u32;
+
+    /// Reads whole frames of samples into `buf`. Returns number of samples read, `0` means the
+    /// end of the stream.
+    fn read(&mut self, buf: &mut [i16]) -> usize;
+
+    /// Restarts the source from the beginning. Returns `false` if not supported.
+    fn rewind(&mut self) -> bool;
+}
+
+/// Fully decoded samples that can be shared between sounds.
+#[derive(Clone)]
+pub struct Buffer {
+    channels: u16,
+    sample_rate: u32,
+    samples: Rc<[i16]>,
+    pos: usize,
+}
+
+impl Buffer {
+    pub fn new(channels: u16, sample_rate: u32, samples: Rc<[i16]>) -> Self {
+        assert!(channels > 0);
+        Self {
+            channels,
+            sample_rate,
+            samples,
+            pos: 0,
+        }
+    }
+}
+
+impl Source for Buffer {
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, buf: &mut [i16]) -> usize {
+        let n = cmp::min(buf.len(), self.samples.len() - self.pos);
+        let n = n - n % self.channels as usize;
+        buf[..n].copy_from_slice(&self.samples[self.pos..self.pos + n]);
+        self.pos += n;
+        n
+    }
+
+    fn rewind(&mut self) -> bool {
+        self.pos = 0;
+        true
+    }
+}
+
+struct QueueInner {
+    channels: u16,
+    sample_rate: u32,
+    samples: VecDeque<i16>,
+    finished: bool,
+}
+
+/// Source of samples pushed while it's playing, for example by a movie decoder. Plays silence
+/// when it runs out of samples until `finish()` is called.
+#[derive(Clone)]
+pub struct Queue(Rc<RefCell<QueueInner>>);
+
+impl Queue {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        assert!(channels > 0);
+        Queue(Rc::new(RefCell::new(QueueInner {
+            channels,
+            sample_rate,
+            samples: VecDeque::new(),
+            finished: false,
+        })))
+    }
+
+    pub fn push(&self, samples: &[i16]) {
+        self.0.borrow_mut().samples.extend(samples);
+    }
+
+    /// Marks the end of the stream. The sound will stop after playing the queued samples.
+    pub fn finish(&self) {
+        self.0.borrow_mut().finished = true;
+    }
+
+    /// Drops the queued samples and finishes the stream.
+    pub fn stop(&self) {
+        let mut inner = self.0.borrow_mut();
+        inner.samples.clear();
+        inner.finished = true;
+    }
+}
+
+impl Source for Queue {
+    fn channels(&self) -> u16 {
+        self.0.borrow().channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.0.borrow().sample_rate
+    }
+
+    fn read(&mut self, buf: &mut [i16]) -> usize {
+        let mut inner = self.0.borrow_mut();
+        let channels = inner.channels as usize;
+        let n = cmp::min(buf.len(), inner.samples.len());
+        let n = n - n % channels;
+        if n == 0 {
+            if inner.finished {
+                return 0;
+            }
+            // Underrun, play a single frame of silence to pick up new samples as soon as they
+            // arrive.
+            for v in &mut buf[..channels] {
+                *v = 0;
+            }
+            return channels;
+        }
+        for (dst, src) in buf[..n].iter_mut().zip(inner.samples.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+
+    fn rewind(&mut self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum Channel {
+    Sfx,
+    Music,
+    Speech,
+}
+
+new_handle_type! {
+    pub struct SoundHandle;
+}
+
+struct Voice {
+    source: Box<dyn Source>,
+    channel: Channel,
+    looping: bool,
+    paused: bool,
+    buf: Box<[i16]>,
+    buf_pos: usize,
+    buf_len: usize,
+    /// Current frame as left and right samples.
+    frame: [i32; 2],
+    /// Resampling phase in units of `1 / (source_rate * output_rate)` seconds.
+    phase: u32,
+}
+
+impl Voice {
+    /// Advances to the next source frame. Returns `false` at the end of the source.
+    fn next_frame(&mut self) -> bool {
+        let channels = self.source.channels() as usize;
+        if self.buf_pos + channels > self.buf_len {
+            self.buf_len = self.source.read(&mut self.buf);
+            if self.buf_len == 0 && self.looping && self.source.rewind() {
+                self.buf_len = self.source.read(&mut self.buf);
+            }
+            self.buf_pos = 0;
+            if self.buf_len < channels {
+                return false;
+            }
+        }
+        let l = self.buf[self.buf_pos] as i32;
+        let r = if channels > 1 {
+            self.buf[self.buf_pos + 1] as i32
+        } else {
+            l
+        };
+        self.frame = [l, r];
+        self.buf_pos += channels;
+        true
+    }
+}
+
+/// Mixes playing sounds into interleaved stereo output.
+pub struct Mixer {
+    sample_rate: u32,
+    handles: SlotMap<SoundHandle, ()>,
+    voices: SecondaryMap<SoundHandle, Voice>,
+    volumes: EnumMap<Channel, f32>,
+    master_volume: f32,
+    acc: Vec<i32>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            handles: SlotMap::with_key(),
+            voices: SecondaryMap::new(),
+            volumes: enum_map! { _ => 1.0 },
+            master_volume: 1.0,
+            acc: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Volume in range `[0..1]`.
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.volumes[channel]
+    }
+
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volumes[channel] = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Starts playing `source`. A `looping` sound is rewound when it reaches the end.
+    pub fn play(&mut self, source: Box<dyn Source>, channel: Channel, looping: bool)
+        -> SoundHandle
+    {
+        let channels = source.channels() as usize;
+        assert!(channels > 0);
+        let mut voice = Voice {
+            source,
+            channel,
+            looping,
+            paused: false,
+            buf: vec![0; 1024 * channels].into(),
+            buf_pos: 0,
+            buf_len: 0,
+            frame: [0; 2],
+            phase: 0,
+        };
+        let h = self.handles.insert(());
+        if voice.next_frame() {
+            self.voices.insert(h, voice);
+        } else {
+            self.handles.remove(h);
+        }
+        h
+    }
+
+    /// Returns `true` if the sound has not finished nor stopped yet. Paused sounds are
+    /// considered playing.
+    pub fn is_playing(&self, sound: SoundHandle) -> bool {
+        self.voices.contains_key(sound)
+    }
+
+    pub fn stop(&mut self, sound: SoundHandle) -> bool {
+        self.handles.remove(sound);
+        self.voices.remove(sound).is_some()
+    }
+
+    pub fn stop_channel(&mut self, channel: Channel) {
+        let sounds: Vec<_> = self.voices.iter()
+            .filter(|(_, v)| v.channel == channel)
+            .map(|(h, _)| h)
+            .collect();
+        for sound in sounds {
+            self.stop(sound);
+        }
+    }
+
+    pub fn set_paused(&mut self, sound: SoundHandle, paused: bool) -> bool {
+        if let Some(v) = self.voices.get_mut(sound) {
+            v.paused = paused;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Restarts the sound from the beginning.
+    pub fn rewind(&mut self, sound: SoundHandle) -> bool {
+        let v = if let Some(v) = self.voices.get_mut(sound) {
+            v
+        } else {
+            return false;
+        };
+        if !v.source.rewind() {
+            return false;
+        }
+        v.buf_pos = 0;
+        v.buf_len = 0;
+        v.phase = 0;
+        if !v.next_frame() {
+            self.stop(sound);
+        }
+        true
+    }
+
+    /// Mixes the next `out.len() / 2` frames into `out`. Sounds that reach the end are removed.
+    pub fn mix(&mut self, out: &mut [i16]) {
+        assert_eq!(out.len() % 2, 0);
+        self.acc.clear();
+        self.acc.resize(out.len(), 0);
+
+        let out_rate = self.sample_rate;
+        let mut finished = Vec::new();
+        for (h, voice) in self.voices.iter_mut() {
+            if voice.paused {
+                continue;
+            }
+            let volume = (self.volumes[voice.channel] * self.master_volume * 256.0) as i32;
+            let src_rate = voice.source.sample_rate();
+            for acc in self.acc.chunks_mut(2) {
+                acc[0] += voice.frame[0] * volume / 256;
+                acc[1] += voice.frame[1] * volume / 256;
+                voice.phase += src_rate;
+                let mut more = true;
+                while voice.phase >= out_rate {
+                    voice.phase -= out_rate;
+                    if !voice.next_frame() {
+                        more = false;
+                        break;
+                    }
+                }
+                if !more {
+                    finished.push(h);
+                    break;
+                }
+            }
+        }
+        for h in finished {
+            self.stop(h);
+        }
+
+        for (o, &a) in out.iter_mut().zip(&self.acc) {
+            *o = a.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn buffer(channels: u16, sample_rate: u32, samples: &[i16]) -> Box<Buffer> {
+        Box::new(Buffer::new(channels, sample_rate, samples.into()))
+    }
+
+    #[test]
+    fn mix() {
+        let mut m = Mixer::new(100);
+        let mono = m.play(buffer(1, 100, &[1, 2, 3]), Channel::Sfx, false);
+        let stereo = m.play(buffer(2, 100, &[10, 20, 30, 40]), Channel::Music, true);
+        let out = &mut [0; 10];
+        m.mix(out);
+        assert_eq!(out, &[11, 21, 32, 42, 13, 23, 30, 40, 10, 20]);
+        assert!(!m.is_playing(mono));
+        assert!(m.is_playing(stereo));
+
+        m.set_volume(Channel::Music, 0.5);
+        m.rewind(stereo);
+        let out = &mut [0; 4];
+        m.mix(out);
+        assert_eq!(out, &[5, 10, 15, 20]);
+
+        m.set_paused(stereo, true);
+        m.mix(out);
+        assert_eq!(out, &[0; 4]);
+
+        m.stop_channel(Channel::Music);
+        assert!(!m.is_playing(stereo));
+    }
+
+    #[test]
+    fn resample() {
+        let mut m = Mixer::new(200);
+        m.play(buffer(1, 100, &[1, 2]), Channel::Sfx, false);
+        let out = &mut [0; 10];
+        m.mix(out);
+        assert_eq!(out, &[1, 1, 1, 1, 2, 2, 2, 2, 0, 0]);
+
+        let mut m = Mixer::new(50);
+        m.play(buffer(1, 100, &[1, 2, 3, 4]), Channel::Sfx, false);
+        let out = &mut [0; 6];
+        m.mix(out);
+        assert_eq!(out, &[1, 1, 3, 3, 0, 0]);
+    }
+
+    #[test]
+    fn queue() {
+        let mut m = Mixer::new(100);
+        let q = Queue::new(1, 100);
+        let sound = m.play(Box::new(q.clone()), Channel::Speech, false);
+        q.push(&[1, 2]);
+        let out = &mut [0; 6];
+        m.mix(out);
+        assert_eq!(out, &[0, 0, 1, 1, 2, 2]);
+
+        q.push(&[3]);
+        q.finish();
+        m.mix(out);
+        assert_eq!(out, &[0, 0, 3, 3, 0, 0]);
+        assert!(!m.is_playing(sound));
+    }
+
+    #[test]
+    fn clip() {
+        let mut m = Mixer::new(100);
+        m.play(buffer(1, 100, &[30000, -30000]), Channel::Sfx, false);
+        m.play(buffer(1, 100, &[30000, -30000]), Channel::Speech, false);
+        let out = &mut [0; 4];
+        m.mix(out);
+        assert_eq!(out, &[32767, 32767, -32768, -32768]);
+    }
+}
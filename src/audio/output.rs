@@ -0,0 +1,95 @@
+use log::*;
+use sdl2::AudioSubsystem;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use std::time::Duration;
+
+/// Sink of the mixed interleaved stereo samples.
+pub trait Output {
+    fn sample_rate(&self) -> u32;
+
+    /// Returns number of frames the output needs to keep playing without gaps after `elapsed`
+    /// time has passed since the previous call.
+    fn frames_wanted(&mut self, elapsed: Duration) -> usize;
+
+    fn write(&mut self, samples: &[i16]);
+}
+
+/// Output that discards everything at the real-time pace.
+pub struct NullOutput {
+    sample_rate: u32,
+    /// Fractional frame left from the previous `frames_wanted()` call, in units of
+    /// `1 / sample_rate` nanoseconds.
+    rem: u64,
+}
+
+impl NullOutput {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            rem: 0,
+        }
+    }
+}
+
+impl Output for NullOutput {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn frames_wanted(&mut self, elapsed: Duration) -> usize {
+        let v = elapsed.as_nanos() as u64 * self.sample_rate as u64 + self.rem;
+        self.rem = v % 1_000_000_000;
+        (v / 1_000_000_000) as usize
+    }
+
+    fn write(&mut self, _samples: &[i16]) {
+    }
+}
+
+/// Output to the SDL audio device. Keeps about `latency` worth of samples queued.
+pub struct SdlOutput {
+    queue: AudioQueue<i16>,
+    latency_frames: usize,
+}
+
+impl SdlOutput {
+    pub fn new(audio: &AudioSubsystem, sample_rate: u32, latency: Duration)
+        -> Result<Self, String>
+    {
+        info!("Audio drivers:");
+        for driver in sdl2::audio::drivers() {
+            info!("  {}", driver);
+        }
+
+        let queue = audio.open_queue(None, &AudioSpecDesired {
+            freq: Some(sample_rate as i32),
+            channels: Some(2),
+            samples: None,
+        })?;
+        let spec = queue.spec();
+        info!("Using audio driver: {}, {} Hz", audio.current_audio_driver(), spec.freq);
+        let latency_frames = (latency.as_millis() as u64 * spec.freq as u64 / 1000) as usize;
+        queue.resume();
+        Ok(Self {
+            queue,
+            latency_frames,
+        })
+    }
+}
+
+impl Output for SdlOutput {
+    fn sample_rate(&self) -> u32 {
+        self.queue.spec().freq as u32
+    }
+
+    fn frames_wanted(&mut self, _elapsed: Duration) -> usize {
+        let queued = self.queue.size() as usize / 4;
+        self.latency_frames.saturating_sub(queued)
+    }
+
+    fn write(&mut self, samples: &[i16]) {
+        if !self.queue.queue(samples) {
+            warn!("couldn't queue audio: {}", sdl2::get_error());
+        }
+    }
+}
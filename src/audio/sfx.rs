@@ -0,0 +1,147 @@
+//! Sound effect file names. The names are built from fixed width fields so they may contain
+//! spaces which `Audio::play_sfx()` takes care of.
+
+use enum_primitive_derive::Primitive;
+
+use crate::asset::{CritterAnim, Material, WeaponKind};
+use crate::asset::frame::critter_anim_codes;
+
+/// Sound effect variation of the critter animation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum CharSfx {
+    Unused = 0,
+    Knockdown = 1,
+    PassOut = 2,
+    Die = 3,
+    Contact = 4,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum WeaponSfx {
+    Ready = 0,
+    Attack = 1,
+    OutOfAmmo = 2,
+    AmmoFlying = 3,
+    Hit = 4,
+}
+
+impl WeaponSfx {
+    fn code(self) -> char {
+        b"RAOFH"[self as usize] as char
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+pub enum SceneryAction {
+    Open = 0,
+    Close = 1,
+    Lock = 2,
+    Unlock = 3,
+    Use = 4,
+}
+
+impl SceneryAction {
+    fn code(self) -> char {
+        b"OCLNU"[self as usize] as char
+    }
+}
+
+// gsnd_build_ambient_sfx_name()
+pub fn ambient_name(name: &str) -> String {
+    format!("A{:>6}1", name).to_uppercase()
+}
+
+// gsnd_build_interface_sfx_name()
+pub fn interface_name(name: &str) -> String {
+    format!("N{:>6}1", name).to_uppercase()
+}
+
+// gsnd_build_item_sfx_name()
+pub fn item_name(name: &str) -> String {
+    format!("I{:>6}1", name).to_uppercase()
+}
+
+// gsnd_build_character_sfx_name()
+/// `art_name` is the base name of the critter art like `hmjmps`. For `CritterAnim::TakeOut`
+/// the `weapon` is the one being taken out.
+pub fn char_name(art_name: &str, anim: CritterAnim, weapon: WeaponKind, extra: CharSfx)
+    -> Option<String>
+{
+    let (mut c1, c2) = critter_anim_codes(weapon, anim)?;
+    match anim {
+        CritterAnim::FallBack | CritterAnim::FallFront => match extra {
+            CharSfx::PassOut => c1 = 'y',
+            CharSfx::Die => c1 = 'z',
+            _ => {}
+        }
+        CritterAnim::ThrowPunch | CritterAnim::KickLeg if extra == CharSfx::Contact => c1 = 'z',
+        _ => {}
+    }
+    Some(format!("{}{}{}", art_name, c1, c2).to_uppercase())
+}
+
+// gsnd_build_weapon_sfx_name()
+/// `sound_id` is the weapon proto sound code. `secondary` is whether the weapon is used in its
+/// secondary mode. `target_material` is the material of the hit target if it's an item,
+/// scenery or wall and the damage is not explosive, plasma or EMP.
+pub fn weapon_name(effect: WeaponSfx, sound_id: u8, secondary: bool,
+    target_material: Option<Material>) -> String
+{
+    let mode = if secondary && effect != WeaponSfx::Ready && effect != WeaponSfx::OutOfAmmo {
+        2
+    } else {
+        1
+    };
+    let material = if effect == WeaponSfx::Hit {
+        match target_material {
+            None => 'X',
+            Some(Material::Glass) | Some(Material::Metal) | Some(Material::Plastic) => 'M',
+            Some(Material::Wood) => 'W',
+            Some(Material::Dirt) | Some(Material::Stone) | Some(Material::Cement) => 'S',
+            Some(Material::Leather) => 'F',
+        }
+    } else {
+        'X'
+    };
+    format!("W{}{}{}{}XX1", effect.code(), sound_id as char, mode, material).to_uppercase()
+}
+
+// gsnd_build_scenery_sfx_name()
+pub fn scenery_name(active: bool, action: SceneryAction, name: &str) -> String {
+    format!("S{}{}{:>4}1", if active { 'A' } else { 'P' }, action.code(), name).to_uppercase()
+}
+
+// gsnd_build_open_sfx_name()
+/// Name of the door (if `scenery` is set) or container open/close sound. `sound_id` is the
+/// sound code from the object proto.
+pub fn open_name(scenery: bool, action: SceneryAction, sound_id: u8) -> String {
+    let (kind, what) = if scenery {
+        ('S', "DOORS")
+    } else {
+        ('I', "CNTNR")
+    };
+    format!("{}{}{}{}", kind, action.code(), what, sound_id as char).to_uppercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(ambient_name("water"), "A WATER1");
+        assert_eq!(interface_name("ib1p1"), "N IB1P11");
+        assert_eq!(char_name("hmjmps", CritterAnim::Walk, WeaponKind::Unarmed, CharSfx::Unused),
+            Some("HMJMPSAB".into()));
+        assert_eq!(char_name("hmjmps", CritterAnim::FallBack, WeaponKind::Unarmed, CharSfx::Die),
+            Some("HMJMPSZA".into()));
+        assert_eq!(char_name("hmjmps", CritterAnim::TakeOut, WeaponKind::Rifle, CharSfx::Unused),
+            Some("HMJMPSJC".into()));
+        assert_eq!(weapon_name(WeaponSfx::Attack, b'd', true, None), "WAD2XXX1");
+        assert_eq!(weapon_name(WeaponSfx::Hit, b'd', false, Some(Material::Wood)), "WHD1WXX1");
+        assert_eq!(weapon_name(WeaponSfx::Ready, b'd', true, None), "WRD1XXX1");
+        assert_eq!(scenery_name(true, SceneryAction::Use, "cmp"), "SAU CMP1");
+        assert_eq!(open_name(true, SceneryAction::Open, b'a'), "SODOORSA");
+        assert_eq!(open_name(false, SceneryAction::Close, b'b'), "ICCNTNRB");
+    }
+}
@@ -0,0 +1,31 @@
+pub mod acm;
+pub mod compile;
+pub mod dat;
+pub mod disasm;
+pub mod frm;
+
+use clap::{App, ArgMatches};
+
+/// Returns all tool subcommands.
+pub fn subcommands() -> Vec<App<'static, 'static>> {
+    vec![
+        acm::subcommand(),
+        compile::subcommand(),
+        dat::subcommand(),
+        disasm::subcommand(),
+        frm::subcommand(),
+    ]
+}
+
+/// Runs tool subcommand if one is present in `args`. Returns `None` if no subcommand was given,
+/// otherwise returns the process exit code.
+pub fn run(args: &ArgMatches) -> Option<i32> {
+    Some(match args.subcommand() {
+        ("acm", Some(args)) => acm::run(args),
+        ("compile", Some(args)) => compile::run(args),
+        ("dat", Some(args)) => dat::run(args),
+        ("disasm", Some(args)) => disasm::run(args),
+        ("frm", Some(args)) => frm::run(args),
+        _ => return None,
+    })
+}
@@ -0,0 +1,62 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result};
+
+use crate::asset::sound::{write_wav, AcmReader};
+use crate::fs::FileSystem;
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("acm")
+        .about("ACM audio tools")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("export")
+            .about("Converts ACM to WAV")
+            .arg(Arg::with_name("RESOURCE_DIR")
+                .help("Resource directory where master.dat, critter.dat and patchXXX.dat can be \
+                       found")
+                .required(true))
+            .arg(Arg::with_name("ACM")
+                .help("ACM path (for example: sound/music/07desert.acm)")
+                .required(true))
+            .arg(Arg::with_name("OUT_WAV")
+                .help("WAV file to create")
+                .required(true))
+            .arg(Arg::with_name("channels")
+                .help("Overrides the channel count from the ACM header. Music files need 2")
+                .long("channels")
+                .takes_value(true)))
+}
+
+pub fn run(args: &ArgMatches) -> i32 {
+    let r = match args.subcommand() {
+        ("export", Some(args)) => export(args),
+        _ => unreachable!(),
+    };
+    match r {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+fn export(args: &ArgMatches) -> Result<()> {
+    let channels = args.value_of("channels")
+        .map(|s| s.parse().ok()
+            .filter(|&v| v > 0)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput,
+                format!("invalid channel count: {}", s))))
+        .transpose()?;
+
+    let mut fs = FileSystem::new();
+    crate::setup_file_system(&mut fs, args);
+
+    let mut acm = AcmReader::new(fs.reader(args.value_of("ACM").unwrap())?)?;
+    if let Some(channels) = channels {
+        acm.set_channels(channels);
+    }
+
+    let mut w = BufWriter::new(File::create(args.value_of("OUT_WAV").unwrap())?);
+    write_wav(&mut acm, &mut w)
+}
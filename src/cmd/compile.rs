@@ -0,0 +1,67 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::vm::compiler;
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("compile")
+        .about("Compiles SSL script source into INT script")
+        .arg(Arg::with_name("SOURCE")
+            .help("Script source file (for example: artemple.ssl)")
+            .required(true))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("FILE")
+            .help("Output file. Defaults to the source file name with .int extension"))
+        .arg(Arg::with_name("include")
+            .short("I")
+            .long("include")
+            .value_name("DIR")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Additional directory to search for included files"))
+}
+
+pub fn run(args: &ArgMatches) -> i32 {
+    match compile(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+/// Reads file decoding it as Latin-1.
+fn read_source(path: &Path) -> Result<String> {
+    Ok(fs::read(path)?.into_iter().map(|b| b as char).collect())
+}
+
+fn compile(args: &ArgMatches) -> Result<()> {
+    let src_path = Path::new(args.value_of("SOURCE").unwrap());
+    let src = read_source(src_path)?;
+
+    let mut include_dirs = vec![src_path.parent().map(|p| p.to_owned()).unwrap_or_default()];
+    include_dirs.extend(args.values_of("include").into_iter().flatten().map(PathBuf::from));
+    let mut include = |path: &str| {
+        let path = path.replace('\\', "/");
+        for dir in &include_dirs {
+            let path = dir.join(&path);
+            if path.is_file() {
+                return read_source(&path);
+            }
+        }
+        Err(ErrorKind::NotFound.into())
+    };
+
+    let file_name = src_path.file_name().unwrap().to_string_lossy();
+    let code = compiler::compile(&file_name, &src, &mut include)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+    let out_path = args.value_of("output").map(PathBuf::from)
+        .unwrap_or_else(|| src_path.with_extension("int"));
+    fs::write(out_path, code)
+}
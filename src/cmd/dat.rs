@@ -0,0 +1,179 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+
+use crate::fs::dat::{self, Archive, Entry};
+use crate::fs::dat::v2::{Compression, DatWriter};
+use crate::util::glob_match;
+
+pub fn subcommand() -> App<'static, 'static> {
+    let dat_arg = || Arg::with_name("DAT")
+        .help("DAT1 (Fallout 1) or DAT2 (Fallout 2) archive file")
+        .required(true);
+    let patterns_arg = || Arg::with_name("PATTERN")
+        .help("Entry path patterns. Matching is case-insensitive and both `/` and `\\` are \
+               accepted as separators. `*` matches any sequence of characters including \
+               separators, `?` matches any single character")
+        .multiple(true);
+    SubCommand::with_name("dat")
+        .about("DAT archive tools")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("list")
+            .about("Lists archive entries with their sizes and compression")
+            .arg(dat_arg())
+            .arg(patterns_arg()))
+        .subcommand(SubCommand::with_name("extract")
+            .about("Extracts archive entries")
+            .arg(dat_arg())
+            .arg(Arg::with_name("OUT_DIR")
+                .help("Directory to extract to")
+                .required(true))
+            .arg(patterns_arg()))
+        .subcommand(SubCommand::with_name("verify")
+            .about("Verifies archive integrity by fully decompressing every entry")
+            .arg(dat_arg())
+            .arg(patterns_arg()))
+        .subcommand(SubCommand::with_name("pack")
+            .about("Packs directory into a DAT2 archive")
+            .arg(Arg::with_name("DIR")
+                .help("Directory to pack")
+                .required(true))
+            .arg(Arg::with_name("DAT")
+                .help("DAT2 archive file to create")
+                .required(true))
+            .arg(Arg::with_name("store")
+                .long("store")
+                .help("Don't compress entries")))
+}
+
+pub fn run(args: &ArgMatches) -> i32 {
+    let r = match args.subcommand() {
+        ("list", Some(args)) => list(args),
+        ("extract", Some(args)) => extract(args),
+        ("verify", Some(args)) => verify(args),
+        ("pack", Some(args)) => pack(args),
+        _ => unreachable!(),
+    };
+    match r {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+fn open(args: &ArgMatches) -> Result<Box<dyn Archive>> {
+    let path = args.value_of("DAT").unwrap();
+    dat::open(path)
+        .map_err(|e| Error::new(e.kind(), format!("couldn't open {}: {}", path, e)))
+}
+
+/// Returns entries matching `PATTERN` args sorted by path.
+fn matching_entries<'a>(archive: &'a dyn Archive, args: &ArgMatches) -> Vec<Entry<'a>> {
+    let patterns: Vec<_> = args.values_of("PATTERN")
+        .map(|v| v.map(dat::normalize_path).collect())
+        .unwrap_or_default();
+    let mut r: Vec<_> = archive.entries()
+        .filter(|e| patterns.is_empty() || patterns.iter().any(|p| glob_match(p, e.path)))
+        .collect();
+    r.sort_by_key(|e| e.path);
+    r
+}
+
+fn list(args: &ArgMatches) -> Result<bool> {
+    let archive = open(args)?;
+    let entries = matching_entries(&*archive, args);
+    let mut total_size = 0;
+    let mut total_compressed_size = 0;
+    for e in &entries {
+        let compressed_size = e.compressed_size.unwrap_or(e.size);
+        total_size += e.size as u64;
+        total_compressed_size += compressed_size as u64;
+        println!("{:>10} {:>10} {:>6} {}",
+            e.size,
+            compressed_size,
+            if e.compressed_size.is_some() { "packed" } else { "stored" },
+            e.path);
+    }
+    println!("{:>10} {:>10} {:>6} {} entries ({:?})",
+        total_size, total_compressed_size, "", entries.len(), archive.version());
+    Ok(true)
+}
+
+fn extract(args: &ArgMatches) -> Result<bool> {
+    let archive = open(args)?;
+    let out_dir = Path::new(args.value_of("OUT_DIR").unwrap());
+    let mut ok = true;
+    for e in matching_entries(&*archive, args) {
+        let r = extract_entry(&*archive, e.path, out_dir);
+        match r {
+            Ok(()) => println!("{}", e.path),
+            Err(e2) => {
+                eprintln!("error extracting {}: {}", e.path, e2);
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
+
+fn extract_entry(archive: &dyn Archive, path: &str, out_dir: &Path) -> Result<()> {
+    let out_path = out_path(out_dir, path)?;
+    if let Some(dir) = out_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut w = BufWriter::new(File::create(&out_path)?);
+    io::copy(&mut archive.reader(path)?, &mut w)?;
+    Ok(())
+}
+
+/// Converts entry path to file system path under `out_dir`. Fails on paths that would escape
+/// `out_dir`.
+fn out_path(out_dir: &Path, path: &str) -> Result<PathBuf> {
+    let mut r = out_dir.to_path_buf();
+    for s in path.split('\\') {
+        let s = Path::new(s);
+        match s.components().next() {
+            Some(Component::Normal(_)) if s.components().count() == 1 => r.push(s),
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unsafe entry path")),
+        }
+    }
+    Ok(r)
+}
+
+fn verify(args: &ArgMatches) -> Result<bool> {
+    let archive = open(args)?;
+    let entries = matching_entries(&*archive, args);
+    let mut failed = 0;
+    for e in &entries {
+        let r = archive.reader(e.path)
+            .and_then(|mut r| io::copy(&mut r, &mut io::sink()))
+            .and_then(|len| if len == e.size as u64 {
+                Ok(())
+            } else {
+                Err(Error::new(ErrorKind::InvalidData,
+                    format!("expected {} bytes but got {}", e.size, len)))
+            });
+        if let Err(e2) = r {
+            eprintln!("{}: {}", e.path, e2);
+            failed += 1;
+        }
+    }
+    println!("{} entries verified, {} failed", entries.len(), failed);
+    Ok(failed == 0)
+}
+
+fn pack(args: &ArgMatches) -> Result<bool> {
+    let compression = if args.is_present("store") {
+        Compression::None
+    } else {
+        Compression::Zlib
+    };
+    let mut w = DatWriter::create(args.value_of("DAT").unwrap())?;
+    w.add_dir(args.value_of("DIR").unwrap(), compression)?;
+    w.finish()?;
+    Ok(true)
+}
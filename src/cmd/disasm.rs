@@ -0,0 +1,58 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::io::{self, BufWriter, Error, ErrorKind, Result};
+
+use crate::fs::FileSystem;
+use crate::vm::{disasm, Vm};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("disasm")
+        .about("Disassembles or decompiles INT script")
+        .arg(Arg::with_name("RESOURCE_DIR")
+            .help("Resource directory where master.dat, critter.dat and patchXXX.dat can be found")
+            .required(true))
+        .arg(Arg::with_name("SCRIPT")
+            .help("Script name (for example: artemple) or path (for example: \
+                   scripts/artemple.int)")
+            .required(true))
+        .arg(Arg::with_name("decompile")
+            .short("d")
+            .long("decompile")
+            .help("Prints SSL-like source instead of the instruction listing"))
+}
+
+pub fn run(args: &ArgMatches) -> i32 {
+    match disasm(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+fn disasm(args: &ArgMatches) -> Result<()> {
+    let mut fs = FileSystem::new();
+    crate::setup_file_system(&mut fs, args);
+
+    let script = args.value_of("SCRIPT").unwrap();
+    let path = if script.contains(&['/', '\\', '.'][..]) {
+        script.to_owned()
+    } else {
+        format!("scripts/{}.int", script)
+    };
+    let name = path.rsplit(&['/', '\\'][..]).next().unwrap();
+    let name = name.rsplitn(2, '.').last().unwrap().to_ascii_lowercase();
+
+    let mut code = Vec::new();
+    io::copy(&mut fs.reader(&path)?, &mut code)?;
+    let program = Vm::default().load(name, code.into())
+        .map_err(|e| Error::new(ErrorKind::InvalidData,
+            format!("couldn't load {}: {:?}", path, e)))?;
+
+    let out = &mut BufWriter::new(io::stdout());
+    if args.is_present("decompile") {
+        disasm::decompile(&program, out)
+    } else {
+        disasm::disassemble(&program, out)
+    }
+}
@@ -0,0 +1,96 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::asset::frame::{read_frm, write_frm, FrameDb, FrameId};
+use crate::asset::frame::convert::{export_png, import_png};
+use crate::asset::palette::read_palette;
+use crate::fs::FileSystem;
+use crate::graphics::color::palette::Palette;
+use crate::graphics::render::TextureFactory;
+
+pub fn subcommand() -> App<'static, 'static> {
+    let res_dir_arg = || Arg::with_name("RESOURCE_DIR")
+        .help("Resource directory where master.dat, critter.dat and patchXXX.dat can be found")
+        .required(true);
+    SubCommand::with_name("frm")
+        .about("FRM image tools")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("export")
+            .about("Exports FRM to PNG images and a sidecar text file")
+            .arg(res_dir_arg())
+            .arg(Arg::with_name("FRM")
+                .help("FRM path (for example: art/critters/hmjmpsaa.frm) or FID in hex \
+                       (for example: 0x0100000c)")
+                .required(true))
+            .arg(Arg::with_name("OUT_DIR")
+                .help("Directory to export to")
+                .required(true)))
+        .subcommand(SubCommand::with_name("import")
+            .about("Creates FRM from a sidecar text file and PNG images")
+            .arg(res_dir_arg())
+            .arg(Arg::with_name("SIDECAR")
+                .help("Sidecar text file as created by the export command")
+                .required(true))
+            .arg(Arg::with_name("OUT_FRM")
+                .help("FRM file to create")
+                .required(true)))
+}
+
+pub fn run(args: &ArgMatches) -> i32 {
+    let r = match args.subcommand() {
+        ("export", Some(args)) => export(args),
+        ("import", Some(args)) => import(args),
+        _ => unreachable!(),
+    };
+    match r {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+fn setup(args: &ArgMatches) -> Result<(Rc<FileSystem>, Palette)> {
+    let mut fs = FileSystem::new();
+    crate::setup_file_system(&mut fs, args);
+    let palette = read_palette(&mut fs.reader("color.pal")?)?;
+    Ok((Rc::new(fs), palette))
+}
+
+fn export(args: &ArgMatches) -> Result<()> {
+    let (fs, palette) = setup(args)?;
+    let texture_factory = TextureFactory::new_in_memory();
+
+    let frm = args.value_of("FRM").unwrap();
+    let (frame_set, path) = if let Some(hex) = frm.strip_prefix("0x") {
+        let fid = u32::from_str_radix(hex, 16).ok()
+            .and_then(FrameId::from_packed)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid FID: {}", frm)))?;
+        let frm_db = FrameDb::new(fs, "english", texture_factory.clone())?;
+        let name = frm_db.name(fid)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no name for FID: {}", frm)))?;
+        (frm_db.get(fid)?, name)
+    } else {
+        let frame_set = read_frm(&mut fs.reader(frm)?, &texture_factory)?;
+        (Rc::new(frame_set), frm.to_owned())
+    };
+
+    let name = path.rsplit(&['/', '\\'][..]).next().unwrap();
+    let name = name.rsplitn(2, '.').last().unwrap().to_ascii_lowercase();
+    export_png(&frame_set, &texture_factory, &palette,
+        Path::new(args.value_of("OUT_DIR").unwrap()), &name)
+}
+
+fn import(args: &ArgMatches) -> Result<()> {
+    let (_, palette) = setup(args)?;
+    let texture_factory = TextureFactory::new_in_memory();
+
+    let frame_set = import_png(Path::new(args.value_of("SIDECAR").unwrap()), &palette,
+        &texture_factory)?;
+    let mut w = BufWriter::new(File::create(args.value_of("OUT_FRM").unwrap())?);
+    write_frm(&mut w, &frame_set, &texture_factory)
+}
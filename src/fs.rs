@@ -1,8 +1,14 @@
 pub mod dat;
+pub mod mods;
 pub mod std;
 
+use ::std::collections::btree_map::{self, BTreeMap};
 use ::std::io::prelude::*;
 use ::std::io::{Error, ErrorKind, Result};
+use log::*;
+
+use crate::util::glob_match;
+use dat::normalize_path;
 
 #[derive(Clone, Debug)]
 pub struct Metadata {
@@ -15,8 +21,43 @@ impl Metadata {
     }
 }
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirEntry {
+    name: String,
+    is_dir: bool,
+}
+
+impl DirEntry {
+    /// Entry name without the parent directory path.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+}
+
+/// File found in more than one provider.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Override {
+    /// Normalized file path.
+    pub path: String,
+
+    /// Name of the provider the file is read from.
+    pub provider: String,
+
+    /// Names of the providers where the file is shadowed, in priority order.
+    pub shadowed: Vec<String>,
+}
+
+struct NamedProvider {
+    name: String,
+    provider: Box<dyn Provider>,
+}
+
 pub struct FileSystem {
-    providers: Vec<Box<dyn Provider>>,
+    providers: Vec<NamedProvider>,
 }
 
 impl FileSystem {
@@ -24,8 +65,42 @@ impl FileSystem {
         FileSystem { providers: Vec::new() }
     }
 
-    pub fn register_provider(&mut self, provider: Box<dyn Provider>) {
-        self.providers.push(provider);
+    /// Registers `provider` with lower priority than all previously registered providers.
+    /// The `name` is used for diagnostics.
+    pub fn register_provider(&mut self, name: impl Into<String>, provider: Box<dyn Provider>) {
+        self.providers.push(NamedProvider {
+            name: name.into(),
+            provider,
+        });
+    }
+
+    /// Returns name of the provider `path` is read from.
+    pub fn provider_name(&self, path: &str) -> Option<&str> {
+        self.providers.iter()
+            .find(|p| p.provider.metadata(path).is_ok())
+            .map(|p| &p.name[..])
+    }
+
+    /// Returns all files found in more than one provider sorted by path.
+    pub fn overrides(&self) -> Result<Vec<Override>> {
+        let mut providers_by_path = BTreeMap::new();
+        for (i, p) in self.providers.iter().enumerate() {
+            let mut paths = Vec::new();
+            walk_provider(p.provider.as_ref(), "", &mut paths)?;
+            for path in paths {
+                providers_by_path.entry(normalize_path(&path)).or_insert_with(Vec::new).push(i);
+            }
+        }
+        Ok(providers_by_path.into_iter()
+            .filter(|(_, providers)| providers.len() > 1)
+            .map(|(path, providers)| Override {
+                path,
+                provider: self.providers[providers[0]].name.clone(),
+                shadowed: providers[1..].iter()
+                    .map(|&i| self.providers[i].name.clone())
+                    .collect(),
+            })
+            .collect())
     }
 
     pub fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>> {
@@ -36,10 +111,69 @@ impl FileSystem {
         self.find_provider(path, |p| p.metadata(path))
     }
 
+    /// Lists immediate children of the directory at `path`. Empty `path` denotes the root.
+    /// Listings of all providers are merged. When the same name (compared case-insensitively)
+    /// is found in more than one provider, the entry of the provider registered first wins.
+    /// Files and directories don't shadow each other so a file and a directory with the same
+    /// name are both listed. Providers that fail to list the directory are skipped.
+    /// The result is sorted by lowercase name, files first.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let mut r = BTreeMap::new();
+        let mut found = false;
+        for provider in &self.providers {
+            match provider.provider.read_dir(path) {
+                Ok(entries) => {
+                    found = true;
+                    for entry in entries {
+                        let key = (entry.name.to_ascii_lowercase(), entry.is_dir);
+                        if let btree_map::Entry::Vacant(e) = r.entry(key) {
+                            e.insert(entry);
+                        }
+                    }
+                }
+                Err(e) => if e.kind() != ErrorKind::NotFound {
+                    warn!("error listing directory `{}` in {}: {}", path, provider.name, e);
+                }
+            }
+        }
+        if found {
+            Ok(r.into_values().collect())
+        } else {
+            Err(Error::new(ErrorKind::NotFound, format!("directory not found: {}", path)))
+        }
+    }
+
+    /// Recursively finds all files which paths match shell-like `pattern`.
+    /// Matching is case-insensitive and both `/` and `\` are accepted as path separators.
+    /// `*` matches any sequence of characters including path separators, `?` matches any single
+    /// character. Returned paths use `\` as separator and are sorted.
+    pub fn find(&self, pattern: &str) -> Result<Vec<String>> {
+        let pattern = normalize_path(pattern);
+        let mut r = Vec::new();
+        self.find0("", &pattern, &mut r)?;
+        Ok(r)
+    }
+
+    fn find0(&self, dir: &str, pattern: &str, out: &mut Vec<String>) -> Result<()> {
+        for entry in self.read_dir(dir)? {
+            let path = if dir.is_empty() {
+                entry.name
+            } else {
+                format!("{}\\{}", dir, entry.name)
+            };
+            if entry.is_dir {
+                self.find0(&path, pattern, out)?;
+            } else if glob_match(pattern, &normalize_path(&path)) {
+                out.push(path);
+            }
+        }
+        Ok(())
+    }
+
     fn find_provider<T>(&self, path: &str, f: impl Fn(&dyn Provider) -> Result<T>) -> Result<T> {
         let mut error: Option<Error> = None;
         for provider in &self.providers {
-            match f(provider.as_ref()) {
+            match f(provider.provider.as_ref()) {
                 Ok(r) => return Ok(r),
                 Err(e) => {
                     if e.kind() == ErrorKind::NotFound {
@@ -59,9 +193,216 @@ impl FileSystem {
     pub fn exists(&self, path: &str) -> bool {
         self.metadata(path).is_ok()
     }
+
+    /// Drops cached directory contents of all providers.
+    pub fn invalidate(&self) {
+        for p in &self.providers {
+            p.provider.invalidate();
+        }
+    }
 }
 
 pub trait Provider {
     fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>>;
     fn metadata(&self, path: &str) -> Result<Metadata>;
+
+    /// Lists immediate children of the directory at `path`. Empty `path` denotes the root.
+    /// Returns error of `NotFound` kind if there's no such directory.
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>>;
+
+    /// Drops cached directory contents so the files changed since are picked up.
+    fn invalidate(&self) {}
+}
+
+/// Recursively collects paths of all files under `dir` in `provider`.
+fn walk_provider(provider: &dyn Provider, dir: &str, out: &mut Vec<String>) -> Result<()> {
+    let entries = match provider.read_dir(dir) {
+        Ok(v) => v,
+        Err(ref e) if e.kind() == ErrorKind::NotFound && dir.is_empty() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = if dir.is_empty() {
+            entry.name
+        } else {
+            format!("{}\\{}", dir, entry.name)
+        };
+        if entry.is_dir {
+            walk_provider(provider, &path, out)?;
+        } else {
+            out.push(path);
+        }
+    }
+    Ok(())
 }
+
+/// Lists immediate children of the directory `dir` given the full list of normalized file `paths`.
+fn read_dir_from_paths<'a>(paths: impl IntoIterator<Item=&'a str>, dir: &str)
+    -> Result<Vec<DirEntry>>
+{
+    let mut prefix = normalize_path(dir);
+    if !prefix.is_empty() && !prefix.ends_with('\\') {
+        prefix.push('\\');
+    }
+    let mut found = prefix.is_empty();
+    let mut r = BTreeMap::new();
+    for path in paths {
+        if !path.starts_with(&prefix) {
+            continue;
+        }
+        found = true;
+        let rest = &path[prefix.len()..];
+        let (name, is_dir) = if let Some(i) = rest.find('\\') {
+            (&rest[..i], true)
+        } else {
+            (rest, false)
+        };
+        if !name.is_empty() {
+            r.entry(name).or_insert(is_dir);
+        }
+    }
+    if found {
+        Ok(r.into_iter()
+            .map(|(name, is_dir)| DirEntry { name: name.into(), is_dir })
+            .collect())
+    } else {
+        Err(Error::new(ErrorKind::NotFound, "directory not found"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct MemProvider(Vec<(&'static str, &'static str)>);
+
+    impl Provider for MemProvider {
+        fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>> {
+            let path = normalize_path(path);
+            self.0.iter()
+                .find(|(p, _)| normalize_path(p) == path)
+                .map(|&(_, content)| Box::new(content.as_bytes()) as Box<dyn BufRead + Send>)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "file not found"))
+        }
+
+        fn metadata(&self, path: &str) -> Result<Metadata> {
+            let mut r = Vec::new();
+            self.reader(path)?.read_to_end(&mut r)?;
+            Ok(Metadata { len: r.len() as u64 })
+        }
+
+        fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+            read_dir_from_paths(self.0.iter().map(|(p, _)| *p), path)
+        }
+    }
+
+    fn dir_entry(name: &str, is_dir: bool) -> DirEntry {
+        DirEntry { name: name.into(), is_dir }
+    }
+
+    #[test]
+    fn read_dir_from_paths_() {
+        let paths = &["a.txt", "art\\critters\\a.frm", "art\\critters\\b.frm", "art\\c.frm"];
+        let f = |dir| read_dir_from_paths(paths.iter().cloned(), dir);
+        assert_eq!(f("").unwrap(), vec![dir_entry("a.txt", false), dir_entry("art", true)]);
+        assert_eq!(f("Art/").unwrap(), vec![dir_entry("c.frm", false), dir_entry("critters", true)]);
+        assert_eq!(f("art\\critters").unwrap(),
+            vec![dir_entry("a.frm", false), dir_entry("b.frm", false)]);
+        assert_eq!(f("art\\cr").unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(f("b").unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn read_dir_shadowing() {
+        let mut fs = FileSystem::new();
+        fs.register_provider("patch", Box::new(MemProvider(vec![
+            ("data\\A.txt", "patch"),
+            ("data\\x", "patch"),
+        ])));
+        fs.register_provider("master", Box::new(MemProvider(vec![
+            ("data\\a.txt", "master"),
+            ("data\\b.txt", "master"),
+            ("data\\x\\y.txt", "master"),
+            ("other\\c.txt", "master"),
+        ])));
+
+        assert_eq!(fs.read_dir("").unwrap(), vec![dir_entry("data", true), dir_entry("other", true)]);
+        // File doesn't shadow the directory of the same name.
+        assert_eq!(fs.read_dir("data").unwrap(), vec![
+            dir_entry("A.txt", false),
+            dir_entry("b.txt", false),
+            dir_entry("x", false),
+            dir_entry("x", true),
+        ]);
+        assert_eq!(fs.read_dir("data/x").unwrap(), vec![dir_entry("y.txt", false)]);
+        assert_eq!(fs.read_dir("other").unwrap(), vec![dir_entry("c.txt", false)]);
+        assert_eq!(fs.read_dir("none").unwrap_err().kind(), ErrorKind::NotFound);
+
+        assert_eq!(fs.find("*.TXT").unwrap(),
+            vec!["data\\A.txt", "data\\b.txt", "data\\x\\y.txt", "other\\c.txt"]);
+        assert_eq!(fs.find("data/?.txt").unwrap(), vec!["data\\A.txt", "data\\b.txt"]);
+        assert_eq!(fs.find("*.frm").unwrap(), Vec::<String>::new());
+    }
+
+    struct BrokenProvider;
+
+    impl Provider for BrokenProvider {
+        fn reader(&self, _path: &str) -> Result<Box<dyn BufRead + Send>> {
+            Err(Error::other("broken"))
+        }
+
+        fn metadata(&self, _path: &str) -> Result<Metadata> {
+            Err(Error::other("broken"))
+        }
+
+        fn read_dir(&self, _path: &str) -> Result<Vec<DirEntry>> {
+            Err(Error::other("broken"))
+        }
+    }
+
+    #[test]
+    fn read_dir_broken_provider() {
+        let mut fs = FileSystem::new();
+        fs.register_provider("broken", Box::new(BrokenProvider));
+        fs.register_provider("master", Box::new(MemProvider(vec![
+            ("data\\a.txt", "master"),
+        ])));
+
+        assert_eq!(fs.read_dir("data").unwrap(), vec![dir_entry("a.txt", false)]);
+        assert_eq!(fs.find("*.txt").unwrap(), vec!["data\\a.txt"]);
+    }
+
+    #[test]
+    fn overrides() {
+        let mut fs = FileSystem::new();
+        fs.register_provider("mod", Box::new(MemProvider(vec![
+            ("art\\a.frm", "mod"),
+            ("mod.txt", "mod"),
+        ])));
+        fs.register_provider("patch", Box::new(MemProvider(vec![
+            ("art\\a.frm", "patch"),
+            ("text.txt", "patch"),
+        ])));
+        fs.register_provider("master", Box::new(MemProvider(vec![
+            ("art\\a.frm", "master"),
+            ("art\\b.frm", "master"),
+            ("text.txt", "master"),
+        ])));
+
+        assert_eq!(fs.overrides().unwrap(), vec![
+            Override {
+                path: "art\\a.frm".into(),
+                provider: "mod".into(),
+                shadowed: vec!["patch".into(), "master".into()],
+            },
+            Override {
+                path: "text.txt".into(),
+                provider: "patch".into(),
+                shadowed: vec!["master".into()],
+            },
+        ]);
+        assert_eq!(fs.provider_name("ART/a.frm"), Some("mod"));
+        assert_eq!(fs.provider_name("art/b.frm"), Some("master"));
+        assert_eq!(fs.provider_name("art/c.frm"), None);
+    }
+}
\ No newline at end of file
@@ -2,3 +2,61 @@ mod lzss;
 mod util;
 pub mod v1;
 pub mod v2;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::fs::File;
+use std::io::{Result, SeekFrom};
+use std::io::prelude::*;
+use std::path::Path;
+
+use super::Provider;
+
+pub use util::normalize_path;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Version {
+    /// Fallout 1 archive with LZSS compression.
+    V1,
+
+    /// Fallout 2 archive with zlib compression.
+    V2,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<'a> {
+    /// Normalized entry path.
+    pub path: &'a str,
+
+    /// Uncompressed size.
+    pub size: u32,
+
+    /// Size of the compressed data or `None` if the entry is stored uncompressed.
+    pub compressed_size: Option<u32>,
+}
+
+pub trait Archive: Provider {
+    fn version(&self) -> Version;
+
+    /// Returns all entries in unspecified order.
+    fn entries(&self) -> Box<dyn Iterator<Item=Entry<'_>> + '_>;
+}
+
+/// Detects archive version by checking whether the DAT2 footer matches the actual file size.
+pub fn detect_version<P: AsRef<Path>>(path: P) -> Result<Version> {
+    let mut f = File::open(path.as_ref())?;
+    let len = f.metadata()?.len();
+    if len >= 8 {
+        f.seek(SeekFrom::End(-4))?;
+        if f.read_u32::<LittleEndian>()? as u64 == len {
+            return Ok(Version::V2);
+        }
+    }
+    Ok(Version::V1)
+}
+
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn Archive>> {
+    Ok(match detect_version(path.as_ref())? {
+        Version::V1 => Box::new(v1::Dat::new(path)?),
+        Version::V2 => Box::new(v2::Dat::new(path)?),
+    })
+}
\ No newline at end of file
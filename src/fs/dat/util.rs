@@ -1,3 +1,10 @@
+use memmap::Mmap;
+use std::cmp;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+
 pub fn normalize_path(path: &str) -> String {
     let mut r = String::with_capacity(path.len());
 
@@ -29,6 +36,51 @@ pub fn build_normalized_path(path: &mut String, c: Option<char>) {
     }
 }
 
+/// Maps the whole `file` into memory.
+pub fn map_file(file: &File) -> Result<Arc<Mmap>> {
+    // The mapping is read-only. Modifying archives while the game is running is not supported.
+    Ok(Arc::new(unsafe { Mmap::map(file)? }))
+}
+
+/// Zero-copy reader of a region of a memory-mapped file.
+pub struct MmapReader {
+    mmap: Arc<Mmap>,
+    pos: usize,
+    end: usize,
+}
+
+impl MmapReader {
+    pub fn new(mmap: Arc<Mmap>, offset: usize, len: usize) -> Result<Self> {
+        let end = offset.checked_add(len)
+            .filter(|&end| end <= mmap.len())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "entry is out of archive bounds"))?;
+        Ok(Self {
+            mmap,
+            pos: offset,
+            end,
+        })
+    }
+}
+
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = cmp::min(buf.len(), self.end - self.pos);
+        buf[..len].copy_from_slice(&self.mmap[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl BufRead for MmapReader {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        Ok(&self.mmap[self.pos..self.end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.end);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::normalize_path;
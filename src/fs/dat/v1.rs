@@ -1,21 +1,25 @@
 use byteorder::{ReadBytesExt, BigEndian};
+use memmap::Mmap;
+use std::cmp;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Error, ErrorKind, Result, SeekFrom};
+use std::io::{BufReader, Cursor, Error, ErrorKind, Result, SeekFrom};
 use std::io::prelude::*;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
 
 use super::lzss;
-use super::super::{Metadata, Provider};
-use super::util::{build_normalized_path, normalize_path};
+use super::{Archive, Entry, Version};
+use super::super::{DirEntry, Metadata, Provider, read_dir_from_paths};
+use super::util::{build_normalized_path, map_file, normalize_path, MmapReader};
 
 pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
     Ok(Box::new(Dat::new(path)?))
 }
 
 #[derive(Debug)]
-struct Dat {
-    path: PathBuf,
+pub struct Dat {
+    mmap: Arc<Mmap>,
     files: HashMap<String, DatFile>,
 }
 
@@ -28,13 +32,15 @@ struct DatFile {
 
 impl Dat {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut reader = BufReader::new(File::open(path.as_ref())?);
+        let mmap = map_file(&File::open(path)?)?;
+        let mut reader = Cursor::new(&mmap[..]);
 
         let dir_count = reader.read_u32::<BigEndian>()?;
 
         reader.seek(SeekFrom::Current(4 * 3))?;
 
-        let mut dirs = Vec::with_capacity(dir_count as usize);
+        // Each dir name takes at least a byte, don't trust the count of a broken file.
+        let mut dirs = Vec::with_capacity(cmp::min(dir_count as usize, mmap.len()));
         for _ in 0..dir_count {
             dirs.push(read_path(&mut reader)?);
         }
@@ -71,7 +77,7 @@ impl Dat {
         }
 
         Ok(Dat {
-            path: path.as_ref().to_path_buf(),
+            mmap,
             files,
         })
     }
@@ -96,11 +102,8 @@ impl Provider for Dat {
         } else {
             dat_file.size
         };
-        let reader = BufReader::new({
-            let mut f = File::open(&self.path)?;
-            f.seek(SeekFrom::Start(dat_file.offset as u64))?;
-            f.take(read_size as u64)
-        });
+        let reader = MmapReader::new(self.mmap.clone(),
+            dat_file.offset as usize, read_size as usize)?;
         Ok(if dat_file.is_compressed() {
             // TODO make LzssDecoder implement BufRead
             Box::new(BufReader::new(lzss::LzssDecoder::new(reader, dat_file.size as u64)))
@@ -112,6 +115,28 @@ impl Provider for Dat {
     fn metadata(&self, path: &str) -> Result<Metadata> {
         self.file(path).map(|f| Metadata { len: f.size as u64 })
     }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        read_dir_from_paths(self.files.keys().map(|k| k.as_str()), path)
+    }
+}
+
+impl Archive for Dat {
+    fn version(&self) -> Version {
+        Version::V1
+    }
+
+    fn entries(&self) -> Box<dyn Iterator<Item=Entry<'_>> + '_> {
+        Box::new(self.files.iter().map(|(path, f)| Entry {
+            path,
+            size: f.size,
+            compressed_size: if f.is_compressed() {
+                Some(f.compressed_size)
+            } else {
+                None
+            },
+        }))
+    }
 }
 
 fn read_path<R: Read>(reader: &mut R) -> Result<String> {
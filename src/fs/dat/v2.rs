@@ -1,21 +1,24 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, Error, ErrorKind, Result, SeekFrom};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use memmap::Mmap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Cursor, Error, ErrorKind, Result, SeekFrom};
 use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
 
-use std::path::{Path, PathBuf};
-
-use super::super::{Metadata, Provider};
-use super::util::{build_normalized_path, normalize_path};
+use super::{Archive, Entry, Version};
+use super::super::{DirEntry, Metadata, Provider, read_dir_from_paths};
+use super::util::{build_normalized_path, map_file, normalize_path, MmapReader};
 
 pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
     Ok(Box::new(Dat::new(path)?))
 }
 
 #[derive(Debug)]
-struct Dat {
-    path: PathBuf,
+pub struct Dat {
+    mmap: Arc<Mmap>,
     files: HashMap<String, DatFile>,
 }
 
@@ -28,13 +31,14 @@ struct DatFile {
 
 impl Dat {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut reader = BufReader::new(File::open(path.as_ref())?);
+        let mmap = map_file(&File::open(path)?)?;
+        let mut reader = Cursor::new(&mmap[..]);
 
         reader.seek(SeekFrom::End(-8))?;
         let file_list_size = reader.read_u32::<LittleEndian>()?;
         let size = reader.read_u32::<LittleEndian>()?;
 
-        if path.as_ref().metadata()?.len() != size as u64 {
+        if mmap.len() as u64 != size as u64 {
             return Err(Error::new(ErrorKind::InvalidData,
                                       "Actual file size and in-file size differ"));
         }
@@ -70,7 +74,7 @@ impl Dat {
         }
 
         Ok(Dat {
-            path: path.as_ref().to_path_buf(),
+            mmap,
             files,
         })
     }
@@ -95,11 +99,8 @@ impl Provider for Dat {
         } else {
             dat_file.size
         };
-        let reader = BufReader::new({
-            let mut f = File::open(&self.path)?;
-            f.seek(SeekFrom::Start(dat_file.offset as u64))?;
-            f.take(read_size as u64)
-        });
+        let reader = MmapReader::new(self.mmap.clone(),
+            dat_file.offset as usize, read_size as usize)?;
         Ok(if dat_file.is_compressed() {
             use flate2::bufread::ZlibDecoder;
             Box::new(BufReader::new(ZlibDecoder::new(reader)))
@@ -111,6 +112,28 @@ impl Provider for Dat {
     fn metadata(&self, path: &str) -> Result<Metadata> {
         self.file(path).map(|f| Metadata { len: f.size as u64 })
     }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        read_dir_from_paths(self.files.keys().map(|k| k.as_str()), path)
+    }
+}
+
+impl Archive for Dat {
+    fn version(&self) -> Version {
+        Version::V2
+    }
+
+    fn entries(&self) -> Box<dyn Iterator<Item=Entry<'_>> + '_> {
+        Box::new(self.files.iter().map(|(path, f)| Entry {
+            path,
+            size: f.size,
+            compressed_size: if f.is_compressed() {
+                Some(f.compressed_size)
+            } else {
+                None
+            },
+        }))
+    }
 }
 
 fn read_path<R: Read>(r: &mut R) -> Result<String> {
@@ -126,3 +149,219 @@ fn read_path<R: Read>(r: &mut R) -> Result<String> {
 
     Ok(s)
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// Entry data is stored as is.
+    None,
+
+    /// Entry data is zlib-compressed. If compression doesn't make the entry smaller it's stored
+    /// uncompressed instead.
+    Zlib,
+}
+
+/// Writes DAT2 archives readable by `Dat`.
+/// Entry data is written as entries are added. The file list and the footer are written by
+/// `finish()`.
+pub struct DatWriter<W> {
+    writer: W,
+    offset: u32,
+    entries: Vec<DatWriterEntry>,
+    paths: HashSet<String>,
+}
+
+struct DatWriterEntry {
+    path: String,
+    compressed: bool,
+    size: u32,
+    compressed_size: u32,
+    offset: u32,
+}
+
+impl DatWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> DatWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+            paths: HashSet::new(),
+        }
+    }
+
+    /// Adds entry at `path` with contents read from `reader`.
+    /// Both `/` and `\` are accepted as path separators. The path must be ASCII.
+    pub fn add_file(&mut self, path: &str, compression: Compression, reader: &mut impl Read)
+        -> Result<()>
+    {
+        if path.is_empty() || !path.is_ascii() {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("invalid DAT entry path: {:?}", path)));
+        }
+        if !self.paths.insert(normalize_path(path)) {
+            return Err(Error::new(ErrorKind::AlreadyExists,
+                format!("duplicate DAT entry: {}", path)));
+        }
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let size = to_u32(data.len())?;
+
+        let compressed_data = match compression {
+            Compression::None => None,
+            Compression::Zlib => {
+                use flate2::write::ZlibEncoder;
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(&data)?;
+                Some(encoder.finish()?)
+                    .filter(|d| d.len() < data.len())
+            }
+        };
+        let (compressed, data) = if let Some(d) = compressed_data.as_ref() {
+            (true, d)
+        } else {
+            (false, &data)
+        };
+        let compressed_size = to_u32(data.len())?;
+
+        let offset = self.offset;
+        self.offset = self.offset.checked_add(compressed_size)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "DAT file is too big"))?;
+        self.writer.write_all(data)?;
+
+        self.entries.push(DatWriterEntry {
+            path: path.replace('/', "\\"),
+            compressed,
+            size,
+            compressed_size,
+            offset,
+        });
+
+        Ok(())
+    }
+
+    /// Recursively adds all files under `dir`. Entry paths are relative to `dir`.
+    /// Entries are added in sorted order so the output doesn't depend on the directory listing
+    /// order.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P, compression: Compression) -> Result<()> {
+        self.add_dir0(dir.as_ref(), "", compression)
+    }
+
+    fn add_dir0(&mut self, dir: &Path, prefix: &str, compression: Compression) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name().into_string()
+                .map_err(|n| Error::new(ErrorKind::InvalidInput,
+                    format!("invalid file name: {:?}", n)))?;
+            let path = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}\\{}", prefix, name)
+            };
+            if entry.file_type()?.is_dir() {
+                self.add_dir0(&entry.path(), &path, compression)?;
+            } else {
+                self.add_file(&path, compression, &mut File::open(entry.path())?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the file list and the footer and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let mut file_list = Vec::new();
+        file_list.write_u32::<LittleEndian>(to_u32(self.entries.len())?)?;
+        for entry in &self.entries {
+            file_list.write_u32::<LittleEndian>(to_u32(entry.path.len())?)?;
+            file_list.write_all(entry.path.as_bytes())?;
+            file_list.write_u8(entry.compressed as u8)?;
+            file_list.write_u32::<LittleEndian>(entry.size)?;
+            file_list.write_u32::<LittleEndian>(entry.compressed_size)?;
+            file_list.write_u32::<LittleEndian>(entry.offset)?;
+        }
+        let file_list_size = to_u32(file_list.len())?;
+        let size = self.offset.checked_add(file_list_size)
+            .and_then(|v| v.checked_add(8))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "DAT file is too big"))?;
+
+        self.writer.write_all(&file_list)?;
+        self.writer.write_u32::<LittleEndian>(file_list_size)?;
+        self.writer.write_u32::<LittleEndian>(size)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+fn to_u32(v: usize) -> Result<u32> {
+    u32::try_from(v).map_err(|_| Error::new(ErrorKind::InvalidInput, "DAT file is too big"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::test::TempDir;
+
+    fn read(dat: &Dat, path: &str) -> Vec<u8> {
+        let mut r = Vec::new();
+        dat.reader(path).unwrap().read_to_end(&mut r).unwrap();
+        r
+    }
+
+    #[test]
+    fn write_read_roundtrip() {
+        let tmp = TempDir::new("dat2-roundtrip");
+        let dat_path = tmp.path().join("test.dat");
+
+        let big: Vec<u8> = (0..10000).map(|i| (i % 7) as u8).collect();
+        let mut w = DatWriter::create(&dat_path).unwrap();
+        w.add_file("art/critters/Test.frm", Compression::Zlib, &mut &big[..]).unwrap();
+        w.add_file("text\\stored.txt", Compression::None, &mut &b"stored"[..]).unwrap();
+        w.add_file("tiny", Compression::Zlib, &mut &b"x"[..]).unwrap();
+        w.add_file("empty", Compression::None, &mut &b""[..]).unwrap();
+        assert_eq!(w.add_file("TINY", Compression::None, &mut &b""[..]).unwrap_err().kind(),
+            ErrorKind::AlreadyExists);
+        w.finish().unwrap();
+
+        let dat = Dat::new(&dat_path).unwrap();
+        assert_eq!(dat.files.len(), 4);
+
+        assert!(dat.file("art\\critters\\test.frm").unwrap().is_compressed());
+        assert_eq!(dat.metadata("art/critters/test.frm").unwrap().len(), big.len() as u64);
+        assert_eq!(read(&dat, "art/critters/test.frm"), big);
+
+        assert!(!dat.file("text/stored.txt").unwrap().is_compressed());
+        assert_eq!(read(&dat, "text/stored.txt"), b"stored");
+
+        // Compression doesn't help here so it's stored.
+        assert!(!dat.file("tiny").unwrap().is_compressed());
+        assert_eq!(read(&dat, "tiny"), b"x");
+
+        assert_eq!(read(&dat, "empty"), b"");
+    }
+
+    #[test]
+    fn write_dir() {
+        let tmp = TempDir::new("dat2-dir");
+        let src = tmp.path().join("src");
+        fs::create_dir_all(src.join("art").join("items")).unwrap();
+        fs::write(src.join("art").join("items").join("a.frm"), b"aaaaaaaaaaaaaaaaaaaa").unwrap();
+        fs::write(src.join("b.txt"), b"b").unwrap();
+
+        let dat_path = tmp.path().join("test.dat");
+        let mut w = DatWriter::create(&dat_path).unwrap();
+        w.add_dir(&src, Compression::Zlib).unwrap();
+        w.finish().unwrap();
+
+        let dat = Dat::new(&dat_path).unwrap();
+        assert_eq!(dat.files.len(), 2);
+        assert_eq!(read(&dat, "art/items/a.frm"), b"aaaaaaaaaaaaaaaaaaaa");
+        assert_eq!(read(&dat, "b.txt"), b"b");
+    }
+}
@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use super::Provider;
+use super::dat::{self, Version};
+
+/// Config file in the resource dir.
+pub const CONFIG_FILE: &str = "vault13.ini";
+
+/// Directory in the resource dir where mods are discovered when there's no config file.
+pub const MODS_DIR: &str = "mods";
+
+/// List of mods to load. Each mod is either a directory or a DAT archive.
+///
+/// The config file is INI-like. Mods are listed one per line in the `[mods]` section, mods listed
+/// first take priority over mods listed later. Relative paths are resolved against the resource
+/// dir. Lines starting with `;` or `#` are comments. Other sections are ignored.
+///
+/// ```ini
+/// [mods]
+/// ; Overrides everything below.
+/// mods/hires_portraits
+/// mods/restoration.dat
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ModConfig {
+    mods: Vec<PathBuf>,
+}
+
+impl ModConfig {
+    /// Reads `CONFIG_FILE` from `res_dir` if it exists. Otherwise discovers all directories and
+    /// `.dat` files in `MODS_DIR`. Discovered mods are loaded in alphabetical order with later
+    /// ones taking priority over earlier ones.
+    pub fn load(res_dir: &Path) -> Result<Self> {
+        let config_file = res_dir.join(CONFIG_FILE);
+        if config_file.is_file() {
+            let mut r = Self::parse(&fs::read_to_string(&config_file)?)
+                .map_err(|e| Error::new(e.kind(),
+                    format!("error reading {}: {}", config_file.display(), e)))?;
+            for m in &mut r.mods {
+                *m = res_dir.join(&m);
+            }
+            Ok(r)
+        } else {
+            Self::discover(&res_dir.join(MODS_DIR))
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut mods = Vec::new();
+        let mut in_mods = false;
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                        format!("malformed section header at line {}", i + 1)));
+                }
+                in_mods = line[1..line.len() - 1].trim().eq_ignore_ascii_case("mods");
+            } else if in_mods {
+                mods.push(line.into());
+            }
+        }
+        Ok(Self { mods })
+    }
+
+    fn discover(mods_dir: &Path) -> Result<Self> {
+        if !mods_dir.is_dir() {
+            return Ok(Self::default());
+        }
+        let mut mods = Vec::new();
+        for entry in fs::read_dir(mods_dir)? {
+            let path = entry?.path();
+            let is_dat = path.extension()
+                .map(|e| e.to_string_lossy().eq_ignore_ascii_case("dat"))
+                .unwrap_or(false);
+            if path.is_dir() || is_dat && path.is_file() {
+                mods.push(path);
+            }
+        }
+        mods.sort();
+        mods.reverse();
+        Ok(Self { mods })
+    }
+
+    /// Mod paths in priority order, highest priority first.
+    pub fn mods(&self) -> &[PathBuf] {
+        &self.mods
+    }
+}
+
+/// Creates provider for mod directory or DAT archive at `path`.
+pub fn new_provider(path: &Path) -> Result<Box<dyn Provider>> {
+    if path.is_dir() {
+        super::std::new_provider(path)
+    } else {
+        match dat::detect_version(path)? {
+            Version::V1 => dat::v1::new_provider(path),
+            Version::V2 => dat::v2::new_provider(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use crate::util::test::TempDir;
+
+    #[test]
+    fn parse() {
+        let c = ModConfig::parse("
+            ; comment
+            [general]
+            ignored
+
+            [Mods]
+            # comment
+            mods/a
+              mods/b.dat
+
+            [other]
+            ignored
+            ").unwrap();
+        assert_eq!(c.mods(), &[PathBuf::from("mods/a"), PathBuf::from("mods/b.dat")]);
+
+        assert_eq!(ModConfig::parse("[mods\na").unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn load_config() {
+        let tmp = TempDir::new("mods-config");
+        fs::write(tmp.path().join(CONFIG_FILE), "[mods]\nmods/b\n/abs/a.dat\n").unwrap();
+        // Ignored when there's a config file.
+        fs::create_dir_all(tmp.path().join(MODS_DIR).join("c")).unwrap();
+
+        let c = ModConfig::load(tmp.path()).unwrap();
+        assert_eq!(c.mods(), &[tmp.path().join("mods/b"), PathBuf::from("/abs/a.dat")]);
+
+        fs::write(tmp.path().join(CONFIG_FILE), "[mods\n").unwrap();
+        assert_eq!(ModConfig::load(tmp.path()).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn discover() {
+        let tmp = TempDir::new("mods-discover");
+        assert_eq!(ModConfig::load(tmp.path()).unwrap(), ModConfig::default());
+
+        let mods_dir = tmp.path().join(MODS_DIR);
+        fs::create_dir_all(mods_dir.join("a")).unwrap();
+        fs::create_dir_all(mods_dir.join("c")).unwrap();
+        fs::write(mods_dir.join("b.DAT"), "").unwrap();
+        fs::write(mods_dir.join("readme.txt"), "").unwrap();
+
+        let c = ModConfig::load(tmp.path()).unwrap();
+        assert_eq!(c.mods(), &[mods_dir.join("c"), mods_dir.join("b.DAT"), mods_dir.join("a")]);
+    }
+
+    #[test]
+    fn new_provider_() {
+        let tmp = TempDir::new("mods-provider");
+        fs::create_dir_all(tmp.path().join("a")).unwrap();
+        fs::write(tmp.path().join("a").join("FILE.TXT"), "text").unwrap();
+
+        let p = new_provider(&tmp.path().join("a")).unwrap();
+        let mut s = String::new();
+        p.reader("file.txt").unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "text");
+
+        assert!(new_provider(&tmp.path().join("missing.dat")).is_err());
+        fs::write(tmp.path().join("bad.dat"), "not a dat").unwrap();
+        assert!(new_provider(&tmp.path().join("bad.dat")).is_err());
+    }
+}
@@ -1,39 +1,164 @@
-use std::fs::File;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
-use std::io::{BufRead, BufReader, Result};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
 
-use super::{Metadata, Provider};
+use super::{DirEntry, Metadata, Provider};
 
 pub fn new_provider<P: AsRef<Path>>(path: P) -> Result<Box<dyn Provider>> {
     Ok(Box::new(StdFileSystem::new(path)))
 }
 
+/// Provider backed by a plain directory. Paths are resolved case-insensitively and both `/` and
+/// `\` are accepted as separators, same as in DAT archives.
 struct StdFileSystem {
     root: PathBuf,
+
+    /// Maps real directory path to the index of its children: lowercase name -> real name.
+    dir_index: RefCell<HashMap<PathBuf, HashMap<String, OsString>>>,
 }
 
 impl StdFileSystem {
     pub fn new<P: AsRef<Path>>(root: P) -> Self {
-        StdFileSystem { root: root.as_ref().to_path_buf() }
+        StdFileSystem {
+            root: root.as_ref().to_path_buf(),
+            dir_index: RefCell::new(HashMap::new()),
+        }
     }
 
-    fn to_fs_path(&self, path: &str) -> PathBuf {
-        let mut r = PathBuf::new();
-        r.push(&self.root);
-        for s in path.split(|c| c == '/' || c == '\\') {
-            r.push(s);
+    /// Resolves `path` to the real path under the root. `..` can't go above the root.
+    fn to_fs_path(&self, path: &str) -> Result<PathBuf> {
+        let mut r = self.root.clone();
+        let mut depth = 0;
+        for s in path.split(&['/', '\\'][..]) {
+            match s {
+                "" | "." => {}
+                ".." => {
+                    if depth == 0 {
+                        return Err(Error::new(ErrorKind::InvalidInput,
+                            format!("path is outside of the root: {}", path)));
+                    }
+                    r.pop();
+                    depth -= 1;
+                }
+                _ => {
+                    let name = self.resolve_name(&r, s)?;
+                    r.push(name);
+                    depth += 1;
+                }
+            }
         }
-        r
+        Ok(r)
+    }
+
+    /// Finds real name of the `dir` child matching `name` case-insensitively.
+    /// The directory is indexed on first access and the index is kept until `invalidate()`,
+    /// so misses don't rescan the directory.
+    fn resolve_name(&self, dir: &Path, name: &str) -> Result<OsString> {
+        let key = name.to_ascii_lowercase();
+        if !self.dir_index.borrow().contains_key(dir) {
+            let idx = Self::index_dir(dir)?;
+            self.dir_index.borrow_mut().insert(dir.to_path_buf(), idx);
+        }
+        self.dir_index.borrow()[dir].get(&key).cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound,
+                format!("file not found: {}", dir.join(name).display())))
+    }
+
+    fn index_dir(dir: &Path) -> Result<HashMap<String, OsString>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            // Names that aren't valid UTF-8 can't be addressed by the game anyway.
+            if let Some(s) = name.to_str() {
+                names.push((s.to_ascii_lowercase(), name.clone()));
+            }
+        }
+        // Make the choice deterministic when names differ only in case.
+        names.sort();
+        let mut r = HashMap::with_capacity(names.len());
+        for (key, name) in names {
+            r.entry(key).or_insert(name);
+        }
+        Ok(r)
     }
 }
 
 impl Provider for StdFileSystem {
     fn reader(&self, path: &str) -> Result<Box<dyn BufRead + Send>> {
-        Ok(Box::new(BufReader::new(File::open(self.to_fs_path(path))?)))
+        Ok(Box::new(BufReader::new(File::open(self.to_fs_path(path)?)?)))
     }
 
     fn metadata(&self, path: &str) -> Result<Metadata> {
-        let len = self.to_fs_path(path).metadata()?.len();
+        let len = self.to_fs_path(path)?.metadata()?.len();
         Ok(Metadata { len })
     }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let mut r = Vec::new();
+        for entry in fs::read_dir(self.to_fs_path(path)?)? {
+            let entry = entry?;
+            // Names that aren't valid UTF-8 can't be addressed by the game anyway.
+            if let Ok(name) = entry.file_name().into_string() {
+                let is_dir = entry.path().is_dir();
+                r.push(DirEntry { name, is_dir });
+            }
+        }
+        Ok(r)
+    }
+
+    fn invalidate(&self) {
+        self.dir_index.borrow_mut().clear();
+    }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use crate::util::test::TempDir;
+
+    fn read(fs: &StdFileSystem, path: &str) -> String {
+        let mut r = String::new();
+        fs.reader(path).unwrap().read_to_string(&mut r).unwrap();
+        r
+    }
+
+    #[test]
+    fn resolves_case_insensitively() {
+        let tmp = TempDir::new("std-fs");
+        let dir = tmp.path().join("ART").join("Critters");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("HMJMPSAA.FRM"), "frm").unwrap();
+
+        let p = StdFileSystem::new(tmp.path());
+        assert_eq!(read(&p, "art\\critters\\hmjmpsaa.frm"), "frm");
+        assert_eq!(read(&p, "./Art/CRITTERS\\HmJmPsAa.fRm"), "frm");
+        assert_eq!(p.metadata("art/critters/hmjmpsaa.frm").unwrap().len(), 3);
+        assert_eq!(p.read_dir("art").unwrap(), vec![DirEntry { name: "Critters".into(), is_dir: true }]);
+        assert_eq!(p.metadata("art/critters/none.frm").unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(p.metadata("none/none.frm").unwrap_err().kind(), ErrorKind::NotFound);
+
+        // Files created after the directory was indexed are found after invalidation.
+        fs::write(dir.join("New.frm"), "new").unwrap();
+        assert_eq!(p.metadata("art/critters/new.FRM").unwrap_err().kind(), ErrorKind::NotFound);
+        p.invalidate();
+        assert_eq!(read(&p, "art/critters/new.FRM"), "new");
+    }
+
+    #[test]
+    fn parent_dir() {
+        let tmp = TempDir::new("std-fs-parent");
+        fs::create_dir_all(tmp.path().join("root").join("art")).unwrap();
+        fs::write(tmp.path().join("root").join("a.txt"), "a").unwrap();
+        fs::write(tmp.path().join("outside.txt"), "outside").unwrap();
+
+        let p = StdFileSystem::new(tmp.path().join("root"));
+        assert_eq!(read(&p, "art/../A.TXT"), "a");
+        assert_eq!(p.metadata("../outside.txt").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(p.metadata("art/../../outside.txt").unwrap_err().kind(),
+            ErrorKind::InvalidInput);
+    }
+}
\ No newline at end of file
@@ -1,17 +1,24 @@
+pub mod combat;
 pub mod dialog;
+pub mod encounter;
 pub mod fidget;
+pub mod inventory;
+pub mod loot;
 pub mod object;
 pub mod rpg;
 pub mod script;
+pub mod script_ui;
 pub mod sequence;
 pub mod skilldex;
 pub mod state;
+pub mod timer;
 pub mod ui;
 pub mod world;
+pub mod worldmap;
 
 use crate::util::random::RollChecker;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct GameTime(u32);
 
 impl GameTime {
@@ -23,6 +30,10 @@ impl GameTime {
         self.0
     }
 
+    pub fn add_decis(self, decis: u32) -> Self {
+        Self(self.0.saturating_add(decis))
+    }
+
     pub fn as_seconds(self) -> u32 {
         self.0 / 10
     }
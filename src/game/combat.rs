@@ -0,0 +1,1002 @@
+//! Turn-based combat: turn order, action points and attack resolution.
+//!
+//! `Combat` only keeps track of the combat state. The turns are driven by `GameState` which
+//! runs the attacks, moves and script hooks.
+
+use enumflags2::BitFlags;
+use slotmap::SecondaryMap;
+use std::cmp;
+use std::mem;
+
+use crate::asset::{AttackKind, DamageKind, EntityKind, Flag, FlagExt, Perk, Skill, Stat};
+use crate::asset::message::MessageId;
+use crate::asset::proto::CritterFlag;
+use crate::game::object::{DamageFlag, Handle, Objects};
+use crate::game::rpg::Rpg;
+use crate::util::random::{random, RollChecker, RollCheckResult};
+
+/// `fixed_param` of `combat_p_proc` executed at the start of the critter's turn.
+pub const COMBAT_SUBTYPE_TURN: i32 = 4;
+
+/// AP cost of the unarmed attacks.
+const UNARMED_AP_COST: i32 = 3;
+
+/// AP cost of moving by one hex.
+pub const MOVE_AP_COST: i32 = 1;
+
+/// AP cost of standing up for a knocked down critter.
+const STAND_UP_AP_COST: i32 = 3;
+
+const MIN_HIT_CHANCE: i32 = -100;
+const MAX_HIT_CHANCE: i32 = 95;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HitMode {
+    Primary,
+    Secondary,
+}
+
+/// Properties of the attack with the weapon in hand (or unarmed) in certain `HitMode`.
+#[derive(Clone, Debug)]
+pub struct AttackInfo {
+    /// `None` for unarmed attacks.
+    pub weapon: Option<Handle>,
+    pub kind: AttackKind,
+    pub skill: Skill,
+    pub min_damage: i32,
+    pub max_damage: i32,
+    pub damage_kind: DamageKind,
+    pub range: u32,
+    pub ap_cost: i32,
+}
+
+impl AttackInfo {
+    pub fn is_ranged(&self) -> bool {
+        match self.kind {
+            AttackKind::Throw
+            | AttackKind::FireSingle
+            | AttackKind::FireBurst
+            | AttackKind::FireContinuous
+            => true,
+            AttackKind::Stand
+            | AttackKind::Punch
+            | AttackKind::Kick
+            | AttackKind::Swing
+            | AttackKind::Thrust
+            => false,
+        }
+    }
+}
+
+/// Modifiers of a single attack as given to `attack()` script instruction.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AttackOptions {
+    pub to_hit_bonus: i32,
+    pub min_damage: i32,
+    pub max_damage: i32,
+}
+
+impl Default for AttackOptions {
+    fn default() -> Self {
+        Self {
+            to_hit_bonus: 0,
+            min_damage: 0,
+            max_damage: i32::MAX,
+        }
+    }
+}
+
+/// Attack requested by scripts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AttackRequest {
+    pub attacker: Handle,
+    pub target: Handle,
+    pub options: AttackOptions,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AttackResult {
+    pub roll: RollCheckResult,
+    pub hit_chance: i32,
+    pub location: HitLocation,
+    pub damage: i32,
+    /// Flags to set on the target.
+    pub target_flags: BitFlags<DamageFlag>,
+    /// Message in `combat.msg` describing the effect of the critical hit.
+    pub message: Option<MessageId>,
+}
+
+/// Body part hit by the attack. Attacks that don't aim at a specific part are `Uncalled`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HitLocation {
+    Head,
+    LeftArm,
+    RightArm,
+    Torso,
+    RightLeg,
+    LeftLeg,
+    Eyes,
+    Groin,
+    Uncalled,
+}
+
+/// Row of the critical hit table.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CriticalEffect {
+    /// Damage multiplier in halves.
+    pub damage_mult: i32,
+    flags: u32,
+    /// Stat roll of the target with a bonus. If the roll fails, `fail_flags` are added.
+    pub check: Option<(Stat, i32)>,
+    fail_flags: u32,
+    pub message: MessageId,
+    pub fail_message: MessageId,
+}
+
+impl CriticalEffect {
+    pub fn flags(&self) -> BitFlags<DamageFlag> {
+        BitFlags::from_bits_truncate(self.flags)
+    }
+
+    pub fn fail_flags(&self) -> BitFlags<DamageFlag> {
+        BitFlags::from_bits_truncate(self.fail_flags)
+    }
+}
+
+/// Message of the critical effects that don't have their own description.
+const NO_CRITICAL_MESSAGE: MessageId = 5000;
+
+const fn crit(damage_mult: i32, flags: u32, check: Option<(Stat, i32)>, fail_flags: u32,
+    message: MessageId, fail_message: MessageId) -> CriticalEffect
+{
+    CriticalEffect { damage_mult, flags, check, fail_flags, message, fail_message }
+}
+
+const KNOCKED_OUT: u32 = DamageFlag::KnockedOut as u32;
+const KNOCKED_DOWN: u32 = DamageFlag::KnockedDown as u32;
+const CRIP_LEG_LEFT: u32 = DamageFlag::CripLegLeft as u32;
+const CRIP_LEG_RIGHT: u32 = DamageFlag::CripLegRight as u32;
+const CRIP_ARM_LEFT: u32 = DamageFlag::CripArmLeft as u32;
+const CRIP_ARM_RIGHT: u32 = DamageFlag::CripArmRight as u32;
+const BLIND: u32 = DamageFlag::Blind as u32;
+const DEAD: u32 = DamageFlag::Dead as u32;
+const BYPASS: u32 = DamageFlag::Bypass as u32;
+const LOSE_TURN: u32 = DamageFlag::LoseTurn as u32;
+const NO_MSG: MessageId = NO_CRITICAL_MESSAGE;
+
+// crit_succ_eff
+/// Critical hit effects for each hit location (except `Uncalled` which uses the `Torso` row)
+/// in the order of increasing severity.
+/// The original has a table per kill kind, this is the table of the humanoids.
+const CRITICAL_HITS: [[CriticalEffect; 6]; 8] = {
+    use Stat::*;
+    [
+        // Head
+        [
+            crit(4, 0, None, 0, 5001, NO_MSG),
+            crit(4, BYPASS, Some((Endurance, 3)), KNOCKED_DOWN, 5002, 5003),
+            crit(5, BYPASS, Some((Endurance, 0)), KNOCKED_DOWN, 5002, 5003),
+            crit(5, KNOCKED_DOWN | BYPASS, Some((Endurance, -2)), KNOCKED_OUT, 5003, 5004),
+            crit(6, KNOCKED_OUT | BYPASS, Some((Luck, 0)), BLIND, 5005, 5006),
+            crit(6, DEAD, None, 0, 5007, NO_MSG),
+        ],
+        // LeftArm
+        [
+            crit(3, 0, None, 0, 5008, NO_MSG),
+            crit(3, LOSE_TURN, None, 0, 5009, NO_MSG),
+            crit(4, 0, Some((Endurance, -2)), CRIP_ARM_LEFT, 5010, 5011),
+            crit(4, CRIP_ARM_LEFT, None, 0, 5012, NO_MSG),
+            crit(4, CRIP_ARM_LEFT | BYPASS, None, 0, 5012, NO_MSG),
+            crit(4, CRIP_ARM_LEFT | BYPASS, None, 0, 5013, NO_MSG),
+        ],
+        // RightArm
+        [
+            crit(3, 0, None, 0, 5008, NO_MSG),
+            crit(3, LOSE_TURN, None, 0, 5009, NO_MSG),
+            crit(4, 0, Some((Endurance, -2)), CRIP_ARM_RIGHT, 5010, 5011),
+            crit(4, CRIP_ARM_RIGHT, None, 0, 5012, NO_MSG),
+            crit(4, CRIP_ARM_RIGHT | BYPASS, None, 0, 5012, NO_MSG),
+            crit(4, CRIP_ARM_RIGHT | BYPASS, None, 0, 5013, NO_MSG),
+        ],
+        // Torso
+        [
+            crit(3, 0, None, 0, 5014, NO_MSG),
+            crit(3, BYPASS, None, 0, 5014, NO_MSG),
+            crit(4, KNOCKED_DOWN, None, 0, 5015, NO_MSG),
+            crit(4, KNOCKED_DOWN | BYPASS, Some((Endurance, -2)), KNOCKED_OUT, 5015, 5016),
+            crit(6, KNOCKED_OUT | BYPASS, None, 0, 5016, NO_MSG),
+            crit(6, DEAD, None, 0, 5017, NO_MSG),
+        ],
+        // RightLeg
+        [
+            crit(3, 0, None, 0, 5018, NO_MSG),
+            crit(3, KNOCKED_DOWN, None, 0, 5019, NO_MSG),
+            crit(4, KNOCKED_DOWN, Some((Endurance, 0)), CRIP_LEG_RIGHT, 5019, 5020),
+            crit(4, CRIP_LEG_RIGHT, None, 0, 5021, NO_MSG),
+            crit(5, KNOCKED_DOWN | BYPASS, Some((Endurance, 0)), CRIP_LEG_RIGHT, 5019, 5022),
+            crit(5, CRIP_LEG_RIGHT | KNOCKED_DOWN | BYPASS, None, 0, 5022, NO_MSG),
+        ],
+        // LeftLeg
+        [
+            crit(3, 0, None, 0, 5018, NO_MSG),
+            crit(3, KNOCKED_DOWN, None, 0, 5019, NO_MSG),
+            crit(4, KNOCKED_DOWN, Some((Endurance, 0)), CRIP_LEG_LEFT, 5019, 5020),
+            crit(4, CRIP_LEG_LEFT, None, 0, 5021, NO_MSG),
+            crit(5, KNOCKED_DOWN | BYPASS, Some((Endurance, 0)), CRIP_LEG_LEFT, 5019, 5022),
+            crit(5, CRIP_LEG_LEFT | KNOCKED_DOWN | BYPASS, None, 0, 5022, NO_MSG),
+        ],
+        // Eyes
+        [
+            crit(4, 0, Some((Luck, 4)), BLIND, 5023, 5024),
+            crit(4, BYPASS, Some((Luck, 3)), BLIND, 5023, 5024),
+            crit(6, BYPASS, Some((Luck, 2)), BLIND, 5025, 5024),
+            crit(6, BLIND | BYPASS, None, 0, 5026, NO_MSG),
+            crit(8, KNOCKED_OUT | BLIND | BYPASS, None, 0, 5027, NO_MSG),
+            crit(8, DEAD, None, 0, 5028, NO_MSG),
+        ],
+        // Groin
+        [
+            crit(3, 0, None, 0, 5029, NO_MSG),
+            crit(3, BYPASS, Some((Endurance, -3)), KNOCKED_DOWN, 5029, 5030),
+            crit(3, KNOCKED_DOWN, Some((Endurance, -3)), KNOCKED_OUT, 5030, 5031),
+            crit(3, KNOCKED_OUT, None, 0, 5032, NO_MSG),
+            crit(4, KNOCKED_DOWN | BYPASS, Some((Endurance, 0)), KNOCKED_OUT, 5030, 5031),
+            crit(4, KNOCKED_OUT | BYPASS, None, 0, 5032, NO_MSG),
+        ],
+    ]
+};
+
+/// Returns critical hit effect for the `location` and severity `level` in range `0..6`.
+pub fn critical_effect(location: HitLocation, level: usize) -> &'static CriticalEffect {
+    let row = match location {
+        HitLocation::Uncalled => HitLocation::Torso,
+        l => l,
+    };
+    &CRITICAL_HITS[row as usize][level]
+}
+
+/// Maps the critical hit roll (`1..=100` plus bonuses) to severity level in range `0..6`.
+pub fn critical_level(roll: i32) -> usize {
+    match roll {
+        i32::MIN..=20 => 0,
+        21..=45 => 1,
+        46..=70 => 2,
+        71..=90 => 3,
+        91..=100 => 4,
+        _ => 5,
+    }
+}
+
+// item_w_anim_code(), item_w_skill()
+/// Returns the attack with the weapon in `attacker`'s hands or unarmed attack if there's no
+/// weapon. Returns `None` if the weapon can't attack in the `mode`.
+pub fn attack_info(rpg: &Rpg, objs: &Objects, attacker: Handle, mode: HitMode)
+    -> Option<AttackInfo>
+{
+    let attackero = objs.get(attacker);
+    let weapon = attackero.in_right_hand(objs)
+        .or_else(|| attackero.in_left_hand(objs))
+        .filter(|&h| objs.get(h).proto().map(|p|
+            p.sub.as_item().and_then(|i| i.sub.as_weapon()).is_some()).unwrap_or(false));
+
+    let weapon = if let Some(weapon) = weapon {
+        weapon
+    } else {
+        let (kind, ap_cost) = match mode {
+            HitMode::Primary => (AttackKind::Punch, UNARMED_AP_COST),
+            HitMode::Secondary => (AttackKind::Kick, UNARMED_AP_COST),
+        };
+        return Some(AttackInfo {
+            weapon: None,
+            kind,
+            skill: Skill::UnarmedCombat,
+            min_damage: 1,
+            max_damage: 2 + rpg.stat(Stat::MeleeDmg, &attackero, objs),
+            damage_kind: DamageKind::Melee,
+            range: 1,
+            ap_cost,
+        });
+    };
+
+    let weapono = objs.get(weapon);
+    let proto = weapono.proto().unwrap();
+    let weapon_proto = proto.sub.as_item().unwrap().sub.as_weapon().unwrap();
+    let (kind, range, ap_cost) = match mode {
+        HitMode::Primary => (weapon_proto.attack_kind.primary, weapon_proto.max_range.primary,
+            weapon_proto.ap_cost.primary),
+        HitMode::Secondary => (weapon_proto.attack_kind.secondary,
+            weapon_proto.max_range.secondary, weapon_proto.ap_cost.secondary),
+    };
+    let skill = match kind {
+        AttackKind::Stand => return None,
+        AttackKind::Punch | AttackKind::Kick => Skill::UnarmedCombat,
+        AttackKind::Swing | AttackKind::Thrust => Skill::Melee,
+        AttackKind::Throw => Skill::Throwing,
+        AttackKind::FireSingle | AttackKind::FireBurst | AttackKind::FireContinuous =>
+            match weapon_proto.damage_kind {
+                DamageKind::Laser | DamageKind::Plasma | DamageKind::Electric =>
+                    Skill::EnergyWeapons,
+                _ if proto.flags_ext.contains(FlagExt::BigGun) => Skill::BigGuns,
+                _ => Skill::SmallGuns,
+            }
+    };
+    let mut info = AttackInfo {
+        weapon: Some(weapon),
+        kind,
+        skill,
+        min_damage: *weapon_proto.damage.start(),
+        max_damage: *weapon_proto.damage.end(),
+        damage_kind: weapon_proto.damage_kind,
+        range: cmp::max(range, 1) as u32,
+        ap_cost,
+    };
+    if !info.is_ranged() {
+        info.max_damage += rpg.stat(Stat::MeleeDmg, &attackero, objs);
+    }
+    Some(info)
+}
+
+/// Returns ammo left in the weapon or `None` if the weapon doesn't use ammo.
+pub fn ammo_count(objs: &Objects, weapon: Handle) -> Option<u32> {
+    let weapono = objs.get(weapon);
+    let proto = weapono.proto().unwrap();
+    let max = proto.sub.as_item()?.sub.as_weapon()?.max_ammo_count;
+    if max > 0 {
+        weapono.sub.as_item().map(|i| i.ammo_count)
+    } else {
+        None
+    }
+}
+
+// item_w_dr_adjust(), item_w_dam_mult(), item_w_dam_div(), item_w_ac_adjust()
+/// Returns `(ac_modifier, dr_modifier, damage_mult, damage_div)` of the ammo loaded in weapon.
+fn ammo_modifiers(objs: &Objects, weapon: Option<Handle>) -> (i32, i32, i32, i32) {
+    weapon
+        .and_then(|w| objs.get(w).sub.as_item().and_then(|i| i.ammo_proto.clone()))
+        .and_then(|p| p.borrow().sub.as_item()
+            .and_then(|i| i.sub.as_ammo())
+            .map(|a| (a.ac_modifier, a.dr_modifier, a.damage_mult, cmp::max(a.damage_div, 1))))
+        .unwrap_or((0, 0, 1, 1))
+}
+
+/// Returns damage threshold and damage resistance stats for the damage kind.
+fn armor_stats(damage_kind: DamageKind) -> Option<(Stat, Stat)> {
+    use Stat::*;
+    Some(match damage_kind {
+        DamageKind::Melee => (DmgThresh, DmgResist),
+        DamageKind::Laser => (DmgThreshLaser, DmgResistLaser),
+        DamageKind::Fire => (DmgThreshFire, DmgResistFire),
+        DamageKind::Plasma => (DmgThreshPlasma, DmgResistPlasma),
+        DamageKind::Electric => (DmgThreshElectrical, DmgResistElectrical),
+        DamageKind::Emp => (DmgThreshEmp, DmgResistEmp),
+        DamageKind::Explosion => (DmgThreshExplosion, DmgResistExplosion),
+        DamageKind::Radiation | DamageKind::Poison => return None,
+    })
+}
+
+/// Returns armor class of the `obj` including the worn armor.
+pub fn armor_class(rpg: &Rpg, objs: &Objects, obj: Handle) -> i32 {
+    let o = objs.get(obj);
+    let worn = o.wearing(objs)
+        .and_then(|a| objs.get(a).proto().unwrap().sub.as_item()?.sub.as_armor()
+            .map(|a| a.armor_class))
+        .unwrap_or(0);
+    rpg.stat(Stat::ArmorClass, &o, objs) + worn
+}
+
+/// Returns damage threshold and damage resistance of the `obj` including the worn armor.
+pub fn damage_protection(rpg: &Rpg, objs: &Objects, obj: Handle, damage_kind: DamageKind)
+    -> (i32, i32)
+{
+    let (dt_stat, dr_stat) = if let Some(v) = armor_stats(damage_kind) {
+        v
+    } else {
+        return (0, 0);
+    };
+    let o = objs.get(obj);
+    let (worn_dt, worn_dr) = o.wearing(objs)
+        .and_then(|a| objs.get(a).proto().unwrap().sub.as_item()?.sub.as_armor()
+            .map(|a| (a.damage_threshold[damage_kind], a.damage_resistance[damage_kind])))
+        .unwrap_or((0, 0));
+    (rpg.stat(dt_stat, &o, objs) + worn_dt, rpg.stat(dr_stat, &o, objs) + worn_dr)
+}
+
+/// Applies damage threshold `dt` and damage resistance `dr` (in percents) to the `damage`.
+pub fn reduce_damage(damage: i32, dt: i32, dr: i32) -> i32 {
+    let damage = damage - cmp::max(dt, 0);
+    if damage > 0 {
+        damage - damage * dr.clamp(0, 100) / 100
+    } else {
+        0
+    }
+}
+
+// determine_to_hit_func()
+/// Returns chance to hit `target` in percents.
+pub fn hit_chance(rpg: &Rpg, objs: &Objects, attacker: Handle, target: Handle,
+    info: &AttackInfo, bonus: i32) -> i32
+{
+    let mut r = {
+        let attackero = objs.get(attacker);
+        rpg.skill(info.skill, &attackero, objs)
+    };
+
+    if info.is_ranged() {
+        let attackero = objs.get(attacker);
+        // Beyond twice the perception each hex reduces the chance.
+        let mut perception = rpg.stat(Stat::Perception, &attackero, objs);
+        if attackero.proto_id().map(|p| p.is_dude()).unwrap_or(false) {
+            perception -= 2;
+        }
+        let distance = objs.distance(attacker, target).unwrap_or(0) as i32;
+        r -= 4 * cmp::max(distance - 2 * perception, 0);
+    }
+
+    r -= armor_class(rpg, objs, target);
+    r += ammo_modifiers(objs, info.weapon).0;
+    r += bonus;
+
+    if let Some(c) = objs.get(attacker).sub.as_critter() {
+        if c.combat.damage_flags.contains(DamageFlag::Blind) {
+            r -= 25;
+        }
+    }
+    if let Some(c) = objs.get(target).sub.as_critter() {
+        if c.combat.damage_flags.intersects(DamageFlag::KnockedDown | DamageFlag::KnockedOut) {
+            r += 40;
+        }
+    }
+
+    r.clamp(MIN_HIT_CHANCE, MAX_HIT_CHANCE)
+}
+
+// compute_attack()
+/// Rolls the attack and its damage. Doesn't change any state.
+pub fn roll_attack(rpg: &Rpg, objs: &Objects, roll_checker: RollChecker, attacker: Handle,
+    target: Handle, info: &AttackInfo, options: &AttackOptions) -> AttackResult
+{
+    let hit_chance = hit_chance(rpg, objs, attacker, target, info, options.to_hit_bonus);
+    let crit_chance = {
+        let attackero = objs.get(attacker);
+        rpg.stat(Stat::CritChance, &attackero, objs)
+    };
+    let (roll, _) = roll_checker.roll_check(hit_chance, crit_chance);
+    resolve_attack(rpg, objs, attacker, target, info, options, roll, hit_chance)
+}
+
+// attack_crit_success(), compute_damage()
+/// Computes damage and effects of the attack with already rolled `roll`.
+#[allow(clippy::too_many_arguments)]
+pub fn resolve_attack(rpg: &Rpg, objs: &Objects, attacker: Handle, target: Handle,
+    info: &AttackInfo, options: &AttackOptions, roll: RollCheckResult, hit_chance: i32)
+    -> AttackResult
+{
+    let location = HitLocation::Uncalled;
+    let mut target_flags = BitFlags::empty();
+    let mut message = None;
+    let damage = if roll.is_success() {
+        target_flags |= DamageFlag::Hit;
+
+        // Normal hits have the multiplier of 2 halves.
+        let mut crit_mult = 2;
+        if roll == RollCheckResult::CriticalSuccess {
+            target_flags |= DamageFlag::Critical;
+
+            let bonus = objs.get(attacker).proto_id()
+                .filter(|&pid| rpg.has_perk(Perk::BetterCriticals, pid))
+                .map(|_| 20)
+                .unwrap_or(0);
+            let effect = critical_effect(location, critical_level(random(1, 100) + bonus));
+            crit_mult = effect.damage_mult;
+            target_flags |= effect.flags();
+            let mut msg = effect.message;
+            if let Some((stat, bonus)) = effect.check {
+                let targeto = objs.get(target);
+                if rpg.roll_check_stat(stat, bonus, &targeto, objs).0 == RollCheckResult::Failure {
+                    target_flags |= effect.fail_flags();
+                    msg = effect.fail_message;
+                }
+            }
+            if msg != NO_CRITICAL_MESSAGE {
+                message = Some(msg);
+            }
+        }
+
+        let (_, dr_mod, mult, div) = ammo_modifiers(objs, info.weapon);
+        let (mut dt, mut dr) = damage_protection(rpg, objs, target, info.damage_kind);
+        dr += dr_mod;
+        // Bypassing armor leaves only a fifth of the protection.
+        if target_flags.contains(DamageFlag::Bypass) {
+            dt = dt * 20 / 100;
+            dr = dr * 20 / 100;
+        }
+        let damage = random(info.min_damage, cmp::max(info.max_damage, info.min_damage))
+            * mult * crit_mult / (div * 2);
+        reduce_damage(damage, dt, dr)
+            .clamp(options.min_damage, cmp::max(options.max_damage, options.min_damage))
+    } else {
+        0
+    };
+
+    AttackResult {
+        roll,
+        hit_chance,
+        location,
+        damage,
+        target_flags,
+        message,
+    }
+}
+
+// critter_damage(), critter_kill()
+/// Applies damage to the critter. Returns `true` if the critter has been killed.
+pub fn apply_damage(objs: &Objects, target: Handle, damage: i32, flags: BitFlags<DamageFlag>)
+    -> bool
+{
+    let mut targeto = objs.get_mut(target);
+    let invulnerable = targeto.proto()
+        .and_then(|p| p.sub.as_critter().map(|c| c.flags.contains(CritterFlag::Invulnerable)))
+        .unwrap_or(false);
+    let critter = if let Some(v) = targeto.sub.as_critter_mut() {
+        v
+    } else {
+        return false;
+    };
+    if critter.is_dead() {
+        return false;
+    }
+    // Critical hits can kill regardless of the damage.
+    let killing_blow = flags.contains(DamageFlag::Dead);
+    critter.combat.damage_flags |= flags & !BitFlags::from(DamageFlag::Dead);
+    if !invulnerable {
+        critter.hit_points -= damage;
+    }
+    if (critter.hit_points <= 0 || killing_blow) && !invulnerable {
+        critter.hit_points = 0;
+        critter.combat.damage_flags |= DamageFlag::Dead;
+        targeto.flags.insert(Flag::NoBlock);
+        true
+    } else {
+        false
+    }
+}
+
+/// Consumes a round of ammo for the attack. Returns `false` if the weapon is out of ammo.
+pub fn consume_ammo(objs: &Objects, weapon: Option<Handle>) -> bool {
+    let weapon = if let Some(v) = weapon {
+        v
+    } else {
+        return true;
+    };
+    match ammo_count(objs, weapon) {
+        None => true,
+        Some(0) => false,
+        Some(_) => {
+            objs.get_mut(weapon).sub.as_item_mut().unwrap().ammo_count -= 1;
+            true
+        }
+    }
+}
+
+/// State of the ongoing combat.
+#[derive(Default)]
+pub struct Combat {
+    /// Critters taking part in the combat in the turn order. Empty if there's no combat.
+    combatants: Vec<Handle>,
+    turn: usize,
+    round: u32,
+    action_points: SecondaryMap<Handle, i32>,
+    /// Who each critter is attacking.
+    targets: SecondaryMap<Handle, Handle>,
+    /// Modifiers of the next attack of the critter requested by scripts.
+    options: SecondaryMap<Handle, AttackOptions>,
+    requests: Vec<AttackRequest>,
+    end_requested: bool,
+}
+
+impl Combat {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.combatants.is_empty()
+    }
+
+    pub fn combatants(&self) -> &[Handle] {
+        &self.combatants
+    }
+
+    /// Number of the current round starting from `1`.
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    /// Critter whose turn it is.
+    pub fn current(&self) -> Option<Handle> {
+        self.combatants.get(self.turn).cloned()
+    }
+
+    // combat_begin(), combat_sequence()
+    /// Starts combat between the `critters`. They take turns in the order of their sequence
+    /// stat except the `first` who starts the combat.
+    pub fn start(&mut self, rpg: &Rpg, objs: &Objects, first: Option<Handle>,
+        critters: impl IntoIterator<Item=Handle>)
+    {
+        let mut combatants: Vec<_> = critters.into_iter()
+            .filter(|&h| Some(h) != first && Self::can_act(objs, h))
+            .map(|h| (h, rpg.stat(Stat::Sequence, &objs.get(h), objs)))
+            .collect();
+        combatants.sort_by_key(|&(_, seq)| cmp::Reverse(seq));
+
+        self.combatants.clear();
+        self.combatants.extend(first);
+        self.combatants.extend(combatants.into_iter().map(|(h, _)| h));
+        self.turn = 0;
+        self.round = 1;
+        self.action_points.clear();
+        if let Some(h) = self.current() {
+            self.begin_turn(rpg, objs, h);
+        }
+    }
+
+    /// Clears the combat state. Doesn't run any script hooks.
+    pub fn clear(&mut self) {
+        self.combatants.clear();
+        self.turn = 0;
+        self.round = 0;
+        self.action_points.clear();
+        self.targets.clear();
+        self.options.clear();
+        self.requests.clear();
+        self.end_requested = false;
+    }
+
+    pub fn action_points(&self, obj: Handle) -> i32 {
+        self.action_points.get(obj).cloned().unwrap_or(0)
+    }
+
+    /// Spends `cost` action points of the `obj`. Returns `false` if there's not enough points.
+    pub fn spend_action_points(&mut self, obj: Handle, cost: i32) -> bool {
+        match self.action_points.get_mut(obj) {
+            Some(ap) if *ap >= cost => {
+                *ap -= cost;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // combat_turn()
+    /// Passes the turn to the next critter that is able to act. Returns the new current critter.
+    pub fn end_turn(&mut self, rpg: &Rpg, objs: &Objects) -> Option<Handle> {
+        if self.combatants.is_empty() {
+            return None;
+        }
+        if let Some(h) = self.current() {
+            self.action_points.insert(h, 0);
+        }
+        for _ in 0..self.combatants.len() {
+            self.turn += 1;
+            if self.turn >= self.combatants.len() {
+                self.turn = 0;
+                self.round += 1;
+            }
+            let h = self.combatants[self.turn];
+            if Self::can_act(objs, h) {
+                self.begin_turn(rpg, objs, h);
+                return Some(h);
+            }
+            Self::recover(objs, h);
+        }
+        None
+    }
+
+    /// Adds critter to the combat. It will act after all current combatants.
+    pub fn add(&mut self, obj: Handle) {
+        if !self.combatants.contains(&obj) {
+            self.combatants.push(obj);
+        }
+    }
+
+    pub fn target(&self, obj: Handle) -> Option<Handle> {
+        self.targets.get(obj).cloned()
+    }
+
+    pub fn set_target(&mut self, obj: Handle, target: Handle) {
+        self.targets.insert(obj, target);
+    }
+
+    // critter_stop_attacking
+    pub fn stop_attacking(&mut self, obj: Handle) {
+        self.targets.remove(obj);
+        self.options.remove(obj);
+    }
+
+    /// Whether any combatant is still attacking a live critter.
+    pub fn has_hostiles(&self, objs: &Objects) -> bool {
+        self.combatants.iter()
+            .filter(|&&h| objs.contains(h) && !Self::is_dead(objs, h))
+            .filter_map(|&h| self.target(h))
+            .any(|t| objs.contains(t) && !Self::is_dead(objs, t))
+    }
+
+    /// Modifiers for the next attack of `obj` requested by scripts.
+    pub fn take_attack_options(&mut self, obj: Handle) -> AttackOptions {
+        self.options.remove(obj).unwrap_or_default()
+    }
+
+    // scripts_request_combat()
+    pub fn request_attack(&mut self, request: AttackRequest) {
+        self.requests.push(request);
+    }
+
+    pub fn has_attack_requests(&self) -> bool {
+        !self.requests.is_empty()
+    }
+
+    pub fn take_attack_requests(&mut self) -> Vec<AttackRequest> {
+        mem::take(&mut self.requests)
+    }
+
+    /// Accepts the attack requested by scripts.
+    pub fn accept_attack_request(&mut self, request: &AttackRequest) {
+        self.set_target(request.attacker, request.target);
+        if request.options != AttackOptions::default() {
+            self.options.insert(request.attacker, request.options);
+        }
+    }
+
+    pub fn request_end(&mut self) {
+        self.end_requested = true;
+    }
+
+    pub fn take_end_request(&mut self) -> bool {
+        mem::replace(&mut self.end_requested, false)
+    }
+
+    fn refill_action_points(&mut self, rpg: &Rpg, objs: &Objects, obj: Handle) {
+        let ap = rpg.stat(Stat::ActionPoints, &objs.get(obj), objs);
+        self.action_points.insert(obj, ap);
+    }
+
+    /// Refills action points of the critter whose turn begins. Knocked down critter stands up
+    /// spending some of the points.
+    fn begin_turn(&mut self, rpg: &Rpg, objs: &Objects, obj: Handle) {
+        self.refill_action_points(rpg, objs, obj);
+        let knocked_down = objs.get_mut(obj).sub.as_critter_mut()
+            .map(|c| {
+                let flags = &mut c.combat.damage_flags;
+                let r = flags.contains(DamageFlag::KnockedDown);
+                flags.remove(DamageFlag::KnockedDown);
+                r
+            })
+            .unwrap_or(false);
+        if knocked_down {
+            let ap = self.action_points.get_mut(obj).unwrap();
+            *ap = cmp::max(*ap - STAND_UP_AP_COST, 0);
+        }
+    }
+
+    /// Recovers the critter that skipped its turn: the lost turn is over and the knocked out
+    /// critter comes to but stays knocked down.
+    fn recover(objs: &Objects, obj: Handle) {
+        if !objs.contains(obj) {
+            return;
+        }
+        let mut o = objs.get_mut(obj);
+        if let Some(c) = o.sub.as_critter_mut() {
+            let flags = &mut c.combat.damage_flags;
+            if !flags.contains(DamageFlag::Dead) {
+                flags.remove(DamageFlag::LoseTurn);
+                if flags.contains(DamageFlag::KnockedOut) {
+                    flags.remove(DamageFlag::KnockedOut);
+                    flags.insert(DamageFlag::KnockedDown);
+                }
+            }
+        }
+    }
+
+    fn is_dead(objs: &Objects, obj: Handle) -> bool {
+        objs.get(obj).sub.as_critter().map(|c| c.is_dead()).unwrap_or(true)
+    }
+
+    fn can_act(objs: &Objects, obj: Handle) -> bool {
+        objs.contains(obj) && {
+            let o = objs.get(obj);
+            o.kind() == EntityKind::Critter
+                && o.sub.as_critter().map(|c| c.is_active()).unwrap_or(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asset::map::test::*;
+    use crate::game::object::{Object, SubObject};
+    use crate::graphics::{EPoint, Point};
+
+    fn new_critter(fx: &Fixture, objs: &mut Objects, x: i32) -> Handle {
+        let proto = fx.proto(PID_CRITTER);
+        let fid = proto.borrow().fid;
+        let mut obj = Object::new(fid, Some(proto), Some(EPoint::new(0, Point::new(x, 10))),
+            SubObject::Critter(Default::default()));
+        obj.sub.as_critter_mut().unwrap().hit_points = 10;
+        objs.insert(obj)
+    }
+
+    #[test]
+    fn reduce_damage_() {
+        assert_eq!(reduce_damage(10, 0, 0), 10);
+        assert_eq!(reduce_damage(10, 4, 50), 3);
+        assert_eq!(reduce_damage(10, 10, 0), 0);
+        assert_eq!(reduce_damage(10, -5, 200), 0);
+    }
+
+    #[test]
+    fn turns() {
+        let fx = Fixture::new("combat-turns");
+        let rpg = fx.new_rpg();
+        let mut objs = fx.new_objects();
+        let c1 = new_critter(&fx, &mut objs, 10);
+        let c2 = new_critter(&fx, &mut objs, 12);
+        let c3 = new_critter(&fx, &mut objs, 14);
+        fx.proto(PID_CRITTER).borrow_mut().sub.as_critter_mut().unwrap()
+            .base_stats[Stat::ActionPoints] = 5;
+
+        let mut c = Combat::new();
+        assert!(!c.is_active());
+        c.start(&rpg, &objs, Some(c2), vec![c1, c2, c3]);
+        assert_eq!(c.combatants(), &[c2, c1, c3]);
+        assert_eq!(c.current(), Some(c2));
+        assert_eq!(c.action_points(c2), 5);
+        assert!(c.spend_action_points(c2, 3));
+        assert!(!c.spend_action_points(c2, 3));
+        assert_eq!(c.action_points(c2), 2);
+
+        assert_eq!(c.end_turn(&rpg, &objs), Some(c1));
+        assert_eq!(c.action_points(c2), 0);
+        assert_eq!(c.action_points(c1), 5);
+
+        assert!(!c.has_hostiles(&objs));
+        c.set_target(c1, c3);
+        assert!(c.has_hostiles(&objs));
+
+        assert!(!apply_damage(&objs, c3, 4, DamageFlag::Hit.into()));
+        assert_eq!(objs.get(c3).sub.as_critter().unwrap().hit_points, 6);
+        assert!(apply_damage(&objs, c3, 10, BitFlags::empty()));
+        assert!(objs.get(c3).sub.as_critter().unwrap().is_dead());
+        assert!(!c.has_hostiles(&objs));
+
+        // Dead critters are skipped.
+        assert_eq!(c.end_turn(&rpg, &objs), Some(c2));
+        assert_eq!(c.round(), 2);
+
+        c.clear();
+        assert!(!c.is_active());
+    }
+
+    #[test]
+    fn unarmed_attack() {
+        let fx = Fixture::new("combat-unarmed");
+        let rpg = fx.new_rpg();
+        let mut objs = fx.new_objects();
+        let c1 = new_critter(&fx, &mut objs, 10);
+        let c2 = new_critter(&fx, &mut objs, 11);
+
+        let info = attack_info(&rpg, &objs, c1, HitMode::Primary).unwrap();
+        assert_eq!(info.kind, AttackKind::Punch);
+        assert_eq!(info.skill, Skill::UnarmedCombat);
+        assert_eq!(info.ap_cost, UNARMED_AP_COST);
+        assert!(!info.is_ranged());
+
+        let options = AttackOptions {
+            to_hit_bonus: 1000,
+            min_damage: 7,
+            max_damage: 7,
+        };
+        assert_eq!(hit_chance(&rpg, &objs, c1, c2, &info, options.to_hit_bonus), MAX_HIT_CHANCE);
+
+        let r = resolve_attack(&rpg, &objs, c1, c2, &info, &options, RollCheckResult::Success,
+            MAX_HIT_CHANCE);
+        assert_eq!(r.damage, 7);
+        assert_eq!(r.target_flags, DamageFlag::Hit);
+        assert_eq!(r.message, None);
+
+        let r = resolve_attack(&rpg, &objs, c1, c2, &info, &options, RollCheckResult::Failure,
+            MAX_HIT_CHANCE);
+        assert_eq!(r.damage, 0);
+        assert!(r.target_flags.is_empty());
+
+        let r = roll_attack(&rpg, &objs, RollChecker::new(true), c1, c2, &info, &options);
+        assert_eq!(r.hit_chance, MAX_HIT_CHANCE);
+        assert!(!r.roll.is_critical());
+    }
+
+    #[test]
+    fn critical_effects() {
+        assert_eq!(critical_level(1), 0);
+        assert_eq!(critical_level(20), 0);
+        assert_eq!(critical_level(21), 1);
+        assert_eq!(critical_level(90), 3);
+        assert_eq!(critical_level(100), 4);
+        assert_eq!(critical_level(120), 5);
+
+        for level in 0..6 {
+            assert_eq!(critical_effect(HitLocation::Uncalled, level),
+                critical_effect(HitLocation::Torso, level));
+        }
+        let e = critical_effect(HitLocation::Head, 5);
+        assert!(e.flags().contains(DamageFlag::Dead));
+        let e = critical_effect(HitLocation::LeftLeg, 2);
+        assert_eq!(e.check, Some((Stat::Endurance, 0)));
+        assert_eq!(e.fail_flags(), DamageFlag::CripLegLeft);
+    }
+
+    #[test]
+    fn critical_hit() {
+        let fx = Fixture::new("combat-critical");
+        let rpg = fx.new_rpg();
+        let mut objs = fx.new_objects();
+        let c1 = new_critter(&fx, &mut objs, 10);
+        let c2 = new_critter(&fx, &mut objs, 11);
+
+        let info = AttackInfo {
+            weapon: None,
+            kind: AttackKind::Punch,
+            skill: Skill::UnarmedCombat,
+            min_damage: 4,
+            max_damage: 4,
+            damage_kind: DamageKind::Melee,
+            range: 1,
+            ap_cost: UNARMED_AP_COST,
+        };
+        let r = resolve_attack(&rpg, &objs, c1, c2, &info, &AttackOptions::default(),
+            RollCheckResult::CriticalSuccess, 50);
+        assert_eq!(r.location, HitLocation::Uncalled);
+        assert!(r.target_flags.contains(DamageFlag::Hit | DamageFlag::Critical));
+        // The lowest critical multiplier is 1.5.
+        assert!(r.damage >= 6);
+
+        // Critical hit can kill regardless of the damage.
+        assert!(apply_damage(&objs, c2, 1, DamageFlag::Hit | DamageFlag::Dead));
+        assert_eq!(objs.get(c2).sub.as_critter().unwrap().hit_points, 0);
+    }
+
+    #[test]
+    fn lost_turn() {
+        let fx = Fixture::new("combat-lost-turn");
+        let rpg = fx.new_rpg();
+        let mut objs = fx.new_objects();
+        let c1 = new_critter(&fx, &mut objs, 10);
+        let c2 = new_critter(&fx, &mut objs, 12);
+
+        let mut c = Combat::new();
+        c.start(&rpg, &objs, Some(c1), vec![c1, c2]);
+        c.set_target(c2, c1);
+        assert!(!apply_damage(&objs, c2, 1, DamageFlag::Hit | DamageFlag::KnockedOut));
+        // Knocked out critter is still hostile.
+        assert!(c.has_hostiles(&objs));
+
+        // Knocked out critter skips its turn and then stays knocked down.
+        assert_eq!(c.end_turn(&rpg, &objs), Some(c1));
+        let flags = objs.get(c2).sub.as_critter().unwrap().combat.damage_flags;
+        assert!(!flags.contains(DamageFlag::KnockedOut));
+        assert!(flags.contains(DamageFlag::KnockedDown));
+        assert!(objs.get(c2).is_critter_prone());
+
+        // Knocked down critter stands up at the start of its turn.
+        let full_ap = rpg.stat(Stat::ActionPoints, &objs.get(c2), &objs);
+        assert_eq!(c.end_turn(&rpg, &objs), Some(c2));
+        assert!(!objs.get(c2).sub.as_critter().unwrap().combat.damage_flags
+            .contains(DamageFlag::KnockedDown));
+        assert!(!objs.get(c2).is_critter_prone());
+        assert_eq!(c.action_points(c2), cmp::max(full_ap - STAND_UP_AP_COST, 0));
+
+        assert!(!apply_damage(&objs, c1, 1, DamageFlag::Hit | DamageFlag::LoseTurn));
+        assert_eq!(c.end_turn(&rpg, &objs), Some(c2));
+        assert!(!objs.get(c1).sub.as_critter().unwrap().combat.damage_flags
+            .contains(DamageFlag::LoseTurn));
+    }
+}
@@ -1,17 +1,165 @@
 use bstring::{bstr, BString};
+use enum_map::EnumMap;
+use std::mem;
 
+use crate::asset::{Perk, Skill, Stat};
 use crate::asset::frame::FrameId;
-use crate::asset::message::BULLET_STR;
-use crate::game::object;
+use crate::asset::message::{BULLET_STR, MessageId, Messages};
+use crate::asset::proto::ProtoId;
+use crate::game::object::{self, Object, Objects, SubObject};
+use crate::game::rpg::Rpg;
 use crate::game::script::ScriptIid;
+use crate::game::ui::inventory_list::{self, InventoryList};
 use crate::game::world::World;
 use crate::graphics::{Point, Rect};
 use crate::graphics::color::{Rgb15, GREEN};
 use crate::graphics::font::FontKey;
 use crate::graphics::sprite::{Sprite, Effect};
 use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::{BarterCommand, UiCommandData};
 use crate::ui::message_panel::{MessagePanel, MouseControl};
-use crate::ui::panel::Panel;
+use crate::ui::panel::{self, Panel};
+
+/// Item lists of the barter screen.
+#[derive(Clone, Copy, Debug, enum_map_derive::Enum, Eq, PartialEq)]
+pub enum BarterList {
+    /// Player's inventory.
+    Dude,
+    /// Items offered by the player.
+    DudeTable,
+    /// Items offered by the NPC.
+    NpcTable,
+    /// NPC's inventory.
+    Npc,
+}
+
+impl BarterList {
+    fn table(self) -> Option<Self> {
+        match self {
+            Self::Dude => Some(Self::DudeTable),
+            Self::Npc => Some(Self::NpcTable),
+            Self::DudeTable | Self::NpcTable => None,
+        }
+    }
+
+    fn owner(self) -> Self {
+        match self {
+            Self::DudeTable => Self::Dude,
+            Self::NpcTable => Self::Npc,
+            Self::Dude | Self::Npc => self,
+        }
+    }
+}
+
+const BARTER_LIST_ITEM_HEIGHT: i32 = 48;
+const BARTER_LIST_VISIBLE_COUNT: usize = 3;
+
+struct Barter {
+    /// All widgets of the barter screen.
+    widgets: Vec<Handle>,
+    lists: EnumMap<BarterList, Handle>,
+    costs: EnumMap<BarterList, Option<Handle>>,
+    /// Temporary containers holding the offered items.
+    dude_table: object::Handle,
+    npc_table: object::Handle,
+}
+
+// barter_compute_value()
+/// Computes the price the NPC asks for the items on its table. `cost` is the total cost of the
+/// items which includes `caps`. Caps are not subject to the markup.
+pub fn barter_value(
+    cost: i32,
+    caps: i32,
+    dude_barter: i32,
+    npc_barter: i32,
+    barter_mod: i32,
+    master_trader: bool,
+) -> i32 {
+    let perk_bonus = if master_trader { 25.0 } else { 0.0 };
+    let mut mod_mult = (barter_mod as f64 + 100.0 - perk_bonus) * 0.01;
+    if mod_mult < 0.0 {
+        mod_mult = 0.01;
+    }
+    let balanced_cost = (160.0 + npc_barter as f64) / (160.0 + dude_barter as f64)
+        * ((cost - caps) as f64 * 2.0);
+    (mod_mult * balanced_cost) as i32 + caps
+}
+
+// barter_attempt_transaction()
+/// Checks whether the NPC `npc` accepts the items on `dude_table` in exchange for the items on
+/// `npc_table`.
+pub fn check_offer(
+    objs: &Objects,
+    rpg: &Rpg,
+    dude: object::Handle,
+    npc: object::Handle,
+    dude_table: object::Handle,
+    npc_table: object::Handle,
+    barter_mod: i32,
+) -> OfferResult {
+    let dudeo = objs.get(dude);
+    let npco = objs.get(npc);
+    let dude_tableo = objs.get(dude_table);
+    let npc_tableo = objs.get(npc_table);
+
+    let can_carry = |obj: &Object, table: &Object| {
+        obj.sub.as_critter().is_none() ||
+            (obj.inventory.weight(objs) + table.inventory.weight(objs)) as i32
+                <= rpg.stat(Stat::CarryWeight, obj, objs)
+    };
+    if !can_carry(&dudeo, &npc_tableo) {
+        return OfferResult::DudeOverweight;
+    }
+    if !can_carry(&npco, &dude_tableo) {
+        return OfferResult::NpcOverweight;
+    }
+
+    let offer = dude_tableo.inventory.cost(objs);
+    let master_trader = dudeo.proto_id()
+        .map(|pid| rpg.has_perk(Perk::MasterTrader, pid))
+        .unwrap_or(false);
+    let npc_barter = if npco.sub.as_critter().is_some() {
+        rpg.skill(Skill::Barter, &npco, objs)
+    } else {
+        0
+    };
+    let value = barter_value(
+        npc_tableo.inventory.cost(objs),
+        objs.inventory_count(npc_table, ProtoId::BOTTLE_CAPS) as i32,
+        rpg.skill(Skill::Barter, &dudeo, objs),
+        npc_barter,
+        barter_mod,
+        master_trader);
+    if offer >= value {
+        OfferResult::Accepted
+    } else {
+        OfferResult::Refused
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OfferResult {
+    Accepted,
+    /// Player's offer is not good enough.
+    Refused,
+    /// The player can't carry the items from the NPC table.
+    DudeOverweight,
+    /// The NPC can't carry the items from the player table.
+    NpcOverweight,
+}
+
+impl OfferResult {
+    /// Message in `inventry.msg` the NPC replies with.
+    pub fn message_id(self) -> MessageId {
+        match self {
+            Self::Accepted => 27,
+            Self::Refused => 28,
+            Self::DudeOverweight => 31,
+            Self::NpcOverweight => 32,
+        }
+    }
+}
 
 pub struct OptionInfo {
     pub proc_id: Option<u32>,
@@ -24,6 +172,9 @@ pub struct Dialog {
     options: Vec<OptionInfo>,
     sid: ScriptIid,
     saved_camera_origin: Point,
+    barter: Option<Barter>,
+    barter_requested: bool,
+    barter_mod: i32,
     pub obj: object::Handle,
     pub running: bool,
 }
@@ -55,6 +206,10 @@ impl Dialog {
         ui.new_widget(window, Rect::with_size(129, 214 - 2 - 131, 1, 1), None, Some(spr),
             Panel::new());
 
+        ui.new_widget(window, Rect::with_size(593, 480 - 190 + 41, 14, 14), None, None,
+            Button::new(FrameId::DI_RDBT1, FrameId::DI_RDBT2,
+                Some(UiCommandData::Barter(BarterCommand::Start))));
+
         let (obj_pos, sid) = {
             let obj = world.objects().get(obj);
             let (sid, _) = obj.script.unwrap();
@@ -69,6 +224,9 @@ impl Dialog {
             reply,
             options_widget,
             options: Vec::new(),
+            barter: None,
+            barter_requested: false,
+            barter_mod: 0,
             running: false,
             sid,
             saved_camera_origin,
@@ -76,7 +234,10 @@ impl Dialog {
         }
     }
 
-    pub fn hide(self, ui: &mut Ui, world: &mut World) {
+    pub fn hide(mut self, ui: &mut Ui, world: &mut World) {
+        if self.is_bartering() {
+            self.end_barter(ui, world);
+        }
         ui.remove(self.window);
         world.camera_mut().origin = self.saved_camera_origin;
     }
@@ -116,8 +277,285 @@ impl Dialog {
         self.sid
     }
 
-    fn build_option(option: &bstr) -> BString {
+    pub fn build_option(option: &bstr) -> BString {
         BString::concat(&[&b"  "[..], BULLET_STR, &b" "[..], option.as_bytes()])
     }
+
+    pub fn barter_mod(&self) -> i32 {
+        self.barter_mod
+    }
+
+    pub fn set_barter_mod(&mut self, barter_mod: i32) {
+        self.barter_mod = barter_mod;
+    }
+
+    /// Requests switching to the barter screen. The request is handled after the script
+    /// procedure finishes.
+    pub fn request_barter(&mut self) {
+        self.barter_requested = true;
+    }
+
+    /// Switches to the barter screen if it was requested. Returns `true` if switched.
+    pub fn start_requested_barter(&mut self, ui: &mut Ui, world: &mut World) -> bool {
+        let r = mem::replace(&mut self.barter_requested, false);
+        if r {
+            self.start_barter(ui, world);
+        }
+        r
+    }
+
+    pub fn is_bartering(&self) -> bool {
+        self.barter.is_some()
+    }
+
+    // barter_inventory()
+    pub fn start_barter(&mut self, ui: &mut Ui, world: &mut World) {
+        assert!(self.barter.is_none());
+
+        let window = self.window;
+        let mut widgets = Vec::new();
+        widgets.push(ui.new_widget(window, Rect::with_size(0, 480 - 190, 640, 190), None,
+            Some(Sprite::new(FrameId::BARTER)), Panel::new()));
+        let base = Point::new(0, 480 - 190);
+
+        let mut new_list = |x, y| {
+            let h = ui.new_widget(window, Rect::with_size(base.x + x, base.y + y,
+                    64, BARTER_LIST_ITEM_HEIGHT * BARTER_LIST_VISIBLE_COUNT as i32),
+                None, None, InventoryList::new(BARTER_LIST_ITEM_HEIGHT));
+            widgets.push(h);
+            h
+        };
+        let lists = enum_map::enum_map! {
+            BarterList::Dude => new_list(29, 35),
+            BarterList::DudeTable => new_list(165, 20),
+            BarterList::NpcTable => new_list(250, 20),
+            BarterList::Npc => new_list(395, 35),
+        };
+
+        for &(list, x) in &[
+            (BarterList::Dude, 109),
+            (BarterList::DudeTable, 139),
+            (BarterList::NpcTable, 320),
+            (BarterList::Npc, 475),
+        ] {
+            for &(up, y, fid_up, fid_down) in &[
+                (true, 56, FrameId::INVUPOUT, FrameId::INVUPIN),
+                (false, 82, FrameId::INVDNOUT, FrameId::INVDNIN),
+            ] {
+                widgets.push(ui.new_widget(window,
+                    Rect::with_size(base.x + x, base.y + y, 22, 23), None, None,
+                    Button::new(fid_up, fid_down,
+                        Some(UiCommandData::Barter(BarterCommand::Scroll { list, up })))));
+            }
+        }
+
+        let mut new_cost = |x| {
+            let h = ui.new_widget(window, Rect::with_size(base.x + x, base.y + 167, 64, 12),
+                None, None, Panel::new());
+            widgets.push(h);
+            Some(h)
+        };
+        let costs = enum_map::enum_map! {
+            BarterList::DudeTable => new_cost(165),
+            BarterList::NpcTable => new_cost(250),
+            BarterList::Dude | BarterList::Npc => None,
+        };
+
+        for &(x, cmd) in &[(41, BarterCommand::Offer), (583, BarterCommand::Talk)] {
+            widgets.push(ui.new_widget(window,
+                Rect::with_size(base.x + x, base.y + 162, 14, 14), None, None,
+                Button::new(FrameId::DI_RDBT1, FrameId::DI_RDBT2,
+                    Some(UiCommandData::Barter(cmd)))));
+        }
+
+        let objs = world.objects_mut();
+        let mut new_table = || objs.insert(Object::new(FrameId::BLANK, None, None,
+            SubObject::None));
+        let dude_table = new_table();
+        let npc_table = new_table();
+
+        self.barter = Some(Barter {
+            widgets,
+            lists,
+            costs,
+            dude_table,
+            npc_table,
+        });
+        self.sync_barter(ui, world);
+    }
+
+    /// Returns all offered items to their owners and switches back to the dialog.
+    pub fn end_barter(&mut self, ui: &mut Ui, world: &mut World) {
+        let barter = self.barter.take().unwrap();
+        let dude = world.dude_obj().unwrap();
+        let objs = world.objects_mut();
+        move_all_items(objs, barter.dude_table, dude);
+        move_all_items(objs, barter.npc_table, self.obj);
+        objs.remove(barter.dude_table);
+        objs.remove(barter.npc_table);
+
+        for w in barter.widgets {
+            ui.remove(w);
+        }
+    }
+
+    pub fn scroll_barter_list(&mut self, ui: &mut Ui, list: BarterList, up: bool) {
+        let h = self.barter.as_ref().unwrap().lists[list];
+        ui.widget_mut::<InventoryList>(h).scroll(up, BARTER_LIST_VISIBLE_COUNT);
+    }
+
+    /// Handles item dragged from the `source` list widget and dropped at `pos`. Moves the item
+    /// between the inventory and the corresponding table.
+    pub fn barter_drop(&mut self,
+        ui: &mut Ui,
+        world: &mut World,
+        source: Handle,
+        item: object::Handle,
+        pos: Point,
+    ) {
+        let barter = self.barter.as_ref().unwrap();
+        let src = barter.lists.iter().find(|&(_, &h)| h == source).map(|(l, _)| l);
+        let dst = barter.lists.iter()
+            .find(|&(_, &h)| ui.widget_base(h).borrow().rect().contains(pos))
+            .map(|(l, _)| l);
+        if let (Some(src), Some(dst)) = (src, dst) {
+            if src != dst && src.owner() == dst.owner() {
+                let src_obj = self.barter_list_owner(world, src);
+                let dst_obj = self.barter_list_owner(world, dst);
+                let objs = world.objects_mut();
+                let count = objs.get(src_obj).inventory.items.iter()
+                    .find(|i| i.object == item)
+                    .unwrap()
+                    .count;
+                let item = objs.remove_from_inventory(src_obj, item, count).unwrap();
+                objs.add_to_inventory(dst_obj, item, count);
+            }
+        }
+        self.sync_barter(ui, world);
+    }
+
+    // barter_attempt_transaction()
+    /// Tries to make the deal. If the NPC accepts the offer the table contents are exchanged.
+    /// `msgs` are the messages of `inventry.msg`.
+    pub fn offer(&mut self, ui: &mut Ui, world: &mut World, rpg: &Rpg, msgs: &Messages)
+        -> OfferResult
+    {
+        let r = {
+            let barter = self.barter.as_ref().unwrap();
+            let dude = world.dude_obj().unwrap();
+            let objs = world.objects_mut();
+
+            let r = check_offer(objs, rpg, dude, self.obj, barter.dude_table, barter.npc_table,
+                self.barter_mod);
+            if r == OfferResult::Accepted {
+                move_all_items(objs, barter.dude_table, self.obj);
+                move_all_items(objs, barter.npc_table, dude);
+            }
+            r
+        };
+
+        if let Some(msg) = msgs.get(r.message_id()) {
+            self.set_reply(ui, &msg.text);
+        }
+        self.sync_barter(ui, world);
+
+        r
+    }
+
+    fn barter_list_owner(&self, world: &World, list: BarterList) -> object::Handle {
+        let barter = self.barter.as_ref().unwrap();
+        match list {
+            BarterList::Dude => world.dude_obj().unwrap(),
+            BarterList::DudeTable => barter.dude_table,
+            BarterList::NpcTable => barter.npc_table,
+            BarterList::Npc => self.obj,
+        }
+    }
+
+    fn sync_barter(&mut self, ui: &mut Ui, world: &World) {
+        let barter = self.barter.as_ref().unwrap();
+        let objs = world.objects();
+        for (list, &h) in &barter.lists {
+            let owner = objs.get(self.barter_list_owner(world, list));
+            let items = owner.inventory.items.iter()
+                .filter(|i| list.table().is_none() || !is_equipped(&objs.get(i.object)))
+                .map(|i| inventory_list::Item::new(i, objs))
+                .collect();
+            ui.widget_mut::<InventoryList>(h).set_items(items);
+
+            if let Some(cost_h) = barter.costs[list] {
+                let cost = owner.inventory.cost(objs);
+                ui.widget_mut::<Panel>(cost_h).set_text(Some(panel::Text {
+                    text: format!("${}", cost).into(),
+                    font: FontKey::antialiased(1),
+                    color: GREEN,
+                    options: Default::default(),
+                }));
+            }
+        }
+    }
+}
+
+/// Whether the item is in hand or worn.
+fn is_equipped(item: &Object) -> bool {
+    use crate::asset::Flag;
+    item.flags.intersects(Flag::LeftHand | Flag::RightHand | Flag::Worn)
+}
+
+fn move_all_items(objs: &mut Objects, from: object::Handle, to: object::Handle) {
+    let items = objs.get(from).inventory.items.clone();
+    for item in items {
+        let obj = objs.remove_from_inventory(from, item.object, item.count).unwrap();
+        objs.add_to_inventory(to, obj, item.count);
+    }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn barter_value_() {
+        assert_eq!(barter_value(100, 0, 50, 50, 0, false), 200);
+        assert_eq!(barter_value(120, 20, 50, 50, 0, false), 220);
+        assert_eq!(barter_value(100, 0, 50, 50, 0, true), 150);
+        assert_eq!(barter_value(100, 0, 50, 50, -50, false), 100);
+        assert_eq!(barter_value(100, 0, 50, 50, -200, false), 2);
+        assert_eq!(barter_value(100, 0, 0, 160, 0, false), 400);
+    }
+
+    #[test]
+    fn check_offer_() {
+        use crate::asset::map::test::*;
+        use crate::graphics::{EPoint, Point};
+        use crate::vm::test::Harness;
+
+        let mut h = Harness::new();
+        let dude = h.new_dude(EPoint::new(0, Point::new(10, 20)));
+        let npc = h.new_object(PID_CRITTER, Some(EPoint::new(0, Point::new(12, 20))));
+        let dude_table = h.new_object(PID_CONTAINER, None);
+        let npc_table = h.new_object(PID_CONTAINER, None);
+        let put = |h: &mut Harness, table, count| {
+            let item = h.new_object(PID_MISC_ITEM, None);
+            h.world.objects_mut().add_to_inventory(table, item, count);
+        };
+        put(&mut h, npc_table, 1);
+        put(&mut h, dude_table, 1);
+
+        let check = |h: &Harness, barter_mod|
+            check_offer(h.world.objects(), &h.rpg, dude, npc, dude_table, npc_table, barter_mod);
+
+        // The NPC wants twice the cost of its items when the barter skills are equal.
+        assert_eq!(check(&h, 0), OfferResult::Refused);
+        assert_eq!(check(&h, -50), OfferResult::Accepted);
+
+        put(&mut h, dude_table, 1);
+        assert_eq!(check(&h, 0), OfferResult::Accepted);
+
+        put(&mut h, dude_table, 1000);
+        assert_eq!(check(&h, 0), OfferResult::NpcOverweight);
+
+        put(&mut h, npc_table, 1000);
+        assert_eq!(check(&h, 0), OfferResult::DudeOverweight);
+    }
+}
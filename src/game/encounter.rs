@@ -0,0 +1,1093 @@
+//! Random encounters defined by the encounter tables in `data/worldmap.txt`.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use log::*;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{self, prelude::*, Error, ErrorKind};
+use std::rc::Rc;
+
+use crate::asset::{CritterAnim, Flag, ItemKind};
+use crate::asset::proto::ProtoId;
+use crate::asset::script::ProgramId;
+use crate::asset::worldmap::{invalid_data, parse_int, parse_list};
+use crate::fs::FileSystem;
+use crate::game::GameTime;
+use crate::game::object::{self, DamageFlag, EquipmentSlot};
+use crate::game::rpg::Rpg;
+use crate::game::world::World;
+use crate::graphics::EPoint;
+use crate::graphics::Point;
+use crate::graphics::geometry::hex::{self, Direction};
+use crate::util::EnumExt;
+
+/// Distance from the player to the encounter group when the group doesn't define one.
+const DEFAULT_DISTANCE: u32 = 8;
+
+/// Value compared by the encounter condition.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operand {
+    /// `Global(123)` - global script variable.
+    Global(usize),
+    /// `Player(Level)`
+    PlayerLevel,
+    /// `Days_Played`
+    DaysPlayed,
+    /// `Time_Of_Day` - time as `hhmm`.
+    TimeOfDay,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn eval(self, lhs: i32, rhs: i32) -> bool {
+        match self {
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// Single `If(...)` clause.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Condition {
+    Cmp {
+        lhs: Operand,
+        op: CmpOp,
+        rhs: i32,
+    },
+    /// `Rand(30%)` - holds with the given chance.
+    Rand(u32),
+}
+
+/// Game state the conditions are evaluated against.
+pub struct ConditionContext<'a> {
+    pub global_vars: &'a [i32],
+    pub player_level: i32,
+    pub game_time: GameTime,
+}
+
+/// Conditions that must all hold.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Conditions(pub Vec<Condition>);
+
+impl Conditions {
+    pub fn eval(&self, ctx: &ConditionContext, rng: &mut impl Rng) -> bool {
+        self.0.iter().all(|c| match *c {
+            Condition::Cmp { lhs, op, rhs } => {
+                let lhs = match lhs {
+                    Operand::Global(i) => if let Some(&v) = ctx.global_vars.get(i) {
+                        v
+                    } else {
+                        warn!("encounter condition refers to bad global var {}", i);
+                        return false;
+                    }
+                    Operand::PlayerLevel => ctx.player_level,
+                    Operand::DaysPlayed => (ctx.game_time.as_hours() / 24) as i32,
+                    Operand::TimeOfDay => {
+                        ctx.game_time.hour() as i32 * 100 + ctx.game_time.minute() as i32
+                    }
+                };
+                op.eval(lhs, rhs)
+            }
+            Condition::Rand(chance) => rng.gen_range(0, 100) < chance,
+        })
+    }
+}
+
+/// Reference to the encounter group from the encounter table entry, e.g. `(2-4) ARRO_Geckos`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GroupRef {
+    pub min_count: u32,
+    pub max_count: u32,
+    pub name: String,
+}
+
+/// Entry of the encounter table like
+/// `chance:20%, counter:1, enc:(1-3) Geckos AND (1) Rats, If(Global(123) > 1)`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub chance: u32,
+    /// How many times the encounter can happen. `None` means unlimited.
+    pub counter: Option<u32>,
+    pub special: bool,
+    /// Lookup name of the map to use instead of the table maps.
+    pub map: Option<String>,
+    pub groups: Vec<GroupRef>,
+    pub conditions: Conditions,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Table {
+    pub lookup_name: String,
+    /// Lookup names of the maps the encounter can happen on.
+    pub maps: Vec<String>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Formation {
+    Surrounding,
+    StraightLine,
+    DoubleLine,
+    Wedge,
+    Cone,
+    Huddle,
+}
+
+/// Placement of the group critters, e.g. `surrounding, spacing:3, distance:6`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Position {
+    pub formation: Formation,
+    pub spacing: u32,
+    pub distance: u32,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self {
+            formation: Formation::Surrounding,
+            spacing: 1,
+            distance: DEFAULT_DISTANCE,
+        }
+    }
+}
+
+impl Position {
+    /// Position of the `i`-th critter of the group placed in `direction` from the `center`.
+    pub fn critter_pos(&self, center: Point, direction: Direction, i: u32) -> Point {
+        let front = hex::go(center, direction, self.distance);
+        let back = direction.rotate_cw().rotate_cw().rotate_cw();
+        // Alternate sides of the line going through the front position.
+        let side = |k: u32| if k % 2 == 1 {
+            direction.rotate_ccw().rotate_ccw()
+        } else {
+            direction.rotate_cw().rotate_cw()
+        };
+        let k = i / 2 + i % 2;
+        match self.formation {
+            Formation::Surrounding => {
+                let dir = Direction::from_ordinal(
+                    (direction.ordinal() + i as usize) % Direction::len());
+                hex::go(center, dir, self.distance + i / 6 * self.spacing)
+            }
+            Formation::StraightLine => hex::go(front, side(i), k * self.spacing),
+            Formation::DoubleLine => {
+                let row = hex::go(front, direction, i % 2 * self.spacing);
+                let j = i / 2;
+                hex::go(row, side(j), (j / 2 + j % 2) * self.spacing)
+            }
+            Formation::Wedge => hex::go(hex::go(front, side(i), k * self.spacing),
+                direction, k * self.spacing),
+            Formation::Cone => hex::go(hex::go(front, side(i), k * self.spacing),
+                back, k * self.spacing),
+            Formation::Huddle => if i == 0 {
+                front
+            } else {
+                let dir = Direction::from_ordinal((i as usize - 1) % Direction::len());
+                hex::go(front, dir, ((i - 1) / 6 + 1) * self.spacing)
+            }
+        }
+    }
+}
+
+/// Item in the critter inventory, e.g. `Item:(0-10)41` or `Item:7{Wielded}`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ItemDef {
+    pub pid: ProtoId,
+    pub min_count: u32,
+    pub max_count: u32,
+    pub wielded: bool,
+}
+
+/// Kind of critter in the encounter group, e.g.
+/// `ratio:60%, pid:16777299, Item:(0-10)41, Script:123, If(Player(Level) < 5)`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CritterDef {
+    /// Percentage of the group critters of this kind. `None` means all of them.
+    pub ratio: Option<u32>,
+    pub dead: bool,
+    pub pid: ProtoId,
+    pub items: Vec<ItemDef>,
+    pub script: Option<ProgramId>,
+    pub conditions: Conditions,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Group {
+    pub position: Position,
+    pub critters: Vec<CritterDef>,
+}
+
+#[derive(Default)]
+pub struct EncounterDb {
+    tables: Vec<Table>,
+    /// Groups by lowercase name.
+    groups: HashMap<String, Group>,
+}
+
+impl EncounterDb {
+    pub fn new(fs: &FileSystem) -> io::Result<Self> {
+        Self::read(&mut fs.reader("data/worldmap.txt")?)
+    }
+
+    pub fn read(rd: &mut impl BufRead) -> io::Result<Self> {
+        let ini = crate::asset::read_ini(rd)?;
+
+        let mut tables = Vec::new();
+        while let Some(sec) = ini.get(&format!("Encounter Table {}", tables.len())) {
+            let lookup_name = sec.get("lookup_name")
+                .ok_or_else(|| invalid_data("missing key: lookup_name".into()))?
+                .trim()
+                .to_owned();
+            let maps = sec.get("maps")
+                .map(|s| s.split(',')
+                    .map(|s| s.trim())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_owned())
+                    .collect())
+                .unwrap_or_default();
+            let mut entries = Vec::new();
+            for i in 0.. {
+                let v = if let Some(v) = sec.get(&format!("enc_{:02}", i)) {
+                    v
+                } else {
+                    break;
+                };
+                match parse_entry(v) {
+                    Ok(v) => entries.push(v),
+                    Err(e) => warn!("skipping encounter table {} entry {}: {}",
+                        lookup_name, i, e),
+                }
+            }
+            tables.push(Table {
+                lookup_name,
+                maps,
+                entries,
+            });
+        }
+
+        let mut groups = HashMap::new();
+        for (name, sec) in &ini {
+            if !name.get(..10).map(|s| s.eq_ignore_ascii_case("encounter:")).unwrap_or(false) {
+                continue;
+            }
+            let group_name = name[10..].trim();
+            let position = match sec.get("position").map(|v| parse_position(v)).transpose() {
+                Ok(v) => v.unwrap_or_default(),
+                Err(e) => {
+                    warn!("skipping encounter group {}: {}", group_name, e);
+                    continue;
+                }
+            };
+            let mut critters = Vec::new();
+            for i in 0.. {
+                let v = if let Some(v) = sec.get(&format!("type_{:02}", i)) {
+                    v
+                } else {
+                    break;
+                };
+                match parse_critter(v) {
+                    Ok(v) => critters.push(v),
+                    Err(e) => warn!("skipping encounter group {} critter {}: {}",
+                        group_name, i, e),
+                }
+            }
+            groups.insert(group_name.to_ascii_lowercase(), Group {
+                position,
+                critters,
+            });
+        }
+
+        Ok(Self {
+            tables,
+            groups,
+        })
+    }
+
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    pub fn table(&self, lookup_name: &str) -> Option<(usize, &Table)> {
+        self.tables.iter()
+            .enumerate()
+            .find(|(_, t)| t.lookup_name.eq_ignore_ascii_case(lookup_name))
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.get(&name.to_ascii_lowercase())
+    }
+}
+
+/// Group of critters to place in the encounter map.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncounterGroup {
+    pub position: Position,
+    /// Definition of each critter to create.
+    pub critters: Vec<CritterDef>,
+}
+
+/// Encounter rolled from the encounter table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Encounter {
+    /// Lookup name of the encounter map.
+    pub map: String,
+    pub special: bool,
+    pub groups: Vec<EncounterGroup>,
+}
+
+impl Encounter {
+    /// Creates the encounter critters around `center` in the `world`. Returns the created
+    /// critters that have scripts to attach.
+    pub fn populate(&self,
+        center: EPoint,
+        world: &mut World,
+        rpg: &Rpg,
+        rng: &mut impl Rng,
+    ) -> Vec<(object::Handle, ProgramId)> {
+        let mut r = Vec::new();
+        let mut direction = Direction::from_ordinal(rng.gen_range(0, Direction::len()));
+        for group in &self.groups {
+            for (i, def) in group.critters.iter().enumerate() {
+                let pos = group.position.critter_pos(center.point, direction, i as u32);
+                let pos = world.hex_grid().clip(pos);
+                if let Some(objh) = Self::new_critter(def, pos.elevated(center.elevation),
+                    center.point, world, rpg, rng)
+                {
+                    if let Some(prg_id) = def.script {
+                        r.push((objh, prg_id));
+                    }
+                }
+            }
+            // Place the next group on the other side.
+            direction = direction.rotate_cw().rotate_cw();
+        }
+        r
+    }
+
+    fn new_critter(def: &CritterDef,
+        pos: EPoint,
+        look_at: Point,
+        world: &mut World,
+        rpg: &Rpg,
+        rng: &mut impl Rng,
+    ) -> Option<object::Handle> {
+        let proto = world.proto_db().proto(def.pid)
+            .map_err(|e| warn!("error loading encounter critter proto {:?}: {:?}", def.pid, e))
+            .ok()?;
+        let fid = proto.borrow().fid;
+        let objh = world.new_object(fid, Some(proto), Some(pos), rpg);
+        if pos.point != look_at {
+            world.objects_mut().get_mut(objh).direction = hex::direction(pos.point, look_at);
+        }
+
+        for item in &def.items {
+            let count = rng.gen_range(item.min_count, item.max_count + 1);
+            if count == 0 {
+                continue;
+            }
+            let proto = if let Ok(v) = world.proto_db().proto(item.pid) {
+                v
+            } else {
+                warn!("error loading encounter item proto {:?}", item.pid);
+                continue;
+            };
+            let fid = proto.borrow().fid;
+            let itemh = world.new_object(fid, Some(proto), None, rpg);
+            let objs = world.objects_mut();
+            let itemh = objs.add_to_inventory(objh, itemh, count);
+            if item.wielded {
+                let slot = if objs.get(itemh).item_kind() == Some(ItemKind::Armor) {
+                    EquipmentSlot::Armor
+                } else {
+                    EquipmentSlot::RightHand
+                };
+                objs.equip(objh, itemh, slot);
+            }
+        }
+
+        if def.dead {
+            let mut obj = world.objects().get_mut(objh);
+            if let Some(fid) = obj.fid.critter() {
+                obj.fid = fid.with_anim(CritterAnim::FallBackSf).into();
+            }
+            obj.flags.insert(Flag::NoBlock);
+            if let Some(critter) = obj.sub.as_critter_mut() {
+                critter.hit_points = 0;
+                critter.combat.damage_flags |= DamageFlag::Dead;
+            }
+        }
+
+        Some(objh)
+    }
+}
+
+/// Remaining counts of the encounter table entries with limited count keyed by table and entry
+/// index.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EncounterCounters(BTreeMap<(usize, usize), u32>);
+
+impl EncounterCounters {
+    pub fn read(rd: &mut impl Read) -> io::Result<Self> {
+        let mut r = BTreeMap::new();
+        let count = rd.read_i32::<BigEndian>()?;
+        for _ in 0..count {
+            let table = rd.read_u32::<BigEndian>()? as usize;
+            let entry = rd.read_u32::<BigEndian>()? as usize;
+            let remaining = rd.read_u32::<BigEndian>()?;
+            r.insert((table, entry), remaining);
+        }
+        Ok(Self(r))
+    }
+
+    pub fn write(&self, wr: &mut impl Write) -> io::Result<()> {
+        let to_u32 = |v: usize| u32::try_from(v)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "encounter index is too big"));
+        let count = i32::try_from(self.0.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "too many encounter counters"))?;
+        wr.write_i32::<BigEndian>(count)?;
+        for (&(table, entry), &remaining) in &self.0 {
+            wr.write_u32::<BigEndian>(to_u32(table)?)?;
+            wr.write_u32::<BigEndian>(to_u32(entry)?)?;
+            wr.write_u32::<BigEndian>(remaining)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rolls random encounters keeping track of the encounters with limited count.
+pub struct Encounters {
+    db: Rc<EncounterDb>,
+    counters: EncounterCounters,
+}
+
+impl Encounters {
+    pub fn new(db: Rc<EncounterDb>) -> Self {
+        Self {
+            db,
+            counters: EncounterCounters::default(),
+        }
+    }
+
+    pub fn counters(&self) -> &EncounterCounters {
+        &self.counters
+    }
+
+    pub fn set_counters(&mut self, counters: EncounterCounters) {
+        self.counters = counters;
+    }
+
+    pub fn db(&self) -> &Rc<EncounterDb> {
+        &self.db
+    }
+
+    /// Picks the encounter from the table with `lookup_name` among the entries whose conditions
+    /// hold. The entries are weighted by their chances.
+    pub fn roll(&mut self, table: &str, ctx: &ConditionContext, rng: &mut impl Rng)
+        -> Option<Encounter>
+    {
+        let (table_idx, table) = if let Some(v) = self.db.table(table) {
+            v
+        } else {
+            warn!("unknown encounter table: {}", table);
+            return None;
+        };
+
+        let mut eligible = Vec::new();
+        for (i, entry) in table.entries.iter().enumerate() {
+            let remaining = entry.counter
+                .map(|c| *self.counters.0.get(&(table_idx, i)).unwrap_or(&c));
+            if entry.chance > 0 && remaining != Some(0) && entry.conditions.eval(ctx, rng) {
+                eligible.push(i);
+            }
+        }
+        let total: u32 = eligible.iter().map(|&i| table.entries[i].chance).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = rng.gen_range(0, total);
+        let entry_idx = eligible.into_iter()
+            .find(|&i| {
+                let chance = table.entries[i].chance;
+                if roll < chance {
+                    true
+                } else {
+                    roll -= chance;
+                    false
+                }
+            })
+            .unwrap();
+        let entry = &table.entries[entry_idx];
+
+        if let Some(counter) = entry.counter {
+            let remaining = self.counters.0.entry((table_idx, entry_idx)).or_insert(counter);
+            *remaining -= 1;
+        }
+
+        let map = if let Some(map) = &entry.map {
+            map.clone()
+        } else if !table.maps.is_empty() {
+            table.maps[rng.gen_range(0, table.maps.len())].clone()
+        } else {
+            warn!("encounter table {} has no maps", table.lookup_name);
+            return None;
+        };
+
+        let mut groups = Vec::new();
+        for group_ref in &entry.groups {
+            let group = if let Some(v) = self.db.group(&group_ref.name) {
+                v
+            } else {
+                warn!("unknown encounter group: {}", group_ref.name);
+                continue;
+            };
+            let count = rng.gen_range(group_ref.min_count, group_ref.max_count + 1);
+            groups.push(EncounterGroup {
+                position: group.position,
+                critters: Self::pick_critters(group, count, ctx, rng),
+            });
+        }
+
+        Some(Encounter {
+            map,
+            special: entry.special,
+            groups,
+        })
+    }
+
+    /// Splits `count` critters between the group critter kinds by their ratios. The count left
+    /// after rounding goes to the kinds in order.
+    fn pick_critters(group: &Group, count: u32, ctx: &ConditionContext, rng: &mut impl Rng)
+        -> Vec<CritterDef>
+    {
+        let defs: Vec<_> = group.critters.iter()
+            .filter(|c| c.conditions.eval(ctx, rng))
+            .collect();
+        if defs.is_empty() {
+            return Vec::new();
+        }
+        let mut counts: Vec<_> = defs.iter()
+            .map(|c| count * c.ratio.unwrap_or(100) / 100)
+            .collect();
+        let left = count.saturating_sub(counts.iter().sum());
+        for i in 0..left as usize {
+            let len = counts.len();
+            counts[i % len] += 1;
+        }
+
+        let mut r = Vec::with_capacity(count as usize);
+        for (def, n) in defs.into_iter().zip(counts) {
+            for _ in 0..n {
+                r.push(def.clone());
+            }
+        }
+        // Ratios may add up to more than 100%.
+        r.truncate(count as usize);
+        r
+    }
+}
+
+/// Parses count like `(2-4)` or `(3)`.
+fn parse_count(s: &str) -> io::Result<(u32, u32)> {
+    let s = s.trim();
+    let inner = s.strip_prefix('(').and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| invalid_data(format!("bad count: {}", s)))?;
+    let mut parts = inner.splitn(2, '-');
+    let min = parse_int(parts.next().unwrap())?;
+    let max = parts.next().map(parse_int).transpose()?.unwrap_or(min);
+    if min > max {
+        return Err(invalid_data(format!("bad count: {}", s)));
+    }
+    Ok((min, max))
+}
+
+fn parse_percent(s: &str) -> io::Result<u32> {
+    parse_int(s.trim().trim_end_matches('%'))
+}
+
+fn parse_pid(s: &str) -> io::Result<ProtoId> {
+    ProtoId::from_packed(parse_int(s)?)
+        .ok_or_else(|| invalid_data(format!("bad pid: {}", s)))
+}
+
+fn is_if(s: &str) -> bool {
+    s.get(..3).map(|s| s.eq_ignore_ascii_case("if(")).unwrap_or(false)
+}
+
+/// Parses conditions like `If(Global(123) > 1) And If(Rand(30%))`.
+fn parse_conditions(s: &str, out: &mut Conditions) -> io::Result<()> {
+    let bad = || invalid_data(format!("bad condition: {}", s));
+    let lower = s.to_ascii_lowercase();
+    for clause in lower.split(" and ") {
+        let clause = clause.trim();
+        let clause = clause.strip_prefix("if(").and_then(|s| s.strip_suffix(')'))
+            .ok_or_else(bad)?
+            .trim();
+        if let Some(v) = clause.strip_prefix("rand(").and_then(|s| s.strip_suffix(')')) {
+            out.0.push(Condition::Rand(parse_percent(v)?));
+            continue;
+        }
+        let (i, op, op_len) = [
+            ("==", CmpOp::Eq), ("!=", CmpOp::Ne), ("<=", CmpOp::Le), (">=", CmpOp::Ge),
+            ("<", CmpOp::Lt), (">", CmpOp::Gt),
+        ].iter()
+            .find_map(|&(tok, op)| clause.find(tok).map(|i| (i, op, tok.len())))
+            .ok_or_else(bad)?;
+        let lhs = clause[..i].trim();
+        let lhs = if let Some(v) = lhs.strip_prefix("global(").and_then(|s| s.strip_suffix(')')) {
+            Operand::Global(parse_int(v)?)
+        } else {
+            match lhs {
+                "player(level)" => Operand::PlayerLevel,
+                "days_played" => Operand::DaysPlayed,
+                "time_of_day" => Operand::TimeOfDay,
+                _ => return Err(bad()),
+            }
+        };
+        let rhs = parse_int(&clause[i + op_len..])?;
+        out.0.push(Condition::Cmp { lhs, op, rhs });
+    }
+    Ok(())
+}
+
+/// Parses encounter table entry like
+/// `chance:20%, counter:1, enc:(1-3) Geckos AND (1) Rats, If(Global(123) > 1)`.
+fn parse_entry(s: &str) -> io::Result<Entry> {
+    let mut r = Entry {
+        chance: 0,
+        counter: None,
+        special: false,
+        map: None,
+        groups: Vec::new(),
+        conditions: Conditions::default(),
+    };
+    for (part, (k, v)) in s.split(',').zip(parse_list(s)) {
+        if is_if(k) {
+            parse_conditions(part, &mut r.conditions)?;
+            continue;
+        }
+        match k.to_ascii_lowercase().as_str() {
+            "chance" => r.chance = parse_percent(v)?,
+            "counter" => {
+                let v: i32 = parse_int(v)?;
+                r.counter = if v >= 0 { Some(v as u32) } else { None };
+            }
+            "special" => r.special = true,
+            "map" => r.map = Some(v.to_owned()),
+            "enc" => {
+                let mut words = v.split_whitespace();
+                while let Some(word) = words.next() {
+                    if word.eq_ignore_ascii_case("and") || word.eq_ignore_ascii_case("fighting") {
+                        continue;
+                    }
+                    let (min_count, max_count) = parse_count(word)?;
+                    let name = words.next()
+                        .ok_or_else(|| invalid_data(format!("bad encounter: {}", v)))?;
+                    r.groups.push(GroupRef {
+                        min_count,
+                        max_count,
+                        name: name.into(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(r)
+}
+
+/// Parses encounter group position like `surrounding, spacing:3, distance:6`.
+fn parse_position(s: &str) -> io::Result<Position> {
+    let mut r = Position::default();
+    for (k, v) in parse_list(s) {
+        match k.to_ascii_lowercase().as_str() {
+            "surrounding" => r.formation = Formation::Surrounding,
+            "straight_line" => r.formation = Formation::StraightLine,
+            "double_line" => r.formation = Formation::DoubleLine,
+            "wedge" => r.formation = Formation::Wedge,
+            "cone" => r.formation = Formation::Cone,
+            "huddle" => r.formation = Formation::Huddle,
+            "spacing" => r.spacing = parse_int(v)?,
+            "distance" => r.distance = parse_int(v)?,
+            _ => warn!("ignoring unknown encounter position key {} in: {}", k, s),
+        }
+    }
+    Ok(r)
+}
+
+/// Parses encounter group critter like
+/// `ratio:60%, Dead, pid:16777299, Item:(0-10)41, Item:7{Wielded}, Script:123, If(...)`.
+fn parse_critter(s: &str) -> io::Result<CritterDef> {
+    let mut ratio = None;
+    let mut dead = false;
+    let mut pid = None;
+    let mut items = Vec::new();
+    let mut script = None;
+    let mut conditions = Conditions::default();
+    for (part, (k, v)) in s.split(',').zip(parse_list(s)) {
+        if is_if(k) {
+            parse_conditions(part, &mut conditions)?;
+            continue;
+        }
+        match k.to_ascii_lowercase().as_str() {
+            "ratio" => ratio = Some(parse_percent(v)?),
+            "dead" => dead = true,
+            "pid" => pid = Some(parse_pid(v)?),
+            "item" => {
+                let (v, wielded) = if let Some(i) = v.find('{') {
+                    (&v[..i], v[i..].eq_ignore_ascii_case("{wielded}"))
+                } else {
+                    (v, false)
+                };
+                let (min_count, max_count, v) = if let Some(i) = v.find(')') {
+                    let (min, max) = parse_count(&v[..=i])?;
+                    (min, max, &v[i + 1..])
+                } else {
+                    (1, 1, v)
+                };
+                items.push(ItemDef {
+                    pid: parse_pid(v)?,
+                    min_count,
+                    max_count,
+                    wielded,
+                });
+            }
+            "script" => {
+                let v: i32 = parse_int(v)?;
+                script = if v >= 0 {
+                    Some(ProgramId::new(v as u32)
+                        .ok_or_else(|| invalid_data(format!("bad script: {}", v)))?)
+                } else {
+                    None
+                };
+            }
+            _ => {}
+        }
+    }
+    Ok(CritterDef {
+        ratio,
+        dead,
+        pid: pid.ok_or_else(|| invalid_data(format!("missing pid: {}", s)))?,
+        items,
+        script,
+        conditions,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::io::{BufReader, Cursor};
+    use std::time::Instant;
+
+    use super::*;
+    use crate::asset::map::test::Fixture;
+    use crate::asset::message::Messages;
+    use crate::graphics::Rect;
+    use crate::graphics::font::Fonts;
+
+    const WORLDMAP_TXT: &str = "
+[Encounter Table 0]
+lookup_name=Desert_Table
+maps=Desert Encounter 1, Desert Encounter 2
+enc_00=chance:30%, enc:(2-4) Geckos
+enc_01=chance:10%, counter:1, special, map:Special Cafe, enc:(1) Cafe, If(Global(3) == 0)
+enc_02=chance:20%, enc:(3) Raiders FIGHTING (1-2) Geckos, If(Player(Level) > 5) And If(Rand(100%))
+enc_03=chance:0%, enc:(1) Geckos
+
+[Encounter: Geckos]
+type_00=pid:16777217
+position=huddle, spacing:2
+
+[Encounter: Cafe]
+type_00=pid:16777217, Script:1
+position=straight_line, spacing:1, distance:3
+
+[Encounter: Raiders]
+type_00=ratio:60%, pid:16777217, Item:(0-10)2, Item:3{Wielded}, Script:1
+type_01=ratio:40%, Dead, pid:16777217, If(Time_Of_Day >= 1800)
+position=wedge, spacing:3, distance:6
+";
+
+    fn new_db() -> EncounterDb {
+        EncounterDb::read(&mut BufReader::new(Cursor::new(WORLDMAP_TXT))).unwrap()
+    }
+
+    fn ctx(global_vars: &[i32], player_level: i32, hour: u32) -> ConditionContext<'_> {
+        ConditionContext {
+            global_vars,
+            player_level,
+            game_time: GameTime::from_decis(((24 + hour) * 60 * 60) * 10),
+        }
+    }
+
+    #[test]
+    fn read() {
+        let db = new_db();
+        let pid = ProtoId::from_packed(0x1000001).unwrap();
+
+        assert_eq!(db.tables().len(), 1);
+        let (_, table) = db.table("desert_table").unwrap();
+        assert_eq!(table.maps, &["Desert Encounter 1", "Desert Encounter 2"]);
+        assert_eq!(table.entries.len(), 4);
+        assert_eq!(table.entries[1], Entry {
+            chance: 10,
+            counter: Some(1),
+            special: true,
+            map: Some("Special Cafe".into()),
+            groups: vec![GroupRef { min_count: 1, max_count: 1, name: "Cafe".into() }],
+            conditions: Conditions(vec![Condition::Cmp {
+                lhs: Operand::Global(3),
+                op: CmpOp::Eq,
+                rhs: 0,
+            }]),
+        });
+        assert_eq!(table.entries[2].groups, &[
+            GroupRef { min_count: 3, max_count: 3, name: "Raiders".into() },
+            GroupRef { min_count: 1, max_count: 2, name: "Geckos".into() },
+        ]);
+        assert_eq!(table.entries[2].conditions, Conditions(vec![
+            Condition::Cmp { lhs: Operand::PlayerLevel, op: CmpOp::Gt, rhs: 5 },
+            Condition::Rand(100),
+        ]));
+
+        let raiders = db.group("RAIDERS").unwrap();
+        assert_eq!(raiders.position, Position {
+            formation: Formation::Wedge,
+            spacing: 3,
+            distance: 6,
+        });
+        assert_eq!(raiders.critters, &[
+            CritterDef {
+                ratio: Some(60),
+                dead: false,
+                pid,
+                items: vec![
+                    ItemDef {
+                        pid: ProtoId::from_packed(2).unwrap(),
+                        min_count: 0,
+                        max_count: 10,
+                        wielded: false,
+                    },
+                    ItemDef {
+                        pid: ProtoId::from_packed(3).unwrap(),
+                        min_count: 1,
+                        max_count: 1,
+                        wielded: true,
+                    },
+                ],
+                script: ProgramId::new(1),
+                conditions: Conditions::default(),
+            },
+            CritterDef {
+                ratio: Some(40),
+                dead: true,
+                pid,
+                items: vec![],
+                script: None,
+                conditions: Conditions(vec![Condition::Cmp {
+                    lhs: Operand::TimeOfDay,
+                    op: CmpOp::Ge,
+                    rhs: 1800,
+                }]),
+            },
+        ]);
+        assert_eq!(db.group("Geckos").unwrap().position.distance, DEFAULT_DISTANCE);
+
+        assert!(EncounterDb::read(&mut BufReader::new(Cursor::new(
+            "[Encounter Table 0]\nenc_00=chance:5%"))).is_err());
+    }
+
+    #[test]
+    fn unknown_tokens() {
+        let db = EncounterDb::read(&mut BufReader::new(Cursor::new("
+[Encounter Table 0]
+lookup_name=Table
+maps=Map
+enc_00=chance:10%, enc:(1) Geckos, If(Player(Karma) > 5)
+enc_01=chance:20%, enc:(1) Geckos
+
+[Encounter: Geckos]
+type_00=pid:16777217, If(Unknown(1) < 2)
+type_01=pid:16777217
+position=huddle, facing:north
+
+[Encounter: Rats]
+type_00=pid:16777217
+position=huddle, spacing:far
+"))).unwrap();
+
+        let (_, table) = db.table("table").unwrap();
+        assert_eq!(table.entries.len(), 1);
+        assert_eq!(table.entries[0].chance, 20);
+
+        let geckos = db.group("geckos").unwrap();
+        assert_eq!(geckos.position.formation, Formation::Huddle);
+        assert_eq!(geckos.critters.len(), 1);
+        assert!(geckos.critters[0].conditions.0.is_empty());
+
+        assert!(db.group("rats").is_none());
+    }
+
+    #[test]
+    fn conditions() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut conds = Conditions::default();
+        parse_conditions("If(Global(1) != 2) And if(Days_Played < 3)", &mut conds).unwrap();
+        assert!(conds.eval(&ctx(&[0, 1], 1, 12), rng));
+        assert!(!conds.eval(&ctx(&[0, 2], 1, 12), rng));
+        // Out of range global var.
+        assert!(!conds.eval(&ctx(&[0], 1, 12), rng));
+        // Day 3.
+        assert!(!conds.eval(&ctx(&[0, 1], 1, 48), rng));
+
+        let mut conds = Conditions::default();
+        parse_conditions("If(Time_Of_Day <= 630)", &mut conds).unwrap();
+        assert!(conds.eval(&ctx(&[], 1, 6), rng));
+        assert!(!conds.eval(&ctx(&[], 1, 7), rng));
+
+        let conds = Conditions(vec![Condition::Rand(30)]);
+        let hits = (0..1000).filter(|_| conds.eval(&ctx(&[], 1, 0), rng)).count();
+        assert!(hits > 250 && hits < 350, "{}", hits);
+        assert!(!Conditions(vec![Condition::Rand(0)]).eval(&ctx(&[], 1, 0), rng));
+    }
+
+    #[test]
+    fn roll() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut encs = Encounters::new(Rc::new(new_db()));
+        let globals = &[0, 0, 0, 0];
+
+        assert!(encs.roll("Unknown", &ctx(globals, 1, 12), rng).is_none());
+
+        // The special encounter can happen only once.
+        let mut specials = 0;
+        for _ in 0..100 {
+            let enc = encs.roll("Desert_Table", &ctx(globals, 1, 12), rng).unwrap();
+            if enc.special {
+                specials += 1;
+                assert_eq!(enc.map, "Special Cafe");
+                assert_eq!(enc.groups.len(), 1);
+                assert_eq!(enc.groups[0].critters.len(), 1);
+            } else {
+                assert!(enc.map.starts_with("Desert Encounter "));
+                assert_eq!(enc.groups.len(), 1);
+                let count = enc.groups[0].critters.len();
+                assert!((2..=4).contains(&count), "{}", count);
+            }
+        }
+        assert_eq!(specials, 1);
+
+        // Used up encounters stay used up after the counters are saved and restored.
+        let mut data = Vec::new();
+        encs.counters().write(&mut data).unwrap();
+        let mut restored = Encounters::new(encs.db().clone());
+        restored.set_counters(EncounterCounters::read(&mut &data[..]).unwrap());
+        assert_eq!(restored.counters(), encs.counters());
+        assert!((0..100).all(|_| !restored.roll("Desert_Table", &ctx(globals, 1, 12), rng)
+            .unwrap().special));
+
+        // Raiders become eligible at higher levels and kind ratios depend on time of day.
+        let mut raiders = 0;
+        for _ in 0..100 {
+            let enc = encs.roll("Desert_Table", &ctx(globals, 10, 20), rng).unwrap();
+            if enc.groups.len() == 2 {
+                raiders += 1;
+                let critters = &enc.groups[0].critters;
+                assert_eq!(critters.len(), 3);
+                assert_eq!(critters.iter().filter(|c| c.dead).count(), 1);
+                let count = enc.groups[1].critters.len();
+                assert!((1..=2).contains(&count), "{}", count);
+            }
+        }
+        assert!(raiders > 20 && raiders < 60, "{}", raiders);
+
+        let mut encs = Encounters::new(Rc::new(new_db()));
+        for _ in 0..20 {
+            let enc = encs.roll("Desert_Table", &ctx(globals, 10, 12), rng).unwrap();
+            if enc.groups.len() == 2 {
+                assert!(enc.groups[0].critters.iter().all(|c| !c.dead && c.script.is_some()));
+            }
+        }
+    }
+
+    #[test]
+    fn populate() {
+        let fx = Fixture::new("encounter-populate");
+        let rpg = fx.new_rpg();
+        let mut world = World::new(fx.proto_db.clone(), fx.frm_db.clone(),
+            Messages::read(&mut &b""[..]).unwrap(), hex::TileGrid::default(),
+            Rect::with_size(0, 0, 640, 380), Instant::now(), Rc::new(Fonts::new()));
+
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut encs = Encounters::new(Rc::new(new_db()));
+        let enc = (0..100)
+            .filter_map(|_| encs.roll("Desert_Table", &ctx(&[0; 4], 10, 20), rng))
+            .find(|e| e.groups.len() == 2)
+            .unwrap();
+        let center = EPoint::new(1, Point::new(100, 100));
+        let scripted = enc.populate(center, &mut world, &rpg, rng);
+
+        let objs = world.objects();
+        let critters: Vec<_> = objs.iter()
+            .filter(|&h| objs.get(h).sub.as_critter().is_some())
+            .collect();
+        let count = enc.groups.iter().map(|g| g.critters.len()).sum::<usize>();
+        assert_eq!(critters.len(), count);
+        assert_eq!(scripted.len(), 2);
+        assert!(scripted.iter().all(|&(_, prg_id)| prg_id == ProgramId::new(1).unwrap()));
+
+        let mut dead = 0;
+        for &h in &critters {
+            let obj = objs.get(h);
+            assert_eq!(obj.pos.unwrap().elevation, 1);
+            assert_ne!(obj.pos.unwrap().point, center.point);
+            if obj.sub.as_critter().unwrap().is_dead() {
+                dead += 1;
+                assert!(obj.flags.contains(Flag::NoBlock));
+            }
+        }
+        assert_eq!(dead, 1);
+
+        for &(h, _) in &scripted {
+            let weapon = objs.get(h).in_right_hand(objs);
+            assert_eq!(objs.get(weapon.unwrap()).proto_id(), ProtoId::from_packed(3));
+        }
+    }
+
+    #[test]
+    fn critter_pos() {
+        let center = Point::new(100, 100);
+        let pos = |formation, i| Position {
+            formation,
+            spacing: 2,
+            distance: 4,
+        }.critter_pos(center, Direction::E, i);
+
+        assert_eq!(pos(Formation::StraightLine, 0), hex::go(center, Direction::E, 4));
+        for &formation in &[Formation::Surrounding, Formation::StraightLine,
+            Formation::DoubleLine, Formation::Wedge, Formation::Cone, Formation::Huddle]
+        {
+            let positions: Vec<_> = (0..8).map(|i| pos(formation, i)).collect();
+            for (i, p) in positions.iter().enumerate() {
+                assert!(!positions[i + 1..].contains(p), "{:?} {}", formation, i);
+                assert_ne!(*p, center);
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::asset::ItemKind;
+use crate::asset::frame::FrameId;
+use crate::asset::proto::ProtoId;
+use crate::game::object::{self, EquipmentSlot, Objects};
+use crate::game::ui::inventory_list::{self, InventoryList};
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::GREEN;
+use crate::graphics::font::FontKey;
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::{InventoryCommand, UiCommandData};
+use crate::ui::panel::{self, Panel};
+
+const TEXT_FONT: FontKey = FontKey::antialiased(1);
+
+const LIST_ITEM_HEIGHT: i32 = 48;
+const LIST_VISIBLE_COUNT: usize = 6;
+
+struct Widgets {
+    window: Handle,
+    list: Handle,
+    slots: [(EquipmentSlot, Handle); 3],
+    info: Handle,
+}
+
+/// Inventory screen of the player's character.
+pub struct Inventory {
+    widgets: Option<Widgets>,
+    owner: Option<object::Handle>,
+    carry_weight: i32,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self {
+            widgets: None,
+            owner: None,
+            carry_weight: 0,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.widgets.is_some()
+    }
+
+    pub fn show(&mut self,
+        ui: &mut Ui,
+        objs: &Objects,
+        owner: object::Handle,
+        carry_weight: i32,
+    ) {
+        assert!(self.widgets.is_none());
+
+        let window = ui.new_window(Rect::with_size(80, 0, 499, 377),
+            Some(Sprite::new(FrameId::INVBOX)));
+        ui.set_modal_window(Some(window));
+
+        let list = ui.new_widget(window,
+            Rect::with_size(44, 35, 64, LIST_ITEM_HEIGHT * LIST_VISIBLE_COUNT as i32),
+            None, None, InventoryList::new(LIST_ITEM_HEIGHT));
+
+        ui.new_widget(window, Rect::with_size(128, 39, 22, 23), None, None,
+            Button::new(FrameId::INVUPOUT, FrameId::INVUPIN,
+                Some(UiCommandData::Inventory(InventoryCommand::Scroll { up: true }))));
+        ui.new_widget(window, Rect::with_size(128, 62, 22, 23), None, None,
+            Button::new(FrameId::INVDNOUT, FrameId::INVDNIN,
+                Some(UiCommandData::Inventory(InventoryCommand::Scroll { up: false }))));
+
+        let mut new_slot = |slot, pos: Point| {
+            (slot, ui.new_widget(window, Rect::with_size(pos.x, pos.y, 90, 61),
+                None, None, InventoryList::new(61)))
+        };
+        let slots = [
+            new_slot(EquipmentSlot::Armor, Point::new(154, 183)),
+            new_slot(EquipmentSlot::LeftHand, Point::new(154, 286)),
+            new_slot(EquipmentSlot::RightHand, Point::new(245, 286)),
+        ];
+
+        let info = ui.new_widget(window, Rect::with_size(297, 44, 152, 188), None, None,
+            Panel::new());
+
+        ui.new_widget(window, Rect::with_size(437, 329, 15, 16), None, None,
+            Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                Some(UiCommandData::Inventory(InventoryCommand::Hide))));
+
+        self.widgets = Some(Widgets {
+            window,
+            list,
+            slots,
+            info,
+        });
+        self.owner = Some(owner);
+        self.carry_weight = carry_weight;
+
+        self.sync(ui, objs);
+    }
+
+    pub fn hide(&mut self, ui: &mut Ui) {
+        let widgets = self.widgets.take().unwrap();
+        ui.remove(widgets.window);
+        self.owner = None;
+    }
+
+    pub fn scroll(&mut self, ui: &mut Ui, up: bool) {
+        let list = self.widgets.as_ref().unwrap().list;
+        ui.widget_mut::<InventoryList>(list).scroll(up, LIST_VISIBLE_COUNT);
+    }
+
+    /// Handles item dragged from the `source` list widget and dropped at `pos`. Equips or
+    /// unequips the item depending on where it's been dropped.
+    pub fn drop(&mut self,
+        ui: &mut Ui,
+        objs: &mut Objects,
+        source: Handle,
+        item: object::Handle,
+        pos: Point,
+    ) {
+        let widgets = self.widgets.as_ref().unwrap();
+        let owner = self.owner.unwrap();
+        let target = Some(widgets.list).into_iter()
+            .chain(widgets.slots.iter().map(|&(_, h)| h))
+            .find(|&h| ui.widget_base(h).borrow().rect().contains(pos));
+        match target {
+            Some(target) if target == source => {}
+            Some(target) if target == widgets.list => objs.unequip(owner, item),
+            Some(target) => {
+                let slot = widgets.slots.iter().find(|&&(_, h)| h == target).unwrap().0;
+                let is_armor = objs.get(item).item_kind() == Some(ItemKind::Armor);
+                if slot != EquipmentSlot::Armor || is_armor {
+                    objs.equip(owner, item, slot);
+                }
+            }
+            None => {}
+        }
+        self.sync(ui, objs);
+    }
+
+    /// Updates the widgets from the owner's inventory.
+    pub fn sync(&mut self, ui: &mut Ui, objs: &Objects) {
+        let widgets = self.widgets.as_ref().unwrap();
+        let owner = self.owner.unwrap();
+        let ownero = objs.get(owner);
+
+        let mut list_items = Vec::new();
+        let mut slot_items = [None; 3];
+        for inv_item in &ownero.inventory.items {
+            let itemo = objs.get(inv_item.object);
+            let item = inventory_list::Item::new(inv_item, objs);
+            let slot = widgets.slots.iter()
+                .position(|&(slot, _)| itemo.flags.contains(slot.flag()));
+            if let Some(slot) = slot {
+                slot_items[slot] = Some(item);
+            } else {
+                list_items.push(item);
+            }
+        }
+        ui.widget_mut::<InventoryList>(widgets.list).set_items(list_items);
+        for (&(_, h), item) in widgets.slots.iter().zip(slot_items.iter()) {
+            ui.widget_mut::<InventoryList>(h).set_items(item.iter().cloned().collect());
+        }
+
+        // TODO Use labels from inventry.msg and show the owner's stats.
+        let weight = ownero.inventory.weight(objs);
+        let caps = objs.inventory_count(owner, ProtoId::BOTTLE_CAPS);
+        ui.widget_mut::<Panel>(widgets.info).set_text(Some(panel::Text {
+            text: format!("Total Wt: {}/{}\nCaps: {}", weight, self.carry_weight, caps).into(),
+            font: TEXT_FONT,
+            color: GREEN,
+            options: Default::default(),
+        }));
+    }
+}
@@ -0,0 +1,465 @@
+use crate::asset::Flag;
+use crate::asset::frame::FrameId;
+use crate::game::object::{self, Objects};
+use crate::game::ui::inventory_list::{self, InventoryList};
+use crate::game::world::World;
+use crate::graphics::{Point, Rect};
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::button::Button;
+use crate::ui::command::{LootCommand, QuantityCommand, UiCommandData};
+use crate::ui::image_text::ImageText;
+use crate::vm::PredefinedProc;
+
+const LIST_ITEM_HEIGHT: i32 = 48;
+const LIST_VISIBLE_COUNT: usize = 6;
+
+/// Request to move `count` items of the `item` stack from `from` to `to` inventory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Transfer {
+    pub item: object::Handle,
+    pub count: u32,
+    pub from: object::Handle,
+    pub to: object::Handle,
+}
+
+struct Widgets {
+    window: Handle,
+    /// Inventory lists of the looter and the container.
+    lists: [Handle; 2],
+}
+
+struct Quantity {
+    window: Handle,
+    text: Handle,
+    value: u32,
+    transfer: Transfer,
+}
+
+/// Window for moving items between the player's inventory and a container or a dead critter.
+pub struct Loot {
+    widgets: Option<Widgets>,
+    quantity: Option<Quantity>,
+    looter: Option<object::Handle>,
+    container: Option<object::Handle>,
+}
+
+impl Loot {
+    pub fn new() -> Self {
+        Self {
+            widgets: None,
+            quantity: None,
+            looter: None,
+            container: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.widgets.is_some()
+    }
+
+    pub fn container(&self) -> Option<object::Handle> {
+        self.container
+    }
+
+    /// Shows the window unless the `container` is locked. Returns `false` if it's locked.
+    pub fn show(&mut self,
+        ui: &mut Ui,
+        objs: &Objects,
+        looter: object::Handle,
+        container: object::Handle,
+    ) -> bool {
+        assert!(self.widgets.is_none());
+
+        if objs.get(container).is_locked() == Some(true) {
+            return false;
+        }
+
+        let window = ui.new_window(Rect::with_size(80, 0, 537, 376),
+            Some(Sprite::new(FrameId::LOOT)));
+        ui.set_modal_window(Some(window));
+
+        let mut new_list = |x| {
+            ui.new_widget(window,
+                Rect::with_size(x, 37, 64, LIST_ITEM_HEIGHT * LIST_VISIBLE_COUNT as i32),
+                None, None, InventoryList::new(LIST_ITEM_HEIGHT))
+        };
+        let lists = [new_list(176), new_list(297)];
+
+        for &(container, x) in &[(false, 128), (true, 379)] {
+            for &(up, y, fid_up, fid_down) in &[
+                (true, 39, FrameId::INVUPOUT, FrameId::INVUPIN),
+                (false, 62, FrameId::INVDNOUT, FrameId::INVDNIN),
+            ] {
+                ui.new_widget(window, Rect::with_size(x, y, 22, 23), None, None,
+                    Button::new(fid_up, fid_down,
+                        Some(UiCommandData::Loot(LootCommand::Scroll { container, up }))));
+            }
+        }
+
+        ui.new_widget(window, Rect::with_size(432, 204, 39, 41), None, None,
+            Button::new(FrameId::INVMAUP, FrameId::INVMADN,
+                Some(UiCommandData::Loot(LootCommand::TakeAll))));
+
+        ui.new_widget(window, Rect::with_size(476, 331, 15, 16), None, None,
+            Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                Some(UiCommandData::Loot(LootCommand::Hide))));
+
+        self.widgets = Some(Widgets {
+            window,
+            lists,
+        });
+        self.looter = Some(looter);
+        self.container = Some(container);
+
+        self.sync(ui, objs);
+
+        true
+    }
+
+    pub fn hide(&mut self, ui: &mut Ui) {
+        self.hide_quantity(ui);
+        let widgets = self.widgets.take().unwrap();
+        ui.remove(widgets.window);
+        self.looter = None;
+        self.container = None;
+    }
+
+    pub fn scroll(&mut self, ui: &mut Ui, container: bool, up: bool) {
+        let list = self.widgets.as_ref().unwrap().lists[container as usize];
+        ui.widget_mut::<InventoryList>(list).scroll(up, LIST_VISIBLE_COUNT);
+    }
+
+    /// Handles item dragged from the `source` list widget and dropped at `pos`.
+    /// If the item is a stack, shows the quantity picker and returns `None`.
+    pub fn drop(&mut self,
+        ui: &mut Ui,
+        objs: &Objects,
+        source: Handle,
+        item: object::Handle,
+        pos: Point,
+    ) -> Option<Transfer> {
+        let widgets = self.widgets.as_ref().unwrap();
+        let owners = [self.looter.unwrap(), self.container.unwrap()];
+        let src = widgets.lists.iter().position(|&h| h == source)?;
+        let dst = widgets.lists.iter()
+            .position(|&h| ui.widget_base(h).borrow().rect().contains(pos))?;
+        if src == dst {
+            return None;
+        }
+        let count = objs.get(owners[src]).inventory.items.iter()
+            .find(|i| i.object == item)?
+            .count;
+        let transfer = Transfer {
+            item,
+            count,
+            from: owners[src],
+            to: owners[dst],
+        };
+        if count > 1 {
+            self.show_quantity(ui, transfer);
+            None
+        } else {
+            Some(transfer)
+        }
+    }
+
+    /// Returns transfers that move all items from the container to the looter.
+    pub fn take_all(&self, objs: &Objects) -> Vec<Transfer> {
+        let looter = self.looter.unwrap();
+        let container = self.container.unwrap();
+        objs.get(container).inventory.items.iter()
+            .map(|i| Transfer {
+                item: i.object,
+                count: i.count,
+                from: container,
+                to: looter,
+            })
+            .collect()
+    }
+
+    /// Moves items between the looter and the container if the scripts of the item and the
+    /// container allow it. `run_proc(world, obj, proc)` runs the predefined `proc` of the `obj`
+    /// script and returns `true` if the script overrides the transfer. The procs are
+    /// `pickup_p_proc` of the item when taking it, or `is_dropping_p_proc` of the container and
+    /// `drop_p_proc` of the item when putting it into the container.
+    pub fn transfer(&self,
+        world: &mut World,
+        transfer: Transfer,
+        mut run_proc: impl FnMut(&mut World, object::Handle, PredefinedProc) -> bool,
+    ) {
+        let procs = if transfer.to == self.looter.unwrap() {
+            vec![(transfer.item, PredefinedProc::Pickup)]
+        } else {
+            vec![
+                (self.container.unwrap(), PredefinedProc::IsDropping),
+                (transfer.item, PredefinedProc::Drop),
+            ]
+        };
+        for (obj, proc) in procs {
+            if run_proc(world, obj, proc) {
+                return;
+            }
+        }
+
+        let objs = world.objects_mut();
+        if !objs.contains(transfer.item) {
+            // Destroyed by script.
+            return;
+        }
+        if let Some(item) = objs.remove_from_inventory(transfer.from, transfer.item,
+            transfer.count)
+        {
+            objs.add_to_inventory(transfer.to, item, transfer.count);
+        }
+    }
+
+    /// Handles the quantity picker command. Returns the transfer when the quantity is confirmed.
+    pub fn handle_quantity(&mut self, ui: &mut Ui, cmd: QuantityCommand) -> Option<Transfer> {
+        let quantity = self.quantity.as_mut()?;
+        let max = quantity.transfer.count;
+        match cmd {
+            QuantityCommand::Inc => quantity.value = (quantity.value + 1).min(max),
+            QuantityCommand::Dec => quantity.value = quantity.value.saturating_sub(1).max(1),
+            QuantityCommand::All => quantity.value = max,
+            QuantityCommand::Done => {
+                let transfer = Transfer {
+                    count: quantity.value,
+                    ..quantity.transfer
+                };
+                self.hide_quantity(ui);
+                return Some(transfer);
+            }
+            QuantityCommand::Cancel => {
+                self.hide_quantity(ui);
+                return None;
+            }
+        }
+        Self::sync_quantity(ui, quantity);
+        None
+    }
+
+    /// Updates the widgets from the inventories.
+    pub fn sync(&mut self, ui: &mut Ui, objs: &Objects) {
+        let widgets = self.widgets.as_ref().unwrap();
+        let owners = [self.looter.unwrap(), self.container.unwrap()];
+        for (i, (&list, &owner)) in widgets.lists.iter().zip(owners.iter()).enumerate() {
+            let items = objs.get(owner).inventory.items.iter()
+                // Equipped items of the looter can't be moved.
+                .filter(|item| i == 1 || !objs.get(item.object).flags.intersects(
+                    Flag::LeftHand | Flag::RightHand | Flag::Worn))
+                .map(|item| inventory_list::Item::new(item, objs))
+                .collect();
+            ui.widget_mut::<InventoryList>(list).set_items(items);
+        }
+    }
+
+    // inven_move_mult
+    fn show_quantity(&mut self, ui: &mut Ui, transfer: Transfer) {
+        assert!(self.quantity.is_none());
+
+        let size = ui.frm_db().get(FrameId::MOVEMULT).unwrap().first().size();
+        let window = ui.new_window(Rect::with_size(
+            (640 - size.x) / 2, (480 - size.y) / 2, size.x, size.y),
+            Some(Sprite::new(FrameId::MOVEMULT)));
+        ui.set_modal_window(Some(window));
+
+        let text = ui.new_widget(window, Rect::with_size(125, 45, 1, 1), None, None,
+            ImageText::standard_digits(FrameId::BIG_NUMBERS, 14));
+
+        for &(rect, up, down, cmd) in &[
+            (Rect::with_size(200, 46, 16, 12), FrameId::SPLSOFF, FrameId::SPLSON,
+                QuantityCommand::Inc),
+            (Rect::with_size(200, 58, 16, 12), FrameId::SNEGOFF, FrameId::SNEGON,
+                QuantityCommand::Dec),
+            (Rect::with_size(120, 80, 94, 33), FrameId::ALLBOFF, FrameId::ALLBON,
+                QuantityCommand::All),
+            (Rect::with_size(98, 128, 15, 16), FrameId::SMALL_RED_BUTTON_UP,
+                FrameId::SMALL_RED_BUTTON_DOWN, QuantityCommand::Done),
+            (Rect::with_size(148, 128, 15, 16), FrameId::SMALL_RED_BUTTON_UP,
+                FrameId::SMALL_RED_BUTTON_DOWN, QuantityCommand::Cancel),
+        ] {
+            ui.new_widget(window, rect, None, None,
+                Button::new(up, down, Some(UiCommandData::Loot(LootCommand::Quantity(cmd)))));
+        }
+
+        let quantity = Quantity {
+            window,
+            text,
+            value: 1,
+            transfer,
+        };
+        Self::sync_quantity(ui, &quantity);
+        self.quantity = Some(quantity);
+    }
+
+    fn hide_quantity(&mut self, ui: &mut Ui) {
+        if let Some(quantity) = self.quantity.take() {
+            ui.remove(quantity.window);
+            ui.set_modal_window(self.widgets.as_ref().map(|w| w.window));
+        }
+    }
+
+    fn sync_quantity(ui: &mut Ui, quantity: &Quantity) {
+        *ui.widget_mut::<ImageText>(quantity.text).text_mut() =
+            format!("{:05}", quantity.value).into();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graphics::EPoint;
+    use crate::vm::test::*;
+
+    struct Setup {
+        h: Harness,
+        loot: Loot,
+        looter: object::Handle,
+        container: object::Handle,
+    }
+
+    fn setup() -> Setup {
+        let mut h = Harness::new();
+        let looter = h.new_dude(EPoint::new(0, Point::new(10, 20)));
+        let container = h.new_object(PID_CONTAINER, Some(EPoint::new(0, Point::new(11, 20))));
+        let loot = Loot {
+            widgets: None,
+            quantity: None,
+            looter: Some(looter),
+            container: Some(container),
+        };
+        Setup { h, loot, looter, container }
+    }
+
+    fn add_item(h: &mut Harness, owner: object::Handle, count: u32) -> object::Handle {
+        let item = h.new_object(PID_MISC_ITEM, None);
+        h.world.objects_mut().add_to_inventory(owner, item, count)
+    }
+
+    fn inventory(h: &Harness, owner: object::Handle) -> Vec<(object::Handle, u32)> {
+        h.world.objects().get(owner).inventory.items.iter()
+            .map(|i| (i.object, i.count))
+            .collect()
+    }
+
+    #[test]
+    fn take_all() {
+        let Setup { mut h, loot, looter, container } = setup();
+        let items = add_item(&mut h, container, 3);
+        let more = add_item(&mut h, container, 1);
+        assert_eq!(more, items);
+        let own = add_item(&mut h, looter, 2);
+
+        let transfers = loot.take_all(h.world.objects());
+        assert_eq!(transfers, vec![Transfer {
+            item: items,
+            count: 4,
+            from: container,
+            to: looter,
+        }]);
+
+        let mut procs = Vec::new();
+        for t in transfers {
+            loot.transfer(&mut h.world, t, |_, obj, proc| {
+                procs.push((obj, proc));
+                false
+            });
+        }
+        assert_eq!(procs, vec![(items, PredefinedProc::Pickup)]);
+        assert!(inventory(&h, container).is_empty());
+        assert_eq!(inventory(&h, looter), vec![(own, 6)]);
+    }
+
+    #[test]
+    fn put() {
+        let Setup { mut h, loot, looter, container } = setup();
+        let item = add_item(&mut h, looter, 3);
+        let transfer = Transfer {
+            item,
+            count: 2,
+            from: looter,
+            to: container,
+        };
+
+        let mut procs = Vec::new();
+        loot.transfer(&mut h.world, transfer, |_, obj, proc| {
+            procs.push((obj, proc));
+            false
+        });
+        assert_eq!(procs, vec![
+            (container, PredefinedProc::IsDropping),
+            (item, PredefinedProc::Drop),
+        ]);
+        assert_eq!(inventory(&h, looter), vec![(item, 1)]);
+        assert_eq!(inventory(&h, container).len(), 1);
+        assert_eq!(inventory(&h, container)[0].1, 2);
+    }
+
+    #[test]
+    fn script_overrides() {
+        let Setup { mut h, loot, looter, container } = setup();
+        let taken = add_item(&mut h, container, 1);
+        let put = add_item(&mut h, looter, 1);
+        let take = Transfer {
+            item: taken,
+            count: 1,
+            from: container,
+            to: looter,
+        };
+        let put = Transfer {
+            item: put,
+            count: 1,
+            from: looter,
+            to: container,
+        };
+
+        for &(transfer, overriding) in &[
+            (take, PredefinedProc::Pickup),
+            (put, PredefinedProc::IsDropping),
+            (put, PredefinedProc::Drop),
+        ] {
+            let mut procs = Vec::new();
+            loot.transfer(&mut h.world, transfer, |_, _, proc| {
+                procs.push(proc);
+                proc == overriding
+            });
+            assert_eq!(procs.last(), Some(&overriding));
+            assert_eq!(inventory(&h, container), vec![(taken, 1)]);
+            assert_eq!(inventory(&h, looter), vec![(put.item, 1)]);
+        }
+    }
+
+    #[test]
+    fn destroyed_by_script() {
+        let Setup { mut h, loot, looter, container } = setup();
+        let item = add_item(&mut h, container, 1);
+        loot.transfer(&mut h.world, Transfer {
+            item,
+            count: 1,
+            from: container,
+            to: looter,
+        }, |world, obj, _| {
+            world.objects_mut().remove_from_inventory(container, obj, 1);
+            world.objects_mut().remove(obj);
+            false
+        });
+        assert!(inventory(&h, container).is_empty());
+        assert!(inventory(&h, looter).is_empty());
+    }
+
+    #[test]
+    fn locked() {
+        let Setup { mut h, mut loot, looter, container } = setup();
+        loot.container = None;
+        loot.looter = None;
+        h.world.objects_mut().get_mut(container).set_locked(true);
+        assert!(!loot.show(&mut h.ui, h.world.objects(), looter, container));
+        assert!(!loot.is_visible());
+
+        h.world.objects_mut().get_mut(container).set_locked(false);
+        assert!(loot.show(&mut h.ui, h.world.objects(), looter, container));
+        assert!(loot.is_visible());
+        assert_eq!(loot.container(), Some(container));
+    }
+}
@@ -71,6 +71,41 @@ impl Inventory {
 
         // See https://trello.com/c/ksAC8gWn
     }
+
+    // item_total_cost
+    pub fn cost(&self, objects: &Objects) -> i32 {
+        let mut cost = 0;
+        for item in &self.items {
+            let obj = objects.get(item.object);
+            let count = item.count as i32;
+            cost += if obj.item_kind() == Some(ItemKind::Ammo) {
+                // Only the last pack in the stack can be partially used.
+                obj.item_cost(objects).unwrap() +
+                    obj.proto().unwrap().sub.as_item().unwrap().price * (count - 1)
+            } else {
+                obj.item_cost(objects).unwrap() * count
+            };
+        }
+        cost
+    }
+}
+
+/// Inventory slot of a critter that holds the item in use.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EquipmentSlot {
+    LeftHand,
+    RightHand,
+    Armor,
+}
+
+impl EquipmentSlot {
+    pub fn flag(self) -> Flag {
+        match self {
+            Self::LeftHand => Flag::LeftHand,
+            Self::RightHand => Flag::RightHand,
+            Self::Armor => Flag::Worn,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -246,7 +281,7 @@ impl Object {
     // critter_is_prone()
     pub fn is_critter_prone(&self) -> bool {
         if let Some(critter) = self.sub.as_critter() {
-            critter.combat.damage_flags.contains(DamageFlag::KnockedDown | DamageFlag::KnockedOut)
+            critter.combat.damage_flags.intersects(DamageFlag::KnockedDown | DamageFlag::KnockedOut)
                 || self.fid.critter().unwrap().anim().is_prone()
         } else {
             false
@@ -418,6 +453,42 @@ impl Object {
         Some(weight)
     }
 
+    // item_cost
+    pub fn item_cost(&self, objects: &Objects) -> Option<i32> {
+        let proto = self.proto()?;
+        let item = proto.sub.as_item()?;
+        let mut cost = item.price;
+        match &item.sub {
+            SubItem::Container(_) => cost += self.inventory.cost(objects),
+            SubItem::Weapon(_) => {
+                let item_obj = self.sub.as_item().unwrap();
+                if item_obj.ammo_count > 0 {
+                    if let Some(ammo) = item_obj.ammo_proto.as_ref() {
+                        let ammo = ammo.borrow();
+                        let ammo = ammo.sub.as_item().unwrap();
+                        cost += item_obj.ammo_count as i32 * ammo.price /
+                            cmp::max(ammo.sub.as_ammo().unwrap().max_ammo_count, 1) as i32;
+                    }
+                }
+            }
+            SubItem::Ammo(ammo) => {
+                let item_obj = self.sub.as_item().unwrap();
+                cost = cost * item_obj.ammo_count as i32 /
+                    cmp::max(ammo.max_ammo_count, 1) as i32;
+            }
+            _ => {}
+        }
+        Some(cost)
+    }
+
+    // critter_can_barter
+    /// Whether this is a critter that can barter with the player.
+    pub fn can_barter(&self) -> bool {
+        self.proto()
+            .and_then(|p| p.sub.as_critter().map(|c| c.flags.contains(CritterFlag::Barter)))
+            .unwrap_or(false)
+    }
+
     // inven_left_hand
     pub fn in_left_hand(&self, objects: &Objects) -> Option<Handle> {
         self.find_inventory_item(objects, |o| o.flags.contains(Flag::LeftHand))
@@ -1189,6 +1260,169 @@ impl Objects {
         self.get(from).distance(&self.get(to))
     }
 
+    /// Removes the object from the tile grid keeping it alive, for example when it's moved into
+    /// an inventory.
+    pub fn detach(&mut self, h: Handle) {
+        self.remove_from_tile_grid(h);
+        self.insert_into_tile_grid(h, None, true);
+    }
+
+    // item_add_force()
+    /// Moves `count` items represented by the `item` object into the `owner`'s inventory.
+    /// The item is stacked with an identical item if the inventory has one, in which case the
+    /// `item` object is removed. Returns handle of the resulting inventory stack.
+    pub fn add_to_inventory(&mut self, owner: Handle, item: Handle, count: u32) -> Handle {
+        assert!(count > 0);
+        assert_ne!(owner, item);
+        self.detach(item);
+        self.get_mut(item).flags.remove(Flag::LeftHand | Flag::RightHand | Flag::Worn);
+
+        let stack = self.get(owner).inventory.items.iter()
+            .position(|i| self.can_stack(i.object, item));
+        if let Some(i) = stack {
+            self.remove(item);
+            let mut ownero = self.get_mut(owner);
+            let stack = &mut ownero.inventory.items[i];
+            stack.count += count;
+            stack.object
+        } else {
+            self.get_mut(owner).inventory.items.push(InventoryItem {
+                object: item,
+                count,
+            });
+            item
+        }
+    }
+
+    // item_remove_mult()
+    /// Takes `count` items of the `item` stack out of the `owner`'s inventory. If the whole stack
+    /// is taken the `item` itself is returned, otherwise a copy of the item is split off the
+    /// stack. The returned object is detached. Returns `None` if there's not enough items.
+    pub fn remove_from_inventory(&mut self, owner: Handle, item: Handle, count: u32)
+        -> Option<Handle>
+    {
+        assert!(count > 0);
+        let (i, stack_count) = self.get(owner).inventory.items.iter()
+            .enumerate()
+            .find(|(_, i)| i.object == item)
+            .map(|(i, v)| (i, v.count))?;
+        if count > stack_count {
+            return None;
+        }
+        if count == stack_count {
+            self.unequip(owner, item);
+            self.get_mut(owner).inventory.items.remove(i);
+            Some(item)
+        } else {
+            self.get_mut(owner).inventory.items[i].count -= count;
+            let copy = {
+                let itemo = self.get(item);
+                let sub = match &itemo.sub {
+                    SubObject::Item(v) => SubObject::Item(v.clone()),
+                    SubObject::Key(v) => SubObject::Key(v.clone()),
+                    _ => SubObject::None,
+                };
+                let mut copy = Object::new(itemo.fid, itemo.proto.clone(), None, sub);
+                copy.flags = itemo.flags;
+                copy.flags.remove(Flag::LeftHand | Flag::RightHand | Flag::Worn);
+                copy
+            };
+            Some(self.insert(copy))
+        }
+    }
+
+    /// Returns the object whose inventory has the `item`.
+    pub fn owner(&self, item: Handle) -> Option<Handle> {
+        self.iter().find(|&h| self.get(h).inventory.items.iter().any(|i| i.object == item))
+    }
+
+    /// Returns number of items in the `owner`'s inventory with the specified proto.
+    pub fn inventory_count(&self, owner: Handle, pid: ProtoId) -> u32 {
+        self.get(owner).inventory.items.iter()
+            .filter(|i| self.get(i.object).proto_id() == Some(pid))
+            .map(|i| i.count)
+            .sum()
+    }
+
+    // inven_wield()
+    /// Puts the `item` from `owner`'s inventory into the `slot`. The item previously in the slot
+    /// is moved back to the inventory.
+    pub fn equip(&mut self, owner: Handle, item: Handle, slot: EquipmentSlot) {
+        assert!(self.get(owner).inventory.items.iter().any(|i| i.object == item));
+        let prev = {
+            let ownero = self.get(owner);
+            ownero.find_inventory_item(self, |o| o.flags.contains(slot.flag()))
+        };
+        if let Some(prev) = prev {
+            self.get_mut(prev).flags.remove(slot.flag());
+        }
+        {
+            let mut itemo = self.get_mut(item);
+            itemo.flags.remove(Flag::LeftHand | Flag::RightHand | Flag::Worn);
+            itemo.flags.insert(slot.flag());
+        }
+        self.update_weapon_fid(owner);
+    }
+
+    // inven_unwield()
+    /// Moves the `item` from hand or armor slot back to the `owner`'s inventory.
+    pub fn unequip(&mut self, owner: Handle, item: Handle) {
+        self.get_mut(item).flags.remove(Flag::LeftHand | Flag::RightHand | Flag::Worn);
+        self.update_weapon_fid(owner);
+    }
+
+    /// Makes critter's frame show the weapon in its right hand.
+    // TODO Armor appearance is not updated.
+    fn update_weapon_fid(&mut self, critter: Handle) {
+        let fid = if let Some(v) = self.get(critter).fid.critter() {
+            v
+        } else {
+            return;
+        };
+        let weapon = {
+            let crittero = self.get(critter);
+            crittero.in_right_hand(self)
+                .and_then(|h| self.get(h).proto().unwrap()
+                    .sub.as_item()?.sub.as_weapon()
+                    .map(|w| w.animation_code))
+                .unwrap_or(WeaponKind::Unarmed)
+        };
+        if fid.weapon() == weapon {
+            return;
+        }
+        let new_fid = fid.with_weapon(weapon).with_anim(CritterAnim::Stand);
+        if self.frm_db.exists(new_fid.into()) {
+            self.get_mut(critter).fid = new_fid.into();
+        }
+    }
+
+    /// Whether the inventory items can be merged into a single stack.
+    fn can_stack(&self, item1: Handle, item2: Handle) -> bool {
+        if item1 == item2 {
+            return false;
+        }
+        let item1 = self.get(item1);
+        let item2 = self.get(item2);
+        if item1.proto_id().is_none()
+            || item1.proto_id() != item2.proto_id()
+            || item1.script.is_some()
+            || item2.script.is_some()
+            || !item1.inventory.items.is_empty()
+            || !item2.inventory.items.is_empty()
+        {
+            return false;
+        }
+        match (&item1.sub, &item2.sub) {
+            (SubObject::Item(i1), SubObject::Item(i2)) =>
+                i1.ammo_count == i2.ammo_count
+                    && i1.ammo_proto.as_ref().map(|p| p.borrow().id())
+                        == i2.ammo_proto.as_ref().map(|p| p.borrow().id()),
+            (SubObject::Key(k1), SubObject::Key(k2)) => k1.id == k2.id,
+            (SubObject::None, SubObject::None) => true,
+            _ => false,
+        }
+    }
+
     // obj_intersects_with()
     #[must_use]
     fn is_egg_hit(&self, p: Point, obj: &Object, egg: Egg, tile_grid: &impl TileGridView) -> bool {
@@ -1442,13 +1676,13 @@ pub struct Elevator {
     pub level: u32,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Item {
     pub ammo_count: u32,
     pub ammo_proto: Option<ProtoRef>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Key {
     pub id: i32,
 }
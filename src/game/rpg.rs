@@ -1,18 +1,20 @@
 mod def;
 
 use bstring::bstr;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use enum_map::EnumMap;
-use num_traits::clamp;
+use num_traits::{clamp, FromPrimitive};
 use std::cmp;
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::io;
+use std::io::{self, Error, ErrorKind, prelude::*};
 
 use crate::asset::{Perk, Skill, Stat, Trait};
 use crate::asset::message::{Messages, MessageId};
 use crate::asset::proto::{self, ProtoId};
 use crate::game::object::{DamageFlag, Object, Objects};
 use crate::fs::FileSystem;
+use crate::util::EnumExt;
 use crate::util::random::*;
 
 use def::*;
@@ -28,6 +30,8 @@ const SKILL_FORMULA_MSG_BASE: MessageId = 300;
 const LEVEL_UP_MSG: MessageId = 600;
 const PERK_NAME_MSG_BASE: MessageId = 101;
 const PERK_DESCR_MSG_BASE: MessageId = 1101;
+const TAGGED_SKILL_SLOTS: usize = 4;
+const TRAIT_SLOTS: usize = 2;
 
 struct Tagged {
     tagged: bool,
@@ -43,6 +47,46 @@ impl Default for Tagged {
     }
 }
 
+/// Player choices saved in the savegame: tagged skills, traits and perks.
+pub struct RpgState {
+    tagged: EnumMap<Skill, Tagged>,
+    traits: EnumMap<Trait, bool>,
+    perks: HashMap<ProtoId, EnumMap<Perk, bool>>,
+}
+
+impl RpgState {
+    /// Reads state written by `Rpg::write_state()`.
+    pub fn read(r: &mut impl Read) -> io::Result<Self> {
+        let mut tagged: EnumMap<Skill, Tagged> = Default::default();
+        for skill in read_slots::<Skill>(r, TAGGED_SKILL_SLOTS, "invalid tagged skill")? {
+            tagged[skill].tagged = true;
+        }
+
+        let mut traits: EnumMap<Trait, bool> = Default::default();
+        for tr in read_slots::<Trait>(r, TRAIT_SLOTS, "invalid trait")? {
+            traits[tr] = true;
+        }
+
+        let mut perks = HashMap::new();
+        let count = r.read_i32::<BigEndian>()?;
+        for _ in 0..count {
+            let pid = ProtoId::from_packed(r.read_u32::<BigEndian>()?)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid perk owner proto ID"))?;
+            let mut m: EnumMap<Perk, bool> = Default::default();
+            for perk in Perk::iter() {
+                m[perk] = r.read_i32::<BigEndian>()? != 0;
+            }
+            perks.insert(pid, m);
+        }
+
+        Ok(Self {
+            tagged,
+            traits,
+            perks,
+        })
+    }
+}
+
 pub struct Rpg {
     stat_msgs: Messages,
     skill_msgs: Messages,
@@ -117,6 +161,39 @@ impl Rpg {
         self.tagged[skill].tagged
     }
 
+    /// Writes tagged skills, traits and perks as stored in savegames.
+    pub fn write_state(&self, w: &mut impl Write) -> io::Result<()> {
+        let tagged: Vec<_> = Skill::iter().filter(|&s| self.is_tagged(s)).map(|s| s as i32).collect();
+        write_slots(w, &tagged, TAGGED_SKILL_SLOTS, "tagged skills")?;
+
+        let traits: Vec<_> = Trait::iter().filter(|&t| self.has_trait(t)).map(|t| t as i32).collect();
+        write_slots(w, &traits, TRAIT_SLOTS, "traits")?;
+
+        let mut perks: Vec<_> = self.perks.iter().collect();
+        perks.sort_by_key(|&(&pid, _)| pid.pack());
+        w.write_i32::<BigEndian>(perks.len() as i32)?;
+        for (pid, perks) in perks {
+            w.write_u32::<BigEndian>(pid.pack())?;
+            for perk in Perk::iter() {
+                w.write_i32::<BigEndian>(perks[perk] as i32)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads state written by `write_state()` replacing the current one.
+    pub fn read_state(&mut self, r: &mut impl Read) -> io::Result<()> {
+        self.set_state(RpgState::read(r)?);
+        Ok(())
+    }
+
+    pub fn set_state(&mut self, state: RpgState) {
+        self.tagged = state.tagged;
+        self.traits = state.traits;
+        self.perks = state.perks;
+    }
+
     // stat_level()
     pub fn stat(&self, stat: Stat, obj: &Object, objs: &Objects) -> i32 {
         use Perk::*;
@@ -424,3 +501,26 @@ impl Rpg {
         f(proto.sub.as_critter().unwrap())
     }
 }
+
+fn write_slots(w: &mut impl Write, values: &[i32], slots: usize, what: &str) -> io::Result<()> {
+    if values.len() > slots {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("too many {}: {}", what, values.len())));
+    }
+    for i in 0..slots {
+        w.write_i32::<BigEndian>(values.get(i).cloned().unwrap_or(-1))?;
+    }
+    Ok(())
+}
+
+fn read_slots<T: FromPrimitive>(r: &mut impl Read, slots: usize, err: &str) -> io::Result<Vec<T>> {
+    let mut result = Vec::new();
+    for _ in 0..slots {
+        let v = r.read_i32::<BigEndian>()?;
+        if v >= 0 {
+            result.push(T::from_i32(v)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{}: {}", err, v)))?);
+        }
+    }
+    Ok(result)
+}
\ No newline at end of file
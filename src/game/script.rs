@@ -91,6 +91,10 @@ impl ScriptIid {
         SidInternal::from_packed(v).map(Self)
     }
 
+    pub fn pack(self) -> u32 {
+        self.0.pack()
+    }
+
     pub fn read(rd: &mut impl Read) -> io::Result<Self> {
         SidInternal::read(rd).map(Self)
     }
@@ -155,6 +159,11 @@ pub struct Context<'a> {
     pub world: &'a mut crate::game::world::World,
     pub obj_sequencer: &'a mut crate::game::sequence::ObjSequencer,
     pub dialog: &'a mut Option<crate::game::dialog::Dialog>,
+    pub script_ui: &'a mut crate::game::script_ui::ScriptUi,
+    pub audio: &'a mut crate::audio::Audio,
+    pub movie: &'a mut Option<crate::state::movie::MovieRequest>,
+    pub world_map: &'a mut crate::game::worldmap::WorldMap,
+    pub combat: &'a mut crate::game::combat::Combat,
     pub message_panel: crate::ui::Handle,
     pub map_id: MapId,
     pub source_obj: Option<object::Handle>,
@@ -186,6 +195,8 @@ pub struct Script {
     pub program: vm::Handle,
     pub local_vars: Box<[i32]>,
     pub object: Option<object::Handle>,
+    /// Parameter of the timer event being handled.
+    pub fixed_param: i32,
 }
 
 /// Interface for instantiating new scripts from within a script context.
@@ -196,7 +207,7 @@ pub struct NewScripts {
 }
 
 impl NewScripts {
-    fn new(scripts: &Scripts) -> Self {
+    pub(crate) fn new(scripts: &Scripts) -> Self {
         let mut unused_sids = EnumMap::from(|k| ScriptIid::new(k, 0));
         for &sid in scripts.scripts.keys() {
             if sid.id() > unused_sids[sid.kind()].id() {
@@ -227,7 +238,7 @@ impl NewScripts {
         self.unused_sids[kind] = ScriptIid::new(kind, cur.checked_add(1).unwrap());
     }
 
-    fn instantiate(self, scripts: &mut Scripts) {
+    pub(crate) fn instantiate(self, scripts: &mut Scripts) {
         for (sid, prg_id) in self.new_scripts {
             scripts.instantiate(sid, prg_id, None).unwrap();
         }
@@ -259,6 +270,10 @@ impl Scripts {
         }
     }
 
+    pub fn set_debugger(&mut self, debugger: vm::debug::Debugger) {
+        self.vm.set_debugger(debugger);
+    }
+
     pub fn map_sid(&self) -> Option<ScriptIid> {
         self.map_sid
     }
@@ -307,6 +322,7 @@ impl Scripts {
             program,
             local_vars,
             object: None,
+            fixed_param: 0,
         });
         if let Some(existing) = existing {
             panic!("{:?} program #{} duplicates existing program #{}",
@@ -315,10 +331,13 @@ impl Scripts {
         Ok(())
     }
 
-    pub fn instantiate_map_script(&mut self, program_id: ProgramId) -> io::Result<ScriptIid> {
+    pub fn instantiate_map_script(&mut self,
+        program_id: ProgramId,
+        local_vars: Option<Box<[i32]>>,
+    ) -> io::Result<ScriptIid> {
         assert!(self.map_sid.is_none());
         let sid = NewScripts::new(self).unused_sid(ScriptKind::System);
-        self.instantiate(sid, program_id, None)?;
+        self.instantiate(sid, program_id, local_vars)?;
         self.map_sid = Some(sid);
         Ok(sid)
     }
@@ -327,6 +346,11 @@ impl Scripts {
         self.scripts.get(&sid)
     }
 
+    /// Iterates over all script instances in unspecified order.
+    pub fn iter(&self) -> impl Iterator<Item=(ScriptIid, &Script)> {
+        self.scripts.iter().map(|(&sid, script)| (sid, script))
+    }
+
     pub fn attach_to_object(&mut self, sid: ScriptIid, obj: object::Handle) {
         self.scripts.get_mut(&sid).unwrap().object = Some(obj);
     }
@@ -338,12 +362,14 @@ impl Scripts {
             let new_scripts = NewScripts::new(self);
             let script = self.scripts.get_mut(&sid).unwrap();
             let mut vm_ctx = Self::make_vm_ctx(
+                sid,
                 &mut script.local_vars,
                 &mut self.vars,
                 &mut self.db,
                 new_scripts,
                 &self.proto_db,
                 script.object,
+                script.fixed_param,
                 ctx);
             if !script.inited {
                 debug!("[{:?}#{}:{}] running program initialization code",
@@ -400,6 +426,23 @@ impl Scripts {
         Some(self.execute_proc(sid, proc_id, ctx))
     }
 
+    /// Executes `timed_event_p_proc` making `fixed_param` available to the script.
+    pub fn execute_timed_event(&mut self, sid: ScriptIid, fixed_param: i32,
+        ctx: &mut Context) -> Option<InvocationResult>
+    {
+        self.scripts.get_mut(&sid)?.fixed_param = fixed_param;
+        self.execute_predefined_proc(sid, PredefinedProc::TimedEvent, ctx)
+    }
+
+    /// Executes `combat_p_proc` making the combat event `subtype` available to the script as
+    /// `fixed_param`.
+    pub fn execute_combat_proc(&mut self, sid: ScriptIid, subtype: i32,
+        ctx: &mut Context) -> Option<InvocationResult>
+    {
+        self.scripts.get_mut(&sid)?.fixed_param = subtype;
+        self.execute_predefined_proc(sid, PredefinedProc::Combat, ctx)
+    }
+
     pub fn execute_procs(&mut self, proc: PredefinedProc, ctx: &mut Context,
         filter: impl Fn(ScriptIid) -> bool)
     {
@@ -443,12 +486,14 @@ impl Scripts {
             let new_scripts = NewScripts::new(self);
             let script = self.scripts.get_mut(&sid).unwrap();
             let mut vm_ctx = Self::make_vm_ctx(
+                sid,
                 &mut script.local_vars,
                 &mut self.vars,
                 &mut self.db,
                 new_scripts,
                 &self.proto_db,
                 script.object,
+                script.fixed_param,
                 ctx);
             let r = self.vm.program_state_mut(script.program).resume(&mut vm_ctx).unwrap();
             (r, vm_ctx.new_scripts)
@@ -457,14 +502,17 @@ impl Scripts {
         r
     }
 
+    #[allow(clippy::too_many_arguments)]
     #[inline]
     fn make_vm_ctx<'a>(
+        sid: ScriptIid,
         local_vars: &'a mut [i32],
         vars: &'a mut Vars,
         script_db: &'a mut ScriptDb,
         new_scripts: NewScripts,
         proto_db: &'a ProtoDb,
         self_obj: Option<object::Handle>,
+        fixed_param: i32,
         ctx: &'a mut Context,
     ) -> vm::Context<'a> {
         vm::Context {
@@ -473,7 +521,9 @@ impl Scripts {
             global_vars: &mut vars.global_vars,
             external_vars: &mut vars.external_vars,
 
+            sid,
             self_obj,
+            fixed_param,
             source_obj: ctx.source_obj,
             target_obj: ctx.target_obj,
             skill: ctx.skill,
@@ -481,6 +531,11 @@ impl Scripts {
             world: ctx.world,
             obj_sequencer: ctx.obj_sequencer,
             dialog: ctx.dialog,
+            script_ui: ctx.script_ui,
+            audio: ctx.audio,
+            movie: ctx.movie,
+            world_map: ctx.world_map,
+            combat: ctx.combat,
             message_panel: ctx.message_panel,
             script_db,
             new_scripts,
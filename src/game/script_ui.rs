@@ -0,0 +1,706 @@
+//! Windows, buttons, regions and "say" dialogs created by scripts.
+//!
+//! Scripts refer to windows, buttons and regions by name. Drawing instructions (`print`,
+//! `display`, `fillwin` etc) and the button/region instructions operate on the window selected
+//! with `selectwin()` or the last created one.
+
+use bstring::{bstr, BString};
+use enum_map::EnumMap;
+use log::*;
+
+use crate::asset::EntityKind;
+use crate::asset::frame::FrameId;
+use crate::game::dialog::Dialog;
+use crate::game::script::ScriptIid;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{Rgb15, GREEN};
+use crate::graphics::font::{FontKey, HorzAlign, VertAlign};
+use crate::graphics::sprite::Sprite;
+use crate::ui::{Handle, Ui};
+use crate::ui::button::{self, Button, Trigger};
+use crate::ui::command::UiCommandData;
+use crate::ui::message_panel::{MessagePanel, MouseControl};
+use crate::ui::panel::{self, Panel};
+use crate::vm::ProcedureId;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ControlKind {
+    Button,
+    Region,
+}
+
+struct Control {
+    kind: ControlKind,
+    name: BString,
+    widget: Handle,
+    sid: ScriptIid,
+    procs: EnumMap<Trigger, Option<ProcedureId>>,
+    flags: i32,
+}
+
+struct Window {
+    name: BString,
+    handle: Handle,
+    rect: Rect,
+    /// Widgets created by the drawing instructions. These are always kept beneath the controls.
+    drawings: Vec<Handle>,
+    controls: Vec<Control>,
+}
+
+impl Window {
+    fn control(&self, kind: ControlKind, name: &bstr) -> Option<&Control> {
+        self.controls.iter().find(|c| c.kind == kind && c.name == name)
+    }
+
+    fn control_mut(&mut self, kind: ControlKind, name: &bstr) -> Option<&mut Control> {
+        self.controls.iter_mut().find(|c| c.kind == kind && c.name == name)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SayTarget {
+    Proc(ProcedureId),
+    /// Name of the reply to go to.
+    Reply(BString),
+}
+
+/// Result of picking a reply or option in the say dialog.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SayPick {
+    /// Dialog continues.
+    Continue,
+    /// The procedure must be executed followed by `ScriptUi::say_proc_done()` call.
+    Proc(ScriptIid, ProcedureId),
+    /// Dialog is finished and the script suspended in `sayend()` should be resumed.
+    End,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SayWindowKind {
+    Reply,
+    Option,
+}
+
+#[derive(Clone, Copy)]
+struct SayWindowConfig {
+    rect: Rect,
+    background: Option<FrameId>,
+}
+
+struct SayOption {
+    text: BString,
+    target: SayTarget,
+}
+
+struct SayReply {
+    name: Option<BString>,
+    text: BString,
+    options: Vec<SayOption>,
+}
+
+struct SayWidgets {
+    window: Handle,
+    reply: Handle,
+    options: Handle,
+}
+
+struct Say {
+    sid: ScriptIid,
+    title: Option<BString>,
+    replies: Vec<SayReply>,
+    cur_reply: Option<usize>,
+    widgets: Option<SayWidgets>,
+    /// Whether `saygotoreply()` was called while running an option procedure.
+    jumped: bool,
+    quit: bool,
+}
+
+pub struct ScriptUi {
+    windows: Vec<Window>,
+    cur_window: Option<usize>,
+    text_pos: Point,
+    font: FontKey,
+    text_color: Rgb15,
+    say: Option<Say>,
+    say_windows: [SayWindowConfig; 2],
+}
+
+impl ScriptUi {
+    pub fn new() -> Self {
+        Self {
+            windows: Vec::new(),
+            cur_window: None,
+            text_pos: Point::new(0, 0),
+            font: FontKey::antialiased(1),
+            text_color: GREEN,
+            say: None,
+            say_windows: [
+                SayWindowConfig {
+                    rect: Rect::with_size(135, 235, 382, 47),
+                    background: None,
+                },
+                SayWindowConfig {
+                    rect: Rect::with_size(127, 340, 397, 100),
+                    background: None,
+                },
+            ],
+        }
+    }
+
+    /// Creates window and makes it current. Existing window with the same name is replaced.
+    pub fn create_window(&mut self, ui: &mut Ui, name: BString, rect: Rect) {
+        self.delete_window(ui, name.as_ref());
+        let handle = ui.new_window(rect, None);
+        self.windows.push(Window {
+            name,
+            handle,
+            rect,
+            drawings: Vec::new(),
+            controls: Vec::new(),
+        });
+        self.cur_window = Some(self.windows.len() - 1);
+    }
+
+    pub fn delete_window(&mut self, ui: &mut Ui, name: &bstr) -> bool {
+        let i = if let Some(i) = self.window_idx(name) {
+            i
+        } else {
+            return false;
+        };
+        let win = self.windows.remove(i);
+        ui.remove(win.handle);
+        self.cur_window = match self.cur_window {
+            Some(cur) if cur == i => None,
+            Some(cur) if cur > i => Some(cur - 1),
+            cur => cur,
+        };
+        true
+    }
+
+    pub fn select_window(&mut self, name: &bstr) -> bool {
+        if let Some(i) = self.window_idx(name) {
+            self.cur_window = Some(i);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn window(&self, name: &bstr) -> Option<Handle> {
+        self.window_idx(name).map(|i| self.windows[i].handle)
+    }
+
+    /// Fills `rect` of the current window with `color`. If `rect` is `None` the whole window is
+    /// filled and everything drawn before is discarded.
+    pub fn fill(&mut self, ui: &mut Ui, rect: Option<Rect>, color: Rgb15) -> bool {
+        let win = if let Some(win) = self.cur_window_mut() {
+            win
+        } else {
+            return false;
+        };
+        let rect = if let Some(rect) = rect {
+            rect
+        } else {
+            for w in win.drawings.drain(..) {
+                ui.remove(w);
+            }
+            Rect::with_size(0, 0, win.rect.width(), win.rect.height())
+        };
+        let mut panel = Panel::new();
+        panel.set_fill(Some(color));
+        self.add_drawing(ui, rect, None, panel);
+        true
+    }
+
+    /// Draws image at the top left corner of the current window.
+    pub fn display(&mut self, ui: &mut Ui, fid: FrameId) -> bool {
+        if self.cur_window.is_none() {
+            return false;
+        }
+        self.add_drawing(ui, Rect::with_size(0, 0, 0, 0), Some(Sprite::new(fid)), Panel::new());
+        true
+    }
+
+    pub fn set_text_pos(&mut self, pos: Point) {
+        self.text_pos = pos;
+    }
+
+    /// Sets font by script font number. Numbers starting from 100 refer to antialiased fonts.
+    pub fn set_font(&mut self, ui: &Ui, font: i32) -> bool {
+        let key = if font >= 100 {
+            FontKey::antialiased(font as u32 - 100)
+        } else if font >= 0 {
+            FontKey::non_antialiased(font as u32)
+        } else {
+            return false;
+        };
+        if !ui.fonts().contains(key) {
+            return false;
+        }
+        self.font = key;
+        true
+    }
+
+    pub fn set_text_color(&mut self, color: Rgb15) {
+        self.text_color = color;
+    }
+
+    /// Prints `text` at the position set by `set_text_pos()` in the current window.
+    pub fn print(&mut self, ui: &mut Ui, text: BString) -> bool {
+        if self.cur_window.is_none() {
+            return false;
+        }
+        let mut panel = Panel::new();
+        panel.set_text(Some(panel::Text {
+            text,
+            font: self.font,
+            color: self.text_color,
+            options: Default::default(),
+        }));
+        // Zero-sized so the text doesn't intercept the mouse input.
+        let rect = Rect::with_size(self.text_pos.x, self.text_pos.y, 0, 0);
+        self.add_drawing(ui, rect, None, panel);
+        true
+    }
+
+    /// Adds button without graphics to the current window. The `rect` is relative to the window.
+    pub fn add_button(&mut self, ui: &mut Ui, name: BString, rect: Rect, sid: ScriptIid) -> bool {
+        let mut button = Button::new(FrameId::BLANK, FrameId::BLANK, None);
+        for state in &[button::State::Up, button::State::Down] {
+            button.config_mut(*state).background = None;
+        }
+        self.add_control(ui, ControlKind::Button, name, rect, button, sid)
+    }
+
+    /// Adds region to the current window. The region covers the bounding box of the `points`
+    /// which are relative to the window.
+    pub fn add_region(&mut self, ui: &mut Ui, name: BString, points: &[Point], sid: ScriptIid)
+        -> bool
+    {
+        let left = points.iter().map(|p| p.x).min().unwrap_or(0);
+        let top = points.iter().map(|p| p.y).min().unwrap_or(0);
+        let right = points.iter().map(|p| p.x).max().unwrap_or(0);
+        let bottom = points.iter().map(|p| p.y).max().unwrap_or(0);
+        // Region is an invisible button.
+        let mut button = Button::new(FrameId::BLANK, FrameId::BLANK, None);
+        for state in &[button::State::Up, button::State::Down] {
+            button.config_mut(*state).background = None;
+        }
+        self.add_control(ui, ControlKind::Region, name, Rect::new(left, top, right, bottom),
+            button, sid)
+    }
+
+    pub fn set_button_text(&mut self, ui: &mut Ui, name: &bstr, text: BString) -> bool {
+        let (font, color) = (self.font, self.text_color);
+        self.with_button(ui, name, |button| {
+            let mut text = button::Text::new(text, font);
+            text.color = color;
+            text.options.horz_align = HorzAlign::Center;
+            text.options.vert_align = VertAlign::Middle;
+            button.set_text(Some(text));
+        })
+    }
+
+    pub fn set_button_gfx(&mut self, ui: &mut Ui, name: &bstr, up: Option<FrameId>,
+        down: Option<FrameId>) -> bool
+    {
+        self.with_button(ui, name, |button| {
+            for &(state, fid) in &[(button::State::Up, up), (button::State::Down, down)] {
+                button.config_mut(state).background = fid.map(Sprite::new);
+            }
+        })
+    }
+
+    pub fn set_control_flags(&mut self, kind: ControlKind, name: &bstr, flags: i32) -> bool {
+        if let Some(control) = self.cur_window_mut().and_then(|w| w.control_mut(kind, name)) {
+            control.flags = flags;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn control_flags(&self, kind: ControlKind, name: &bstr) -> Option<i32> {
+        self.cur_window().and_then(|w| w.control(kind, name)).map(|c| c.flags)
+    }
+
+    /// Sets procedures to be called on the `triggers` of the control. `None` procedure disables
+    /// the callback.
+    pub fn set_control_procs(&mut self, ui: &mut Ui, kind: ControlKind, name: &bstr, sid: ScriptIid,
+        procs: &[(Trigger, Option<ProcedureId>)]) -> bool
+    {
+        let control = if let Some(c) = self.cur_window_mut().and_then(|w| w.control_mut(kind, name)) {
+            c
+        } else {
+            return false;
+        };
+        control.sid = sid;
+        let mut button = ui.widget_mut::<Button>(control.widget);
+        for &(trigger, proc_id) in procs {
+            control.procs[trigger] = proc_id;
+            button.set_command(trigger, proc_id.map(|_| UiCommandData::ScriptControl { trigger }));
+        }
+        true
+    }
+
+    pub fn delete_control(&mut self, ui: &mut Ui, kind: ControlKind, name: &bstr) -> bool {
+        let win = if let Some(win) = self.cur_window_mut() {
+            win
+        } else {
+            return false;
+        };
+        if let Some(i) = win.controls.iter().position(|c| c.kind == kind && c.name == name) {
+            let control = win.controls.remove(i);
+            ui.remove(control.widget);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns widget of the control in the current window.
+    pub fn control(&self, kind: ControlKind, name: &bstr) -> Option<Handle> {
+        self.cur_window().and_then(|w| w.control(kind, name)).map(|c| c.widget)
+    }
+
+    /// Returns script procedure to call on `trigger` of the control `widget`.
+    pub fn callback(&self, widget: Handle, trigger: Trigger) -> Option<(ScriptIid, ProcedureId)> {
+        self.windows.iter()
+            .flat_map(|w| &w.controls)
+            .find(|c| c.widget == widget)
+            .and_then(|c| c.procs[trigger].map(|p| (c.sid, p)))
+    }
+
+    /// Returns `true` if any script window or the say dialog is shown.
+    pub fn is_visible(&self) -> bool {
+        !self.windows.is_empty() || self.say.as_ref().map(|s| s.widgets.is_some()) == Some(true)
+    }
+
+    /// Removes all windows and the say dialog.
+    pub fn clear(&mut self, ui: &mut Ui) {
+        for win in self.windows.drain(..) {
+            ui.remove(win.handle);
+        }
+        self.cur_window = None;
+        self.close_say(ui);
+    }
+
+    /// Starts collecting replies and options of a new say dialog. Returns `false` if a say dialog
+    /// is already running.
+    pub fn say_start(&mut self, sid: ScriptIid, start_pos: Option<usize>) -> bool {
+        if self.say.as_ref().map(|s| s.widgets.is_some()) == Some(true) {
+            return false;
+        }
+        self.say = Some(Say {
+            sid,
+            title: None,
+            replies: Vec::new(),
+            cur_reply: start_pos,
+            widgets: None,
+            jumped: false,
+            quit: false,
+        });
+        true
+    }
+
+    pub fn say_reply_title(&mut self, title: Option<BString>) -> bool {
+        if let Some(say) = &mut self.say {
+            say.title = title;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds reply. Options added with `say_option()` are attached to the last added reply.
+    pub fn say_reply(&mut self, name: Option<BString>, text: BString) -> bool {
+        if let Some(say) = &mut self.say {
+            say.replies.push(SayReply {
+                name,
+                text,
+                options: Vec::new(),
+            });
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn say_option(&mut self, text: BString, target: SayTarget) -> bool {
+        if let Some(reply) = self.say.as_mut().and_then(|s| s.replies.last_mut()) {
+            reply.options.push(SayOption {
+                text,
+                target,
+            });
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn say_goto_reply(&mut self, ui: &mut Ui, name: &bstr) -> bool {
+        let say = if let Some(say) = &mut self.say {
+            say
+        } else {
+            return false;
+        };
+        let i = if let Some(i) = say.replies.iter()
+            .position(|r| r.name.as_ref().map(|n| n.as_ref()) == Some(name))
+        {
+            i
+        } else {
+            return false;
+        };
+        say.cur_reply = Some(i);
+        say.jumped = true;
+        if say.widgets.is_some() {
+            self.show_say_reply(ui);
+        }
+        true
+    }
+
+    pub fn set_say_window(&mut self, kind: SayWindowKind, rect: Rect, background: Option<FrameId>) {
+        self.say_windows[kind as usize] = SayWindowConfig {
+            rect,
+            background,
+        };
+    }
+
+    /// Shows the say dialog. Returns `false` if there's nothing to show.
+    pub fn say_show(&mut self, ui: &mut Ui) -> bool {
+        let say = if let Some(say) = &mut self.say {
+            say
+        } else {
+            return false;
+        };
+        if say.replies.is_empty() || say.widgets.is_some() || say.quit {
+            self.say = None;
+            return false;
+        }
+        if say.cur_reply.map(|i| i >= say.replies.len()).unwrap_or(true) {
+            say.cur_reply = Some(0);
+        }
+
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480), None);
+        ui.set_modal_window(Some(window));
+        let new_panel = |ui: &mut Ui, config: SayWindowConfig| {
+            let mut panel = MessagePanel::new(ui.fonts().clone(), FontKey::antialiased(1), GREEN);
+            panel.set_mouse_control(MouseControl::Pick);
+            panel.set_highlight_color(Rgb15::new(31, 31, 15));
+            panel.set_message_spacing(2);
+            ui.new_widget(window, config.rect, None, config.background.map(Sprite::new), panel)
+        };
+        let reply = new_panel(ui, self.say_windows[SayWindowKind::Reply as usize]);
+        let options = new_panel(ui, self.say_windows[SayWindowKind::Option as usize]);
+        say.widgets = Some(SayWidgets {
+            window,
+            reply,
+            options,
+        });
+        self.show_say_reply(ui);
+        true
+    }
+
+    /// Ends the say dialog. If the dialog is running it will be closed after the current option
+    /// procedure finishes.
+    pub fn say_quit(&mut self, ui: &mut Ui) {
+        if let Some(say) = &mut self.say {
+            if say.widgets.is_some() {
+                say.quit = true;
+            } else {
+                self.close_say(ui);
+            }
+        }
+    }
+
+    pub fn is_say_widget(&self, widget: Handle) -> bool {
+        self.say.as_ref()
+            .and_then(|s| s.widgets.as_ref())
+            .map(|w| w.reply == widget || w.options == widget)
+            .unwrap_or(false)
+    }
+
+    pub fn say_pick(&mut self, ui: &mut Ui, widget: Handle, id: u32) -> SayPick {
+        let say = self.say.as_mut().unwrap();
+        let widgets = say.widgets.as_ref().unwrap();
+        let reply = &say.replies[say.cur_reply.unwrap()];
+        if widget == widgets.reply {
+            // Reply without options is a message that is dismissed by clicking on it.
+            return if reply.options.is_empty() {
+                self.close_say(ui);
+                SayPick::End
+            } else {
+                SayPick::Continue
+            };
+        }
+        assert_eq!(widget, widgets.options);
+        match reply.options[id as usize].target.clone() {
+            SayTarget::Proc(proc_id) => {
+                say.jumped = false;
+                SayPick::Proc(say.sid, proc_id)
+            }
+            SayTarget::Reply(name) => if self.say_goto_reply(ui, name.as_ref()) {
+                SayPick::Continue
+            } else {
+                warn!("say option refers to unknown reply {:?}", name.display());
+                self.close_say(ui);
+                SayPick::End
+            }
+        }
+    }
+
+    /// Must be called after the option procedure returned by `say_pick()` is executed.
+    /// Returns `true` if the dialog continues.
+    pub fn say_proc_done(&mut self, ui: &mut Ui) -> bool {
+        let say = if let Some(say) = &self.say {
+            say
+        } else {
+            return false;
+        };
+        if say.jumped && !say.quit {
+            true
+        } else {
+            self.close_say(ui);
+            false
+        }
+    }
+
+    fn close_say(&mut self, ui: &mut Ui) {
+        if let Some(widgets) = self.say.take().and_then(|s| s.widgets) {
+            ui.remove(widgets.window);
+        }
+    }
+
+    fn show_say_reply(&mut self, ui: &mut Ui) {
+        let say = self.say.as_ref().unwrap();
+        let widgets = say.widgets.as_ref().unwrap();
+        let reply = &say.replies[say.cur_reply.unwrap()];
+
+        let mut replyw = ui.widget_mut::<MessagePanel>(widgets.reply);
+        replyw.clear_messages();
+        if let Some(title) = &say.title {
+            replyw.push_message(title);
+        }
+        replyw.push_message(BString::concat(&[&b"  "[..], reply.text.as_bytes()]));
+
+        let mut optionsw = ui.widget_mut::<MessagePanel>(widgets.options);
+        optionsw.clear_messages();
+        for option in &reply.options {
+            optionsw.push_message(Dialog::build_option(option.text.as_ref()));
+        }
+    }
+
+    fn add_control(&mut self, ui: &mut Ui, kind: ControlKind, name: BString, rect: Rect,
+        button: Button, sid: ScriptIid) -> bool
+    {
+        self.delete_control(ui, kind, name.as_ref());
+        let win = if let Some(win) = self.cur_window_mut() {
+            win
+        } else {
+            return false;
+        };
+        let widget = ui.new_widget(win.handle, rect, None, None, button);
+        win.controls.push(Control {
+            kind,
+            name,
+            widget,
+            sid,
+            procs: EnumMap::new(),
+            flags: 0,
+        });
+        true
+    }
+
+    fn with_button(&mut self, ui: &mut Ui, name: &bstr, f: impl FnOnce(&mut Button)) -> bool {
+        if let Some(control) = self.cur_window().and_then(|w| w.control(ControlKind::Button, name)) {
+            f(&mut ui.widget_mut::<Button>(control.widget));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn add_drawing(&mut self, ui: &mut Ui, rect: Rect, background: Option<Sprite>, panel: Panel) {
+        let win = self.cur_window_mut().unwrap();
+        let widget = ui.new_widget(win.handle, rect, None, background, panel);
+        if let Some(control) = win.controls.first() {
+            ui.move_below(widget, control.widget);
+        }
+        win.drawings.push(widget);
+    }
+
+    fn window_idx(&self, name: &bstr) -> Option<usize> {
+        self.windows.iter().position(|w| w.name == name)
+    }
+
+    fn cur_window(&self) -> Option<&Window> {
+        self.cur_window.map(|i| &self.windows[i])
+    }
+
+    fn cur_window_mut(&mut self) -> Option<&mut Window> {
+        self.cur_window.map(move |i| &mut self.windows[i])
+    }
+}
+
+/// Resolves path to an interface art file like `art\intrface\combat.frm`.
+pub fn interface_fid(ui: &Ui, path: &bstr) -> Option<FrameId> {
+    let name = path.as_bytes().rsplit(|&c| c == b'\\' || c == b'/').next().unwrap();
+    let name = std::str::from_utf8(name).ok()?;
+    let id = ui.frm_db().find_id(EntityKind::Interface, name)?;
+    FrameId::new_generic(EntityKind::Interface, id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::test::Harness;
+
+    #[test]
+    fn say_pick() {
+        let mut h = Harness::new();
+        let ui = &mut h.ui;
+        let mut sui = ScriptUi::new();
+        assert!(sui.say_start(h.sid, None));
+        sui.say_reply(Some("r1".into()), "Hello".into());
+        sui.say_option("Next".into(), SayTarget::Reply("r2".into()));
+        sui.say_option("Call".into(), SayTarget::Proc(3));
+        sui.say_reply(Some("r2".into()), "Bye".into());
+        assert!(sui.say_show(ui));
+        assert!(!sui.say_start(h.sid, None));
+
+        let (reply, options) = {
+            let w = sui.say.as_ref().unwrap().widgets.as_ref().unwrap();
+            (w.reply, w.options)
+        };
+        assert!(sui.is_say_widget(options));
+        let texts = |ui: &Ui, h| ui.widget_ref::<MessagePanel>(h).messages()
+            .map(|m| m.to_owned())
+            .collect::<Vec<BString>>();
+        assert_eq!(texts(ui, reply), vec![BString::from("  Hello")]);
+        assert_eq!(texts(ui, options).len(), 2);
+        assert_eq!(sui.say_pick(ui, reply, 0), SayPick::Continue);
+
+        // Option procedure that doesn't jump ends the dialog.
+        assert_eq!(sui.say_pick(ui, options, 1), SayPick::Proc(h.sid, 3));
+        assert!(!sui.say_proc_done(ui));
+        assert!(!sui.is_say_widget(options));
+
+        assert!(sui.say_start(h.sid, None));
+        sui.say_reply(Some("r1".into()), "Hello".into());
+        sui.say_option("Call".into(), SayTarget::Proc(3));
+        sui.say_reply(Some("r2".into()), "Bye".into());
+        assert!(sui.say_show(ui));
+        let (reply, options) = {
+            let w = sui.say.as_ref().unwrap().widgets.as_ref().unwrap();
+            (w.reply, w.options)
+        };
+        assert_eq!(sui.say_pick(ui, options, 0), SayPick::Proc(h.sid, 3));
+        assert!(sui.say_goto_reply(ui, "r2".into()));
+        assert!(sui.say_proc_done(ui));
+        assert_eq!(texts(ui, reply), vec![BString::from("  Bye")]);
+
+        // Reply without options is dismissed by clicking on it.
+        assert_eq!(sui.say_pick(ui, reply, 0), SayPick::End);
+        assert!(sui.say.is_none());
+    }
+}
@@ -7,50 +7,107 @@ use sdl2::event::{Event as SdlEvent};
 use sdl2::keyboard::Keycode;
 use std::cell::RefCell;
 use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, prelude::*};
+use std::path::Path;
 use std::rc::Rc;
 use std::time::{Instant, Duration};
 
 use crate::asset::{self, *};
 use crate::asset::frame::{FrameDb, FrameId};
-use crate::asset::map::{ELEVATION_COUNT, MapId, MapReader};
+use crate::asset::map::{ELEVATION_COUNT, Map, MapId, MapReader, MapWriter, ObjectReader,
+    ObjectWriter};
 use crate::asset::map::db::MapDb;
-use crate::asset::message::{BULLET, Messages};
+use crate::asset::message::{BULLET, MessageId, Messages};
 use crate::asset::proto::*;
+use crate::asset::save::{self, SaveReader, SaveWriter};
 use crate::asset::script::db::ScriptDb;
+use crate::asset::worldmap::WorldMapDb;
+use crate::audio::Audio;
 use crate::fs::FileSystem;
+use crate::game::combat::{self, Combat, HitMode};
 use crate::game::dialog::Dialog;
+use crate::game::encounter::{ConditionContext, EncounterDb, Encounters};
 use crate::game::fidget::Fidget;
+use crate::game::inventory::Inventory;
+use crate::game::loot::{self, Loot};
 use crate::game::object::{self, *};
 use crate::game::rpg::Rpg;
 use crate::game::sequence::ObjSequencer;
 use crate::game::sequence::frame_anim::{AnimDirection, FrameAnim, FrameAnimOptions};
 use crate::game::sequence::move_seq::Move;
 use crate::game::sequence::stand::Stand;
-use crate::game::script::{self, Scripts, ScriptKind};
+use crate::game::script::{self, NewScripts, Scripts, ScriptIid, ScriptKind};
+use crate::game::script_ui::{SayPick, ScriptUi};
 use crate::game::skilldex::{self, Skilldex};
 use crate::game::ui::action_menu::{self, Action};
 use crate::game::ui::hud;
 use crate::game::ui::scroll_area::ScrollArea;
 use crate::game::ui::world::{HexCursorStyle, WorldView};
 use crate::game::world::{ScrollDirection, World};
+use crate::game::worldmap::WorldMap;
 use crate::graphics::{EPoint, Rect};
+use crate::graphics::color::palette::Palette;
 use crate::graphics::font::Fonts;
 use crate::graphics::geometry::hex::{self, Direction};
 use crate::sequence;
 use crate::sequence::event::PushEvent;
 use crate::sequence::chain::Chain;
 use crate::state::{self, *};
+use crate::state::movie::{MovieRequest, MovieState};
+use crate::state::worldmap::WorldMapState;
 use crate::ui::{self, Ui};
-use crate::ui::command::{ObjectPickKind, SkilldexCommand, UiCommand, UiCommandData};
+use crate::ui::command::{BarterCommand, InventoryCommand, LootCommand, ObjectPickKind,
+    SkilldexCommand, UiCommand, UiCommandData};
 use crate::ui::message_panel::MessagePanel;
 use crate::util::{EnumExt, sprintf};
-use crate::util::random::random;
-use crate::vm::{Vm, PredefinedProc, Suspend};
+use crate::util::random::{random, RollCheckResult};
+use crate::vm::{self, InvocationResult, Vm, PredefinedProc, ProcedureId, Suspend};
 
 const SCROLL_STEP: i32 = 10;
 
+/// Message in `proto.msg` shown when the critter can't barter.
+const PROTO_MSG_NO_BARTER: MessageId = 903;
+
+/// Header data of the current map needed to write its state.
+struct CurrentMap {
+    /// Name as in `maps.txt`.
+    name: String,
+    file_name: String,
+    entrance: EPoint,
+    entrance_direction: Direction,
+}
+
+/// App state shown on top of the game, such as a movie or the world map. These need the game
+/// state to be created and to react to their events, so `GameState` owns them instead of the main
+/// loop. The game is paused while any overlay is shown and the topmost one gets all the input,
+/// UI commands and updates.
+enum Overlay {
+    Movie(MovieState),
+    WorldMap(WorldMapState),
+}
+
+impl Overlay {
+    fn app_state(&mut self) -> &mut dyn AppState {
+        match self {
+            Self::Movie(v) => v,
+            Self::WorldMap(v) => v,
+        }
+    }
+
+    fn window(&self) -> ui::Handle {
+        match self {
+            Self::Movie(v) => v.window(),
+            Self::WorldMap(v) => v.window(),
+        }
+    }
+}
+
 pub struct GameState {
     time: PausableTime,
+    /// Time up to which the game time has been advanced.
+    game_time_update: Instant,
     fs: Rc<FileSystem>,
     proto_db: Rc<ProtoDb>,
     frm_db: Rc<FrameDb>,
@@ -62,17 +119,33 @@ pub struct GameState {
     message_panel: ui::Handle,
     world_view: ui::Handle,
     dialog: Option<Dialog>,
+    script_ui: ScriptUi,
+    audio: Audio,
+    palette: Rc<Palette>,
+    /// Overlays from the bottom to the top.
+    overlays: Vec<Overlay>,
+    /// Movie playback change requested by scripts, applied in `update()`.
+    movie_request: Option<MovieRequest>,
+    world_map: Rc<RefCell<WorldMap>>,
+    encounters: Encounters,
     shift_key_down: bool,
     last_picked_obj: Option<object::Handle>,
     object_action_menu: Option<ObjectActionMenu>,
     user_paused: bool,
     map_id: Option<MapId>,
-    in_combat: bool,
+    map: Option<CurrentMap>,
+    /// State of the visited maps keyed by map name. Holds contents of the `.SAV` files.
+    visited_maps: BTreeMap<String, Box<[u8]>>,
+    combat: Combat,
     seq_events: Vec<sequence::Event>,
     misc_msgs: Rc<Messages>,
+    combat_msgs: Messages,
+    inventory_msgs: Messages,
     scroll_areas: EnumMap<ScrollDirection, ui::Handle>,
     rpg: Rpg,
     skilldex: Skilldex,
+    inventory: Inventory,
+    loot: Loot,
 }
 
 impl GameState {
@@ -85,6 +158,8 @@ impl GameState {
         fonts: Rc<Fonts>,
         misc_msgs: Rc<Messages>,
         now: Instant,
+        audio: Audio,
+        palette: Rc<Palette>,
         ui: &mut Ui,
     ) -> Self {
         let time = PausableTime::new(now);
@@ -93,6 +168,8 @@ impl GameState {
         let hex_grid = hex::TileGrid::default();
 
         let critter_names = Messages::read_file(&fs, language, "game/scrname.msg").unwrap();
+        let combat_msgs = Messages::read_file(&fs, language, "game/combat.msg").unwrap();
+        let inventory_msgs = Messages::read_file(&fs, language, "game/inventry.msg").unwrap();
 
         let map_db = MapDb::new(&fs).unwrap();
         let scripts = Scripts::new(
@@ -124,8 +201,18 @@ impl GameState {
 
         let skilldex = Skilldex::new(&fs, language);
 
+        let mut world_map_db = WorldMapDb::new(&fs).unwrap();
+        world_map_db.resolve_map_ids(|name| map_db.find(name));
+        let world_map = WorldMap::new(Rc::new(world_map_db));
+        let encounters = Encounters::new(Rc::new(EncounterDb::new(&fs)
+            .unwrap_or_else(|e| {
+                warn!("couldn't read encounter tables: {}", e);
+                EncounterDb::default()
+            })));
+
         Self {
             time,
+            game_time_update: now,
             fs,
             frm_db,
             proto_db,
@@ -137,17 +224,30 @@ impl GameState {
             message_panel,
             world_view,
             dialog: None,
+            script_ui: ScriptUi::new(),
+            audio,
+            palette,
+            overlays: Vec::new(),
+            movie_request: None,
+            world_map: Rc::new(RefCell::new(world_map)),
+            encounters,
             shift_key_down: false,
             last_picked_obj: None,
             object_action_menu: None,
             user_paused: false,
             map_id: None,
-            in_combat: false,
+            map: None,
+            visited_maps: BTreeMap::new(),
+            combat: Combat::new(),
             seq_events: Vec::new(),
             misc_msgs,
+            combat_msgs,
+            inventory_msgs,
             scroll_areas,
             rpg,
             skilldex,
+            inventory: Inventory::new(),
+            loot: Loot::new(),
         }
     }
 
@@ -163,6 +263,69 @@ impl GameState {
         &self.time
     }
 
+    /// Starts playing movie by path like `art/cuts/intro.mve`. The game is paused until the movie
+    /// ends or is skipped.
+    pub fn push_movie(&mut self, path: &str, ui: &mut Ui) {
+        self.pop_movie(ui);
+        let movie = MovieState::new(&self.fs, path, self.palette.clone(),
+            self.frm_db.texture_factory().clone(), &mut self.audio, ui);
+        match movie {
+            Ok(movie) => {
+                self.audio.set_music_paused(true);
+                self.overlays.push(Overlay::Movie(movie));
+                self.sync_overlay_window(ui);
+            }
+            Err(e) => warn!("couldn't play movie {}: {}", path, e),
+        }
+    }
+
+    /// Stops the playing movie if any.
+    pub fn pop_movie(&mut self, ui: &mut Ui) {
+        if let Some(Overlay::Movie(movie)) =
+            self.take_overlay(|o| matches!(o, Overlay::Movie(_)))
+        {
+            movie.finish(ui);
+            self.audio.set_music_paused(false);
+            self.sync_overlay_window(ui);
+        }
+    }
+
+    /// Shows the world map. The game is paused until the party enters a map.
+    pub fn show_world_map(&mut self, ui: &mut Ui) {
+        if !self.overlays.iter().any(|o| matches!(o, Overlay::WorldMap(_))) {
+            // Movies stay on top.
+            self.overlays.insert(0, Overlay::WorldMap(WorldMapState::new(
+                self.world.clone(), self.world_map.clone(), ui)));
+            self.sync_overlay_window(ui);
+        }
+    }
+
+    /// Hides the world map if it's visible.
+    pub fn hide_world_map(&mut self, ui: &mut Ui) {
+        if let Some(Overlay::WorldMap(world_map_state)) =
+            self.take_overlay(|o| matches!(o, Overlay::WorldMap(_)))
+        {
+            world_map_state.finish(ui);
+            self.sync_overlay_window(ui);
+        }
+    }
+
+    /// Makes the window of the topmost overlay modal.
+    fn sync_overlay_window(&self, ui: &mut Ui) {
+        if let Some(overlay) = self.overlays.last() {
+            ui.set_modal_window(Some(overlay.window()));
+        }
+    }
+
+    fn take_overlay(&mut self, f: impl Fn(&Overlay) -> bool) -> Option<Overlay> {
+        let i = self.overlays.iter().position(f)?;
+        Some(self.overlays.remove(i))
+    }
+
+    pub fn set_script_debugger(&mut self, debugger: vm::debug::Debugger) {
+        self.scripts.set_debugger(debugger);
+    }
+
     pub fn new_game(&mut self) {
         self.scripts.vars.global_vars =
             asset::read_game_global_vars(&mut self.fs.reader("data/vault13.gam").unwrap()).unwrap().into();
@@ -185,6 +348,11 @@ impl GameState {
                 world: &mut self.world.borrow_mut(),
                 obj_sequencer: &mut self.obj_sequencer,
                 dialog: &mut self.dialog,
+                script_ui: &mut self.script_ui,
+                audio: &mut self.audio,
+                movie: &mut self.movie_request,
+                world_map: &mut self.world_map.borrow_mut(),
+                combat: &mut self.combat,
                 message_panel: self.message_panel,
                 ui,
                 map_id,
@@ -196,30 +364,53 @@ impl GameState {
             self.scripts.execute_map_procs(PredefinedProc::MapExit, ctx);
         }
 
-        let mut dude_obj = {
+        self.save_map_state();
+
+        let dude_data = {
             let mut world = self.world.borrow_mut();
-            let dude_obj = world.remove_dude_obj().unwrap();
+            let dude_data = Self::write_dude(&world);
             world.clear();
-            dude_obj
+            dude_data
         };
 
         self.scripts.reset();
+        self.script_ui.clear(ui);
+        self.audio.clear_script_sounds();
         self.obj_sequencer.clear();
+        self.combat.clear();
 
         // Reinsert the hex cursor. Needs `world` to be not borrowed.
         ui.widget_mut::<WorldView>(self.world_view).ensure_hex_cursor();
 
         let world = &mut self.world.borrow_mut();
 
-        let map = MapReader {
-            reader: &mut self.fs.reader(&format!("maps/{}.map", map_name)).unwrap(),
-            objects: world.objects_mut(),
-            proto_db: &self.proto_db,
-            frm_db: &self.frm_db,
-            scripts: &mut self.scripts,
-        }.read().unwrap();
+        let map = {
+            let mut reader: Box<dyn Read> = if let Some(data) = self.visited_maps.get(map_name) {
+                Box::new(&data[..])
+            } else {
+                Box::new(self.fs.reader(&format!("maps/{}.map", map_name)).unwrap())
+            };
+            MapReader {
+                reader: &mut reader,
+                objects: world.objects_mut(),
+                proto_db: &self.proto_db,
+                frm_db: &self.frm_db,
+                scripts: &mut self.scripts,
+            }.read().unwrap()
+        };
 
         self.map_id = Some(map.id);
+        self.map = Some(CurrentMap {
+            name: map_name.into(),
+            file_name: map.name.clone(),
+            entrance: map.entrance,
+            entrance_direction: map.entrance_direction,
+        });
+
+        if let Some(def) = self.map_db.get(map.id) {
+            self.audio.start_map(map.id, def.music.as_deref(), &def.ambient_sfx);
+            self.world_map.borrow_mut().set_current_map(&def.lookup_name);
+        }
 
         for elev in &map.sqr_tiles {
             if let Some(ref elev) = elev {
@@ -252,6 +443,12 @@ impl GameState {
         world.set_sqr_tiles(map.sqr_tiles);
         world.rebuild_light_grid();
 
+        let mut dude_obj = ObjectReader {
+            reader: &mut &dude_data[..],
+            objects: world.objects_mut(),
+            proto_db: &self.proto_db,
+            frm_db: &self.frm_db,
+        }.read_object(true).unwrap();
         dude_obj.direction = map.entrance_direction;
         dude_obj.light_emitter = LightEmitter {
             intensity: 0x10000,
@@ -262,8 +459,9 @@ impl GameState {
 
         world.make_object_standing(dude_obj);
 
-        {
-            assert!(!map.savegame);
+        if map.savegame {
+            self.scripts.vars.map_vars = map.map_vars;
+        } else {
             let path = format!("maps/{}.gam", map_name);
             self.scripts.vars.map_vars = if self.fs.exists(&path) {
                 asset::read_map_global_vars(&mut self.fs.reader(&path).unwrap()).unwrap().into()
@@ -278,6 +476,11 @@ impl GameState {
                 world,
                 obj_sequencer: &mut self.obj_sequencer,
                 dialog: &mut self.dialog,
+                script_ui: &mut self.script_ui,
+                audio: &mut self.audio,
+                movie: &mut self.movie_request,
+                world_map: &mut self.world_map.borrow_mut(),
+                combat: &mut self.combat,
                 message_panel: self.message_panel,
                 ui,
                 map_id: map.id,
@@ -302,6 +505,253 @@ impl GameState {
         world.camera_look_at_dude();
     }
 
+    /// Saves the game session into the slot directory `dir` (e.g. `data/savegame/slot01`).
+    /// The files are written to a temporary directory which then replaces `dir` so a failed save
+    /// leaves the previous one intact.
+    pub fn save_game(&mut self, dir: &Path, save_name: &bstr) -> io::Result<()> {
+        info!("saving game to {}", dir.display());
+
+        let tmp_dir = dir.with_extension("tmp");
+        if tmp_dir.exists() {
+            fs::remove_dir_all(&tmp_dir)?;
+        }
+        fs::create_dir_all(&tmp_dir)?;
+        let r = self.write_save(&tmp_dir, save_name)
+            .and_then(|()| save::replace_dir(&tmp_dir, dir));
+        if r.is_err() {
+            let _ = fs::remove_dir_all(&tmp_dir);
+        }
+        r
+    }
+
+    fn write_save(&self, dir: &Path, save_name: &bstr) -> io::Result<()> {
+        let map = self.map.as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no map is loaded"))?;
+        let map_state = self.write_map_state()?;
+
+        let mut map_files = Vec::new();
+        for (name, data) in &self.visited_maps {
+            if name != &map.name {
+                map_files.push(save::map_file_name(name));
+                fs::write(dir.join(map_files.last().unwrap()), data)?;
+            }
+        }
+        map_files.push(save::map_file_name(&map.name));
+        fs::write(dir.join(map_files.last().unwrap()), map_state)?;
+
+        let world = self.world.borrow();
+        let dude_obj = world.dude_obj()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no dude object"))?;
+        let writer = &mut BufWriter::new(File::create(dir.join(save::SAVE_FILE_NAME))?);
+        SaveWriter {
+            writer,
+            header: &save::Header {
+                player_name: world.dude_name.clone(),
+                save_name: save_name.into(),
+                game_time: world.game_time,
+                map_id: self.map_id.unwrap(),
+                elevation: world.elevation(),
+            },
+            map_name: &map.name,
+            global_vars: &self.scripts.vars.global_vars,
+            map_files: &map_files,
+            objects: world.objects(),
+            dude_obj,
+            rpg: &self.rpg,
+            timer_events: &world.timer_events,
+            encounter_counters: self.encounters.counters(),
+            world_map: &self.world_map.borrow(),
+        }.write()?;
+        writer.flush()
+    }
+
+    /// Loads the game session saved by `save_game()`.
+    pub fn load_game(&mut self, dir: &Path, ui: &mut Ui) -> io::Result<()> {
+        info!("loading game from {}", dir.display());
+
+        let data = fs::read(dir.join(save::SAVE_FILE_NAME))?;
+        let header = save::Header::read(&mut &data[..])?;
+        let map_name = self.map_db.get(header.map_id)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("unknown savegame map: {}", header.map_id)))?
+            .name.clone();
+
+        // Read everything before touching the current state so a broken slot leaves the game
+        // as it was.
+        let mut objects = Objects::new(self.world.borrow().hex_grid().clone(), ELEVATION_COUNT,
+            self.frm_db.clone());
+        let world_map_db = self.world_map.borrow().db().clone();
+        let save = SaveReader {
+            reader: &mut &data[..],
+            objects: &mut objects,
+            proto_db: &self.proto_db,
+            frm_db: &self.frm_db,
+            world_map_db: &world_map_db,
+        }.read()?;
+
+        let mut visited_maps = BTreeMap::new();
+        for file in &save.map_files {
+            let name = save::map_name(file)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                    format!("invalid map file name: {}", file)))?;
+            visited_maps.insert(name, fs::read(dir.join(file))?.into());
+        }
+
+        self.close_windows(ui);
+
+        let (dude_pos, dude_direction) = {
+            let world = &mut self.world.borrow_mut();
+            world.clear();
+            *world.objects_mut() = objects;
+            self.scripts.reset();
+            self.obj_sequencer.clear();
+            self.visited_maps = visited_maps;
+
+            save.dude_stats.apply(self.proto_db.dude().borrow_mut().sub.as_critter_mut().unwrap());
+            self.rpg.set_state(save.rpg_state);
+            self.scripts.vars.global_vars = save.global_vars;
+            world.game_time = save.header.game_time;
+            world.timer_events = save.timer_events;
+            self.encounters.set_counters(save.encounter_counters);
+            self.world_map.borrow_mut().set_state(save.world_map);
+            world.set_dude_name(save.header.player_name);
+
+            let r = (save.dude_obj.pos, save.dude_obj.direction);
+            world.insert_dude_obj(save.dude_obj);
+            r
+        };
+
+        self.map_id = None;
+        self.map = None;
+        self.switch_map(&map_name, ui);
+        if let Some(pos) = dude_pos {
+            self.set_dude_pos(pos, dude_direction, ui);
+        }
+
+        Ok(())
+    }
+
+    /// Closes windows and overlays that refer to the objects of the current session.
+    fn close_windows(&mut self, ui: &mut Ui) {
+        if let Some(dialog) = self.dialog.take() {
+            dialog.hide(ui, &mut self.world.borrow_mut());
+        }
+        if self.inventory.is_visible() {
+            self.inventory.hide(ui);
+        }
+        if self.loot.is_visible() {
+            self.loot.hide(ui);
+        }
+        if self.skilldex.is_visible() {
+            self.skilldex.hide(ui);
+        }
+        if let Some(object_action) = self.object_action_menu.take() {
+            action_menu::hide(object_action.menu, ui);
+            self.time.set_paused(false);
+        }
+        self.last_picked_obj = None;
+        self.script_ui.clear(ui);
+        self.hide_world_map(ui);
+    }
+
+    /// Remembers state of the current map if it's marked as saved in `maps.txt`.
+    fn save_map_state(&mut self) {
+        let name = if let Some(map) = &self.map {
+            map.name.clone()
+        } else {
+            return;
+        };
+        if self.map_db.get(self.map_id.unwrap()).map(|m| m.saved) == Some(true) {
+            let data = self.write_map_state().unwrap();
+            self.visited_maps.insert(name, data.into());
+        }
+    }
+
+    fn write_map_state(&self) -> io::Result<Vec<u8>> {
+        let map = self.map.as_ref().unwrap();
+        let world = self.world.borrow();
+        let mut r = Vec::new();
+        MapWriter {
+            writer: &mut r,
+            map: &Map {
+                id: self.map_id.unwrap(),
+                name: map.file_name.clone(),
+                savegame: true,
+                entrance: map.entrance,
+                entrance_direction: map.entrance_direction,
+                sqr_tiles: world.sqr_tiles().to_vec(),
+                map_vars: self.scripts.vars.map_vars.clone(),
+            },
+            objects: world.objects(),
+            scripts: &self.scripts,
+        }.write()?;
+        Ok(r)
+    }
+
+    /// Serializes the dude object along with its inventory so it can be carried between maps.
+    fn write_dude(world: &World) -> Vec<u8> {
+        let dude_obj = world.dude_obj().unwrap();
+        let mut obj_writer = ObjectWriter::new(world.objects());
+        obj_writer.add(dude_obj);
+        let mut r = Vec::new();
+        obj_writer.write(&mut r, dude_obj).unwrap();
+        r
+    }
+
+    /// Game time flows at the real time rate while the game isn't paused.
+    fn update_game_time(&mut self) {
+        let elapsed = self.time.time() - self.game_time_update;
+        let decis = (elapsed.as_millis() / 100) as u32;
+        if decis > 0 {
+            let world = &mut self.world.borrow_mut();
+            world.game_time = world.game_time.add_decis(decis);
+            self.game_time_update += Duration::from_millis(decis as u64 * 100);
+        }
+    }
+
+    fn handle_timer_events(&mut self, ui: &mut Ui) {
+        let map_id = if let Some(v) = self.map_id {
+            v
+        } else {
+            return;
+        };
+        // Events added by the handlers are left for the next update.
+        let count = self.world.borrow().timer_events.len();
+        for _ in 0..count {
+            let world = &mut self.world.borrow_mut();
+            let game_time = world.game_time;
+            let event = if let Some(v) = world.timer_events.pop_due(map_id, game_time) {
+                v
+            } else {
+                break;
+            };
+            if self.scripts.get(event.sid).is_none() {
+                warn!("dropping timer event of missing script: {:?}", event);
+                continue;
+            }
+            let ctx = &mut script::Context {
+                ui,
+                world,
+                obj_sequencer: &mut self.obj_sequencer,
+                dialog: &mut self.dialog,
+                script_ui: &mut self.script_ui,
+                audio: &mut self.audio,
+                movie: &mut self.movie_request,
+                world_map: &mut self.world_map.borrow_mut(),
+                combat: &mut self.combat,
+                message_panel: self.message_panel,
+                map_id,
+                source_obj: None,
+                target_obj: None,
+                skill: None,
+                rpg: &mut self.rpg,
+            };
+            if let Some(r) = self.scripts.execute_timed_event(event.sid, event.fixed_param, ctx) {
+                r.assert_no_suspend();
+            }
+        }
+    }
+
     fn handle_action(
         &mut self,
         ui: &mut Ui,
@@ -388,7 +838,7 @@ impl GameState {
                     r.push(Action::Rotate);
                 } else {
                     if world.objects().can_talk_to(objh) {
-                        if !self.in_combat {
+                        if !self.combat.is_active() {
                             r.push(Action::Talk);
                         }
                     } else if !obj.proto().unwrap()
@@ -398,7 +848,7 @@ impl GameState {
                         r.push(Action::UseHand);
                     }
                     if world.objects().can_push(world.dude_obj().unwrap(), objh,
-                        &self.scripts, self.in_combat)
+                        &self.scripts, self.combat.is_active())
                     {
                         r.push(Action::Push);
                     }
@@ -455,6 +905,11 @@ impl GameState {
                     world: &mut self.world.borrow_mut(),
                     obj_sequencer: &mut self.obj_sequencer,
                     dialog: &mut self.dialog,
+                    script_ui: &mut self.script_ui,
+                    audio: &mut self.audio,
+                    movie: &mut self.movie_request,
+                    world_map: &mut self.world_map.borrow_mut(),
+                    combat: &mut self.combat,
                     ui,
                     message_panel: self.message_panel,
                     map_id: self.map_id.unwrap(),
@@ -520,6 +975,11 @@ impl GameState {
                     world: &mut self.world.borrow_mut(),
                     obj_sequencer: &mut self.obj_sequencer,
                     dialog: &mut self.dialog,
+                    script_ui: &mut self.script_ui,
+                    audio: &mut self.audio,
+                    movie: &mut self.movie_request,
+                    world_map: &mut self.world_map.borrow_mut(),
+                    combat: &mut self.combat,
                     ui,
                     message_panel: self.message_panel,
                     map_id: self.map_id.unwrap(),
@@ -615,6 +1075,11 @@ impl GameState {
                         world,
                         obj_sequencer: &mut self.obj_sequencer,
                         dialog: &mut self.dialog,
+                        script_ui: &mut self.script_ui,
+                        audio: &mut self.audio,
+                        movie: &mut self.movie_request,
+                        world_map: &mut self.world_map.borrow_mut(),
+                        combat: &mut self.combat,
                         ui,
                         message_panel: self.message_panel,
                         map_id: self.map_id.unwrap(),
@@ -624,9 +1089,12 @@ impl GameState {
                         rpg: &mut self.rpg,
                     }).and_then(|r| r.suspend)
                     {
-                        None | Some(Suspend::GsayEnd) => {}
+                        None | Some(Suspend::GsayEnd) | Some(Suspend::SayEnd) => {}
                     }
             }
+            if let Some(dialog) = self.dialog.as_mut() {
+                dialog.start_requested_barter(ui, world);
+            }
         } else {
             assert_eq!(talker, self.world.borrow().dude_obj().unwrap());
             let msg = &self.misc_msgs.get(2000).unwrap().text;
@@ -691,6 +1159,17 @@ impl GameState {
         if !self.check_next_to(user, used, ui) {
             return;
         }
+        let lootable = {
+            let world = self.world.borrow();
+            let usedo = world.objects().get(used);
+            usedo.item_kind() == Some(ItemKind::Container)
+                || usedo.sub.as_critter().map(|c| c.is_dead()).unwrap_or(false)
+        };
+        if lootable {
+            self.use_container(user, used, ui);
+            return;
+        }
+
         // TODO why different results?
         // if ( user == g_obj_dude )
         //   {
@@ -722,6 +1201,11 @@ impl GameState {
                         world,
                         obj_sequencer: &mut self.obj_sequencer,
                         dialog: &mut self.dialog,
+                        script_ui: &mut self.script_ui,
+                        audio: &mut self.audio,
+                        movie: &mut self.movie_request,
+                        world_map: &mut self.world_map.borrow_mut(),
+                        combat: &mut self.combat,
                         ui,
                         message_panel: self.message_panel,
                         map_id: self.map_id.unwrap(),
@@ -760,6 +1244,92 @@ impl GameState {
         }
     }
 
+    // obj_use_container
+    fn use_container(&mut self, user: object::Handle, container: object::Handle, ui: &mut Ui) {
+        let world = &mut self.world.borrow_mut();
+        if user != world.dude_obj().unwrap() {
+            // TODO Critters looting containers.
+            return;
+        }
+
+        let script = world.objects().get(container).script;
+        if let Some((sid, _)) = script {
+            let script_overrides = self.scripts.execute_predefined_proc(sid, PredefinedProc::Use,
+                &mut script::Context {
+                    world,
+                    obj_sequencer: &mut self.obj_sequencer,
+                    dialog: &mut self.dialog,
+                    script_ui: &mut self.script_ui,
+                    audio: &mut self.audio,
+                    movie: &mut self.movie_request,
+                    world_map: &mut self.world_map.borrow_mut(),
+                    combat: &mut self.combat,
+                    ui,
+                    message_panel: self.message_panel,
+                    map_id: self.map_id.unwrap(),
+                    source_obj: Some(user),
+                    target_obj: Some(container),
+                    skill: None,
+                    rpg: &mut self.rpg,
+                }).map(|r| r.assert_no_suspend().script_overrides).unwrap_or(false);
+            if script_overrides {
+                return;
+            }
+        }
+
+        // TODO Play the container open animation.
+        if !self.loot.is_visible() && !self.loot.show(ui, world.objects(), user, container) {
+            // TODO sfx
+            let msg = &self.proto_db.messages().get(MSG_CONTAINER_LOCKED).unwrap().text;
+            self.push_message(msg, ui);
+        }
+    }
+
+    /// Moves items between the looter and the container if the scripts of the item and the
+    /// container allow it.
+    fn loot_transfer(&mut self, transfer: loot::Transfer, ui: &mut Ui) {
+        let world = &mut self.world.borrow_mut();
+        let looter = world.dude_obj().unwrap();
+
+        let scripts = &mut self.scripts;
+        let obj_sequencer = &mut self.obj_sequencer;
+        let dialog = &mut self.dialog;
+        let script_ui = &mut self.script_ui;
+        let audio = &mut self.audio;
+        let movie = &mut self.movie_request;
+        let world_map = &mut self.world_map.borrow_mut();
+        let combat = &mut self.combat;
+        let rpg = &mut self.rpg;
+        let message_panel = self.message_panel;
+        let map_id = self.map_id.unwrap();
+        self.loot.transfer(world, transfer, |world, obj, proc| {
+            let sid = if let Some((sid, _)) = world.objects().get(obj).script {
+                sid
+            } else {
+                return false;
+            };
+            scripts.execute_predefined_proc(sid, proc,
+                &mut script::Context {
+                    world,
+                    obj_sequencer,
+                    dialog,
+                    script_ui,
+                    audio,
+                    movie,
+                    world_map,
+                    combat,
+                    ui,
+                    message_panel,
+                    map_id,
+                    source_obj: Some(looter),
+                    target_obj: Some(transfer.item),
+                    skill: None,
+                    rpg,
+                }).map(|r| r.assert_no_suspend().script_overrides).unwrap_or(false)
+        });
+        self.loot.sync(ui, world.objects());
+    }
+
     fn use_door(&mut self, user: object::Handle, door: object::Handle, ui: &mut Ui) {
         let world = &mut self.world.borrow_mut();
 
@@ -777,6 +1347,11 @@ impl GameState {
                     world,
                     obj_sequencer: &mut self.obj_sequencer,
                     dialog: &mut self.dialog,
+                    script_ui: &mut self.script_ui,
+                    audio: &mut self.audio,
+                    movie: &mut self.movie_request,
+                    world_map: &mut self.world_map.borrow_mut(),
+                    combat: &mut self.combat,
                     ui,
                     message_panel: self.message_panel,
                     map_id: self.map_id.unwrap(),
@@ -894,6 +1469,75 @@ impl GameState {
         }
     }
 
+    /// Rolls a random encounter from the encounter `table` and enters the encounter map
+    /// populated with the encounter critters. If there's no encounter to enter the party continues
+    /// traveling.
+    fn enter_encounter(&mut self, table: &str, ui: &mut Ui) {
+        let encounter = {
+            let ctx = ConditionContext {
+                global_vars: &self.scripts.vars.global_vars,
+                // TODO use the dude level when leveling is implemented.
+                player_level: 1,
+                game_time: self.world.borrow().game_time,
+            };
+            self.encounters.roll(table, &ctx, &mut rand::thread_rng())
+        };
+        let encounter = if let Some(v) = encounter {
+            v
+        } else {
+            self.world_map.borrow_mut().resume_travel();
+            return;
+        };
+        let map_id = if let Some(v) = self.map_db.find(&encounter.map) {
+            v
+        } else {
+            warn!("unknown random encounter map: {}", encounter.map);
+            self.world_map.borrow_mut().resume_travel();
+            return;
+        };
+
+        self.hide_world_map(ui);
+        let name = self.map_db.get(map_id).unwrap().name.clone();
+        self.switch_map(&name, ui);
+
+        let world = &mut self.world.borrow_mut();
+        let dude_pos = world.objects().get(world.dude_obj().unwrap()).pos.unwrap();
+        let scripted = encounter.populate(dude_pos, world, &self.rpg, &mut rand::thread_rng());
+
+        let mut new_scripts = NewScripts::new(&self.scripts);
+        let sids: Vec<_> = scripted.into_iter()
+            .map(|(objh, prg_id)| {
+                let sid = new_scripts.new_script(ScriptKind::Critter, prg_id);
+                world.objects().get_mut(objh).script = Some((sid, prg_id));
+                (sid, objh)
+            })
+            .collect();
+        new_scripts.instantiate(&mut self.scripts);
+
+        let ctx = &mut script::Context {
+            ui,
+            world,
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            script_ui: &mut self.script_ui,
+            audio: &mut self.audio,
+            movie: &mut self.movie_request,
+            world_map: &mut self.world_map.borrow_mut(),
+            combat: &mut self.combat,
+            message_panel: self.message_panel,
+            map_id,
+            source_obj: None,
+            target_obj: None,
+            skill: None,
+            rpg: &mut self.rpg,
+        };
+        for (sid, objh) in sids {
+            self.scripts.attach_to_object(sid, objh);
+            self.scripts.execute_predefined_proc(sid, PredefinedProc::Start, ctx);
+            self.scripts.execute_predefined_proc(sid, PredefinedProc::MapEnter, ctx);
+        }
+    }
+
     fn set_dude_pos(&mut self, pos: EPoint, direction: Direction, ui: &mut Ui) {
         let world = &mut self.world.borrow_mut();
         let dude_objh = world.dude_obj().unwrap();
@@ -909,6 +1553,11 @@ impl GameState {
                 world,
                 obj_sequencer: &mut self.obj_sequencer,
                 dialog: &mut self.dialog,
+                script_ui: &mut self.script_ui,
+                audio: &mut self.audio,
+                movie: &mut self.movie_request,
+                world_map: &mut self.world_map.borrow_mut(),
+                combat: &mut self.combat,
                 message_panel: self.message_panel,
                 map_id: self.map_id.unwrap(),
                 source_obj: None,
@@ -921,6 +1570,52 @@ impl GameState {
         world.camera_look_at_dude();
     }
 
+    /// Resumes the script suspended in `gsay_end()` which ends the dialog.
+    fn finish_dialog(&mut self, ui: &mut Ui) {
+        let ctx = &mut script::Context {
+            ui,
+            world: &mut self.world.borrow_mut(),
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            script_ui: &mut self.script_ui,
+            audio: &mut self.audio,
+            movie: &mut self.movie_request,
+            world_map: &mut self.world_map.borrow_mut(),
+            combat: &mut self.combat,
+            message_panel: self.message_panel,
+            map_id: self.map_id.unwrap(),
+            source_obj: None,
+            target_obj: None,
+            skill: None,
+            rpg: &mut self.rpg,
+        };
+        self.scripts.resume(ctx).assert_no_suspend();
+        assert!(!self.scripts.can_resume());
+
+        // In original MapUpdate is not always called (see gdialogEnter),
+        // but for now this difference doesn't seem to matter
+        self.scripts.execute_map_procs(PredefinedProc::MapUpdate, ctx);
+    }
+
+    /// Returns `true` while a dialog, a suspended script or a window that takes over the game
+    /// input is shown.
+    fn is_modal_ui_shown(&self) -> bool {
+        self.dialog.is_some()
+            || self.scripts.can_resume()
+            || self.inventory.is_visible()
+            || self.loot.is_visible()
+            || self.skilldex.is_visible()
+            || self.script_ui.is_visible()
+    }
+
+    fn show_inventory(&mut self, ui: &mut Ui) {
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let dude_obj = world.dude_obj().unwrap();
+        let carry_weight = self.rpg.stat(Stat::CarryWeight, &objs.get(dude_obj), objs);
+        self.inventory.show(ui, objs, dude_obj, carry_weight);
+    }
+
     fn show_skilldex(&mut self, ui: &mut Ui, target: Option<object::Handle>) {
         let world = self.world.borrow();
         let dude_obj = world.objects().get(world.dude_obj().unwrap());
@@ -1008,6 +1703,11 @@ impl GameState {
                         world,
                         obj_sequencer: &mut self.obj_sequencer,
                         dialog: &mut self.dialog,
+                        script_ui: &mut self.script_ui,
+                        audio: &mut self.audio,
+                        movie: &mut self.movie_request,
+                        world_map: &mut self.world_map.borrow_mut(),
+                        combat: &mut self.combat,
                         ui,
                         message_panel: self.message_panel,
                         map_id: self.map_id.unwrap(),
@@ -1082,6 +1782,421 @@ impl GameState {
             debug!("TODO");
         }
     }
+
+    // combat()
+    /// Starts combat between the active critters on the dude's elevation. The `first` critter
+    /// takes the first turn.
+    fn start_combat(&mut self, first: Option<object::Handle>, ui: &mut Ui) {
+        if self.combat.is_active() {
+            return;
+        }
+        {
+            let world = self.world.borrow();
+            let elevation = world.elevation();
+            let critters: Vec<_> = world.objects().iter()
+                .filter(|&h| {
+                    let o = world.objects().get(h);
+                    o.pos.map(|p| p.elevation) == Some(elevation)
+                        && o.sub.as_critter().map(|c| c.is_active()).unwrap_or(false)
+                })
+                .collect();
+            for &h in &critters {
+                self.obj_sequencer.cancel(h);
+            }
+            self.combat.start(&self.rpg, world.objects(), first, critters);
+        }
+        debug!("combat started: {:?}", self.combat.combatants());
+
+        self.scripts.execute_procs(PredefinedProc::CombatIsStarting, &mut script::Context {
+            ui,
+            world: &mut self.world.borrow_mut(),
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            script_ui: &mut self.script_ui,
+            audio: &mut self.audio,
+            movie: &mut self.movie_request,
+            world_map: &mut self.world_map.borrow_mut(),
+            combat: &mut self.combat,
+            message_panel: self.message_panel,
+            map_id: self.map_id.unwrap(),
+            source_obj: None,
+            target_obj: None,
+            skill: None,
+            rpg: &mut self.rpg,
+        }, |_| true);
+
+        if let Some(obj) = self.combat.current() {
+            self.begin_combat_turn(obj, ui);
+        }
+    }
+
+    // combat_over()
+    fn end_combat(&mut self, ui: &mut Ui) {
+        if !self.combat.is_active() {
+            return;
+        }
+        debug!("combat is over");
+        self.scripts.execute_procs(PredefinedProc::CombatIsOver, &mut script::Context {
+            ui,
+            world: &mut self.world.borrow_mut(),
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            script_ui: &mut self.script_ui,
+            audio: &mut self.audio,
+            movie: &mut self.movie_request,
+            world_map: &mut self.world_map.borrow_mut(),
+            combat: &mut self.combat,
+            message_panel: self.message_panel,
+            map_id: self.map_id.unwrap(),
+            source_obj: None,
+            target_obj: None,
+            skill: None,
+            rpg: &mut self.rpg,
+        }, |_| true);
+        self.combat.clear();
+    }
+
+    /// Runs `combat_p_proc` of the critter whose turn has started. The turn is skipped if the
+    /// script overrides it.
+    fn begin_combat_turn(&mut self, obj: object::Handle, ui: &mut Ui) {
+        let world = &mut self.world.borrow_mut();
+        if Some(obj) == world.dude_obj() {
+            return;
+        }
+        let script = world.objects().get(obj).script;
+        if let Some((sid, _)) = script {
+            let script_overrides = self.scripts.execute_combat_proc(sid,
+                combat::COMBAT_SUBTYPE_TURN,
+                &mut script::Context {
+                    world,
+                    obj_sequencer: &mut self.obj_sequencer,
+                    dialog: &mut self.dialog,
+                    script_ui: &mut self.script_ui,
+                    audio: &mut self.audio,
+                    movie: &mut self.movie_request,
+                    world_map: &mut self.world_map.borrow_mut(),
+                    combat: &mut self.combat,
+                    ui,
+                    message_panel: self.message_panel,
+                    map_id: self.map_id.unwrap(),
+                    source_obj: None,
+                    target_obj: None,
+                    skill: None,
+                    rpg: &mut self.rpg,
+                }).map(|r| r.assert_no_suspend().script_overrides).unwrap_or(false);
+            if script_overrides {
+                let ap = self.combat.action_points(obj);
+                self.combat.spend_action_points(obj, ap);
+            }
+        }
+    }
+
+    /// Passes the turn to the next combatant. Ends the combat if nobody is hostile anymore.
+    fn end_combat_turn(&mut self, ui: &mut Ui) {
+        let next = {
+            let world = self.world.borrow();
+            if !self.combat.has_hostiles(world.objects()) {
+                None
+            } else {
+                self.combat.end_turn(&self.rpg, world.objects())
+            }
+        };
+        if let Some(next) = next {
+            self.begin_combat_turn(next, ui);
+        } else {
+            self.end_combat(ui);
+        }
+    }
+
+    fn update_combat(&mut self, ui: &mut Ui) {
+        for request in self.combat.take_attack_requests() {
+            self.combat.accept_attack_request(&request);
+            if !self.combat.is_active() {
+                self.start_combat(Some(request.attacker), ui);
+            }
+        }
+        if self.combat.take_end_request() {
+            self.end_combat(ui);
+        }
+        if !self.combat.is_active() {
+            return;
+        }
+
+        // Wait for the animations of the previous action to finish.
+        if self.combat.combatants().iter().any(|&h| self.obj_sequencer.is_running(h)) {
+            return;
+        }
+
+        let (current, dude_obj) = {
+            let world = self.world.borrow();
+            (self.combat.current(), world.dude_obj().unwrap())
+        };
+        let current = if let Some(v) = current {
+            v
+        } else {
+            self.end_combat(ui);
+            return;
+        };
+        if current == dude_obj {
+            let dude_dead = self.world.borrow().objects().get(dude_obj)
+                .sub.as_critter().unwrap().is_dead();
+            if dude_dead {
+                self.end_combat(ui);
+            } else if self.combat.action_points(dude_obj) == 0 {
+                self.end_combat_turn(ui);
+            }
+        } else {
+            self.combat_ai_turn(current, ui);
+        }
+    }
+
+    // combat_ai()
+    /// Makes a single action of the critter: attacks its target if it's in range or moves
+    /// closer to it. Ends the turn if there's nothing to do.
+    fn combat_ai_turn(&mut self, obj: object::Handle, ui: &mut Ui) {
+        let action = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            self.combat.target(obj)
+                .filter(|&t| objs.contains(t)
+                    && objs.get(t).sub.as_critter().map(|c| !c.is_dead()).unwrap_or(false))
+                .and_then(|target| {
+                    let info = combat::attack_info(&self.rpg, objs, obj, HitMode::Primary)?;
+                    let distance = objs.distance(obj, target)?;
+                    let in_range = distance <= info.range
+                        && !(info.is_ranged() && objs.is_shot_blocked(obj, target));
+                    Some((target, in_range))
+                })
+        };
+        let done = match action {
+            Some((target, true)) => !self.combat_attack(obj, target, HitMode::Primary, ui),
+            Some((target, false)) => {
+                let path = self.world.borrow().objects().path(obj, PathTo::Object(target), true);
+                // The path ends at the target's hex.
+                !path.map(|mut p| {
+                    p.pop();
+                    self.combat_move(obj, &p)
+                }).unwrap_or(false)
+            }
+            None => true,
+        };
+        if done {
+            self.end_combat_turn(ui);
+        }
+    }
+
+    /// Moves the critter along `path` as far as its action points allow. Returns `false` if the
+    /// critter can't move.
+    fn combat_move(&mut self, obj: object::Handle, path: &[Direction]) -> bool {
+        let steps = cmp::min(path.len(),
+            (self.combat.action_points(obj) / combat::MOVE_AP_COST) as usize);
+        if steps == 0 {
+            return false;
+        }
+        let world = self.world.borrow();
+        let mut point = world.objects().get(obj).pos.unwrap().point;
+        for &direction in &path[..steps] {
+            point = world.hex_grid().go(point, direction, 1).unwrap();
+        }
+        self.combat.spend_action_points(obj, steps as i32 * combat::MOVE_AP_COST);
+
+        let seq = Chain::new();
+        seq.control()
+            .cancellable(Move::new(obj, PathTo::Point {
+                point,
+                neighbor_if_blocked: false,
+            }, CritterAnim::Walk))
+            .finalizing(Stand::new(obj));
+        self.obj_sequencer.replace(obj, seq);
+        true
+    }
+
+    // combat_attack()
+    /// Spends action points and ammo on the attack and applies its result. Returns `false` if
+    /// the attack can't be made.
+    fn combat_attack(&mut self,
+        attacker: object::Handle,
+        target: object::Handle,
+        mode: HitMode,
+        ui: &mut Ui,
+    ) -> bool {
+        let (info, result, killed) = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            let info = if let Some(v) = combat::attack_info(&self.rpg, objs, attacker, mode) {
+                v
+            } else {
+                return false;
+            };
+            if self.combat.action_points(attacker) < info.ap_cost
+                || !combat::consume_ammo(objs, info.weapon)
+            {
+                return false;
+            }
+            self.combat.spend_action_points(attacker, info.ap_cost);
+            let options = self.combat.take_attack_options(attacker);
+            let result = combat::roll_attack(&self.rpg, objs, world.game_time.roll_checker(),
+                attacker, target, &info, &options);
+            debug!("{:?} attacks {:?}: {:?}", attacker, target, result);
+            let killed = combat::apply_damage(objs, target, result.damage, result.target_flags);
+            (info, result, killed)
+        };
+
+        // The attacked critter fights back.
+        self.combat.set_target(attacker, target);
+        self.combat.set_target(target, attacker);
+        self.combat.add(target);
+        if result.roll == RollCheckResult::CriticalFailure {
+            let ap = self.combat.action_points(attacker);
+            self.combat.spend_action_points(attacker, ap);
+        }
+
+        if let Some(msg) = result.message.and_then(|id| self.combat_msgs.get(id)) {
+            self.push_message(&msg.text, ui);
+        }
+
+        self.animate_attack(attacker, target, &info, result.roll.is_success(), killed);
+
+        let script = self.world.borrow().objects().get(target).script;
+        if let Some((sid, _)) = script {
+            let proc = if killed {
+                Some(PredefinedProc::Destroy)
+            } else if result.damage > 0 {
+                Some(PredefinedProc::Damage)
+            } else {
+                None
+            };
+            if let Some(proc) = proc {
+                if let Some(r) = self.scripts.execute_predefined_proc(sid, proc,
+                    &mut script::Context {
+                        world: &mut self.world.borrow_mut(),
+                        obj_sequencer: &mut self.obj_sequencer,
+                        dialog: &mut self.dialog,
+                        script_ui: &mut self.script_ui,
+                        audio: &mut self.audio,
+                        movie: &mut self.movie_request,
+                        world_map: &mut self.world_map.borrow_mut(),
+                        combat: &mut self.combat,
+                        ui,
+                        message_panel: self.message_panel,
+                        map_id: self.map_id.unwrap(),
+                        source_obj: Some(attacker),
+                        target_obj: Some(target),
+                        skill: None,
+                        rpg: &mut self.rpg,
+                    })
+                {
+                    r.assert_no_suspend();
+                }
+            }
+        }
+        true
+    }
+
+    fn animate_attack(&mut self,
+        attacker: object::Handle,
+        target: object::Handle,
+        info: &combat::AttackInfo,
+        hit: bool,
+        killed: bool,
+    ) {
+        let world = self.world.borrow();
+        let objs = world.objects();
+        let frm_db = &self.frm_db;
+        let has_anim = |obj: object::Handle, anim: CritterAnim| {
+            objs.get(obj).fid.critter()
+                .map(|fid| frm_db.exists(fid.with_anim(anim).into()))
+                .unwrap_or(false)
+        };
+
+        {
+            let (attacker_pos, target_pos) = (objs.get(attacker).pos, objs.get(target).pos);
+            if let (Some(a), Some(t)) = (attacker_pos, target_pos) {
+                if a.point != t.point {
+                    objs.get_mut(attacker).direction = hex::direction(a.point, t.point);
+                }
+            }
+        }
+
+        let anim = match info.kind {
+            AttackKind::Punch => CritterAnim::ThrowPunch,
+            AttackKind::Kick => CritterAnim::KickLeg,
+            AttackKind::Swing => CritterAnim::SwingAnim,
+            AttackKind::Thrust => CritterAnim::ThrustAnim,
+            AttackKind::Throw => CritterAnim::ThrowAnim,
+            AttackKind::FireSingle | AttackKind::Stand => CritterAnim::FireSingle,
+            AttackKind::FireBurst => CritterAnim::FireBurst,
+            AttackKind::FireContinuous => CritterAnim::FireContinuous,
+        };
+        if has_anim(attacker, anim) {
+            let seq = Chain::new();
+            seq.control()
+                .cancellable(FrameAnim::new(attacker,
+                    FrameAnimOptions { anim: Some(anim), ..Default::default() }))
+                .finalizing(Stand::new(attacker));
+            self.obj_sequencer.replace(attacker, seq);
+        }
+
+        let anim = if killed {
+            CritterAnim::FallBack
+        } else {
+            CritterAnim::HitFromFront
+        };
+        if hit && has_anim(target, anim) {
+            let seq = Chain::new();
+            seq.control().cancellable(FrameAnim::new(target,
+                FrameAnimOptions { anim: Some(anim), ..Default::default() }));
+            if !killed {
+                seq.control().finalizing(Stand::new(target));
+            }
+            self.obj_sequencer.replace(target, seq);
+        }
+    }
+
+    /// Attacks the `target` with the dude if it's the dude's turn.
+    fn dude_attack(&mut self, target: object::Handle, ui: &mut Ui) {
+        let dude_obj = self.world.borrow().dude_obj().unwrap();
+        if self.combat.current() != Some(dude_obj) || self.obj_sequencer.is_running(dude_obj)
+            || target == dude_obj
+        {
+            return;
+        }
+        let in_range = {
+            let world = self.world.borrow();
+            let objs = world.objects();
+            combat::attack_info(&self.rpg, objs, dude_obj, HitMode::Primary)
+                .map(|info| objs.distance(dude_obj, target).map(|d| d <= info.range) == Some(true)
+                    && !(info.is_ranged() && objs.is_shot_blocked(dude_obj, target)))
+                .unwrap_or(false)
+        };
+        if in_range {
+            self.combat_attack(dude_obj, target, HitMode::Primary, ui);
+        } else {
+            debug!("{:?} is out of range", target);
+        }
+    }
+
+    fn execute_script_proc(&mut self, sid: ScriptIid, proc_id: ProcedureId, ui: &mut Ui)
+        -> InvocationResult
+    {
+        self.scripts.execute_proc(sid, proc_id, &mut script::Context {
+            ui,
+            world: &mut self.world.borrow_mut(),
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            script_ui: &mut self.script_ui,
+            audio: &mut self.audio,
+            movie: &mut self.movie_request,
+            world_map: &mut self.world_map.borrow_mut(),
+            combat: &mut self.combat,
+            message_panel: self.message_panel,
+            map_id: self.map_id.unwrap(),
+            source_obj: None,
+            target_obj: None,
+            skill: None,
+            rpg: &mut self.rpg,
+        })
+    }
 }
 
 impl AppState for GameState {
@@ -1102,15 +2217,68 @@ impl AppState for GameState {
                         }
                         self.set_dude_pos(pos, direction, ctx.ui);
                     }
-                    TargetMap::WorldMap(k) => {
-                        warn!("map exit to {:?} is not implemented", k);
+                    // TODO Town kind should show the town map of the current area.
+                    TargetMap::WorldMap(_) => self.show_world_map(ctx.ui),
+                }
+            }
+            AppEvent::EnterMap { map, location } => {
+                self.hide_world_map(ctx.ui);
+                if let Some(map_id) = self.map_db.find(&map) {
+                    if self.map_id != Some(map_id) {
+                        let name = self.map_db.get(map_id).unwrap().name.clone();
+                        self.switch_map(&name, ctx.ui);
                     }
+                    if let Some((pos, direction)) = location {
+                        self.set_dude_pos(pos, direction, ctx.ui);
+                    }
+                } else {
+                    warn!("unknown world map entrance map: {}", map);
                 }
             }
+            AppEvent::Encounter { table } => {
+                self.enter_encounter(&table, ctx.ui);
+            }
         }
     }
 
     fn handle_input(&mut self, event: &SdlEvent, ui: &mut Ui) -> bool {
+        if let Some(overlay) = self.overlays.last_mut() {
+            return overlay.app_state().handle_input(event, ui);
+        }
+
+        match event {
+            SdlEvent::KeyDown { keycode: Some(Keycode::Space), .. } |
+            SdlEvent::KeyDown { keycode: Some(Keycode::Return), .. }
+                if self.is_modal_ui_shown() => return true,
+            SdlEvent::KeyDown { keycode: Some(Keycode::Space), .. } => {
+                let dude_obj = self.world.borrow().dude_obj().unwrap();
+                if !self.combat.is_active() {
+                    self.start_combat(Some(dude_obj), ui);
+                } else if self.combat.current() == Some(dude_obj)
+                    && !self.obj_sequencer.is_running(dude_obj)
+                {
+                    self.end_combat_turn(ui);
+                }
+                return true;
+            }
+            SdlEvent::KeyDown { keycode: Some(Keycode::Return), .. } => {
+                let hostile = self.combat.has_hostiles(self.world.borrow().objects());
+                if self.combat.is_active() && !hostile {
+                    self.end_combat(ui);
+                }
+                return true;
+            }
+            SdlEvent::KeyDown { keycode: Some(Keycode::I), .. } => {
+                if self.inventory.is_visible() {
+                    self.inventory.hide(ui);
+                } else if !self.is_modal_ui_shown() {
+                    self.show_inventory(ui);
+                }
+                return true;
+            }
+            _ => {}
+        }
+
         let mut world = self.world.borrow_mut();
         match event {
             SdlEvent::KeyDown { keycode: Some(Keycode::Right), .. } => {
@@ -1181,6 +2349,11 @@ impl AppState for GameState {
     }
 
     fn handle_ui_command(&mut self, command: UiCommand, ui: &mut Ui) {
+        // Overlay windows are modal so all commands come from them.
+        if let Some(overlay) = self.overlays.last_mut() {
+            overlay.app_state().handle_ui_command(command, ui);
+            return;
+        }
         match command.data {
             UiCommandData::ObjectPick { kind, obj: objh } => {
                 let actions = self.actions(objh);
@@ -1211,6 +2384,11 @@ impl AppState for GameState {
 
                         self.time.set_paused(true);
                     }
+                    ObjectPickKind::DefaultAction if self.combat.is_active()
+                        && self.world.borrow().objects().get(objh).kind() == EntityKind::Critter =>
+                    {
+                        self.dude_attack(objh, ui);
+                    }
                     ObjectPickKind::DefaultAction => if let Some(a) = default_action {
                         ui.widget_mut::<WorldView>(self.world_view).default_action_icon = if self.object_action_menu.is_none() {
                             default_action
@@ -1225,7 +2403,20 @@ impl AppState for GameState {
                 }
             }
             UiCommandData::HexPick { action, pos } => {
-                if action {
+                if action && self.combat.is_active() {
+                    let dude_obj = self.world.borrow().dude_obj().unwrap();
+                    if self.combat.current() == Some(dude_obj)
+                        && !self.obj_sequencer.is_running(dude_obj)
+                    {
+                        let path = self.world.borrow().objects().path(dude_obj, PathTo::Point {
+                            point: pos.point,
+                            neighbor_if_blocked: true,
+                        }, true);
+                        if let Some(path) = path {
+                            self.combat_move(dude_obj, &path);
+                        }
+                    }
+                } else if action {
                     let dude_objh = self.world.borrow().dude_obj().unwrap();
 
                     let seq = Chain::new();
@@ -1263,6 +2454,36 @@ impl AppState for GameState {
                 action_menu::hide(object_action.menu, ui);
                 self.time.set_paused(false);
             }
+            UiCommandData::Pick { id } if self.script_ui.is_say_widget(command.source) => {
+                let finished = match self.script_ui.say_pick(ui, command.source, id) {
+                    SayPick::Continue => false,
+                    SayPick::Proc(sid, proc_id) => {
+                        let _ = self.execute_script_proc(sid, proc_id, ui);
+                        !self.script_ui.say_proc_done(ui)
+                    }
+                    SayPick::End => true,
+                };
+                if finished && self.scripts.can_resume() {
+                    // Resume the script suspended in sayend().
+                    let _ = self.scripts.resume(&mut script::Context {
+                        ui,
+                        world: &mut self.world.borrow_mut(),
+                        obj_sequencer: &mut self.obj_sequencer,
+                        dialog: &mut self.dialog,
+                        script_ui: &mut self.script_ui,
+                        audio: &mut self.audio,
+                        movie: &mut self.movie_request,
+                        world_map: &mut self.world_map.borrow_mut(),
+                        combat: &mut self.combat,
+                        message_panel: self.message_panel,
+                        map_id: self.map_id.unwrap(),
+                        source_obj: None,
+                        target_obj: None,
+                        skill: None,
+                        rpg: &mut self.rpg,
+                    });
+                }
+            }
             UiCommandData::Pick { id } => {
                 let (sid, proc_id) = {
                     let dialog = self.dialog.as_mut().unwrap();
@@ -1283,6 +2504,11 @@ impl AppState for GameState {
                             world,
                             obj_sequencer: &mut self.obj_sequencer,
                             dialog: &mut self.dialog,
+                            script_ui: &mut self.script_ui,
+                            audio: &mut self.audio,
+                            movie: &mut self.movie_request,
+                            world_map: &mut self.world_map.borrow_mut(),
+                            combat: &mut self.combat,
                             message_panel: self.message_panel,
                             map_id: self.map_id.unwrap(),
                             source_obj,
@@ -1291,29 +2517,46 @@ impl AppState for GameState {
                             rpg: &mut self.rpg,
                         }).assert_no_suspend();
                     // No dialog options means the dialog is finished.
-                    self.dialog.as_ref().unwrap().is_empty()
+                    let dialog = self.dialog.as_mut().unwrap();
+                    !dialog.start_requested_barter(ui, world) && dialog.is_empty()
                 } else {
                     true
                 };
                 if finished {
-                    let ctx = &mut script::Context {
-                        ui,
-                        world: &mut self.world.borrow_mut(),
-                        obj_sequencer: &mut self.obj_sequencer,
-                        dialog: &mut self.dialog,
-                        message_panel: self.message_panel,
-                        map_id: self.map_id.unwrap(),
-                        source_obj: None,
-                        target_obj: None,
-                        skill: None,
-                        rpg: &mut self.rpg,
+                    self.finish_dialog(ui);
+                }
+            }
+            UiCommandData::Barter(cmd) => match cmd {
+                BarterCommand::Start => {
+                    let dialog = self.dialog.as_mut().unwrap();
+                    if !dialog.is_bartering() {
+                        let world = &mut self.world.borrow_mut();
+                        if world.objects().get(dialog.obj).can_barter() {
+                            dialog.start_barter(ui, world);
+                        } else if let Some(msg) =
+                            self.proto_db.messages().get(PROTO_MSG_NO_BARTER)
+                        {
+                            dialog.set_reply(ui, &msg.text);
+                        }
+                    }
+                }
+                BarterCommand::Offer => {
+                    let world = &mut self.world.borrow_mut();
+                    self.dialog.as_mut().unwrap().offer(ui, world, &self.rpg,
+                        &self.inventory_msgs);
+                }
+                BarterCommand::Talk => {
+                    let finished = {
+                        let dialog = self.dialog.as_mut().unwrap();
+                        dialog.end_barter(ui, &mut self.world.borrow_mut());
+                        dialog.is_empty()
                     };
-                    self.scripts.resume(ctx).assert_no_suspend();
-                    assert!(!self.scripts.can_resume());
-
-                    // In original MapUpdate is not always called (see gdialogEnter),
-                    // but for now this difference doesn't seem to matter
-                    self.scripts.execute_map_procs(PredefinedProc::MapUpdate, ctx);
+                    if finished {
+                        self.finish_dialog(ui);
+                    }
+                }
+                BarterCommand::Scroll { list, up } => {
+                    self.dialog.as_mut().unwrap().scroll_barter_list(ui, list, up);
                 }
             }
             UiCommandData::Scroll => {
@@ -1324,6 +2567,45 @@ impl AppState for GameState {
                 let scrolled = self.world.borrow_mut().scroll(dir, 1) > 0;
                 ui.widget_mut::<ScrollArea>(*widg).set_enabled(scrolled);
             }
+            UiCommandData::ScriptControl { trigger } => {
+                if let Some((sid, proc_id)) = self.script_ui.callback(command.source, trigger) {
+                    let _ = self.execute_script_proc(sid, proc_id, ui);
+                }
+            }
+            UiCommandData::Inventory(cmd) => match cmd {
+                InventoryCommand::Hide => self.inventory.hide(ui),
+                InventoryCommand::Show => self.show_inventory(ui),
+                InventoryCommand::Scroll { up } => self.inventory.scroll(ui, up),
+                InventoryCommand::Drop { obj, pos } => if self.loot.is_visible() {
+                    let transfer = self.loot.drop(ui, self.world.borrow().objects(),
+                        command.source, obj, pos);
+                    if let Some(transfer) = transfer {
+                        self.loot_transfer(transfer, ui);
+                    }
+                } else {
+                    let world = &mut self.world.borrow_mut();
+                    if self.inventory.is_visible() {
+                        self.inventory.drop(ui, world.objects_mut(), command.source, obj, pos);
+                    } else if let Some(dialog) = self.dialog.as_mut() {
+                        dialog.barter_drop(ui, world, command.source, obj, pos);
+                    }
+                }
+            }
+            UiCommandData::Loot(cmd) => match cmd {
+                LootCommand::Hide => self.loot.hide(ui),
+                LootCommand::TakeAll => {
+                    let transfers = self.loot.take_all(self.world.borrow().objects());
+                    for transfer in transfers {
+                        self.loot_transfer(transfer, ui);
+                    }
+                }
+                LootCommand::Scroll { container, up } => self.loot.scroll(ui, container, up),
+                LootCommand::Quantity(cmd) => {
+                    if let Some(transfer) = self.loot.handle_quantity(ui, cmd) {
+                        self.loot_transfer(transfer, ui);
+                    }
+                }
+            }
             UiCommandData::Skilldex(cmd) => match cmd {
                 SkilldexCommand::Cancel => self.skilldex.hide(ui),
                 SkilldexCommand::Show => {
@@ -1338,16 +2620,44 @@ impl AppState for GameState {
                     }
                 }
             }
+            // Handled by the world map state.
+            UiCommandData::WorldMap(_) => {}
         }
     }
 
     fn update(&mut self, mut ctx: state::Update) {
+        self.audio.update(ctx.delta);
+
+        match self.movie_request.take() {
+            Some(MovieRequest::Play(path)) => self.push_movie(&path, ctx.ui),
+            Some(MovieRequest::Stop) => self.pop_movie(ctx.ui),
+            None => {}
+        }
+        if self.world_map.borrow_mut().take_show_request() {
+            self.show_world_map(ctx.ui);
+        }
+        if let Some(overlay) = self.overlays.last_mut() {
+            overlay.app_state().update(state::Update {
+                delta: ctx.delta,
+                ui: ctx.ui,
+                out: ctx.out,
+            });
+            if let Overlay::Movie(movie) = overlay {
+                if movie.is_done() {
+                    self.pop_movie(ctx.ui);
+                }
+            }
+            return;
+        }
+
         self.time.update(ctx.delta);
 
         self.time.set_paused(
             self.user_paused ||
             self.scripts.can_resume() ||
-            self.skilldex.is_visible());
+            self.skilldex.is_visible() ||
+            self.inventory.is_visible() ||
+            self.loot.is_visible());
 
         if self.time.is_running() {
             {
@@ -1355,6 +2665,9 @@ impl AppState for GameState {
                 world.update(self.time.time());
             }
 
+            self.update_game_time();
+            self.handle_timer_events(ctx.ui);
+
             const MAX_ITERS: u32 = 1000;
             for i in 0..MAX_ITERS {
                 assert!(i < MAX_ITERS - 1, "infinite loop in sequencer updating - event handling");
@@ -1373,10 +2686,14 @@ impl AppState for GameState {
                 self.handle_seq_events(&mut ctx);
             }
 
-            self.fidget.update(
-                self.time.time(),
-                &mut self.world.borrow_mut(),
-                &mut self.obj_sequencer);
+            if self.combat.is_active() || self.combat.has_attack_requests() {
+                self.update_combat(ctx.ui);
+            } else {
+                self.fidget.update(
+                    self.time.time(),
+                    &mut self.world.borrow_mut(),
+                    &mut self.obj_sequencer);
+            }
         } else {
             self.obj_sequencer.sync(&mut sequence::Sync {
                 world: &mut self.world.borrow_mut(),
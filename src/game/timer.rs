@@ -0,0 +1,155 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::convert::TryFrom;
+use std::io::{self, Error, ErrorKind, prelude::*};
+
+use crate::asset::map::MapId;
+use crate::game::GameTime;
+use crate::game::script::ScriptIid;
+
+/// Event that triggers `timed_event_p_proc` of the script.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TimerEvent {
+    pub time: GameTime,
+    /// Map the script belongs to. Events of other maps are kept until the map is loaded.
+    pub map_id: MapId,
+    pub sid: ScriptIid,
+    /// Value available to the script via `fixed_param`.
+    pub fixed_param: i32,
+}
+
+/// Timer events ordered by game time.
+#[derive(Default)]
+pub struct TimerEvents {
+    events: Vec<TimerEvent>,
+}
+
+impl TimerEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&TimerEvent> {
+        self.events.iter()
+    }
+
+    /// Adds the event after any other events with the same time.
+    pub fn add(&mut self, event: TimerEvent) {
+        let i = self.events.iter().position(|e| e.time > event.time).unwrap_or(self.events.len());
+        self.events.insert(i, event);
+    }
+
+    /// Removes all events matching the predicate.
+    pub fn remove(&mut self, mut f: impl FnMut(&TimerEvent) -> bool) {
+        self.events.retain(|e| !f(e));
+    }
+
+    /// Removes and returns the earliest event of the map `map_id` that is due at `time`.
+    pub fn pop_due(&mut self, map_id: MapId, time: GameTime) -> Option<TimerEvent> {
+        let i = self.events.iter()
+            .take_while(|e| e.time <= time)
+            .position(|e| e.map_id == map_id)?;
+        Some(self.events.remove(i))
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn read(rd: &mut impl Read) -> io::Result<Self> {
+        let mut r = Self::new();
+        let count = rd.read_i32::<BigEndian>()?;
+        for _ in 0..count {
+            let time = GameTime::from_decis(rd.read_u32::<BigEndian>()?);
+            let map_id = rd.read_u32::<BigEndian>()?;
+            let sid = ScriptIid::read(rd)?;
+            let fixed_param = rd.read_i32::<BigEndian>()?;
+            r.add(TimerEvent {
+                time,
+                map_id,
+                sid,
+                fixed_param,
+            });
+        }
+        Ok(r)
+    }
+
+    pub fn write(&self, wr: &mut impl Write) -> io::Result<()> {
+        let count = i32::try_from(self.events.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "too many timer events"))?;
+        wr.write_i32::<BigEndian>(count)?;
+        for e in &self.events {
+            wr.write_u32::<BigEndian>(e.time.as_decis())?;
+            wr.write_u32::<BigEndian>(e.map_id)?;
+            wr.write_u32::<BigEndian>(e.sid.pack())?;
+            wr.write_i32::<BigEndian>(e.fixed_param)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::script::ScriptKind;
+
+    fn event(time: u32, map_id: MapId, fixed_param: i32) -> TimerEvent {
+        TimerEvent {
+            time: GameTime::from_decis(time),
+            map_id,
+            sid: ScriptIid::new(ScriptKind::Critter, 1),
+            fixed_param,
+        }
+    }
+
+    fn params(events: &TimerEvents) -> Vec<i32> {
+        events.iter().map(|e| e.fixed_param).collect()
+    }
+
+    #[test]
+    fn ordering() {
+        let mut t = TimerEvents::new();
+        t.add(event(20, 1, 1));
+        t.add(event(10, 1, 2));
+        t.add(event(20, 1, 3));
+        t.add(event(15, 2, 4));
+        assert_eq!(params(&t), &[2, 4, 1, 3]);
+
+        let now = GameTime::from_decis(20);
+        assert_eq!(t.pop_due(1, now).map(|e| e.fixed_param), Some(2));
+        assert_eq!(t.pop_due(1, now).map(|e| e.fixed_param), Some(1));
+        assert_eq!(t.pop_due(1, now).map(|e| e.fixed_param), Some(3));
+        assert_eq!(t.pop_due(1, now), None);
+        assert_eq!(t.pop_due(2, GameTime::from_decis(14)), None);
+        assert_eq!(t.pop_due(2, now).map(|e| e.fixed_param), Some(4));
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn remove() {
+        let mut t = TimerEvents::new();
+        t.add(event(1, 1, 1));
+        t.add(event(2, 1, 2));
+        t.add(event(3, 1, 1));
+        t.remove(|e| e.fixed_param == 1);
+        assert_eq!(params(&t), &[2]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut t = TimerEvents::new();
+        t.add(event(5, 1, -1));
+        t.add(event(3, 2, 7));
+        let mut data = Vec::new();
+        t.write(&mut data).unwrap();
+        let act = TimerEvents::read(&mut &data[..]).unwrap();
+        assert_eq!(act.iter().collect::<Vec<_>>(), t.iter().collect::<Vec<_>>());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod action_menu;
 pub mod hud;
+pub mod inventory_list;
 pub mod scroll_area;
 pub mod world;
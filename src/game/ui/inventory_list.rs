@@ -0,0 +1,157 @@
+use crate::asset::frame::FrameId;
+use crate::game::object;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::WHITE;
+use crate::graphics::font::FontKey;
+use crate::graphics::sprite::Sprite;
+use crate::ui::*;
+use crate::ui::command::{InventoryCommand, UiCommandData};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Item {
+    pub object: object::Handle,
+    pub fid: FrameId,
+    pub count: u32,
+}
+
+impl Item {
+    pub fn new(item: &object::InventoryItem, objs: &object::Objects) -> Self {
+        let obj = objs.get(item.object);
+        let fid = obj.proto().unwrap().sub.as_item()
+            .and_then(|i| i.inventory_fid)
+            .unwrap_or(obj.fid);
+        Self {
+            object: item.object,
+            fid,
+            count: item.count,
+        }
+    }
+}
+
+/// Vertical list of inventory item icons. Items can be dragged out of the list, dropping an item
+/// emits `InventoryCommand::Drop` with the screen position where the item was dropped.
+pub struct InventoryList {
+    items: Vec<Item>,
+    item_height: i32,
+    scroll_idx: usize,
+    dragging: Option<usize>,
+}
+
+impl InventoryList {
+    pub fn new(item_height: i32) -> Self {
+        Self {
+            items: Vec::new(),
+            item_height,
+            scroll_idx: 0,
+            dragging: None,
+        }
+    }
+
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    pub fn set_items(&mut self, items: Vec<Item>) {
+        self.items = items;
+        self.dragging = None;
+        if self.scroll_idx >= self.items.len() {
+            self.scroll_idx = self.items.len().saturating_sub(1);
+        }
+    }
+
+    pub fn can_scroll(&self, up: bool, visible_count: usize) -> bool {
+        if up {
+            self.scroll_idx > 0
+        } else {
+            self.scroll_idx + visible_count < self.items.len()
+        }
+    }
+
+    pub fn scroll(&mut self, up: bool, visible_count: usize) -> bool {
+        let r = self.can_scroll(up, visible_count);
+        if r {
+            if up {
+                self.scroll_idx -= 1;
+            } else {
+                self.scroll_idx += 1;
+            }
+        }
+        r
+    }
+
+    fn item_index_at(&self, rect: Rect, pos: Point) -> Option<usize> {
+        if !rect.contains(pos) {
+            return None;
+        }
+        let i = self.scroll_idx + ((pos.y - rect.top) / self.item_height) as usize;
+        if i < self.items.len() {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    fn render_item(&self, item: &Item, rect: Rect, ctx: &mut Render) {
+        let frm = ctx.frm_db.get(item.fid).unwrap();
+        let size = frm.first().size();
+        let pos = rect.center() - size / 2;
+        ctx.canvas.set_clip_rect(rect);
+        Sprite {
+            pos,
+            ..Sprite::new(item.fid)
+        }.render(ctx.canvas, ctx.frm_db);
+        ctx.canvas.reset_clip_rect();
+
+        if item.count > 1 {
+            ctx.canvas.draw_text(format!("x{}", item.count).as_bytes().into(),
+                rect.top_left() + Point::new(2, 2), FontKey::antialiased(1), WHITE,
+                &Default::default());
+        }
+    }
+}
+
+impl Widget for InventoryList {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        match ctx.event {
+            Event::MouseDown { pos, button: MouseButton::Left } => {
+                self.dragging = self.item_index_at(ctx.base.rect(), pos);
+                if self.dragging.is_some() {
+                    ctx.capture();
+                }
+            }
+            Event::MouseUp { pos, button: MouseButton::Left } => {
+                if let Some(i) = self.dragging.take() {
+                    ctx.release();
+                    ctx.out(UiCommandData::Inventory(InventoryCommand::Drop {
+                        obj: self.items[i].object,
+                        pos,
+                    }));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, mut ctx: Render) {
+        let rect = ctx.base.unwrap().rect();
+        let visible_count = (rect.height() / self.item_height) as usize;
+        for (i, item) in self.items.iter()
+            .enumerate()
+            .skip(self.scroll_idx)
+            .take(visible_count)
+        {
+            if self.dragging == Some(i) {
+                continue;
+            }
+            let top = rect.top + (i - self.scroll_idx) as i32 * self.item_height;
+            let item_rect = Rect::with_size(rect.left, top, rect.width(), self.item_height);
+            self.render_item(item, item_rect, &mut ctx);
+        }
+
+        if let Some(i) = self.dragging {
+            let item_rect = Rect::with_size(0, 0, rect.width(), self.item_height);
+            let item_rect = item_rect.translate(ctx.cursor_pos - item_rect.center());
+            self.render_item(&self.items[i], item_rect, &mut ctx);
+        }
+    }
+}
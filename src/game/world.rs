@@ -14,6 +14,7 @@ use crate::asset::map::ELEVATION_COUNT;
 use crate::asset::message::Messages;
 use crate::asset::proto::{ProtoDb, ProtoId, ProtoRef, SubProto, SubItem, SubScenery};
 use crate::game::GameTime;
+use crate::game::timer::TimerEvents;
 use crate::game::object::{self, *};
 use crate::graphics::{EPoint, Point, Rect};
 use crate::graphics::font::Fonts;
@@ -79,6 +80,7 @@ pub struct World {
 
     pub dude_name: BString,
     pub game_time: GameTime,
+    pub timer_events: TimerEvents,
     pub ambient_light: u32,
 }
 
@@ -115,6 +117,7 @@ impl World {
             fonts,
             dude_name: BString::new(),
             game_time: START_GAME_TIME,
+            timer_events: TimerEvents::new(),
             ambient_light: 0x10000,
         }
     }
@@ -161,6 +164,10 @@ impl World {
         self.light_grid.clear();
     }
 
+    pub fn sqr_tiles(&self) -> &[Option<Array2d<(u16, u16)>>] {
+        &self.sqr_tiles
+    }
+
     pub fn set_sqr_tiles(&mut self, sqr_tiles: Vec<Option<Array2d<(u16, u16)>>>) {
         assert_eq!(sqr_tiles.len(), ELEVATION_COUNT as usize);
         self.sqr_tiles = sqr_tiles;
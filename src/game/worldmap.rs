@@ -0,0 +1,565 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use enum_map_derive::Enum;
+use enum_primitive_derive::Primitive;
+use num_traits::FromPrimitive;
+use rand::Rng;
+use std::convert::TryFrom;
+use std::io::{self, prelude::*, Error, ErrorKind};
+use std::rc::Rc;
+
+use crate::asset::map::MapId;
+use crate::asset::worldmap::{DayPart, WorldMapDb};
+use crate::game::GameTime;
+use crate::graphics::Point;
+
+/// Game time a single step of the travel takes on terrain with difficulty 1.
+const STEP_TIME: u32 = 12 * 60 * 10;
+
+/// Game time of the travel between random encounter checks.
+const ENCOUNTER_CHECK_INTERVAL: u32 = 6 * 60 * 60 * 10;
+
+/// Radius in subtiles of the area revealed around the party.
+const REVEAL_RADIUS: i32 = 1;
+
+/// Knowledge of the world map subtile or area. The values are as used by the scripts.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq, Primitive)]
+pub enum MarkState {
+    Unknown = 0,
+    Known = 1,
+    Visited = 2,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TravelEvent {
+    /// Destination has been reached.
+    Arrived,
+    /// Random encounter rolled from the encounter table with `lookup_name`.
+    Encounter {
+        table: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct AreaState {
+    mark: MarkState,
+    pos: Point,
+}
+
+/// Part of the `WorldMap` state stored in savegames.
+pub struct WorldMapSave {
+    pos: Point,
+    subtiles: Vec<MarkState>,
+    areas: Vec<AreaState>,
+    has_car: bool,
+    car_area: Option<usize>,
+}
+
+impl WorldMapSave {
+    /// Reads the state written by `WorldMap::write_state()`. The subtile and area counts must
+    /// match the `db`.
+    pub fn read(rd: &mut impl Read, db: &WorldMapDb) -> io::Result<Self> {
+        fn read_mark(rd: &mut impl Read) -> io::Result<MarkState> {
+            let v = rd.read_u8()?;
+            MarkState::from_u8(v)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                    format!("invalid world map mark state: {}", v)))
+        }
+        fn read_count(rd: &mut impl Read, expected: usize, what: &str) -> io::Result<()> {
+            let count = rd.read_i32::<BigEndian>()?;
+            if usize::try_from(count).ok() != Some(expected) {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("world map {} count mismatch: {} != {}", what, count, expected)));
+            }
+            Ok(())
+        }
+
+        let x = rd.read_i32::<BigEndian>()?;
+        let y = rd.read_i32::<BigEndian>()?;
+        let pos = Point::new(x, y);
+
+        let grid_size = db.subtile_grid_size();
+        let subtile_count = (grid_size.x * grid_size.y) as usize;
+        read_count(rd, subtile_count, "subtile")?;
+        let mut subtiles = Vec::with_capacity(subtile_count);
+        for _ in 0..subtile_count {
+            subtiles.push(read_mark(rd)?);
+        }
+
+        read_count(rd, db.areas().len(), "area")?;
+        let mut areas = Vec::with_capacity(db.areas().len());
+        for _ in 0..db.areas().len() {
+            let mark = read_mark(rd)?;
+            let x = rd.read_i32::<BigEndian>()?;
+            let y = rd.read_i32::<BigEndian>()?;
+            areas.push(AreaState {
+                mark,
+                pos: Point::new(x, y),
+            });
+        }
+
+        let has_car = rd.read_u8()? != 0;
+        let car_area = rd.read_i32::<BigEndian>()?;
+        let car_area = if car_area == -1 {
+            None
+        } else {
+            Some(usize::try_from(car_area).ok()
+                .filter(|&a| a < areas.len())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                    format!("invalid car area: {}", car_area)))?)
+        };
+
+        Ok(Self {
+            pos,
+            subtiles,
+            areas,
+            has_car,
+            car_area,
+        })
+    }
+}
+
+/// State of the world map: party position, fog of war and areas known to the player.
+pub struct WorldMap {
+    db: Rc<WorldMapDb>,
+    pos: Point,
+    dest: Option<Point>,
+    /// Destination of the travel stopped by a random encounter.
+    interrupted_dest: Option<Point>,
+    /// Row-major states of all subtiles.
+    subtiles: Vec<MarkState>,
+    areas: Vec<AreaState>,
+    current_area: Option<usize>,
+    has_car: bool,
+    /// Area where the car is parked. `None` if the party has no car or is traveling with it.
+    car_area: Option<usize>,
+    /// Travel time elapsed since the last random encounter check.
+    encounter_check_time: u32,
+    show_requested: bool,
+}
+
+impl WorldMap {
+    pub fn new(db: Rc<WorldMapDb>) -> Self {
+        let grid_size = db.subtile_grid_size();
+        let areas = db.areas().iter()
+            .map(|a| AreaState {
+                mark: if a.known { MarkState::Known } else { MarkState::Unknown },
+                pos: a.pos,
+            })
+            .collect();
+        Self {
+            db,
+            pos: Point::new(0, 0),
+            dest: None,
+            interrupted_dest: None,
+            subtiles: vec![MarkState::Unknown; (grid_size.x * grid_size.y) as usize],
+            areas,
+            current_area: None,
+            has_car: false,
+            car_area: None,
+            encounter_check_time: 0,
+            show_requested: false,
+        }
+    }
+
+    pub fn db(&self) -> &Rc<WorldMapDb> {
+        &self.db
+    }
+
+    /// Party position on the world map.
+    pub fn pos(&self) -> Point {
+        self.pos
+    }
+
+    pub fn dest(&self) -> Option<Point> {
+        self.dest
+    }
+
+    pub fn is_traveling(&self) -> bool {
+        self.dest.is_some()
+    }
+
+    /// Area the party is currently in.
+    pub fn current_area(&self) -> Option<usize> {
+        self.current_area
+    }
+
+    pub fn area_state(&self, area: usize) -> Option<MarkState> {
+        self.areas.get(area).map(|a| a.mark)
+    }
+
+    pub fn set_area_state(&mut self, area: usize, mark: MarkState) -> bool {
+        if let Some(a) = self.areas.get_mut(area) {
+            a.mark = mark;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn area_pos(&self, area: usize) -> Option<Point> {
+        self.areas.get(area).map(|a| a.pos)
+    }
+
+    pub fn set_area_pos(&mut self, area: usize, pos: Point) -> bool {
+        if let Some(a) = self.areas.get_mut(area) {
+            a.pos = pos;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Marks visited the area having an entrance to the map with `map_id`. Returns `false` if
+    /// there's no such area.
+    pub fn mark_map_visited(&mut self, map_id: MapId) -> bool {
+        if let Some(area) = self.db.area_for_map_id(map_id) {
+            self.areas[area].mark = MarkState::Visited;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn has_car(&self) -> bool {
+        self.has_car
+    }
+
+    /// Area where the party's car is.
+    pub fn car_area(&self) -> Option<usize> {
+        self.car_area
+    }
+
+    /// Gives the car to the party. The car is parked in the current area.
+    pub fn give_car(&mut self) {
+        self.has_car = true;
+        self.car_area = self.current_area;
+    }
+
+    /// Returns known area whose town circle contains `pos`.
+    pub fn area_at(&self, pos: Point) -> Option<usize> {
+        self.areas.iter()
+            .zip(self.db.areas())
+            .position(|(a, def)| {
+                let d = pos - a.pos;
+                let r = def.size.radius();
+                a.mark != MarkState::Unknown && d.x * d.x + d.y * d.y <= r * r
+            })
+    }
+
+    /// State of the subtile at the world map position `pos`.
+    pub fn subtile_state(&self, pos: Point) -> Option<MarkState> {
+        let spos = self.db.subtile_pos(pos)?;
+        Some(self.subtiles[self.subtile_idx(spos)])
+    }
+
+    /// Marks known all unknown subtiles within `radius` subtiles around `pos`.
+    pub fn mark_subtiles(&mut self, pos: Point, radius: i32) {
+        let center = if let Some(v) = self.db.subtile_pos(pos) {
+            v
+        } else {
+            return;
+        };
+        let grid_size = self.db.subtile_grid_size();
+        for y in center.y - radius..=center.y + radius {
+            for x in center.x - radius..=center.x + radius {
+                if x >= 0 && y >= 0 && x < grid_size.x && y < grid_size.y {
+                    let i = self.subtile_idx(Point::new(x, y));
+                    if self.subtiles[i] == MarkState::Unknown {
+                        self.subtiles[i] = MarkState::Known;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves the party to `pos` without traveling.
+    pub fn set_pos(&mut self, pos: Point) {
+        self.pos = pos;
+        self.dest = None;
+        self.interrupted_dest = None;
+        self.reveal();
+    }
+
+    /// Places the party in the area having an entrance to the map with `lookup_name`. If there's
+    /// no such area the party stays where it was.
+    pub fn set_current_map(&mut self, lookup_name: &str) {
+        self.current_area = self.db.area_for_map(lookup_name);
+        if let Some(area) = self.current_area {
+            self.areas[area].mark = MarkState::Visited;
+            let pos = self.areas[area].pos;
+            self.set_pos(pos);
+        }
+        self.park_car();
+    }
+
+    /// Starts traveling to `dest`. The destination is clamped to the world map bounds.
+    pub fn travel_to(&mut self, dest: Point) {
+        let size = self.db.size();
+        let dest = Point::new(
+            dest.x.max(0).min(size.x - 1),
+            dest.y.max(0).min(size.y - 1));
+        self.dest = if dest != self.pos { Some(dest) } else { None };
+        self.interrupted_dest = None;
+        self.current_area = None;
+        self.park_car();
+    }
+
+    /// Moves the party one pixel towards the destination advancing the `game_time` according to
+    /// the terrain difficulty. Rolls for a random encounter whenever enough time has passed.
+    pub fn travel_step(&mut self, game_time: &mut GameTime, rng: &mut impl Rng)
+        -> Option<TravelEvent>
+    {
+        let dest = self.dest?;
+
+        let difficulty = self.db.subtile_at(self.pos)
+            .map(|(_, s)| self.db.terrains()[s.terrain].difficulty)
+            .unwrap_or(1);
+        let time = STEP_TIME * difficulty;
+        *game_time = game_time.add_decis(time);
+
+        let d = dest - self.pos;
+        let len = d.x.abs().max(d.y.abs());
+        let step = |v: i32| (2 * v + v.signum() * len) / (2 * len);
+        self.pos += Point::new(step(d.x), step(d.y));
+        self.reveal();
+
+        if self.pos == dest {
+            self.dest = None;
+            self.current_area = self.area_at(self.pos);
+            if let Some(area) = self.current_area {
+                self.areas[area].mark = MarkState::Visited;
+            }
+            self.park_car();
+            return Some(TravelEvent::Arrived);
+        }
+
+        self.encounter_check_time += time;
+        if self.encounter_check_time >= ENCOUNTER_CHECK_INTERVAL {
+            self.encounter_check_time = 0;
+            if let Some(table) = self.roll_encounter(DayPart::from_hour(game_time.hour()), rng) {
+                self.interrupted_dest = self.dest.take();
+                return Some(TravelEvent::Encounter { table });
+            }
+        }
+
+        None
+    }
+
+    /// Continues the travel stopped by a random encounter. Returns `false` if there's no such
+    /// travel.
+    pub fn resume_travel(&mut self) -> bool {
+        self.dest = self.interrupted_dest.take();
+        self.dest.is_some()
+    }
+
+    /// Writes party position, fog of war, state of the areas and the car.
+    pub fn write_state(&self, wr: &mut impl Write) -> io::Result<()> {
+        let to_i32 = |v: usize| i32::try_from(v)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "world map is too big"));
+        wr.write_i32::<BigEndian>(self.pos.x)?;
+        wr.write_i32::<BigEndian>(self.pos.y)?;
+        wr.write_i32::<BigEndian>(to_i32(self.subtiles.len())?)?;
+        for &mark in &self.subtiles {
+            wr.write_u8(mark as u8)?;
+        }
+        wr.write_i32::<BigEndian>(to_i32(self.areas.len())?)?;
+        for area in &self.areas {
+            wr.write_u8(area.mark as u8)?;
+            wr.write_i32::<BigEndian>(area.pos.x)?;
+            wr.write_i32::<BigEndian>(area.pos.y)?;
+        }
+        wr.write_u8(self.has_car as u8)?;
+        wr.write_i32::<BigEndian>(self.car_area.map(|a| a as i32).unwrap_or(-1))?;
+        Ok(())
+    }
+
+    /// Restores the state read from a savegame. Stops any travel.
+    pub fn set_state(&mut self, state: WorldMapSave) {
+        self.pos = state.pos;
+        self.dest = None;
+        self.interrupted_dest = None;
+        self.subtiles = state.subtiles;
+        self.areas = state.areas;
+        self.current_area = None;
+        self.has_car = state.has_car;
+        self.car_area = state.car_area;
+        self.encounter_check_time = 0;
+    }
+
+    /// Returns true if the script requested showing the world map, clearing the request.
+    pub fn take_show_request(&mut self) -> bool {
+        std::mem::replace(&mut self.show_requested, false)
+    }
+
+    pub fn request_show(&mut self) {
+        self.show_requested = true;
+    }
+
+    fn roll_encounter(&self, day_part: DayPart, rng: &mut impl Rng) -> Option<String> {
+        if self.area_at(self.pos).is_some() {
+            return None;
+        }
+        let (_, subtile) = self.db.subtile_at(self.pos)?;
+        let table = subtile.encounter_table.as_ref()?;
+        if rng.gen_range(0, 100) < subtile.encounter_chance[day_part] {
+            Some(table.clone())
+        } else {
+            None
+        }
+    }
+
+    /// The car follows the party: it's parked in the current area or travels along.
+    fn park_car(&mut self) {
+        if self.has_car {
+            self.car_area = self.current_area;
+        }
+    }
+
+    fn reveal(&mut self) {
+        if let Some(spos) = self.db.subtile_pos(self.pos) {
+            self.mark_subtiles(self.pos, REVEAL_RADIUS);
+            let i = self.subtile_idx(spos);
+            self.subtiles[i] = MarkState::Visited;
+        }
+    }
+
+    fn subtile_idx(&self, spos: Point) -> usize {
+        (spos.y * self.db.subtile_grid_size().x + spos.x) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::asset::worldmap;
+
+    fn new_world_map() -> WorldMap {
+        WorldMap::new(Rc::new(worldmap::test::new_db()))
+    }
+
+    #[test]
+    fn travel() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut wm = new_world_map();
+        wm.set_current_map("Arroyo Village");
+        assert_eq!(wm.current_area(), Some(0));
+        assert_eq!(wm.pos(), Point::new(173, 122));
+        assert_eq!(wm.area_state(0), Some(MarkState::Visited));
+        assert_eq!(wm.subtile_state(Point::new(173, 122)), Some(MarkState::Visited));
+        assert_eq!(wm.subtile_state(Point::new(130, 170)), Some(MarkState::Known));
+        assert_eq!(wm.subtile_state(Point::new(260, 122)), Some(MarkState::Unknown));
+
+        // 6 AM, the encounter chance is 5%.
+        let mut game_time = GameTime::from_decis(6 * 60 * 60 * 10);
+        wm.travel_to(Point::new(203, 122));
+        assert_eq!(wm.current_area(), None);
+        let mut steps = 0;
+        let event = loop {
+            steps += 1;
+            if let Some(e) = wm.travel_step(&mut game_time, rng) {
+                break e;
+            }
+        };
+        assert_eq!(event, TravelEvent::Arrived);
+        assert_eq!(steps, 30);
+        assert_eq!(wm.pos(), Point::new(203, 122));
+        assert!(!wm.is_traveling());
+        assert_eq!(game_time.hour(), 12);
+        assert_eq!(wm.subtile_state(Point::new(260, 122)), Some(MarkState::Known));
+        assert!(wm.travel_step(&mut game_time, rng).is_none());
+    }
+
+    #[test]
+    fn encounter() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut wm = new_world_map();
+        wm.set_pos(Point::new(10, 10));
+
+        // The first check happens at midnight when the encounter chance is 100%.
+        let mut game_time = GameTime::from_decis(18 * 60 * 60 * 10);
+        wm.travel_to(Point::new(10, 290));
+        let event = (0..100).find_map(|_| wm.travel_step(&mut game_time, rng));
+        assert_eq!(event, Some(TravelEvent::Encounter { table: "Desert_Table".into() }));
+        assert_eq!(wm.pos(), Point::new(10, 40));
+        assert!(!wm.is_traveling());
+
+        assert!(wm.resume_travel());
+        assert_eq!(wm.dest(), Some(Point::new(10, 290)));
+        assert!(!wm.resume_travel());
+    }
+
+    #[test]
+    fn areas() {
+        let mut wm = new_world_map();
+        assert_eq!(wm.area_state(1), Some(MarkState::Unknown));
+        assert_eq!(wm.area_at(Point::new(460, 40)), None);
+        assert!(wm.set_area_state(1, MarkState::Known));
+        assert_eq!(wm.area_at(Point::new(463, 44)), Some(1));
+        assert!(wm.set_area_pos(1, Point::new(600, 200)));
+        assert_eq!(wm.area_at(Point::new(600, 200)), Some(1));
+        assert!(!wm.set_area_state(2, MarkState::Known));
+        assert_eq!(wm.area_state(2), None);
+    }
+
+    #[test]
+    fn mark_map_visited() {
+        let mut db = worldmap::test::new_db();
+        db.resolve_map_ids(|name| if name == "Arroyo Temple Area" { Some(2) } else { None });
+        let mut wm = WorldMap::new(Rc::new(db));
+        assert_eq!(wm.area_state(0), Some(MarkState::Known));
+        assert!(wm.mark_map_visited(2));
+        assert_eq!(wm.area_state(0), Some(MarkState::Visited));
+        assert!(!wm.mark_map_visited(3));
+    }
+
+    #[test]
+    fn car() {
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut wm = new_world_map();
+        wm.set_current_map("Arroyo Village");
+        assert!(!wm.has_car());
+        assert_eq!(wm.car_area(), None);
+
+        wm.give_car();
+        assert!(wm.has_car());
+        assert_eq!(wm.car_area(), Some(0));
+
+        let mut game_time = GameTime::from_decis(6 * 60 * 60 * 10);
+        for (dest, car_area) in [(Point::new(203, 122), None), (Point::new(173, 122), Some(0))] {
+            wm.travel_to(dest);
+            assert_eq!(wm.car_area(), None);
+            while wm.travel_step(&mut game_time, rng) != Some(TravelEvent::Arrived) {}
+            assert_eq!(wm.car_area(), car_area);
+        }
+    }
+
+    #[test]
+    fn save_state() {
+        let mut wm = new_world_map();
+        wm.set_current_map("Arroyo Village");
+        assert!(wm.set_area_state(1, MarkState::Known));
+        assert!(wm.set_area_pos(1, Point::new(600, 200)));
+        wm.give_car();
+        wm.set_pos(Point::new(10, 10));
+
+        let mut data = Vec::new();
+        wm.write_state(&mut data).unwrap();
+
+        let mut act = new_world_map();
+        act.set_state(WorldMapSave::read(&mut &data[..], act.db()).unwrap());
+        assert_eq!(act.pos(), Point::new(10, 10));
+        assert_eq!(act.area_state(0), Some(MarkState::Visited));
+        assert_eq!(act.area_state(1), Some(MarkState::Known));
+        assert_eq!(act.area_pos(1), Some(Point::new(600, 200)));
+        assert_eq!(act.subtile_state(Point::new(10, 10)), Some(MarkState::Visited));
+        assert_eq!(act.subtile_state(Point::new(173, 122)), Some(MarkState::Visited));
+        assert_eq!(act.subtile_state(Point::new(260, 122)), Some(MarkState::Unknown));
+        assert!(act.has_car());
+        assert_eq!(act.car_area(), Some(0));
+
+        assert!(WorldMapSave::read(&mut &data[..data.len() - 1], act.db()).is_err());
+    }
+}
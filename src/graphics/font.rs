@@ -289,4 +289,8 @@ impl Fonts {
     pub fn get(&self, key: FontKey) -> &Font {
         &self.fonts[&key]
     }
+
+    pub fn contains(&self, key: FontKey) -> bool {
+        self.fonts.contains_key(&key)
+    }
 }
\ No newline at end of file
@@ -13,6 +13,14 @@ use crate::graphics::font::{self, FontKey, Fonts};
 #[derive(Clone)]
 pub struct TextureHandle(Rc<TextureHandleInner>);
 
+impl PartialEq for TextureHandle {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.key == other.0.key
+    }
+}
+
+impl Eq for TextureHandle {}
+
 impl fmt::Debug for TextureHandle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "TextureHandle@{:?}", self.0.key)
@@ -44,11 +52,24 @@ enum TextureFactoryInner {
 }
 
 impl TextureFactory {
+    /// Creates factory that keeps textures in memory without a rendering backend.
+    /// Useful for tools and tests.
+    pub fn new_in_memory() -> Self {
+        TextureFactory(TextureFactoryInner::Software(software::Textures::new()))
+    }
+
     pub fn new_texture(&self, width: i32, height: i32, data: Box<[u8]>) -> TextureHandle {
         match self.0 {
             TextureFactoryInner::Software(ref i) => i.new_texture(width, height, data),
         }
     }
+
+    /// Returns copy of the texture pixels. The texture must have been created by this factory.
+    pub fn texture_data(&self, texture: &TextureHandle) -> Box<[u8]> {
+        match self.0 {
+            TextureFactoryInner::Software(ref i) => i.texture_data(texture),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -73,6 +94,9 @@ pub trait Canvas {
 
     fn clear(&mut self, color: Rgb15);
 
+    /// Fills the `rect` with solid `color`. Respects the clip rect.
+    fn fill_rect(&mut self, rect: Rect, color: Rgb15);
+
     fn draw(&mut self, tex: &TextureHandle, pos: Point, light: u32);
     fn draw_multi_light(&mut self, tex: &TextureHandle, pos: Point, lights: &[u32]);
 
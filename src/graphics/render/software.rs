@@ -106,7 +106,7 @@ impl TexturesInner {
 pub(in super) struct Textures(Rc<RefCell<TexturesInner>>);
 
 impl Textures {
-    fn new() -> Self {
+    pub(in super) fn new() -> Self {
         Textures(Rc::new(RefCell::new(TexturesInner::new())))
     }
 
@@ -120,6 +120,10 @@ impl Textures {
         self.0.borrow_mut().new_texture(width, height, data)
     }
 
+    pub fn texture_data(&self, h: &TextureHandle) -> Box<[u8]> {
+        self.get(h).data.clone()
+    }
+
     fn get(&self, h: &TextureHandle) -> Ref<Texture> {
         let t = self.0.borrow();
         Ref::map(t, |t| &t.textures[h.0.key])
@@ -270,6 +274,21 @@ impl Canvas for CanvasImpl {
         }
     }
 
+    fn fill_rect(&mut self, rect: Rect, color: Rgb15) {
+        let v = self.palette.color_idx(color);
+        let rect = rect.intersect(self.clip_rect);
+        if rect.is_empty() {
+            return;
+        }
+        let width = self.back_buf.width;
+        for y in rect.top..rect.bottom {
+            let i = (y * width) as usize;
+            for b in &mut self.back_buf.data[i + rect.left as usize..i + rect.right as usize] {
+                *b = v;
+            }
+        }
+    }
+
     fn draw(&mut self, tex: &TextureHandle, pos: Point, light: u32) {
         let pal = &self.palette;
         let tex = self.textures.get(tex);
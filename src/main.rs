@@ -11,6 +11,8 @@
 #[macro_use] mod macros;
 
 mod asset;
+mod audio;
+mod cmd;
 mod fs;
 mod game;
 mod graphics;
@@ -33,6 +35,8 @@ use crate::asset::frame::{FrameDb, FrameId};
 use crate::asset::message::Messages;
 use crate::asset::palette::read_palette;
 use crate::asset::proto::ProtoDb;
+use crate::audio::Audio;
+use crate::audio::output::{NullOutput, Output, SdlOutput};
 use crate::game::state::GameState;
 use crate::game::ui::world::WorldView;
 use crate::graphics::{EPoint, Point};
@@ -67,6 +71,8 @@ fn args() -> clap::App<'static, 'static> {
     use clap::*;
 
     App::new(format!("Vault 13 {} ({})", VERSION, GIT_DATE))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
         .arg(Arg::with_name("RESOURCE_DIR")
             .help("One or more resource directories where master.dat, critter.dat and patchXXX.dat \
                    can be found")
@@ -74,13 +80,44 @@ fn args() -> clap::App<'static, 'static> {
         .arg(Arg::with_name("MAP")
             .help("Map name to load. For example: artemple")
             .required_unless("version"))
+        .arg(Arg::with_name("script-debugger")
+            .long("script-debugger")
+            .value_name("CONSOLE")
+            .help("Enables the script debugger. CONSOLE is either `stdin` or a TCP port number \
+                   to listen on at 127.0.0.1")
+            .takes_value(true))
+        .arg(Arg::with_name("no-sound")
+            .long("no-sound")
+            .help("Disables sound output"))
         .arg(Arg::with_name("version")
             .short("v")
             .long("version")
             .help("Prints version information"))
+        .subcommands(cmd::subcommands())
         .after_help(
             "EXAMPLE:\n\
-          \x20   vault13 /path/to/fallout2 artemple")
+          \x20   vault13 /path/to/fallout2 artemple\n\
+          \x20   vault13 dat list /path/to/fallout2/master.dat")
+}
+
+fn new_script_debugger(console: &str) -> vm::debug::Debugger {
+    use vm::debug::*;
+
+    let console: Box<dyn Console> = if console == "stdin" {
+        Box::new(StdinConsole)
+    } else if let Ok(port) = console.parse::<u16>() {
+        match TcpConsole::bind(("127.0.0.1", port)) {
+            Ok(console) => Box::new(console),
+            Err(e) => {
+                error!("couldn't start script debugger console on port {}: {}", port, e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        error!("invalid script debugger console: {}", console);
+        std::process::exit(1);
+    };
+    vm::debug::Debugger::new(console)
 }
 
 fn setup_file_system(fs: &mut fs::FileSystem, args: &clap::ArgMatches) {
@@ -110,14 +147,54 @@ fn setup_file_system(fs: &mut fs::FileSystem, args: &clap::ArgMatches) {
         }
     }
 
+    let mod_config = fs::mods::ModConfig::load(res_dir).unwrap_or_else(|e| {
+        warn!("couldn't load mod list, not using mods: {}", e);
+        Default::default()
+    });
+    let mut has_mods = false;
+    for mod_path in mod_config.mods() {
+        match fs::mods::new_provider(mod_path) {
+            Ok(provider) => {
+                info!("Using mod: {}", mod_path.display());
+                fs.register_provider(mod_path.display().to_string(), provider);
+                has_mods = true;
+            }
+            Err(e) => warn!("skipping mod {}: {}", mod_path.display(), e),
+        }
+    }
+
     let data_dir: PathBuf = [res_dir, Path::new("data")].iter().collect();
     if data_dir.is_dir() {
         info!("Found `data` dir");
-        fs.register_provider(fs::std::new_provider(data_dir).unwrap());
+        fs.register_provider(data_dir.display().to_string(),
+            fs::std::new_provider(&data_dir).unwrap());
     }
 
     for dat_file in dat_files.iter().rev() {
-        fs.register_provider(fs::dat::v2::new_provider(dat_file).unwrap());
+        fs.register_provider(dat_file.display().to_string(),
+            fs::dat::v2::new_provider(dat_file).unwrap());
+    }
+
+    if has_mods {
+        log_overrides(fs);
+    }
+}
+
+fn log_overrides(fs: &fs::FileSystem) {
+    let overrides = match fs.overrides() {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("couldn't enumerate overridden files: {}", e);
+            return;
+        }
+    };
+    let mut counts = std::collections::BTreeMap::new();
+    for o in &overrides {
+        debug!("{} from {} (shadows {})", o.path, o.provider, o.shadowed.join(", "));
+        *counts.entry(&o.provider).or_insert(0) += 1;
+    }
+    for (provider, count) in counts {
+        info!("{} overrides {} file(s)", provider, count);
     }
 }
 
@@ -190,6 +267,9 @@ fn main() {
     let mut fs = fs::FileSystem::new();
 
     let map_name: String;
+    let quick_save_dir: PathBuf;
+    let script_debugger;
+    let no_sound;
     {
         let args = &args().get_matches();
 
@@ -198,8 +278,22 @@ fn main() {
             return;
         }
 
+        if let Some(exit_code) = cmd::run(args) {
+            std::process::exit(exit_code);
+        }
+
         setup_file_system(&mut fs, args);
 
+        script_debugger = args.value_of("script-debugger").map(new_script_debugger);
+
+        no_sound = args.is_present("no-sound");
+
+        quick_save_dir = [
+            Path::new(args.value_of("RESOURCE_DIR").unwrap()),
+            Path::new("data/savegame"),
+            Path::new(&asset::save::slot_dir_name(1)),
+        ].iter().collect();
+
         let s = args.value_of("MAP").unwrap().to_lowercase();
         map_name = if s.ends_with(".map") {
             s[..s.len() - 4].into()
@@ -229,6 +323,21 @@ fn main() {
         .build()
         .unwrap();
 
+    let audio_output: Box<dyn Output> = if no_sound {
+        Box::new(NullOutput::new(audio::SAMPLE_RATE))
+    } else {
+        match sdl.audio().and_then(|a|
+            SdlOutput::new(&a, audio::SAMPLE_RATE, Duration::from_millis(100)))
+        {
+            Ok(v) => Box::new(v),
+            Err(e) => {
+                warn!("couldn't open audio device, sound is disabled: {}", e);
+                Box::new(NullOutput::new(audio::SAMPLE_RATE))
+            }
+        }
+    };
+    let audio = Audio::new(fs.clone(), audio_output);
+
     let mouse = sdl.mouse();
     mouse.set_relative_mouse_mode(true);
 
@@ -238,7 +347,7 @@ fn main() {
         .unwrap();
     info!("Using render driver: {}", canvas.info().name);
 
-    let gfx_backend: Backend = Backend::new(canvas, Box::new(pal), PaletteOverlay::standard());
+    let gfx_backend: Backend = Backend::new(canvas, Box::new(pal.clone()), PaletteOverlay::standard());
     let texture_factory = gfx_backend.new_texture_factory();
 
     let frm_db = Rc::new(FrameDb::new(fs.clone(), language, texture_factory.clone()).unwrap());
@@ -275,9 +384,14 @@ fn main() {
         fonts,
         misc_msgs,
         start,
+        audio,
+        Rc::new(pal),
         ui,
     );
 
+    if let Some(debugger) = script_debugger {
+        state.set_script_debugger(debugger);
+    }
     state.new_game();
     state.world().borrow_mut().set_dude_name("Narg".into());
     state.switch_map(&map_name, ui);
@@ -313,6 +427,16 @@ fn main() {
                     Event::KeyDown { keycode: Some(Keycode::Backquote), .. } => {
                         draw_debug = !draw_debug;
                     }
+                    Event::KeyDown { keycode: Some(Keycode::F6), .. } => {
+                        if let Err(e) = state.save_game(&quick_save_dir, "Quick save".into()) {
+                            error!("error saving game: {}", e);
+                        }
+                    }
+                    Event::KeyDown { keycode: Some(Keycode::F7), .. } => {
+                        if let Err(e) = state.load_game(&quick_save_dir, ui) {
+                            error!("error loading game: {}", e);
+                        }
+                    }
                     Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                         break 'running
                     },
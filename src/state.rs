@@ -1,4 +1,6 @@
 mod event;
+pub mod movie;
+pub mod worldmap;
 
 use sdl2::event::{Event as SdlEvent};
 use std::time::Duration;
@@ -9,4 +9,16 @@ pub enum AppEvent {
         pos: EPoint,
         direction: Direction,
     },
+    /// Party entered a map from the world map.
+    EnterMap {
+        /// `lookup_name` of the map as in `maps.txt`.
+        map: String,
+        /// Dude position and direction or `None` for the map's own entrance.
+        location: Option<(EPoint, Direction)>,
+    },
+    /// Random encounter happened while traveling on the world map.
+    Encounter {
+        /// `lookup_name` of the encounter table.
+        table: String,
+    },
 }
\ No newline at end of file
@@ -0,0 +1,213 @@
+//! Full-screen movie playback.
+
+use log::*;
+use sdl2::event::{Event as SdlEvent};
+use std::cmp;
+use std::io::{self, prelude::*};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::asset::movie::MveReader;
+use crate::audio::Audio;
+use crate::audio::mixer::{Channel, Queue};
+use crate::fs::FileSystem;
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::palette::Palette;
+use crate::graphics::render::{TextureFactory, TextureHandle};
+use crate::ui::{self, Cursor, Event, HandleEvent, Render, Ui, Widget};
+use crate::ui::command::UiCommand;
+use super::{AppState, HandleAppEvent, Update};
+
+/// Movie playback change requested by a script.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MovieRequest {
+    /// Play movie by path like `art/cuts/intro.mve`.
+    Play(String),
+    Stop,
+}
+
+/// Displays the current movie frame centered in the widget. Any key or mouse button skips the
+/// movie.
+struct MovieView {
+    texture: Option<TextureHandle>,
+    /// Width and height of the `texture`.
+    size: Point,
+    skipped: bool,
+}
+
+impl Widget for MovieView {
+    fn handle_event(&mut self, ctx: HandleEvent) {
+        match ctx.event {
+            Event::KeyDown { .. } | Event::MouseDown { .. } => self.skipped = true,
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        if let Some(tex) = &self.texture {
+            let rect = ctx.base.unwrap().rect();
+            let pos = Point::new(
+                rect.left + (rect.width() - self.size.x) / 2,
+                rect.top + (rect.height() - self.size.y) / 2);
+            ctx.canvas.draw(tex, pos, 0x10000);
+        }
+    }
+}
+
+pub struct MovieState {
+    path: String,
+    movie: MveReader<Box<dyn BufRead + Send>>,
+    palette: Rc<Palette>,
+    texture_factory: TextureFactory,
+    window: ui::Handle,
+    view: ui::Handle,
+    audio: Option<Queue>,
+    samples: Vec<i16>,
+    /// Time elapsed since the current frame was shown.
+    time: Duration,
+    done: bool,
+    skipped: bool,
+}
+
+impl MovieState {
+    /// Opens the movie at `path` and shows its first frame in a modal window covering the whole
+    /// screen. Movie colors are mapped to the closest colors of the game `palette`.
+    pub fn new(
+        fs: &FileSystem,
+        path: &str,
+        palette: Rc<Palette>,
+        texture_factory: TextureFactory,
+        audio: &mut Audio,
+        ui: &mut Ui,
+    ) -> io::Result<Self> {
+        let mut movie = MveReader::new(fs.reader(path)?)?;
+        // Video and audio formats are known only after the first frame.
+        let has_frame = movie.next_frame()?;
+
+        let rect = Rect::with_size(0, 0, 640, 480);
+        let window = ui.new_window(rect, None);
+        let view = ui.new_widget(window, rect, Some(Cursor::Hidden), None, MovieView {
+            texture: None,
+            size: Point::new(0, 0),
+            skipped: false,
+        });
+        ui.set_modal_window(Some(window));
+        ui.set_keyboard_focus(Some(view));
+
+        let audio = movie.audio_info().map(|info| {
+            let queue = Queue::new(info.channels, info.sample_rate);
+            audio.mixer_mut().play(Box::new(queue.clone()), Channel::Speech, false);
+            queue
+        });
+
+        let mut r = Self {
+            path: path.into(),
+            movie,
+            palette,
+            texture_factory,
+            window,
+            view,
+            audio,
+            samples: Vec::new(),
+            time: Duration::from_secs(0),
+            done: !has_frame,
+            skipped: false,
+        };
+        if has_frame {
+            r.show_frame(ui);
+        }
+        Ok(r)
+    }
+
+    pub fn window(&self) -> ui::Handle {
+        self.window
+    }
+
+    /// Whether the movie has reached the end or has been skipped.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Removes the movie window. If the movie was skipped its audio is stopped, otherwise the
+    /// queued audio is left to finish playing.
+    pub fn finish(self, ui: &mut Ui) {
+        if let Some(audio) = &self.audio {
+            if self.skipped {
+                audio.stop();
+            } else {
+                audio.finish();
+            }
+        }
+        ui.remove(self.window);
+    }
+
+    fn show_frame(&mut self, ui: &Ui) {
+        if let Some(audio) = &self.audio {
+            self.movie.drain_audio(&mut self.samples);
+            audio.push(&self.samples);
+            self.samples.clear();
+        }
+
+        let mut colors = [0; 256];
+        for (c, &rgb) in colors.iter_mut().zip(self.movie.palette().iter()) {
+            *c = self.palette.color_idx(rgb);
+        }
+        let pixels = self.movie.pixels().iter().map(|&c| colors[c as usize]).collect();
+        let (width, height) = (self.movie.width(), self.movie.height());
+        let texture = self.texture_factory.new_texture(width, height, pixels);
+        let mut view = ui.widget_mut::<MovieView>(self.view);
+        view.texture = Some(texture);
+        view.size = Point::new(width, height);
+    }
+}
+
+impl AppState for MovieState {
+    fn handle_app_event(&mut self, _ctx: HandleAppEvent) {}
+
+    fn handle_input(&mut self, _event: &SdlEvent, _ui: &mut Ui) -> bool {
+        // The movie owns the input while it's playing.
+        true
+    }
+
+    fn handle_ui_command(&mut self, _command: UiCommand, _ui: &mut Ui) {}
+
+    fn update(&mut self, ctx: Update) {
+        if self.done {
+            return;
+        }
+        if ctx.ui.widget_ref::<MovieView>(self.view).skipped {
+            self.done = true;
+            self.skipped = true;
+            return;
+        }
+
+        let frame_duration = cmp::max(self.movie.frame_duration(), Duration::from_millis(1));
+        self.time += ctx.delta;
+        let mut new_frame = false;
+        while self.time >= frame_duration {
+            self.time -= frame_duration;
+            match self.movie.next_frame() {
+                Ok(true) => new_frame = true,
+                Ok(false) => {
+                    self.done = true;
+                    break;
+                }
+                Err(e) => {
+                    warn!("error decoding movie {}: {}", self.path, e);
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        if new_frame {
+            self.show_frame(ctx.ui);
+        } else if self.done {
+            // Push the audio decoded after the last frame.
+            if let Some(audio) = &self.audio {
+                self.movie.drain_audio(&mut self.samples);
+                audio.push(&self.samples);
+                self.samples.clear();
+            }
+        }
+    }
+}
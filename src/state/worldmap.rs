@@ -0,0 +1,323 @@
+//! World map screen where the party travels between areas.
+
+use sdl2::event::{Event as SdlEvent};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::asset::frame::FrameId;
+use crate::asset::worldmap::{SUBTILE_SIZE, TILE_HEIGHT, TILE_WIDTH};
+use crate::game::world::World;
+use crate::game::worldmap::{MarkState, TravelEvent, WorldMap};
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::{BLACK, GREEN};
+use crate::graphics::font::{DrawOptions, FontKey, HorzAlign};
+use crate::graphics::sprite::Sprite;
+use crate::ui::{self, Event, HandleEvent, MouseButton, Render, Ui, Widget};
+use crate::ui::button::Button;
+use crate::ui::command::{UiCommand, UiCommandData, WorldMapCommand};
+use crate::ui::panel::{self, Panel};
+use super::{AppEvent, AppState, HandleAppEvent, Update};
+
+const TEXT_FONT: FontKey = FontKey::antialiased(1);
+
+/// Real time a single travel step takes.
+const STEP_DURATION: Duration = Duration::from_millis(30);
+
+fn view_rect() -> Rect {
+    Rect::with_size(22, 21, 450, 443)
+}
+
+/// Renders the world map tiles under the fog of war centered on the party. Clicking the map
+/// emits `WorldMapCommand::Travel`.
+struct WorldMapView {
+    world_map: Rc<RefCell<WorldMap>>,
+}
+
+impl WorldMapView {
+    /// World map position shown at the top left corner of the widget.
+    fn origin(&self, rect: Rect) -> Point {
+        let world_map = self.world_map.borrow();
+        let max = world_map.db().size() - Point::new(rect.width(), rect.height());
+        let origin = world_map.pos() - Point::new(rect.width(), rect.height()) / 2;
+        Point::new(origin.x.max(0).min(max.x), origin.y.max(0).min(max.y))
+    }
+}
+
+impl Widget for WorldMapView {
+    fn handle_event(&mut self, mut ctx: HandleEvent) {
+        if let Event::MouseDown { pos, button: MouseButton::Left } = ctx.event {
+            let rect = ctx.base.rect();
+            let pos = pos - rect.top_left() + self.origin(rect);
+            ctx.out(UiCommandData::WorldMap(WorldMapCommand::Travel { pos }));
+        }
+    }
+
+    fn render(&mut self, ctx: Render) {
+        let rect = ctx.base.unwrap().rect();
+        let origin = self.origin(rect);
+        let offset = rect.top_left() - origin;
+        let world_map = self.world_map.borrow();
+        let db = world_map.db();
+
+        ctx.canvas.set_clip_rect(rect);
+
+        for (i, tile) in db.tiles().iter().enumerate() {
+            let pos = db.tile_pos(i) + offset;
+            if Rect::with_size(pos.x, pos.y, TILE_WIDTH, TILE_HEIGHT).intersects(rect) {
+                Sprite {
+                    pos,
+                    ..Sprite::new(tile.fid)
+                }.render(ctx.canvas, ctx.frm_db);
+            }
+        }
+
+        let first = origin / SUBTILE_SIZE;
+        let last = (origin + Point::new(rect.width(), rect.height())) / SUBTILE_SIZE;
+        for y in first.y..=last.y {
+            for x in first.x..=last.x {
+                let pos = Point::new(x, y) * SUBTILE_SIZE;
+                if world_map.subtile_state(pos) == Some(MarkState::Unknown) {
+                    let pos = pos + offset;
+                    ctx.canvas.fill_rect(
+                        Rect::with_size(pos.x, pos.y, SUBTILE_SIZE, SUBTILE_SIZE), BLACK);
+                }
+            }
+        }
+
+        for (i, area) in db.areas().iter().enumerate() {
+            if world_map.area_state(i) != Some(MarkState::Unknown) {
+                let pos = world_map.area_pos(i).unwrap() + offset;
+                ctx.canvas.draw_text(area.name.as_bytes().into(),
+                    pos + Point::new(0, area.size.radius()), TEXT_FONT, GREEN,
+                    &DrawOptions {
+                        horz_align: HorzAlign::Center,
+                        dst_color: Some(BLACK),
+                        ..Default::default()
+                    });
+            }
+        }
+
+        for &(pos, fid) in world_map.dest().map(|p| (p, FrameId::WMAPTARG)).iter()
+            .chain(Some((world_map.pos(), FrameId::WMAPLOC)).iter())
+        {
+            let size = ctx.frm_db.get(fid).unwrap().first().size();
+            Sprite {
+                pos: pos + offset - size / 2,
+                ..Sprite::new(fid)
+            }.render(ctx.canvas, ctx.frm_db);
+        }
+
+        ctx.canvas.reset_clip_rect();
+    }
+}
+
+struct TownMap {
+    window: ui::Handle,
+    area: usize,
+}
+
+pub struct WorldMapState {
+    world: Rc<RefCell<World>>,
+    world_map: Rc<RefCell<WorldMap>>,
+    window: ui::Handle,
+    date: ui::Handle,
+    town: Option<TownMap>,
+    /// Real time elapsed since the last travel step.
+    time: Duration,
+    /// Event to emit in the next `update()`.
+    event: Option<AppEvent>,
+}
+
+impl WorldMapState {
+    /// Shows the world map in a modal window covering the whole screen.
+    pub fn new(world: Rc<RefCell<World>>, world_map: Rc<RefCell<WorldMap>>, ui: &mut Ui) -> Self {
+        let window = ui.new_window(Rect::with_size(0, 0, 640, 480),
+            Some(Sprite::new(FrameId::WMAPBOX)));
+        ui.set_modal_window(Some(window));
+
+        ui.new_widget(window, view_rect(), None, None, WorldMapView {
+            world_map: world_map.clone(),
+        });
+
+        let date = ui.new_widget(window, Rect::with_size(487, 12, 140, 12), None, None,
+            Panel::new());
+
+        ui.new_widget(window, Rect::with_size(519, 439, 15, 16), None, None,
+            Button::new(FrameId::SMALL_RED_BUTTON_UP, FrameId::SMALL_RED_BUTTON_DOWN,
+                Some(UiCommandData::WorldMap(WorldMapCommand::ToggleTown))));
+
+        let r = Self {
+            world,
+            world_map,
+            window,
+            date,
+            town: None,
+            time: Duration::from_secs(0),
+            event: None,
+        };
+        r.sync_date(ui);
+        r
+    }
+
+    pub fn window(&self) -> ui::Handle {
+        self.window
+    }
+
+    /// Removes the world map windows.
+    pub fn finish(self, ui: &mut Ui) {
+        if let Some(town) = self.town {
+            ui.remove(town.window);
+        }
+        ui.remove(self.window);
+    }
+
+    /// Shows the town map of the current area if there's one. Returns `false` if the area has no
+    /// town map.
+    fn show_town(&mut self, ui: &mut Ui) -> bool {
+        assert!(self.town.is_none());
+        let world_map = self.world_map.borrow();
+        let area = if let Some(v) = world_map.current_area() {
+            v
+        } else {
+            return false;
+        };
+        let area_def = &world_map.db().areas()[area];
+        let fid = if let Some(v) = area_def.town_map_fid {
+            v
+        } else {
+            return false;
+        };
+
+        let window = ui.new_window(view_rect(), Some(Sprite::new(fid)));
+        let origin = view_rect().top_left();
+        for (i, entrance) in area_def.entrances.iter().enumerate() {
+            if !entrance.known {
+                continue;
+            }
+            let pos = origin + entrance.pos;
+            ui.new_widget(window, Rect::with_size(pos.x, pos.y, 25, 13), None, None,
+                Button::new(FrameId::HOTSPOT1, FrameId::HOTSPOT2,
+                    Some(UiCommandData::WorldMap(WorldMapCommand::Enter { entrance: i }))));
+            let label = ui.new_widget(window, Rect::with_size(pos.x, pos.y + 15, 150, 12),
+                None, None, Panel::new());
+            ui.widget_mut::<Panel>(label).set_text(Some(panel::Text {
+                text: entrance.map.as_bytes().into(),
+                font: TEXT_FONT,
+                color: GREEN,
+                options: DrawOptions {
+                    dst_color: Some(BLACK),
+                    ..Default::default()
+                },
+            }));
+        }
+
+        self.town = Some(TownMap {
+            window,
+            area,
+        });
+        true
+    }
+
+    fn hide_town(&mut self, ui: &mut Ui) {
+        let town = self.town.take().unwrap();
+        ui.remove(town.window);
+    }
+
+    /// Requests entering the map by `entrance` of the `area`.
+    fn enter(&mut self, area: usize, entrance: usize) {
+        let world_map = self.world_map.borrow();
+        let entrance = &world_map.db().areas()[area].entrances[entrance];
+        self.event = Some(AppEvent::EnterMap {
+            map: entrance.map.clone(),
+            location: entrance.location,
+        });
+    }
+
+    fn sync_date(&self, ui: &Ui) {
+        let t = self.world.borrow().game_time;
+        ui.widget_mut::<Panel>(self.date).set_text(Some(panel::Text {
+            text: format!("{:02}.{:02}.{} {:02}:{:02}",
+                t.day(), t.month(), t.year(), t.hour(), t.minute()).into(),
+            font: TEXT_FONT,
+            color: GREEN,
+            options: Default::default(),
+        }));
+    }
+}
+
+impl AppState for WorldMapState {
+    fn handle_app_event(&mut self, _ctx: HandleAppEvent) {}
+
+    fn handle_input(&mut self, _event: &SdlEvent, _ui: &mut Ui) -> bool {
+        false
+    }
+
+    fn handle_ui_command(&mut self, command: UiCommand, ui: &mut Ui) {
+        let command = if let UiCommandData::WorldMap(v) = command.data {
+            v
+        } else {
+            return;
+        };
+        match command {
+            WorldMapCommand::Travel { pos } => {
+                let mut world_map = self.world_map.borrow_mut();
+                // Clicking a town circle targets the town center.
+                let pos = world_map.area_at(pos)
+                    .and_then(|a| world_map.area_pos(a))
+                    .unwrap_or(pos);
+                world_map.travel_to(pos);
+            }
+            WorldMapCommand::ToggleTown => {
+                if self.town.is_some() {
+                    self.hide_town(ui);
+                } else if !self.show_town(ui) {
+                    // No town map, enter the first known entrance.
+                    let world_map = self.world_map.borrow();
+                    let entrance = world_map.current_area()
+                        .and_then(|area| world_map.db().areas()[area].entrances.iter()
+                            .position(|e| e.known)
+                            .map(|e| (area, e)));
+                    drop(world_map);
+                    if let Some((area, entrance)) = entrance {
+                        self.enter(area, entrance);
+                    }
+                }
+            }
+            WorldMapCommand::Enter { entrance } => {
+                let area = self.town.as_ref().unwrap().area;
+                self.enter(area, entrance);
+            }
+        }
+    }
+
+    fn update(&mut self, ctx: Update) {
+        if let Some(event) = self.event.take() {
+            ctx.out.push(event);
+            return;
+        }
+
+        if self.world_map.borrow().is_traveling() && self.town.is_none() {
+            self.time += ctx.delta;
+            while self.time >= STEP_DURATION {
+                self.time -= STEP_DURATION;
+                let event = self.world_map.borrow_mut().travel_step(
+                    &mut self.world.borrow_mut().game_time, &mut rand::thread_rng());
+                match event {
+                    Some(TravelEvent::Arrived) => {
+                        self.time = Duration::from_secs(0);
+                        self.show_town(ctx.ui);
+                        break;
+                    }
+                    Some(TravelEvent::Encounter { table }) => {
+                        self.time = Duration::from_secs(0);
+                        ctx.out.push(AppEvent::Encounter { table });
+                        break;
+                    }
+                    None => {}
+                }
+            }
+            self.sync_date(ctx.ui);
+        }
+    }
+}
@@ -252,6 +252,19 @@ impl Ui {
         h
     }
 
+    /// Moves `widget` in the z-order of its window so it's right below the `other` widget.
+    /// Both widgets must belong to the same window.
+    pub fn move_below(&mut self, widget: Handle, other: Handle) {
+        let winh = self.window_of(widget).unwrap();
+        let mut win = self.widgets[winh].borrow_mut();
+        let win = win.downcast_mut::<Window>().unwrap();
+        win.widgets.remove_first(&widget).unwrap();
+        let i = win.widgets.iter().position(|&w| w == other).unwrap();
+        win.widgets.insert(i, widget);
+
+        self.simulate_mouse_move = true;
+    }
+
     pub fn widget_base(&self, handle: Handle) -> &RefCell<Base> {
         &self.widget_bases[handle]
     }
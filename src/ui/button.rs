@@ -13,6 +13,23 @@ pub enum State {
     Down,
 }
 
+/// Mouse interaction a button can emit command on.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Trigger {
+    /// Mouse cursor entered the button.
+    Enter,
+    /// Mouse cursor left the button.
+    Leave,
+    /// Left mouse button pressed.
+    Press,
+    /// Left mouse button released inside the button.
+    Release,
+    /// Right mouse button pressed.
+    RightPress,
+    /// Right mouse button released inside the button.
+    RightRelease,
+}
+
 pub struct Config {
     pub background: Option<Sprite>,
     pub text: Option<Text>,
@@ -41,12 +58,16 @@ impl Text {
 
 pub struct Button {
     configs: EnumMap<State, Config>,
-    command: Option<UiCommandData>,
+    commands: EnumMap<Trigger, Option<UiCommandData>>,
     state: State,
+    hover: bool,
 }
 
 impl Button {
+    /// Creates button that emits `command` on `Trigger::Release`.
     pub fn new(up: FrameId, down: FrameId, command: Option<UiCommandData>) -> Self {
+        let mut commands = EnumMap::new();
+        commands[Trigger::Release] = command;
         Self {
             configs: enum_map! {
                 State::Up => Config {
@@ -58,11 +79,16 @@ impl Button {
                     text: None,
                 },
             },
-            command,
+            commands,
             state: State::Up,
+            hover: false,
         }
     }
 
+    pub fn set_command(&mut self, trigger: Trigger, command: Option<UiCommandData>) {
+        self.commands[trigger] = command;
+    }
+
     pub fn config(&self, state: State) -> &Config {
         &self.configs[state]
     }
@@ -77,12 +103,34 @@ impl Button {
     }
 }
 
+impl Button {
+    fn fire(&self, trigger: Trigger, ctx: &mut HandleEvent) {
+        if let Some(cmd) = self.commands[trigger].clone() {
+            ctx.out(cmd);
+        }
+    }
+}
+
 impl Widget for Button {
     fn handle_event(&mut self, mut ctx: HandleEvent) {
         match ctx.event {
             Event::MouseDown { button, .. } if button == MouseButton::Left => {
                 self.state = State::Down;
                 ctx.capture();
+                self.fire(Trigger::Press, &mut ctx);
+            }
+            Event::MouseDown { button, .. } if button == MouseButton::Right => {
+                self.fire(Trigger::RightPress, &mut ctx);
+            }
+            Event::MouseMove { .. } if !self.hover && !ctx.is_captured() => {
+                self.hover = true;
+                self.fire(Trigger::Enter, &mut ctx);
+            }
+            Event::MouseLeave => {
+                if self.hover {
+                    self.hover = false;
+                    self.fire(Trigger::Leave, &mut ctx);
+                }
             }
             Event::MouseMove { pos } if ctx.is_captured() => {
                 // FIXME should optionally hit test the frame as in original.
@@ -96,12 +144,15 @@ impl Widget for Button {
                 self.state = State::Up;
                 // FIXME should optionally hit test the frame as in original.
                 if ctx.base.rect.contains(pos) {
-                    if let Some(cmd) = self.command.clone() {
-                        ctx.out(cmd);
-                    }
+                    self.fire(Trigger::Release, &mut ctx);
                 }
                 ctx.release();
             }
+            Event::MouseUp { pos, button } if button == MouseButton::Right => {
+                if ctx.base.rect.contains(pos) {
+                    self.fire(Trigger::RightRelease, &mut ctx);
+                }
+            }
             _ => {}
         }
     }
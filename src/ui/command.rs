@@ -27,7 +27,15 @@ pub enum UiCommandData {
         id: u32,
     },
     Scroll,
+    Barter(BarterCommand),
+    Inventory(InventoryCommand),
+    Loot(LootCommand),
     Skilldex(SkilldexCommand),
+    WorldMap(WorldMapCommand),
+    /// Mouse interaction with a button or region created by script.
+    ScriptControl {
+        trigger: crate::ui::button::Trigger,
+    },
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -38,6 +46,51 @@ pub enum ObjectPickKind {
     Skill(crate::asset::Skill),
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BarterCommand {
+    Start,
+    Offer,
+    Talk,
+    Scroll {
+        list: crate::game::dialog::BarterList,
+        up: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InventoryCommand {
+    Hide,
+    Show,
+    Scroll {
+        up: bool,
+    },
+    /// Item dragged out of an inventory list widget and dropped at `pos`.
+    Drop {
+        obj: object::Handle,
+        pos: Point,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LootCommand {
+    Hide,
+    TakeAll,
+    Scroll {
+        container: bool,
+        up: bool,
+    },
+    Quantity(QuantityCommand),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuantityCommand {
+    Inc,
+    Dec,
+    All,
+    Done,
+    Cancel,
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum SkilldexCommand {
     Cancel,
@@ -46,4 +99,17 @@ pub enum SkilldexCommand {
         skill: crate::asset::Skill,
         target: Option<object::Handle>,
     },
-}
\ No newline at end of file
+}
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WorldMapCommand {
+    /// Travel to the world map position.
+    Travel {
+        pos: Point,
+    },
+    /// Switch between the world map and the town map of the current area.
+    ToggleTown,
+    /// Enter the map by entrance index of the current area.
+    Enter {
+        entrance: usize,
+    },
+}
@@ -152,6 +152,10 @@ impl MessagePanel {
         }
     }
 
+    pub fn messages(&self) -> impl Iterator<Item=&bstr> {
+        self.messages.iter().map(|m| m.text.as_bstr())
+    }
+
     pub fn clear_messages(&mut self) {
         self.messages.clear();
         self.lines.clear();
@@ -16,12 +16,14 @@ pub struct Text {
 /// to draw text.
 pub struct Panel {
     text: Option<Text>,
+    fill: Option<Rgb15>,
 }
 
 impl Panel {
     pub fn new() -> Self {
         Self {
             text: None,
+            fill: None,
         }
     }
 
@@ -36,10 +38,18 @@ impl Panel {
     pub fn set_text(&mut self, text: Option<Text>) {
         self.text = text;
     }
+
+    /// Sets color to fill the whole widget rect with. The fill is drawn beneath the text.
+    pub fn set_fill(&mut self, fill: Option<Rgb15>) {
+        self.fill = fill;
+    }
 }
 
 impl Widget for Panel {
     fn render(&mut self, ctx: Render) {
+        if let Some(fill) = self.fill {
+            ctx.canvas.fill_rect(ctx.base.unwrap().rect, fill);
+        }
         if let Some(text) = self.text() {
             ctx.canvas.draw_text(
                 &text.text,
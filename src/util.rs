@@ -172,6 +172,33 @@ pub fn sprintf(fmt: &bstr, args: &[&bstr]) -> BString {
     r
 }
 
+/// Matches `s` against shell-like `pattern`. `*` matches any sequence of characters (including
+/// none) and `?` matches any single character.
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let p = pattern.as_bytes();
+    let s = s.as_bytes();
+    let mut pi = 0;
+    let mut si = 0;
+    // Position of the last `*` in pattern and position in `s` it's currently matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if let Some((star_pi, star_si)) = star {
+            pi = star_pi + 1;
+            si = star_si + 1;
+            star = Some((star_pi, si));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
 #[cfg(test)]
 mod test_ {
     use super::*;
@@ -191,4 +218,21 @@ mod test_ {
             bs("arg1 two args arg2"));
         assert_eq!(f("%%s escape %%".into(), &[]), bs("%s escape %"));
     }
+
+    #[test]
+    fn glob_match_() {
+        let f = glob_match;
+        assert!(f("", ""));
+        assert!(!f("", "a"));
+        assert!(f("*", ""));
+        assert!(f("*", "abc"));
+        assert!(f("a?c", "abc"));
+        assert!(!f("a?c", "ac"));
+        assert!(f("*.frm", "art\\critters\\hmjmpsaa.frm"));
+        assert!(!f("*.frm", "art\\critters\\hmjmpsaa.fr0"));
+        assert!(f("art\\*\\*.frm", "art\\critters\\hmjmpsaa.frm"));
+        assert!(f("a*b*c", "aXbYbZc"));
+        assert!(!f("a*b*c", "aXbYbZ"));
+        assert!(f("a**", "a"));
+    }
 }
\ No newline at end of file
@@ -1,8 +1,33 @@
 use flate2::bufread::GzDecoder;
+use std::fs;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
 pub fn ungz(buf: &[u8]) -> Vec<u8> {
     let mut r = Vec::new();
     GzDecoder::new(buf).read_to_end(&mut r).unwrap();
     r
+}
+
+/// Temporary directory which is removed on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir()
+            .join(format!("vault13-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
 }
\ No newline at end of file
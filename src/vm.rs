@@ -85,9 +85,14 @@
 //!
 //! Stored in `save.dat`. Defined in `vault13.gam`.
 
+pub mod compiler;
+pub mod debug;
+pub mod disasm;
 mod error;
 mod instruction;
 mod stack;
+#[cfg(test)]
+pub(crate) mod test;
 pub mod value;
 
 use bstring::{bstr, BString};
@@ -97,6 +102,7 @@ use enumflags2_derive::EnumFlags;
 use log::*;
 use matches::matches;
 use slotmap::{SecondaryMap, SlotMap};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, Cursor};
@@ -144,9 +150,9 @@ impl PredefinedProc {
     pub fn name(self) -> &'static str {
         use PredefinedProc::*;
         match self {
-            Combat => "combat_is_over_p_proc",
-            CombatIsOver => "combat_is_starting_p_proc",
-            CombatIsStarting => "combat_p_proc",
+            Combat => "combat_p_proc",
+            CombatIsOver => "combat_is_over_p_proc",
+            CombatIsStarting => "combat_is_starting_p_proc",
             Create => "create_p_proc",
             Critter => "critter_p_proc",
             Damage => "damage_p_proc",
@@ -180,6 +186,7 @@ impl fmt::Display for PredefinedProc {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Suspend {
     GsayEnd,
+    SayEnd,
 }
 
 /// Result of program invocation.
@@ -217,7 +224,10 @@ pub struct Context<'a> {
     /// External variables.
     pub external_vars: &'a mut HashMap<Rc<BString>, Option<Value>>,
 
+    /// Script instance the program is running as.
+    pub sid: crate::game::script::ScriptIid,
     pub self_obj: Option<object::Handle>,
+    pub fixed_param: i32,
     pub source_obj: Option<object::Handle>,
     pub target_obj: Option<object::Handle>,
     pub skill: Option<crate::asset::Skill>,
@@ -225,6 +235,11 @@ pub struct Context<'a> {
     pub world: &'a mut crate::game::world::World,
     pub obj_sequencer: &'a mut crate::game::sequence::ObjSequencer,
     pub dialog: &'a mut Option<crate::game::dialog::Dialog>,
+    pub script_ui: &'a mut crate::game::script_ui::ScriptUi,
+    pub audio: &'a mut crate::audio::Audio,
+    pub movie: &'a mut Option<crate::state::movie::MovieRequest>,
+    pub world_map: &'a mut crate::game::worldmap::WorldMap,
+    pub combat: &'a mut crate::game::combat::Combat,
     pub message_panel: crate::ui::Handle,
     pub script_db: &'a mut crate::asset::script::db::ScriptDb,
     pub new_scripts: NewScripts,
@@ -300,10 +315,15 @@ struct Procs {
     by_name: HashMap<Rc<BString>, ProcedureId>,
 }
 
+/// Offset of the procedure table. Code before it is the program entry point.
+const PROC_TABLE_START: usize = 42;
+
 pub struct Program {
     name: String,
     config: Rc<VmConfig>,
     code: Box<[u8]>,
+    /// Offset of the first instruction after the metadata tables.
+    code_start: usize,
     names: StringMap,
     strings: StringMap,
     procs: Procs,
@@ -311,7 +331,6 @@ pub struct Program {
 
 impl Program {
     fn new(name: String, code: Box<[u8]>, config: Rc<VmConfig>) -> Result<Self> {
-        const PROC_TABLE_START: usize = 42;
         const PROC_TABLE_HEADER_LEN: usize = 4;
         const PROC_ENTRY_LEN: usize = 24;
 
@@ -329,8 +348,9 @@ impl Program {
 
         let string_table_start = name_table_start + name_table_len_bytes;
         debug!("reading string table at 0x{:04x}", string_table_start);
-        let (strings, _) =
+        let (strings, string_table_len_bytes) =
             Self::read_string_table(&code[string_table_start..])?;
+        let code_start = string_table_start + string_table_len_bytes;
 
         debug!("reading procedure table at 0x{:04x}", PROC_TABLE_START);
         let procs = Self::read_proc_table(&code[PROC_TABLE_START..], &names)?;
@@ -339,6 +359,7 @@ impl Program {
             name,
             config,
             code,
+            code_start,
             names,
             strings,
             procs,
@@ -472,6 +493,7 @@ pub struct ProgramState {
     instr_state: instruction::State,
     /// Stack of code positions where suspend requested.
     suspend_stack: Vec<usize>,
+    debugger: Option<Rc<RefCell<debug::Debugger>>>,
 }
 
 impl ProgramState {
@@ -489,6 +511,7 @@ impl ProgramState {
             global_base: None,
             instr_state: instruction::State::new(),
             suspend_stack: Vec::new(),
+            debugger: None,
         }
     }
 
@@ -507,6 +530,9 @@ impl ProgramState {
     fn run(&mut self, ctx: &mut Context) -> Result<InvocationResult> {
         self.instr_state.script_overrides = false;
         let suspend = loop {
+            if let Some(debugger) = self.debugger.clone() {
+                debugger.borrow_mut().on_step(self, ctx);
+            }
             match self.step(ctx) {
                 Ok(r) => {
                     if let Some(s) = r {
@@ -691,6 +717,7 @@ pub struct Vm {
     config: Rc<VmConfig>,
     program_handles: SlotMap<Handle, ()>,
     program_states: SecondaryMap<Handle, ProgramState>,
+    debugger: Option<Rc<RefCell<debug::Debugger>>>,
 }
 
 impl Vm {
@@ -699,6 +726,7 @@ impl Vm {
             config,
             program_handles: SlotMap::with_key(),
             program_states: SecondaryMap::new(),
+            debugger: None,
         }
     }
 
@@ -707,12 +735,22 @@ impl Vm {
     }
 
     pub fn insert(&mut self, program: Rc<Program>) -> Handle {
-        let program_state = ProgramState::new(program);
+        let mut program_state = ProgramState::new(program);
+        program_state.debugger = self.debugger.clone();
         let h = self.program_handles.insert(());
         self.program_states.insert(h, program_state);
         h
     }
 
+    /// Attaches debugger to all existing and future programs.
+    pub fn set_debugger(&mut self, debugger: debug::Debugger) {
+        let debugger = Rc::new(RefCell::new(debugger));
+        for state in self.program_states.values_mut() {
+            state.debugger = Some(debugger.clone());
+        }
+        self.debugger = Some(debugger);
+    }
+
     pub fn run(&mut self, program: Handle, ctx: &mut Context) -> Result<InvocationResult> {
         self.program_state_mut(program).run(ctx)
     }
@@ -0,0 +1,258 @@
+//! Compiler of SSL (Star-Trek Scripting Language) sources into the bytecode loadable by `Vm`.
+//!
+//! Supported is the subset of the language used by the original game scripts: C-like
+//! preprocessor (`#define`, `#include`, `#ifdef` and friends), program global, imported and
+//! exported variables, procedures with arguments, `if`/`else`, `while`, `return`, `call` and
+//! expressions. Instructions that behave like regular functions (see `Opcode::signature()`) are
+//! callable by their script names, for example `set_global_var(1, 2)`.
+//!
+//! The emitted code follows the patterns of the original compiler closely enough for
+//! `disasm::decompile()` to recover the structured statements.
+
+mod codegen;
+mod lex;
+mod parse;
+
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Loc {
+    file: Rc<str>,
+    line: u32,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub file: String,
+    /// One-based line number or 0 if the location is unknown.
+    pub line: u32,
+    pub message: String,
+}
+
+impl Error {
+    fn new(loc: Loc, message: impl Into<String>) -> Self {
+        Self {
+            file: loc.file.to_string(),
+            line: loc.line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Compiles `src` into program bytecode. The `include` is called to read files referenced
+/// by `#include` directives. Sources are expected to be decoded as Latin-1.
+pub fn compile(file_name: &str, src: &str, include: &mut dyn FnMut(&str) -> io::Result<String>)
+    -> Result<Vec<u8>>
+{
+    let tokens = lex::Preprocessor::new(include).process(src, file_name)?;
+    let decls = parse::parse(&tokens)?;
+    codegen::generate(decls)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::{disasm, Vm};
+    use crate::vm::test::Harness;
+
+    fn compile_str(src: &str) -> Result<Vec<u8>> {
+        compile("test.ssl", src, &mut |path| Ok(match path {
+            "defs.h" => "#define ANSWER (6 * 7)\n#define SET(i, v) set_global_var(i, v)\n".into(),
+            _ => return Err(io::ErrorKind::NotFound.into()),
+        }))
+    }
+
+    /// Compiles and runs the `start` procedure twice.
+    fn run(src: &str) -> Harness {
+        let mut h = Harness::new();
+        let prg = h.load(compile_str(src).unwrap()).unwrap();
+        let global_count = h.vm.program_state(prg).data_stack.len();
+        // Make sure the stacks are balanced.
+        for _ in 0..2 {
+            h.execute_proc(prg, "start").unwrap().assert_no_suspend();
+            let prg = h.vm.program_state(prg);
+            assert_eq!(prg.data_stack.len(), global_count);
+            assert_eq!(prg.return_stack.len(), 0);
+        }
+        h
+    }
+
+    #[test]
+    fn arithmetic() {
+        let gvars = run("
+            procedure start begin
+                set_global_var(0, 1 + 2 * 3 - -4);
+                set_global_var(1, (1 + 2) * 3 % 5);
+                set_global_var(2, 7 / 2 + (3 > 2) + (2 <= 1) + (1 == 1 and 0 or 1));
+                set_global_var(3, 6 bwand 3 bwor 8 bwxor 1);
+                set_global_var(4, not 0 + bwnot 0);
+                set_global_var(5, 100000 + 0x10);
+            end
+        ").global_vars;
+        assert_eq!(&gvars[..6], &[11, 4, 5, 11, 0, 100016]);
+    }
+
+    #[test]
+    fn control_flow() {
+        let gvars = run("
+            procedure start begin
+                variable i := 0, sum;
+                while i < 10 do begin
+                    if i % 2 == 0 then
+                        sum += i;
+                    else if i == 5 then
+                        sum := sum + 100;
+                    else begin
+                        sum -= 1;
+                    end
+                    i++;
+                end
+                set_global_var(0, sum);
+                set_global_var(1, i);
+                if sum > 1000 then set_global_var(2, 1); else set_global_var(2, 2);
+                if sum < 1000 then set_global_var(3, 3);
+            end
+        ").global_vars;
+        assert_eq!(&gvars[..4], &[116, 10, 2, 3]);
+    }
+
+    #[test]
+    fn procedures() {
+        let gvars = run("
+            procedure fact(variable n);
+            variable calls;
+
+            procedure start begin
+                set_global_var(0, fact(5));
+                call record(1, 2);
+                call record(fact(3), 3);
+                set_global_var(4, calls);
+                fact(1);
+            end
+
+            procedure fact(variable n) begin
+                calls++;
+                if n <= 1 then return 1;
+                return n * fact(n - 1);
+            end
+
+            procedure record(variable v, variable i) begin
+                variable tmp;
+                tmp := v * 10;
+                set_global_var(i, tmp);
+            end
+        ").global_vars;
+        assert_eq!(&gvars[..5], &[120, 0, 10, 60, 17]);
+    }
+
+    #[test]
+    fn variables() {
+        let h = run("
+            #include \"defs.h\"
+            #define LOCAL 1
+
+            variable g1 := 5, g2;
+            variable begin
+                g3 := -2;
+            end
+            export variable shared := 7;
+            export variable other;
+            import variable elsewhere;
+
+            procedure start begin
+                variable x := g1 + g3;
+                g2 += x;
+                shared := shared + g2;
+            #ifdef LOCAL
+                SET(0, g2);
+            #else
+                SET(0, -1);
+            #endif
+                SET(1, ANSWER);
+                SET(2, shared);
+            end
+        ");
+        // Globals and externals are initialized only once when the program is loaded.
+        assert_eq!(&h.global_vars[..3], &[6, 42, 16]);
+        let ext: std::collections::HashMap<_, _> = h.external_vars.into_iter()
+            .map(|(k, v)| (k.display().to_string(), v.map(|v| v.into_int().unwrap())))
+            .collect();
+        assert_eq!(ext.len(), 2);
+        assert_eq!(ext["shared"], Some(16));
+        assert_eq!(ext["other"], None);
+    }
+
+    #[test]
+    fn program_layout() {
+        let code = compile_str("
+            variable g;
+            procedure foo;
+            critical procedure start begin
+                display_msg(\"hello\");
+                display_msg(\"hello\" + \"!\");
+                g := foo;
+            end
+            procedure foo begin end
+        ").unwrap();
+        let program = Vm::default().load("test".into(), code.into()).unwrap();
+        let start = program.proc(program.proc_id(&Rc::new("start".into())).unwrap()).unwrap();
+        assert!(start.flags.contains(crate::vm::ProcedureFlag::Critical));
+        assert_eq!(program.strings.map.len(), 2);
+        assert_eq!(program.proc_id(&Rc::new("foo".into())), Some(0));
+    }
+
+    #[test]
+    fn decompile_compiled() {
+        let code = compile_str("
+            procedure start begin
+                variable i;
+                while i < 3 do begin
+                    if i == 1 then
+                        display_msg(\"one\");
+                    else
+                        display_msg(\"other\");
+                    i := i + 1;
+                end
+            end
+        ").unwrap();
+        let program = Vm::default().load("test".into(), code.into()).unwrap();
+        let mut out = Vec::new();
+        disasm::decompile(&program, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("exit_prog"), "{}", out);
+        assert!(out.contains("while (var0 < 3) do begin"), "{}", out);
+        assert!(out.contains("end else begin"), "{}", out);
+    }
+
+    #[test]
+    fn errors() {
+        let e = |src: &str| {
+            let e = compile_str(src).unwrap_err();
+            (e.line, e.message)
+        };
+        assert_eq!(e("procedure start begin\n  x := 1;\nend"),
+            (2, "undefined variable `x`".into()));
+        assert_eq!(e("procedure start begin\n  display_msg(1, 2);\nend"),
+            (2, "`display_msg` expects 1 arguments".into()));
+        assert_eq!(e("procedure start begin\n  variable x := display_msg(1);\nend"),
+            (2, "`display_msg` doesn't return a value".into()));
+        assert_eq!(e("procedure foo;\nprocedure start begin\nend"),
+            (1, "procedure `foo` is declared but not defined".into()));
+        assert_eq!(e("procedure start begin\n  if 1 then\nend"),
+            (3, "expected expression".into()));
+        assert_eq!(e("#include \"missing.h\""),
+            (1, "#include: couldn't read missing.h: entity not found".into()));
+    }
+}
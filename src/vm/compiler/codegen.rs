@@ -0,0 +1,614 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use enumflags2::BitFlags;
+use std::collections::HashMap;
+
+use super::{Error, Loc, Result};
+use super::parse::*;
+use crate::vm::{PROC_TABLE_START, ProcedureFlag};
+use crate::vm::instruction::{instructions::INSTRUCTIONS, Opcode};
+use crate::vm::instruction::Opcode::*;
+
+/// Code position of the entry point procedures return to when invoked from outside.
+/// See `ProgramState::execute_proc()`.
+const PROC_EXIT_POS: usize = 24;
+
+#[derive(Clone, Copy)]
+struct Builtin {
+    opcode: Opcode,
+    arg_count: usize,
+    returns: bool,
+}
+
+fn builtins() -> HashMap<String, Builtin> {
+    let mut instrs: Vec<_> = INSTRUCTIONS.iter().map(|i| i.opcode()).collect();
+    instrs.sort_by_key(|&o| o as u16);
+    let mut r = HashMap::new();
+    for opcode in instrs {
+        if let Some((arg_count, returns)) = opcode.signature() {
+            r.entry(opcode.mnemonic()).or_insert(Builtin { opcode, arg_count, returns });
+        }
+    }
+    r
+}
+
+/// Name or string table.
+#[derive(Default)]
+struct StringTable {
+    data: Vec<u8>,
+    offsets: HashMap<String, usize>,
+}
+
+impl StringTable {
+    /// Returns offset of the string relative to the table start as expected by `const_string`.
+    /// The string is expected to contain only Latin-1 characters.
+    fn intern(&mut self, s: &str) -> usize {
+        if let Some(&offset) = self.offsets.get(s) {
+            return offset;
+        }
+        let mut bytes: Vec<u8> = s.chars().map(|c| c as u8).collect();
+        // Null-terminated and padded to even length to keep the code aligned.
+        bytes.push(0);
+        bytes.resize((bytes.len() + 1) & !1, 0);
+        self.data.write_u16::<BigEndian>(bytes.len() as u16).unwrap();
+        let offset = 4 + self.data.len();
+        self.data.extend_from_slice(&bytes);
+        self.offsets.insert(s.into(), offset);
+        offset
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        if self.data.is_empty() {
+            out.write_u32::<BigEndian>(0xffff_ffff).unwrap();
+        } else {
+            out.write_u32::<BigEndian>(self.data.len() as u32).unwrap();
+            out.extend_from_slice(&self.data);
+            out.extend_from_slice(&[0xff, 0xff, 0, 0]);
+        }
+    }
+}
+
+struct ProcInfo {
+    name: String,
+    loc: Loc,
+    flags: BitFlags<ProcedureFlag>,
+    params: Vec<String>,
+    name_offset: usize,
+    body: Option<Vec<Stmt>>,
+    body_pos: Option<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Var {
+    Local(usize),
+    Global(usize),
+    External,
+}
+
+struct Gen {
+    /// Code following the metadata tables. Addresses are relative to its start until relocated.
+    code: Vec<u8>,
+    /// Positions of address operands that need relocation.
+    fixups: Vec<usize>,
+    names: StringTable,
+    strings: StringTable,
+    procs: Vec<ProcInfo>,
+    proc_ids: HashMap<String, usize>,
+    vars: HashMap<String, Var>,
+    builtins: HashMap<String, Builtin>,
+    /// Procedure arguments and variables of the procedure being generated.
+    locals: HashMap<String, usize>,
+}
+
+pub fn generate(decls: Vec<Decl>) -> Result<Vec<u8>> {
+    let mut gen = Gen {
+        code: Vec::new(),
+        fixups: Vec::new(),
+        names: StringTable::default(),
+        strings: StringTable::default(),
+        procs: Vec::new(),
+        proc_ids: HashMap::new(),
+        vars: HashMap::new(),
+        builtins: builtins(),
+        locals: HashMap::new(),
+    };
+
+    let mut globals = Vec::new();
+    let mut exports = Vec::new();
+    for decl in decls {
+        match decl {
+            Decl::Var(kind, var) => {
+                if gen.vars.contains_key(&var.name) {
+                    return Err(Error::new(var.loc,
+                        format!("variable `{}` is already defined", var.name)));
+                }
+                let v = match kind {
+                    VarKind::Global => {
+                        globals.push(var.clone());
+                        Var::Global(globals.len() - 1)
+                    }
+                    VarKind::Import => Var::External,
+                    VarKind::Export => {
+                        exports.push(var.clone());
+                        Var::External
+                    }
+                };
+                gen.vars.insert(var.name, v);
+            }
+            Decl::Proc(proc) => gen.declare_proc(proc)?,
+        }
+    }
+
+    for proc in &gen.procs {
+        if proc.body.is_none() && !proc.flags.contains(ProcedureFlag::Import) {
+            return Err(Error::new(proc.loc.clone(),
+                format!("procedure `{}` is declared but not defined", proc.name)));
+        }
+    }
+
+    gen.init(&globals, &exports)?;
+    for i in 0..gen.procs.len() {
+        if let Some(body) = gen.procs[i].body.take() {
+            gen.procs[i].body_pos = Some(gen.code.len());
+            gen.proc(i, &body)?;
+        }
+    }
+
+    Ok(gen.assemble())
+}
+
+impl Gen {
+    fn declare_proc(&mut self, proc: Proc) -> Result<()> {
+        let mut flags = BitFlags::empty();
+        if proc.flags.critical {
+            flags |= ProcedureFlag::Critical;
+        }
+        if proc.flags.import {
+            flags |= ProcedureFlag::Import;
+        }
+        if proc.flags.export {
+            flags |= ProcedureFlag::Export;
+        }
+        if let Some(&id) = self.proc_ids.get(&proc.name) {
+            let existing = &mut self.procs[id];
+            if existing.params.len() != proc.params.len() {
+                return Err(Error::new(proc.loc, format!(
+                    "procedure `{}` was declared with {} arguments",
+                    proc.name, existing.params.len())));
+            }
+            if proc.body.is_some() {
+                if existing.body.is_some() {
+                    return Err(Error::new(proc.loc,
+                        format!("procedure `{}` is already defined", proc.name)));
+                }
+                existing.body = proc.body;
+                existing.loc = proc.loc;
+                existing.params = proc.params;
+            }
+            existing.flags |= flags;
+            return Ok(());
+        }
+        let name_offset = self.names.intern(&proc.name);
+        self.proc_ids.insert(proc.name.clone(), self.procs.len());
+        self.procs.push(ProcInfo {
+            name: proc.name,
+            loc: proc.loc,
+            flags,
+            params: proc.params,
+            name_offset,
+            body: proc.body,
+            body_pos: None,
+        });
+        Ok(())
+    }
+
+    /// Generates program initialization code that sets up global and exported variables.
+    fn init(&mut self, globals: &[VarDecl], exports: &[VarDecl]) -> Result<()> {
+        self.op(SetGlobal);
+        for var in globals {
+            match &var.init {
+                Some(e) => self.constant(e, &var.loc)?,
+                None => self.int(0),
+            }
+        }
+        for var in exports {
+            self.name(&var.name);
+            self.op(ExportVar);
+            if let Some(e) = &var.init {
+                self.constant(e, &var.loc)?;
+                self.name(&var.name);
+                self.op(StoreExternal);
+            }
+        }
+        self.op(ExitProg);
+        Ok(())
+    }
+
+    fn proc(&mut self, id: usize, body: &[Stmt]) -> Result<()> {
+        self.locals.clear();
+        let proc = &self.procs[id];
+        let params: Vec<_> = proc.params.iter()
+            .map(|name| VarDecl { name: name.clone(), init: None, loc: proc.loc.clone() })
+            .collect();
+        for param in &params {
+            self.declare_local(param)?;
+        }
+
+        self.op(PushBase);
+        let mut local_count = 0;
+        self.collect_locals(body, &mut local_count)?;
+        for _ in 0..local_count {
+            self.int(0);
+        }
+        self.block(body)?;
+        self.int(0);
+        self.ret();
+        Ok(())
+    }
+
+    fn declare_local(&mut self, var: &VarDecl) -> Result<()> {
+        if self.locals.contains_key(&var.name) {
+            return Err(Error::new(var.loc.clone(),
+                format!("variable `{}` is already defined", var.name)));
+        }
+        let idx = self.locals.len();
+        self.locals.insert(var.name.clone(), idx);
+        Ok(())
+    }
+
+    /// Allocates all procedure variables upfront since the stack frame can't grow in the middle
+    /// of expression evaluation.
+    fn collect_locals(&mut self, stmts: &[Stmt], count: &mut usize) -> Result<()> {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Var(v) => {
+                    self.declare_local(v)?;
+                    *count += 1;
+                }
+                Stmt::If { then, else_, .. } => {
+                    self.collect_locals(then, count)?;
+                    self.collect_locals(else_, count)?;
+                }
+                Stmt::While { body, .. } => self.collect_locals(body, count)?,
+                Stmt::Assign { .. } | Stmt::Return(_) | Stmt::Expr(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns from procedure with the value on top of the stack.
+    fn ret(&mut self) {
+        self.op(DToA);
+        self.op(PopToBase);
+        self.op(Swapa);
+        self.op(PopBase);
+        self.op(AToD);
+        self.op(PopReturn);
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<()> {
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Var(v) => if let Some(init) = &v.init {
+                self.expr(init)?;
+                self.store(&v.name, &v.loc)?;
+            }
+            Stmt::Assign { name, op, value, loc } => {
+                if let Some(op) = *op {
+                    self.fetch(name, loc)?;
+                    self.expr(value)?;
+                    self.op(binary_opcode(op));
+                } else {
+                    self.expr(value)?;
+                }
+                self.store(name, loc)?;
+            }
+            Stmt::If { cond, then, else_ } => {
+                let else_pos = self.addr_placeholder();
+                self.expr(cond)?;
+                self.op(If);
+                self.block(then)?;
+                if else_.is_empty() {
+                    self.patch(else_pos);
+                } else {
+                    let end_pos = self.addr_placeholder();
+                    self.op(Jmp);
+                    self.patch(else_pos);
+                    self.block(else_)?;
+                    self.patch(end_pos);
+                }
+            }
+            Stmt::While { cond, body } => {
+                let loop_pos = self.code.len();
+                let end_pos = self.addr_placeholder();
+                self.expr(cond)?;
+                self.op(If);
+                self.block(body)?;
+                self.addr(loop_pos);
+                self.op(Jmp);
+                self.patch(end_pos);
+            }
+            Stmt::Return(v) => {
+                if let Some(v) = v {
+                    self.expr(v)?;
+                } else {
+                    self.int(0);
+                }
+                self.ret();
+            }
+            Stmt::Expr(e) => {
+                let returns = match e {
+                    Expr::Call(name, args, loc) if !self.proc_ids.contains_key(name) => {
+                        let b = self.builtin(name, args.len(), loc)?;
+                        self.builtin_call(b, args)?;
+                        b.returns
+                    }
+                    _ => {
+                        self.expr(e)?;
+                        true
+                    }
+                };
+                if returns {
+                    self.op(Pop);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, e: &Expr) -> Result<()> {
+        match e {
+            &Expr::Int(v) => self.int(v),
+            &Expr::Float(v) => {
+                self.op(ConstFloat);
+                self.code.write_f32::<BigEndian>(v).unwrap();
+            }
+            Expr::Str(s) => {
+                let offset = self.strings.intern(s);
+                self.op(ConstString);
+                self.code.write_i32::<BigEndian>(offset as i32).unwrap();
+            }
+            Expr::Ident(name, loc) => {
+                if self.var(name).is_some() {
+                    self.fetch(name, loc)?;
+                } else if let Some(&id) = self.proc_ids.get(name) {
+                    self.int(id as i32);
+                } else if self.builtins.contains_key(name) {
+                    let b = self.builtin(name, 0, loc)?;
+                    self.value_builtin_call(name, b, &[], loc)?;
+                } else {
+                    return Err(Error::new(loc.clone(),
+                        format!("undefined identifier `{}`", name)));
+                }
+            }
+            Expr::Call(name, args, loc) => {
+                if let Some(&id) = self.proc_ids.get(name) {
+                    self.proc_call(id, args, loc)?;
+                } else {
+                    let b = self.builtin(name, args.len(), loc)?;
+                    self.value_builtin_call(name, b, args, loc)?;
+                }
+            }
+            Expr::Unary(op, e) => {
+                self.expr(e)?;
+                self.op(match op {
+                    UnOp::Neg => Negate,
+                    UnOp::Not => Not,
+                    UnOp::BwNot => Bwnot,
+                });
+            }
+            Expr::Binary(op, l, r) => {
+                self.expr(l)?;
+                self.expr(r)?;
+                self.op(binary_opcode(*op));
+            }
+        }
+        Ok(())
+    }
+
+    fn proc_call(&mut self, id: usize, args: &[Expr], loc: &Loc) -> Result<()> {
+        let proc = &self.procs[id];
+        if args.len() != proc.params.len() {
+            return Err(Error::new(loc.clone(), format!(
+                "procedure `{}` expects {} arguments", proc.name, proc.params.len())));
+        }
+        let ret_pos = self.addr_placeholder();
+        self.op(DToA);
+        for arg in args {
+            self.expr(arg)?;
+        }
+        self.int(args.len() as i32);
+        self.int(id as i32);
+        self.op(Call);
+        self.patch(ret_pos);
+        Ok(())
+    }
+
+    fn builtin(&self, name: &str, arg_count: usize, loc: &Loc) -> Result<Builtin> {
+        let b = *self.builtins.get(name)
+            .ok_or_else(|| Error::new(loc.clone(),
+                format!("undefined procedure `{}`", name)))?;
+        if b.arg_count != arg_count {
+            return Err(Error::new(loc.clone(),
+                format!("`{}` expects {} arguments", name, b.arg_count)));
+        }
+        Ok(b)
+    }
+
+    fn builtin_call(&mut self, b: Builtin, args: &[Expr]) -> Result<()> {
+        for arg in args {
+            self.expr(arg)?;
+        }
+        self.op(b.opcode);
+        Ok(())
+    }
+
+    fn value_builtin_call(&mut self, name: &str, b: Builtin, args: &[Expr], loc: &Loc)
+        -> Result<()>
+    {
+        if !b.returns {
+            return Err(Error::new(loc.clone(),
+                format!("`{}` doesn't return a value", name)));
+        }
+        self.builtin_call(b, args)
+    }
+
+    /// Evaluates compile-time constant.
+    fn constant(&mut self, e: &Expr, loc: &Loc) -> Result<()> {
+        match e {
+            Expr::Int(_) | Expr::Float(_) | Expr::Str(_) => self.expr(e),
+            _ => Err(Error::new(loc.clone(), "initializer must be a constant")),
+        }
+    }
+
+    fn var(&self, name: &str) -> Option<Var> {
+        self.locals.get(name).map(|&i| Var::Local(i))
+            .or_else(|| self.vars.get(name).cloned())
+    }
+
+    fn fetch(&mut self, name: &str, loc: &Loc) -> Result<()> {
+        match self.var(name) {
+            Some(Var::Local(i)) => {
+                self.int(i as i32);
+                self.op(Fetch);
+            }
+            Some(Var::Global(i)) => {
+                self.int(i as i32);
+                self.op(FetchGlobal);
+            }
+            Some(Var::External) => {
+                self.name(name);
+                self.op(FetchExternal);
+            }
+            None => return Err(Error::new(loc.clone(),
+                format!("undefined variable `{}`", name))),
+        }
+        Ok(())
+    }
+
+    fn store(&mut self, name: &str, loc: &Loc) -> Result<()> {
+        match self.var(name) {
+            Some(Var::Local(i)) => {
+                self.int(i as i32);
+                self.op(Store);
+            }
+            Some(Var::Global(i)) => {
+                self.int(i as i32);
+                self.op(StoreGlobal);
+            }
+            Some(Var::External) => {
+                self.name(name);
+                self.op(StoreExternal);
+            }
+            None => return Err(Error::new(loc.clone(),
+                format!("undefined variable `{}`", name))),
+        }
+        Ok(())
+    }
+
+    fn op(&mut self, opcode: Opcode) {
+        self.code.write_u16::<BigEndian>(opcode as u16).unwrap();
+    }
+
+    fn int(&mut self, v: i32) {
+        self.op(if v as i16 as i32 == v { ConstShort } else { ConstLong });
+        self.code.write_i32::<BigEndian>(v).unwrap();
+    }
+
+    fn name(&mut self, name: &str) {
+        let offset = self.names.intern(name);
+        self.op(ConstString);
+        self.code.write_i32::<BigEndian>(offset as i32).unwrap();
+    }
+
+    /// Pushes code address.
+    fn addr(&mut self, pos: usize) {
+        self.op(ConstLong);
+        self.fixups.push(self.code.len());
+        self.code.write_i32::<BigEndian>(pos as i32).unwrap();
+    }
+
+    /// Pushes code address that will be set by `patch()`. Returns the operand position.
+    fn addr_placeholder(&mut self) -> usize {
+        self.addr(0);
+        self.code.len() - 4
+    }
+
+    /// Sets the address operand at `operand_pos` to the current code position.
+    fn patch(&mut self, operand_pos: usize) {
+        let pos = self.code.len() as i32;
+        (&mut self.code[operand_pos..operand_pos + 4]).write_i32::<BigEndian>(pos).unwrap();
+    }
+
+    fn assemble(self) -> Vec<u8> {
+        let mut tables = Vec::new();
+        tables.write_u32::<BigEndian>(self.procs.len() as u32).unwrap();
+        let mut names = Vec::new();
+        self.names.write(&mut names);
+        let mut strings = Vec::new();
+        self.strings.write(&mut strings);
+        let code_start = PROC_TABLE_START + 4 + self.procs.len() * 24 + names.len() + strings.len();
+        for proc in &self.procs {
+            let body_pos = proc.body_pos.map(|p| p + code_start).unwrap_or(0);
+            for &v in &[proc.name_offset, proc.flags.bits() as usize, 0, 0, body_pos,
+                proc.params.len()]
+            {
+                tables.write_u32::<BigEndian>(v as u32).unwrap();
+            }
+        }
+        tables.extend_from_slice(&names);
+        tables.extend_from_slice(&strings);
+
+        let mut r = Vec::with_capacity(code_start + self.code.len());
+        // Entry point: jump to the initialization code at the start of code.
+        r.write_u16::<BigEndian>(ConstLong as u16).unwrap();
+        r.write_i32::<BigEndian>(code_start as i32).unwrap();
+        r.write_u16::<BigEndian>(Jmp as u16).unwrap();
+        while r.len() < PROC_EXIT_POS {
+            r.write_u16::<BigEndian>(Noop8000 as u16).unwrap();
+        }
+        // Discard the return value and exit. Procedures called by the engine return here.
+        r.write_u16::<BigEndian>(Pop as u16).unwrap();
+        r.write_u16::<BigEndian>(PopFlagsExit as u16).unwrap();
+        while r.len() < PROC_TABLE_START {
+            r.write_u16::<BigEndian>(Noop8000 as u16).unwrap();
+        }
+        r.extend_from_slice(&tables);
+
+        let mut code = self.code;
+        for pos in self.fixups {
+            let v = &mut code[pos..pos + 4];
+            let addr = i32::from_be_bytes([v[0], v[1], v[2], v[3]]) + code_start as i32;
+            v.copy_from_slice(&addr.to_be_bytes());
+        }
+        r.extend_from_slice(&code);
+        r
+    }
+}
+
+fn binary_opcode(op: BinOp) -> Opcode {
+    use BinOp::*;
+    match op {
+        Or => Opcode::Or,
+        And => Opcode::And,
+        Eq => Equal,
+        Ne => NotEqual,
+        Lt => Less,
+        Le => LessEqual,
+        Gt => Greater,
+        Ge => GreaterEqual,
+        Add => Opcode::Add,
+        Sub => Opcode::Sub,
+        BwOr => Bwor,
+        BwXor => Bwxor,
+        Mul => Opcode::Mul,
+        Div => Opcode::Div,
+        Mod => Opcode::Mod,
+        BwAnd => Bwand,
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::{Error, Loc, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tok {
+    Ident(String),
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Punct(&'static str),
+    /// Preprocessor directive line without the leading `#`.
+    Directive(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub tok: Tok,
+    pub loc: Loc,
+}
+
+const PUNCTS: &[&str] = &[
+    ":=", "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "++", "--",
+    "<", ">", "+", "-", "*", "/", "%", "(", ")", ",", ";", "=",
+];
+
+pub fn lex(src: &str, file: &Rc<str>) -> Result<Vec<Token>> {
+    let src: Vec<char> = src.chars().collect();
+    let mut r = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut line_start = true;
+    let loc = |line| Loc { file: file.clone(), line };
+    while i < src.len() {
+        let c = src[i];
+        if c == '\n' {
+            line += 1;
+            line_start = true;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '/' && src.get(i + 1) == Some(&'/') {
+            while i < src.len() && src[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && src.get(i + 1) == Some(&'*') {
+            let start_line = line;
+            i += 2;
+            loop {
+                if i + 1 >= src.len() {
+                    return Err(Error::new(loc(start_line), "unterminated comment"));
+                }
+                if src[i] == '*' && src[i + 1] == '/' {
+                    i += 2;
+                    break;
+                }
+                if src[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            continue;
+        }
+
+        let tok_line = line;
+        if c == '#' && line_start {
+            // Directive spans until the end of line, `\` continues it on the next line.
+            let mut s = String::new();
+            i += 1;
+            while i < src.len() && src[i] != '\n' {
+                if src[i] == '\\' && src.get(i + 1) == Some(&'\n') {
+                    line += 1;
+                    i += 2;
+                    s.push(' ');
+                    continue;
+                }
+                if src[i] == '/' && src.get(i + 1) == Some(&'/') {
+                    while i < src.len() && src[i] != '\n' {
+                        i += 1;
+                    }
+                    break;
+                }
+                s.push(src[i]);
+                i += 1;
+            }
+            r.push(Token { tok: Tok::Directive(s), loc: loc(tok_line) });
+            continue;
+        }
+        line_start = false;
+
+        let tok = if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < src.len() && (src[i].is_ascii_alphanumeric() || src[i] == '_') {
+                i += 1;
+            }
+            Tok::Ident(src[start..i].iter().collect())
+        } else if c.is_ascii_digit() {
+            let start = i;
+            if c == '0' && (src.get(i + 1) == Some(&'x') || src.get(i + 1) == Some(&'X')) {
+                i += 2;
+                while i < src.len() && src[i].is_ascii_hexdigit() {
+                    i += 1;
+                }
+                let s: String = src[start + 2..i].iter().collect();
+                let v = u32::from_str_radix(&s, 16)
+                    .map_err(|_| Error::new(loc(tok_line), "invalid hex number"))?;
+                Tok::Int(v as i32)
+            } else {
+                while i < src.len() && src[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let float = src.get(i) == Some(&'.')
+                    && src.get(i + 1).map(|c| c.is_ascii_digit()) == Some(true);
+                if float {
+                    i += 1;
+                    while i < src.len() && src[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                let s: String = src[start..i].iter().collect();
+                if float {
+                    Tok::Float(s.parse()
+                        .map_err(|_| Error::new(loc(tok_line), "invalid number"))?)
+                } else {
+                    // Wrap around like the original compiler does for e.g. 0xffffffff.
+                    Tok::Int(s.parse::<i64>()
+                        .map_err(|_| Error::new(loc(tok_line), "invalid number"))? as i32)
+                }
+            }
+        } else if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match src.get(i) {
+                    None | Some('\n') =>
+                        return Err(Error::new(loc(tok_line), "unterminated string")),
+                    Some('"') => break,
+                    Some('\\') => {
+                        i += 1;
+                        match src.get(i) {
+                            Some('n') => s.push('\n'),
+                            Some(&c) => s.push(c),
+                            None => {}
+                        }
+                    }
+                    Some(&c) if c as u32 > 0xff => return Err(Error::new(loc(tok_line),
+                        format!("character `{}` can't be encoded", c))),
+                    Some(&c) => s.push(c),
+                }
+                i += 1;
+            }
+            if s.chars().count() >= 0xfffe {
+                return Err(Error::new(loc(tok_line), "string is too long"));
+            }
+            i += 1;
+            Tok::Str(s)
+        } else if let Some(p) = PUNCTS.iter().find(|p| {
+            p.chars().enumerate().all(|(j, pc)| src.get(i + j) == Some(&pc))
+        }) {
+            i += p.len();
+            Tok::Punct(p)
+        } else {
+            return Err(Error::new(loc(tok_line), format!("unexpected character `{}`", c)));
+        };
+        r.push(Token { tok, loc: loc(tok_line) });
+    }
+    Ok(r)
+}
+
+struct Macro {
+    /// `None` for object-like macros.
+    params: Option<Vec<String>>,
+    body: Vec<Token>,
+}
+
+/// Loads source of the included file.
+pub type IncludeLoader<'a> = dyn FnMut(&str) -> std::io::Result<String> + 'a;
+
+/// Handles `#define`, `#undef`, `#ifdef`, `#ifndef`, `#else`, `#endif` and `#include` directives
+/// and expands macros.
+pub struct Preprocessor<'a, 'b> {
+    include: &'a mut IncludeLoader<'b>,
+    macros: HashMap<String, Macro>,
+    depth: usize,
+}
+
+impl<'a, 'b> Preprocessor<'a, 'b> {
+    pub fn new(include: &'a mut IncludeLoader<'b>) -> Self {
+        Self {
+            include,
+            macros: HashMap::new(),
+            depth: 0,
+        }
+    }
+
+    pub fn process(&mut self, src: &str, file: &str) -> Result<Vec<Token>> {
+        let file: Rc<str> = file.into();
+        let tokens = lex(src, &file)?;
+        let mut r = Vec::new();
+        // Whether the current conditional block is active.
+        let mut conds: Vec<bool> = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            let active = conds.iter().all(|&c| c);
+            if let Tok::Directive(d) = &token.tok {
+                self.directive(d, &token.loc, &mut conds, active, &mut r)?;
+                i += 1;
+                continue;
+            }
+            if !active {
+                i += 1;
+                continue;
+            }
+            // Collect tokens up to the next directive so function-like macro arguments can
+            // span several lines.
+            let end = tokens[i..].iter().position(|t| matches!(t.tok, Tok::Directive(_)))
+                .map(|e| i + e)
+                .unwrap_or(tokens.len());
+            self.expand(&tokens[i..end], &HashSet::new(), &mut r)?;
+            i = end;
+        }
+        if !conds.is_empty() {
+            return Err(Error::new(Loc { file, line: 0 }, "missing #endif"));
+        }
+        Ok(r)
+    }
+
+    fn directive(&mut self, d: &str, loc: &Loc, conds: &mut Vec<bool>, active: bool,
+        out: &mut Vec<Token>) -> Result<()>
+    {
+        let d = d.trim_start();
+        let name_end = d.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(d.len());
+        let (name, rest) = d.split_at(name_end);
+        let arg = || {
+            let a = rest.trim();
+            if a.is_empty() {
+                Err(Error::new(loc.clone(), format!("#{}: missing argument", name)))
+            } else {
+                Ok(a)
+            }
+        };
+        match name {
+            "ifdef" | "ifndef" => {
+                let defined = self.macros.contains_key(arg()?);
+                conds.push(defined == (name == "ifdef"));
+            }
+            "else" => {
+                let c = conds.last_mut()
+                    .ok_or_else(|| Error::new(loc.clone(), "#else without #if"))?;
+                *c = !*c;
+            }
+            "endif" => {
+                conds.pop().ok_or_else(|| Error::new(loc.clone(), "#endif without #if"))?;
+            }
+            _ if !active => {}
+            "define" => self.define(rest, loc)?,
+            "undef" => {
+                self.macros.remove(arg()?);
+            }
+            "include" => {
+                let path = arg()?;
+                let path = if path.len() >= 2 && path.starts_with('"') && path.ends_with('"') {
+                    &path[1..path.len() - 1]
+                } else {
+                    return Err(Error::new(loc.clone(), "#include: expected quoted file name"));
+                };
+                if self.depth >= 32 {
+                    return Err(Error::new(loc.clone(), "#include: nesting is too deep"));
+                }
+                let src = (self.include)(path)
+                    .map_err(|e| Error::new(loc.clone(),
+                        format!("#include: couldn't read {}: {}", path, e)))?;
+                self.depth += 1;
+                let r = self.process(&src, path);
+                self.depth -= 1;
+                out.extend(r?);
+            }
+            _ => return Err(Error::new(loc.clone(), format!("unknown directive #{}", name))),
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, s: &str, loc: &Loc) -> Result<()> {
+        let s = s.trim_start();
+        let name_end = s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(s.len());
+        let (name, mut rest) = s.split_at(name_end);
+        if name.is_empty() {
+            return Err(Error::new(loc.clone(), "#define: missing macro name"));
+        }
+        let params = if rest.starts_with('(') {
+            let end = rest.find(')')
+                .ok_or_else(|| Error::new(loc.clone(), "#define: missing `)`"))?;
+            let params = rest[1..end].split(',')
+                .map(|p| p.trim().to_owned())
+                .filter(|p| !p.is_empty())
+                .collect();
+            rest = &rest[end + 1..];
+            Some(params)
+        } else {
+            None
+        };
+        let body = lex(rest, &loc.file)?.into_iter()
+            .map(|t| Token { tok: t.tok, loc: loc.clone() })
+            .collect();
+        self.macros.insert(name.into(), Macro { params, body });
+        Ok(())
+    }
+
+    fn expand(&self, tokens: &[Token], disabled: &HashSet<String>, out: &mut Vec<Token>)
+        -> Result<()>
+    {
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            i += 1;
+            let name = match &token.tok {
+                Tok::Ident(name) if !disabled.contains(name) => name,
+                _ => {
+                    out.push(token.clone());
+                    continue;
+                }
+            };
+            let m = if let Some(m) = self.macros.get(name) {
+                m
+            } else {
+                out.push(token.clone());
+                continue;
+            };
+            let body = if let Some(params) = &m.params {
+                if tokens.get(i).map(|t| &t.tok) != Some(&Tok::Punct("(")) {
+                    out.push(token.clone());
+                    continue;
+                }
+                let (args, end) = macro_args(tokens, i + 1, &token.loc)?;
+                i = end;
+                if args.len() != params.len() && !(params.is_empty() && args.len() == 1
+                    && args[0].is_empty())
+                {
+                    return Err(Error::new(token.loc.clone(),
+                        format!("macro `{}` expects {} arguments", name, params.len())));
+                }
+                let mut body = Vec::new();
+                for t in &m.body {
+                    match &t.tok {
+                        Tok::Ident(n) if params.contains(n) => {
+                            let arg = &args[params.iter().position(|p| p == n).unwrap()];
+                            self.expand(arg, disabled, &mut body)?;
+                        }
+                        _ => body.push(t.clone()),
+                    }
+                }
+                body
+            } else {
+                m.body.clone()
+            };
+            let body: Vec<_> = body.into_iter()
+                .map(|t| Token { tok: t.tok, loc: token.loc.clone() })
+                .collect();
+            let mut disabled = disabled.clone();
+            disabled.insert(name.clone());
+            self.expand(&body, &disabled, out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits macro invocation arguments starting after the opening parenthesis.
+/// Returns the arguments and position after the closing parenthesis.
+fn macro_args(tokens: &[Token], start: usize, loc: &Loc) -> Result<(Vec<Vec<Token>>, usize)> {
+    let mut args = vec![Vec::new()];
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate().skip(start) {
+        match t.tok {
+            Tok::Punct("(") => depth += 1,
+            Tok::Punct(")") if depth == 0 => return Ok((args, i + 1)),
+            Tok::Punct(")") => depth -= 1,
+            Tok::Punct(",") if depth == 0 => {
+                args.push(Vec::new());
+                continue;
+            }
+            _ => {}
+        }
+        args.last_mut().unwrap().push(t.clone());
+    }
+    Err(Error::new(loc.clone(), "unterminated macro invocation"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toks(src: &str) -> Vec<Tok> {
+        let mut include = |path: &str| Ok(match path {
+            "inc.h" => "#define INC 7\n".to_owned(),
+            _ => return Err(std::io::ErrorKind::NotFound.into()),
+        });
+        Preprocessor::new(&mut include).process(src, "test.ssl").unwrap()
+            .into_iter().map(|t| t.tok).collect()
+    }
+
+    fn ident(s: &str) -> Tok {
+        Tok::Ident(s.into())
+    }
+
+    #[test]
+    fn lex_() {
+        assert_eq!(toks("a := 0x10 + 1.5; // c\n/* x\n */ \"s\\\"\" <= foo_1"), vec![
+            ident("a"), Tok::Punct(":="), Tok::Int(16), Tok::Punct("+"), Tok::Float(1.5),
+            Tok::Punct(";"), Tok::Str("s\"".into()), Tok::Punct("<="), ident("foo_1"),
+        ]);
+    }
+
+    #[test]
+    fn macros() {
+        assert_eq!(toks("\
+            #define A 1\n\
+            #define F(x, y) (x + y * A)\n\
+            #include \"inc.h\"\n\
+            F(A, (2, 3)) INC\n\
+            #undef A\n\
+            #ifdef A\n\
+            no\n\
+            #else\n\
+            A\n\
+            #endif\n"), vec![
+            Tok::Punct("("), Tok::Int(1), Tok::Punct("+"),
+            Tok::Punct("("), Tok::Int(2), Tok::Punct(","), Tok::Int(3), Tok::Punct(")"),
+            Tok::Punct("*"), Tok::Int(1), Tok::Punct(")"), Tok::Int(7),
+            ident("A"),
+        ]);
+    }
+}
@@ -0,0 +1,502 @@
+use super::{Error, Loc, Result};
+use super::lex::{Tok, Token};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    BwNot,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    BwOr,
+    BwXor,
+    Mul,
+    Div,
+    Mod,
+    BwAnd,
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Ident(String, Loc),
+    Call(String, Vec<Expr>, Loc),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug)]
+pub struct VarDecl {
+    pub name: String,
+    pub init: Option<Expr>,
+    pub loc: Loc,
+}
+
+#[derive(Clone, Debug)]
+pub enum Stmt {
+    Var(VarDecl),
+    /// Assignment, `op` is set for compound assignments like `+=`.
+    Assign {
+        name: String,
+        op: Option<BinOp>,
+        value: Expr,
+        loc: Loc,
+    },
+    If {
+        cond: Expr,
+        then: Vec<Stmt>,
+        else_: Vec<Stmt>,
+    },
+    While {
+        cond: Expr,
+        body: Vec<Stmt>,
+    },
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProcFlags {
+    pub critical: bool,
+    pub import: bool,
+    pub export: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Proc {
+    pub name: String,
+    pub loc: Loc,
+    pub flags: ProcFlags,
+    pub params: Vec<String>,
+    /// `None` for forward declarations and imports.
+    pub body: Option<Vec<Stmt>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VarKind {
+    /// Program global variable.
+    Global,
+    /// External variable defined by other program.
+    Import,
+    /// External variable defined by this program.
+    Export,
+}
+
+#[derive(Clone, Debug)]
+pub enum Decl {
+    Var(VarKind, VarDecl),
+    Proc(Proc),
+}
+
+pub fn parse(tokens: &[Token]) -> Result<Vec<Decl>> {
+    let mut p = Parser { tokens, pos: 0 };
+    let mut r = Vec::new();
+    while p.pos < tokens.len() {
+        p.decl(&mut r)?;
+    }
+    Ok(r)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos).map(|t| &t.tok)
+    }
+
+    fn loc(&self) -> Loc {
+        self.tokens.get(self.pos).or_else(|| self.tokens.last())
+            .map(|t| t.loc.clone())
+            .unwrap_or_else(|| Loc { file: "".into(), line: 0 })
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        Err(Error::new(self.loc(), message))
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        match self.peek() {
+            Some(Tok::Ident(s)) => s.eq_ignore_ascii_case(kw),
+            _ => false,
+        }
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Punct(t)) if *t == p)
+    }
+
+    fn eat_keyword(&mut self, kw: &str) -> bool {
+        let r = self.is_keyword(kw);
+        if r {
+            self.pos += 1;
+        }
+        r
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        let r = self.is_punct(p);
+        if r {
+            self.pos += 1;
+        }
+        r
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<()> {
+        if self.eat_keyword(kw) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`", kw))
+        }
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<()> {
+        if self.eat_punct(p) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`", p))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Tok::Ident(s)) if !is_reserved(s) => {
+                let s = s.clone();
+                self.pos += 1;
+                Ok(s)
+            }
+            _ => self.error("expected identifier"),
+        }
+    }
+
+    fn decl(&mut self, out: &mut Vec<Decl>) -> Result<()> {
+        if self.eat_punct(";") {
+            return Ok(());
+        }
+        let mut flags = ProcFlags::default();
+        let mut var_kind = VarKind::Global;
+        if self.eat_keyword("import") {
+            flags.import = true;
+            var_kind = VarKind::Import;
+        } else if self.eat_keyword("export") {
+            flags.export = true;
+            var_kind = VarKind::Export;
+        }
+        if self.eat_keyword("critical") {
+            flags.critical = true;
+        }
+        if self.eat_keyword("variable") {
+            if flags.critical {
+                return self.error("variable can't be critical");
+            }
+            for v in self.var_decls()? {
+                if var_kind == VarKind::Import && v.init.is_some() {
+                    return Err(Error::new(v.loc,
+                        "imported variable can't have initializer"));
+                }
+                out.push(Decl::Var(var_kind, v));
+            }
+            Ok(())
+        } else if self.eat_keyword("procedure") {
+            let proc = self.proc(flags)?;
+            out.push(Decl::Proc(proc));
+            Ok(())
+        } else {
+            self.error("expected `procedure` or `variable`")
+        }
+    }
+
+    /// Parses variable declaration list after the `variable` keyword including the trailing `;`.
+    fn var_decls(&mut self) -> Result<Vec<VarDecl>> {
+        let mut r = Vec::new();
+        if self.eat_keyword("begin") {
+            while !self.eat_keyword("end") {
+                r.extend(self.var_decl_list()?);
+            }
+            self.eat_punct(";");
+        } else {
+            r = self.var_decl_list()?;
+        }
+        Ok(r)
+    }
+
+    fn var_decl_list(&mut self) -> Result<Vec<VarDecl>> {
+        let mut r = Vec::new();
+        loop {
+            let loc = self.loc();
+            let name = self.ident()?;
+            let init = if self.eat_punct(":=") || self.eat_punct("=") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            r.push(VarDecl { name, init, loc });
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(";")?;
+        Ok(r)
+    }
+
+    fn proc(&mut self, flags: ProcFlags) -> Result<Proc> {
+        let loc = self.loc();
+        let name = self.ident()?;
+        let mut params = Vec::new();
+        if self.eat_punct("(") && !self.eat_punct(")") {
+            loop {
+                self.eat_keyword("variable");
+                params.push(self.ident()?);
+                if self.eat_punct(")") {
+                    break;
+                }
+                self.expect_punct(",")?;
+            }
+        }
+        let body = if self.eat_punct(";") {
+            None
+        } else if flags.import {
+            return self.error("imported procedure can't have body");
+        } else {
+            self.expect_keyword("begin")?;
+            Some(self.block()?)
+        };
+        Ok(Proc { name, loc, flags, params, body })
+    }
+
+    /// Parses statements up to and including the closing `end`.
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        let mut r = Vec::new();
+        while !self.eat_keyword("end") {
+            if self.peek().is_none() {
+                return self.error("expected `end`");
+            }
+            self.stmt(&mut r)?;
+        }
+        Ok(r)
+    }
+
+    /// Parses single statement or `begin ... end` block.
+    fn body(&mut self) -> Result<Vec<Stmt>> {
+        let mut r = Vec::new();
+        self.stmt(&mut r)?;
+        Ok(r)
+    }
+
+    fn stmt(&mut self, out: &mut Vec<Stmt>) -> Result<()> {
+        if self.eat_punct(";") {
+            return Ok(());
+        }
+        if self.eat_keyword("begin") {
+            out.extend(self.block()?);
+            return Ok(());
+        }
+        if self.eat_keyword("variable") {
+            out.extend(self.var_decls()?.into_iter().map(Stmt::Var));
+            return Ok(());
+        }
+        if self.eat_keyword("if") {
+            let cond = self.expr()?;
+            self.expect_keyword("then")?;
+            let then = self.body()?;
+            let else_ = if self.eat_keyword("else") {
+                self.body()?
+            } else {
+                Vec::new()
+            };
+            out.push(Stmt::If { cond, then, else_ });
+            return Ok(());
+        }
+        if self.eat_keyword("while") {
+            let cond = self.expr()?;
+            self.expect_keyword("do")?;
+            let body = self.body()?;
+            out.push(Stmt::While { cond, body });
+            return Ok(());
+        }
+        if self.eat_keyword("return") {
+            let value = if self.is_punct(";") {
+                None
+            } else {
+                Some(self.expr()?)
+            };
+            self.expect_punct(";")?;
+            out.push(Stmt::Return(value));
+            return Ok(());
+        }
+        if self.eat_keyword("call") {
+            let loc = self.loc();
+            let name = self.ident()?;
+            let args = if self.eat_punct("(") {
+                self.args()?
+            } else {
+                Vec::new()
+            };
+            self.expect_punct(";")?;
+            out.push(Stmt::Expr(Expr::Call(name, args, loc)));
+            return Ok(());
+        }
+
+        let loc = self.loc();
+        if let Some(Tok::Ident(name)) = self.peek() {
+            let name = name.clone();
+            let op = match self.tokens.get(self.pos + 1).map(|t| &t.tok) {
+                Some(Tok::Punct(":=")) | Some(Tok::Punct("=")) => Some(None),
+                Some(Tok::Punct("+=")) => Some(Some(BinOp::Add)),
+                Some(Tok::Punct("-=")) => Some(Some(BinOp::Sub)),
+                Some(Tok::Punct("*=")) => Some(Some(BinOp::Mul)),
+                Some(Tok::Punct("/=")) => Some(Some(BinOp::Div)),
+                Some(Tok::Punct("++")) | Some(Tok::Punct("--")) => {
+                    let op = if self.tokens[self.pos + 1].tok == Tok::Punct("++") {
+                        BinOp::Add
+                    } else {
+                        BinOp::Sub
+                    };
+                    self.pos += 2;
+                    self.expect_punct(";")?;
+                    out.push(Stmt::Assign { name, op: Some(op), value: Expr::Int(1), loc });
+                    return Ok(());
+                }
+                _ => None,
+            };
+            if let Some(op) = op {
+                if is_reserved(&name) {
+                    return self.error("expected identifier");
+                }
+                self.pos += 2;
+                let value = self.expr()?;
+                self.expect_punct(";")?;
+                out.push(Stmt::Assign { name, op, value, loc });
+                return Ok(());
+            }
+        }
+        let e = self.expr()?;
+        self.expect_punct(";")?;
+        out.push(Stmt::Expr(e));
+        Ok(())
+    }
+
+    /// Parses call arguments after the opening parenthesis.
+    fn args(&mut self) -> Result<Vec<Expr>> {
+        let mut r = Vec::new();
+        if self.eat_punct(")") {
+            return Ok(r);
+        }
+        loop {
+            r.push(self.expr()?);
+            if self.eat_punct(")") {
+                break;
+            }
+            self.expect_punct(",")?;
+        }
+        Ok(r)
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.binary(0)
+    }
+
+    fn binary_op(&self, level: usize) -> Option<BinOp> {
+        use BinOp::*;
+        type Ops = &'static [(&'static str, BinOp)];
+        let (puncts, keywords): (Ops, Ops) = match level {
+            0 => (&[], &[("or", Or)]),
+            1 => (&[], &[("and", And)]),
+            2 => (&[("==", Eq), ("!=", Ne), ("<=", Le), (">=", Ge), ("<", Lt), (">", Gt)], &[]),
+            3 => (&[("+", Add), ("-", Sub)], &[("bwor", BwOr), ("bwxor", BwXor)]),
+            4 => (&[("*", Mul), ("/", Div), ("%", Mod)], &[("mod", Mod), ("bwand", BwAnd)]),
+            _ => unreachable!(),
+        };
+        puncts.iter().find(|(p, _)| self.is_punct(p))
+            .or_else(|| keywords.iter().find(|(k, _)| self.is_keyword(k)))
+            .map(|&(_, op)| op)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        if level > 4 {
+            return self.unary();
+        }
+        let mut l = self.binary(level + 1)?;
+        while let Some(op) = self.binary_op(level) {
+            self.pos += 1;
+            let r = self.binary(level + 1)?;
+            l = Expr::Binary(op, Box::new(l), Box::new(r));
+        }
+        Ok(l)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let op = if self.eat_keyword("not") {
+            UnOp::Not
+        } else if self.eat_keyword("bwnot") {
+            UnOp::BwNot
+        } else if self.eat_punct("-") {
+            UnOp::Neg
+        } else {
+            return self.primary();
+        };
+        let e = self.unary()?;
+        Ok(match (op, e) {
+            (UnOp::Neg, Expr::Int(v)) => Expr::Int(v.wrapping_neg()),
+            (UnOp::Neg, Expr::Float(v)) => Expr::Float(-v),
+            (op, e) => Expr::Unary(op, Box::new(e)),
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let loc = self.loc();
+        let tok = if let Some(tok) = self.peek() {
+            tok.clone()
+        } else {
+            return self.error("expected expression");
+        };
+        self.pos += 1;
+        Ok(match tok {
+            Tok::Int(v) => Expr::Int(v),
+            Tok::Float(v) => Expr::Float(v),
+            Tok::Str(s) => Expr::Str(s),
+            Tok::Punct("(") => {
+                let e = self.expr()?;
+                self.expect_punct(")")?;
+                e
+            }
+            Tok::Ident(name) if !is_reserved(&name) => {
+                if self.eat_punct("(") {
+                    Expr::Call(name, self.args()?, loc)
+                } else {
+                    Expr::Ident(name, loc)
+                }
+            }
+            _ => {
+                self.pos -= 1;
+                return self.error("expected expression");
+            }
+        })
+    }
+}
+
+const RESERVED: &[&str] = &[
+    "and", "begin", "bwand", "bwnot", "bwor", "bwxor", "call", "critical", "do", "else", "end",
+    "export", "if", "import", "mod", "not", "or", "procedure", "return", "then", "variable",
+    "while",
+];
+
+fn is_reserved(s: &str) -> bool {
+    RESERVED.iter().any(|r| r.eq_ignore_ascii_case(s))
+}
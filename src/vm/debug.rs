@@ -0,0 +1,598 @@
+//! Interactive debugger for programs running in the VM.
+//!
+//! The debugger is consulted before every instruction. When a breakpoint is hit or a single step
+//! completes the program is stopped and commands are read from the `Console` until execution
+//! is resumed. Type `help` in the console for the list of commands.
+
+use log::*;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, prelude::*, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use super::*;
+
+const HELP: &str = "\
+commands:
+  break <[program:]proc|[program:]offset>  set breakpoint (alias: b)
+  delete [n]                               delete breakpoint n or all breakpoints (alias: d)
+  list                                     list breakpoints (alias: l)
+  step [n]                                 execute n instructions, 1 by default (alias: s)
+  continue                                 resume execution (alias: c)
+  where                                    print current location (alias: w)
+  procs                                    list procedures of the program
+  stack                                    print data stack
+  rstack                                   print return stack
+  globals                                  print program global variables
+  lvars                                    print local variables (LVAR)
+  mvars                                    print map variables (MVAR)
+  gvars [first [last]]                     print game global variables (GVAR)
+  externals                                print exported/external variables (alias: ext)
+  detach                                   disable stepping and resume execution (alias: q)
+  help                                     print this help (alias: h)";
+
+/// Line based input/output of the debugger.
+pub trait Console {
+    /// Reads the next command line. Returns `None` if the console has been closed.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+}
+
+/// Console on the process stdin/stdout.
+pub struct StdinConsole;
+
+impl Console for StdinConsole {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut stdout = io::stdout();
+        stdout.write_all(b"(dbg) ")?;
+        stdout.flush()?;
+        let mut line = String::new();
+        Ok(if io::stdin().read_line(&mut line)? == 0 {
+            None
+        } else {
+            Some(line)
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", line)
+    }
+}
+
+/// Console served over a TCP connection. A client is accepted when the debugger needs the
+/// console for the first time and after the previous client disconnects.
+pub struct TcpConsole {
+    listener: TcpListener,
+    stream: Option<BufReader<TcpStream>>,
+}
+
+impl TcpConsole {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!("script debugger is listening on {}", listener.local_addr()?);
+        Ok(Self {
+            listener,
+            stream: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn stream(&mut self) -> io::Result<&mut BufReader<TcpStream>> {
+        if self.stream.is_none() {
+            info!("script debugger: waiting for connection on {}", self.listener.local_addr()?);
+            let (stream, addr) = self.listener.accept()?;
+            info!("script debugger: accepted connection from {}", addr);
+            self.stream = Some(BufReader::new(stream));
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+}
+
+impl Console for TcpConsole {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.stream()?.read_line(&mut line)? == 0 {
+            info!("script debugger: client disconnected");
+            self.stream = None;
+            Ok(None)
+        } else {
+            Ok(Some(line))
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let r = writeln!(self.stream()?.get_mut(), "{}", line);
+        if r.is_err() {
+            self.stream = None;
+        }
+        r
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Location {
+    /// Start of the procedure body.
+    Proc(String),
+    /// Code offset.
+    Offset(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Breakpoint {
+    /// If set, the breakpoint only applies to the program with this name.
+    pub program: Option<String>,
+    pub location: Location,
+}
+
+impl Breakpoint {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (program, loc) = if let Some(i) = s.find(':') {
+            (Some(s[..i].to_lowercase()), &s[i + 1..])
+        } else {
+            (None, s)
+        };
+        if loc.is_empty() || program.as_ref().map(|p| p.is_empty()) == Some(true) {
+            return None;
+        }
+        let location = if loc.as_bytes()[0].is_ascii_digit() {
+            Location::Offset(parse_usize(loc)?)
+        } else {
+            Location::Proc(loc.to_owned())
+        };
+        Some(Self {
+            program,
+            location,
+        })
+    }
+
+    pub fn matches(&self, program: &Program, code_pos: usize) -> bool {
+        if let Some(p) = &self.program {
+            if !program.name().eq_ignore_ascii_case(p) {
+                return false;
+            }
+        }
+        match &self.location {
+            &Location::Offset(pos) => pos == code_pos,
+            Location::Proc(name) => program.procs.by_id.iter()
+                .any(|p| p.body_pos == code_pos && p.name.as_bytes() == name.as_bytes()),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(p) = &self.program {
+            write!(f, "{}:", p)?;
+        }
+        match &self.location {
+            Location::Proc(name) => write!(f, "{}", name),
+            Location::Offset(pos) => write!(f, "0x{:04x}", pos),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    Break(Breakpoint),
+    Delete(Option<usize>),
+    List,
+    Step(usize),
+    Continue,
+    Where,
+    Procs,
+    Stack,
+    ReturnStack,
+    Globals,
+    LocalVars,
+    MapVars,
+    GlobalVars(Option<usize>, Option<usize>),
+    Externals,
+    Detach,
+    Help,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> std::result::Result<Self, String> {
+        let mut args = line.split_whitespace();
+        let cmd = args.next().unwrap_or("");
+        let mut next_usize = || args.next()
+            .map(|s| parse_usize(s).ok_or_else(|| format!("invalid number: {}", s)))
+            .transpose();
+        let r = match cmd {
+            "b" | "break" => {
+                let s = line.split_whitespace().nth(1)
+                    .ok_or_else(|| "missing breakpoint location".to_owned())?;
+                Command::Break(Breakpoint::parse(s)
+                    .ok_or_else(|| format!("invalid breakpoint location: {}", s))?)
+            }
+            "d" | "delete" => Command::Delete(next_usize()?),
+            "l" | "list" => Command::List,
+            "s" | "step" => Command::Step(next_usize()?.unwrap_or(1).max(1)),
+            "c" | "continue" => Command::Continue,
+            "w" | "where" => Command::Where,
+            "procs" => Command::Procs,
+            "stack" => Command::Stack,
+            "rstack" => Command::ReturnStack,
+            "globals" => Command::Globals,
+            "lvars" => Command::LocalVars,
+            "mvars" => Command::MapVars,
+            "gvars" => {
+                let first = next_usize()?;
+                Command::GlobalVars(first, next_usize()?)
+            }
+            "ext" | "externals" => Command::Externals,
+            "q" | "detach" => Command::Detach,
+            "h" | "help" => Command::Help,
+            _ => return Err(format!("unknown command: {} (type `help` for the list of commands)",
+                cmd)),
+        };
+        Ok(r)
+    }
+}
+
+fn parse_usize(s: &str) -> Option<usize> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        usize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+pub struct Debugger {
+    console: Box<dyn Console>,
+    breakpoints: Vec<Breakpoint>,
+    /// Number of instructions to execute before stopping. `None` if not stepping.
+    steps: Option<usize>,
+}
+
+impl Debugger {
+    /// Creates debugger that stops before the first executed instruction.
+    pub fn new(console: Box<dyn Console>) -> Self {
+        Self {
+            console,
+            breakpoints: Vec::new(),
+            steps: Some(1),
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// Called before `prg` executes instruction at its current code position.
+    pub(super) fn on_step(&mut self, prg: &ProgramState, ctx: &Context) {
+        let stop = match self.steps {
+            Some(n) if n > 1 => {
+                self.steps = Some(n - 1);
+                false
+            }
+            Some(_) => true,
+            None => self.breakpoints.iter().any(|b| b.matches(&prg.program, prg.code_pos)),
+        };
+        if !stop {
+            return;
+        }
+        self.steps = None;
+
+        let loc = location(prg);
+        self.print(&format!("stopped at {}", loc));
+        loop {
+            let line = match self.console.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("script debugger: console error: {}", e);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let cmd = match Command::parse(&line) {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    self.print(&e);
+                    continue;
+                }
+            };
+            if self.execute(cmd, prg, ctx) {
+                break;
+            }
+        }
+    }
+
+    /// Returns `true` if the program should resume.
+    fn execute(&mut self, cmd: Command, prg: &ProgramState, ctx: &Context) -> bool {
+        let mut out = String::new();
+        let resume = match cmd {
+            Command::Break(b) => {
+                writeln!(out, "breakpoint {}: {}", self.breakpoints.len(), b).unwrap();
+                self.breakpoints.push(b);
+                false
+            }
+            Command::Delete(Some(i)) => {
+                if i < self.breakpoints.len() {
+                    self.breakpoints.remove(i);
+                } else {
+                    writeln!(out, "no breakpoint {}", i).unwrap();
+                }
+                false
+            }
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                false
+            }
+            Command::List => {
+                for (i, b) in self.breakpoints.iter().enumerate() {
+                    writeln!(out, "{}: {}", i, b).unwrap();
+                }
+                false
+            }
+            Command::Step(n) => {
+                self.steps = Some(n);
+                true
+            }
+            Command::Continue => true,
+            Command::Where => {
+                writeln!(out, "{}", location(prg)).unwrap();
+                false
+            }
+            Command::Procs => {
+                for (i, p) in prg.program.procs.by_id.iter().enumerate() {
+                    writeln!(out, "{}: {} 0x{:04x} args={} flags={:?}",
+                        i, p.name.display(), p.body_pos, p.arg_count, p.flags).unwrap();
+                }
+                false
+            }
+            Command::Stack => {
+                for (i, v) in prg.data_stack.iter().enumerate() {
+                    let mark = if Some(i) == prg.base {
+                        " <- base"
+                    } else if Some(i) == prg.global_base {
+                        " <- global base"
+                    } else {
+                        ""
+                    };
+                    writeln!(out, "{}: {}{}", i, value(prg, v), mark).unwrap();
+                }
+                false
+            }
+            Command::ReturnStack => {
+                for (i, v) in prg.return_stack.iter().enumerate() {
+                    writeln!(out, "{}: {}", i, value(prg, v)).unwrap();
+                }
+                false
+            }
+            Command::Globals => {
+                if let Some(global_base) = prg.global_base {
+                    // The number of globals is not recorded in the program so show everything
+                    // up to the current procedure frame.
+                    let end = prg.base.unwrap_or_else(|| prg.data_stack.len()).max(global_base);
+                    for (i, v) in prg.data_stack.iter().enumerate().take(end).skip(global_base) {
+                        writeln!(out, "{}: {}", i - global_base, value(prg, v)).unwrap();
+                    }
+                } else {
+                    writeln!(out, "program globals are not initialized").unwrap();
+                }
+                false
+            }
+            Command::LocalVars => {
+                write_vars(&mut out, ctx.local_vars, 0, None);
+                false
+            }
+            Command::MapVars => {
+                write_vars(&mut out, ctx.map_vars, 0, None);
+                false
+            }
+            Command::GlobalVars(first, last) => {
+                write_vars(&mut out, ctx.global_vars, first.unwrap_or(0), last);
+                false
+            }
+            Command::Externals => {
+                let mut vars: Vec<_> = ctx.external_vars.iter().collect();
+                vars.sort_by(|a, b| a.0.cmp(b.0));
+                for (name, v) in vars {
+                    let v = v.as_ref().map(|v| value(prg, v)).unwrap_or_else(|| "<unset>".into());
+                    writeln!(out, "{} = {}", name.display(), v).unwrap();
+                }
+                false
+            }
+            Command::Detach => {
+                self.breakpoints.clear();
+                true
+            }
+            Command::Help => {
+                writeln!(out, "{}", HELP).unwrap();
+                false
+            }
+        };
+        if out.ends_with('\n') {
+            out.pop();
+        }
+        if !out.is_empty() {
+            self.print(&out);
+        }
+        resume
+    }
+
+    fn print(&mut self, s: &str) {
+        for line in s.lines() {
+            if let Err(e) = self.console.write_line(line) {
+                warn!("script debugger: console error: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn location(prg: &ProgramState) -> String {
+    let pos = prg.code_pos;
+    let mut r = format!("{}:0x{:04x}", prg.program.name(), pos);
+    let proc = prg.program.procs.by_id.iter()
+        .filter(|p| p.body_pos <= pos)
+        .max_by_key(|p| p.body_pos);
+    if let Some(proc) = proc {
+        write!(r, " ({}+0x{:x})", proc.name.display(), pos - proc.body_pos).unwrap();
+    }
+    let opcode = prg.code().get(pos..pos + 2)
+        .map(BigEndian::read_u16)
+        .and_then(|v| prg.program.config.instructions.get(&v));
+    if let Some(instr) = opcode {
+        write!(r, " {:?}", instr.opcode()).unwrap();
+    }
+    r
+}
+
+fn value(prg: &ProgramState, v: &Value) -> String {
+    match v.clone().resolved(prg.strings()) {
+        Ok(Value::Int(v)) => v.to_string(),
+        Ok(Value::Float(v)) => format!("{:?}", v),
+        Ok(Value::String(s)) => format!("\"{}\"", s.into_direct().unwrap().display()),
+        Ok(Value::Object(Some(h))) => format!("{:?}", h),
+        Ok(Value::Object(None)) => "null".into(),
+        Err(_) => format!("{:?}", v),
+    }
+}
+
+fn write_vars(out: &mut String, vars: &[i32], first: usize, last: Option<usize>) {
+    for (i, v) in vars.iter().enumerate().skip(first) {
+        if last.map(|l| i > l) == Some(true) {
+            break;
+        }
+        writeln!(out, "{}: {}", i, v).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::vm::instruction::Opcode::*;
+    use crate::vm::test::{Asm, Harness};
+
+    /// Console that reads the prepared lines and records the output.
+    struct ScriptedConsole {
+        input: VecDeque<&'static str>,
+        output: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Console for ScriptedConsole {
+        fn read_line(&mut self) -> io::Result<Option<String>> {
+            Ok(self.input.pop_front().map(|s| s.into()))
+        }
+
+        fn write_line(&mut self, line: &str) -> io::Result<()> {
+            self.output.borrow_mut().push(line.into());
+            Ok(())
+        }
+    }
+
+    fn program() -> Program {
+        let mut code = vec![0; 42];
+        // Procedure table.
+        code.extend_from_slice(&1u32.to_be_bytes());
+        for &v in &[6, 0, 0, 0, 0x60, 0] {
+            code.extend_from_slice(&(v as u32).to_be_bytes());
+        }
+        // Name table.
+        code.extend_from_slice(&8u32.to_be_bytes());
+        code.extend_from_slice(&6u16.to_be_bytes());
+        code.extend_from_slice(b"start\0");
+        code.extend_from_slice(&[0xff, 0xff, 0, 0]);
+        // String table.
+        code.extend_from_slice(&[0xff; 4]);
+        Program::new("test".into(), code.into(), Default::default()).unwrap()
+    }
+
+    #[test]
+    fn parse_breakpoint() {
+        assert_eq!(Breakpoint::parse("start"), Some(Breakpoint {
+            program: None,
+            location: Location::Proc("start".into()),
+        }));
+        assert_eq!(Breakpoint::parse("ArTemple:0x1a4"), Some(Breakpoint {
+            program: Some("artemple".into()),
+            location: Location::Offset(0x1a4),
+        }));
+        assert_eq!(Breakpoint::parse("42"), Some(Breakpoint {
+            program: None,
+            location: Location::Offset(42),
+        }));
+        assert_eq!(Breakpoint::parse(":start"), None);
+        assert_eq!(Breakpoint::parse("test:"), None);
+        assert_eq!(Breakpoint::parse("0xzz"), None);
+    }
+
+    #[test]
+    fn parse_command() {
+        assert_eq!(Command::parse("s"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("step 0x10"), Ok(Command::Step(16)));
+        assert_eq!(Command::parse(" gvars 5 10\n"), Ok(Command::GlobalVars(Some(5), Some(10))));
+        assert_eq!(Command::parse("delete"), Ok(Command::Delete(None)));
+        assert_eq!(Command::parse("b test:start"), Ok(Command::Break(Breakpoint {
+            program: Some("test".into()),
+            location: Location::Proc("start".into()),
+        })));
+        assert!(Command::parse("b").is_err());
+        assert!(Command::parse("step x").is_err());
+        assert!(Command::parse("foo").is_err());
+    }
+
+    #[test]
+    fn breakpoint_matches() {
+        let prg = program();
+        assert!(Breakpoint::parse("start").unwrap().matches(&prg, 0x60));
+        assert!(Breakpoint::parse("TEST:start").unwrap().matches(&prg, 0x60));
+        assert!(!Breakpoint::parse("other:start").unwrap().matches(&prg, 0x60));
+        assert!(!Breakpoint::parse("start").unwrap().matches(&prg, 0x62));
+        assert!(!Breakpoint::parse("foo").unwrap().matches(&prg, 0x60));
+        assert!(Breakpoint::parse("0x62").unwrap().matches(&prg, 0x62));
+    }
+
+    #[test]
+    fn debugger() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut h = Harness::new();
+        h.vm.set_debugger(Debugger::new(Box::new(ScriptedConsole {
+            input: vec![
+                // Stopped at the first instruction of the program.
+                "b foo", "c",
+                // Stopped at the breakpoint.
+                "s 2", "stack", "where", "c",
+            ].into(),
+            output: output.clone(),
+        })));
+
+        let prg = h.load(Asm::new()
+            .proc("foo", "foo", 0)
+            .op(ExitProg)
+            .label("foo")
+            .ints(&[7, 8])
+            .ops(&[Pop, Pop, ExitProg])
+            .assemble()).unwrap();
+        {
+            let output = output.borrow();
+            assert_eq!(output.len(), 2);
+            assert!(output[0].starts_with("stopped at test:0x0000"), "{:?}", output);
+            assert_eq!(output[1], "breakpoint 0: foo");
+        }
+
+        h.execute_proc(prg, "foo").unwrap().assert_no_suspend();
+        let output = output.borrow();
+        let n = output.len();
+        assert!(output[2].ends_with(" (foo+0x0) ConstLong"), "{:?}", output);
+        // Stepped over two instructions.
+        assert!(output[3].starts_with("stopped at test:"), "{:?}", output);
+        assert!(output[3].ends_with(" (foo+0xc) Pop"), "{:?}", output);
+        assert_eq!(&output[n - 3..n - 1], &["4: 7", "5: 8"]);
+        assert_eq!(output[n - 1], &output[3]["stopped at ".len()..]);
+    }
+}
@@ -0,0 +1,867 @@
+//! Disassembler and decompiler of compiled scripts.
+//!
+//! The decompiler is best effort: it symbolically executes each procedure and recognizes the
+//! code patterns the original script compiler emits for `if`/`else` and `while` statements.
+//! Anything it can't make sense of is kept as a comment with the instruction offset.
+
+use matches::matches;
+use num_traits::FromPrimitive;
+use std::io::{self, prelude::*};
+
+use super::*;
+
+use Opcode::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    Int(i32),
+    Float(f32),
+    String(i32),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Instr {
+    pos: usize,
+    raw: u16,
+    opcode: Option<Opcode>,
+    operand: Option<Operand>,
+}
+
+impl Instr {
+    fn decode(code: &[u8], pos: usize) -> Option<Self> {
+        if pos + 2 > code.len() {
+            return None;
+        }
+        let raw = BigEndian::read_u16(&code[pos..]);
+        let opcode = Opcode::from_u16(raw);
+        let operand = match opcode {
+            Some(ConstShort) | Some(ConstLong) | Some(ConstFloat) | Some(ConstString) => {
+                if pos + 6 > code.len() {
+                    return None;
+                }
+                let v = &code[pos + 2..];
+                Some(match opcode.unwrap() {
+                    ConstFloat => Operand::Float(BigEndian::read_f32(v)),
+                    ConstString => Operand::String(BigEndian::read_i32(v)),
+                    _ => Operand::Int(BigEndian::read_i32(v)),
+                })
+            }
+            _ => None,
+        };
+        Some(Self {
+            pos,
+            raw,
+            opcode,
+            operand,
+        })
+    }
+
+    fn len(&self) -> usize {
+        if self.operand.is_some() { 6 } else { 2 }
+    }
+
+    fn end(&self) -> usize {
+        self.pos + self.len()
+    }
+
+    fn is(&self, opcode: Opcode) -> bool {
+        self.opcode == Some(opcode)
+    }
+
+    fn int_operand(&self) -> Option<i32> {
+        if let Some(Operand::Int(v)) = self.operand {
+            Some(v)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes instructions in `start..end` range. Undecodable tail is ignored.
+fn decode(code: &[u8], start: usize, end: usize) -> Vec<Instr> {
+    let mut r = Vec::new();
+    let mut pos = start;
+    while pos < end {
+        if let Some(instr) = Instr::decode(code, pos) {
+            pos = instr.end();
+            r.push(instr);
+        } else {
+            break;
+        }
+    }
+    r
+}
+
+fn is_return(opcode: Opcode) -> bool {
+    matches!(opcode,
+        PopReturn
+        | PopExit
+        | PopFlagsReturn
+        | PopFlagsExit
+        | PopFlagsReturnExtern
+        | PopFlagsExitExtern
+        | PopFlagsReturnValExtern
+        | PopFlagsReturnValExit
+        | PopFlagsReturnValExitExtern)
+}
+
+/// Whether the string operand pushed right before `next` refers to the name table.
+fn takes_name(next: Option<&Instr>) -> bool {
+    next.and_then(|i| i.opcode).map(|o| matches!(o,
+        ExportVar | ExportProc | FetchExternal | StoreExternal)) == Some(true)
+}
+
+fn quote(s: &bstr) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.display().to_string().chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            _ => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+fn sorted_strings(map: &StringMap) -> Vec<(usize, &Rc<BString>)> {
+    let mut r: Vec<_> = map.map.iter().map(|(&k, v)| (k, v)).collect();
+    r.sort_by_key(|&(k, _)| k);
+    r
+}
+
+fn proc_name(program: &Program, id: i32) -> Option<&bstr> {
+    if id >= 0 {
+        program.proc(id as ProcedureId).map(|p| p.name())
+    } else {
+        None
+    }
+}
+
+/// Writes listing of the program code with resolved string and procedure references.
+pub fn disassemble(program: &Program, out: &mut impl Write) -> io::Result<()> {
+    let code = &program.code[..];
+
+    writeln!(out, "; program: {}", program.name())?;
+    writeln!(out, ";")?;
+    writeln!(out, "; procedures:")?;
+    for (i, proc) in program.procs.by_id.iter().enumerate() {
+        writeln!(out, ";   {:3} {:32} body=0x{:04x} cond=0x{:04x} args={} delay={}ms flags={:?}",
+            i, proc.name.display(), proc.body_pos, proc.condition_pos, proc.arg_count,
+            proc.delay.as_millis(), proc.flags)?;
+    }
+    for (title, map) in &[("names", &program.names), ("strings", &program.strings)] {
+        writeln!(out, ";")?;
+        writeln!(out, "; {}:", title)?;
+        for (offset, s) in sorted_strings(map) {
+            writeln!(out, ";   0x{:04x} {}", offset, quote(s))?;
+        }
+    }
+
+    for &(start, end) in &[(0, PROC_TABLE_START), (program.code_start, code.len())] {
+        writeln!(out)?;
+        let instrs = decode(code, start, end);
+        for (i, instr) in instrs.iter().enumerate() {
+            for (id, proc) in program.procs.by_id.iter().enumerate() {
+                if proc.body_pos == instr.pos {
+                    writeln!(out, "\n; procedure {} {}", id, proc.name.display())?;
+                } else if proc.condition_pos == instr.pos && proc.condition_pos != 0 {
+                    writeln!(out, "\n; condition of procedure {} {}", id, proc.name.display())?;
+                }
+            }
+            write!(out, "0x{:04x}  {:04x}  ", instr.pos, instr.raw)?;
+            let opcode = if let Some(opcode) = instr.opcode {
+                opcode
+            } else {
+                writeln!(out, "<unknown opcode>")?;
+                continue;
+            };
+            write!(out, "{}", opcode.mnemonic())?;
+            let next = instrs.get(i + 1);
+            match instr.operand {
+                Some(Operand::Int(v)) => {
+                    write!(out, " {}", v)?;
+                    if next.map(|n| n.is(Call) || n.is(FetchProcAddress)) == Some(true) {
+                        if let Some(name) = proc_name(program, v) {
+                            write!(out, "  ; procedure {}", name.display())?;
+                        }
+                    }
+                }
+                Some(Operand::Float(v)) => write!(out, " {:?}", v)?,
+                Some(Operand::String(v)) => {
+                    write!(out, " 0x{:04x}", v)?;
+                    let map = if takes_name(next) { &program.names } else { &program.strings };
+                    if let Some(s) = map.get(v as usize) {
+                        write!(out, "  ; {}", quote(s))?;
+                    }
+                }
+                None => {}
+            }
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Int(i32),
+    Float(f32),
+    /// String table offset.
+    String(i32),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// Call of an instruction.
+    Builtin(String, Vec<Expr>),
+    /// Call of a procedure of the program.
+    Call(String, Vec<Expr>),
+    Unknown,
+}
+
+enum Stmt {
+    Expr(Expr),
+    Assign(String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Export(String),
+    Comment(String),
+}
+
+struct Decompiler<'a> {
+    program: &'a Program,
+    instrs: &'a [Instr],
+    arg_count: usize,
+    stack: Vec<Expr>,
+    return_stack: Vec<Expr>,
+    /// Data stack depth where procedure variables start.
+    frame_start: usize,
+    /// Number of procedure variables excluding arguments.
+    var_count: Option<usize>,
+    /// Data stack depth where program globals start.
+    global_start: Option<usize>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(program: &'a Program, instrs: &'a [Instr], arg_count: usize) -> Self {
+        Self {
+            program,
+            instrs,
+            arg_count,
+            stack: Vec::new(),
+            return_stack: Vec::new(),
+            frame_start: 0,
+            var_count: None,
+            global_start: None,
+        }
+    }
+
+    fn index_of(&self, pos: i32) -> Option<usize> {
+        if pos < 0 {
+            return None;
+        }
+        self.instrs.binary_search_by_key(&(pos as usize), |i| i.pos).ok()
+    }
+
+    fn pop(&mut self) -> Expr {
+        self.stack.pop().unwrap_or(Expr::Unknown)
+    }
+
+    fn pop_n(&mut self, n: usize) -> Vec<Expr> {
+        let mut r: Vec<_> = (0..n).map(|_| self.pop()).collect();
+        r.reverse();
+        r
+    }
+
+    fn pop_int(&mut self) -> Option<i32> {
+        match self.pop() {
+            Expr::Int(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn pop_name(&mut self) -> String {
+        match self.pop() {
+            Expr::String(v) if v >= 0 => self.program.names.get(v as usize)
+                .map(|s| s.display().to_string())
+                .unwrap_or_else(|| format!("name_0x{:x}", v)),
+            _ => "?".into(),
+        }
+    }
+
+    fn var_name(&self, id: Option<i32>) -> String {
+        match id {
+            Some(id) if id >= 0 && (id as usize) < self.arg_count => format!("arg{}", id),
+            Some(id) if id >= 0 => format!("var{}", id as usize - self.arg_count),
+            _ => "var?".into(),
+        }
+    }
+
+    fn block(&mut self, start: usize, end: usize) -> Vec<Stmt> {
+        let mut r = Vec::new();
+        let mut i = start;
+        while i < end {
+            let instr = self.instrs[i];
+            if instr.is(If) {
+                if let Some(next) = self.structured_if(i, end, &mut r) {
+                    i = next;
+                    continue;
+                }
+            }
+            self.instruction(instr, &mut r);
+            i += 1;
+        }
+        r
+    }
+
+    /// Tries to recognize `if` or `while` statement at `i`. Returns index of the instruction
+    /// after the statement.
+    fn structured_if(&mut self, i: usize, end: usize, out: &mut Vec<Stmt>) -> Option<usize> {
+        let target = match self.stack.get(self.stack.len().checked_sub(2)?) {
+            Some(&Expr::Int(v)) => self.index_of(v)?,
+            _ => return None,
+        };
+        if target <= i || target > end {
+            return None;
+        }
+        let cond = self.pop();
+        self.pop();
+
+        // Jump over the else branch or back to the loop condition.
+        let jump = if target >= i + 3 && self.instrs[target - 1].is(Jmp) {
+            self.instrs[target - 2].int_operand()
+        } else {
+            None
+        };
+        if let Some(jump) = jump {
+            if jump as usize <= self.instrs[i].pos {
+                let body = self.block(i + 1, target - 2);
+                out.push(Stmt::While(cond, body));
+                return Some(target);
+            }
+            if let Some(else_end) = self.index_of(jump).filter(|&e| e > target && e <= end) {
+                let then = self.block(i + 1, target - 2);
+                let else_ = self.block(target, else_end);
+                out.push(Stmt::If(cond, then, else_));
+                return Some(else_end);
+            }
+        }
+        let then = self.block(i + 1, target);
+        out.push(Stmt::If(cond, then, Vec::new()));
+        Some(target)
+    }
+
+    fn instruction(&mut self, instr: Instr, out: &mut Vec<Stmt>) {
+        let opcode = if let Some(opcode) = instr.opcode {
+            opcode
+        } else {
+            out.push(Stmt::Comment(format!("0x{:04x}: unknown opcode 0x{:04x}",
+                instr.pos, instr.raw)));
+            return;
+        };
+        match opcode {
+            Noop8000 | CriticalStart | CriticalDone | CriticalStart804a | CriticalDone804b
+            | PopFlags | PopBase | Swapa => {}
+            ConstShort | ConstLong => self.stack.push(Expr::Int(instr.int_operand().unwrap())),
+            ConstFloat => if let Some(Operand::Float(v)) = instr.operand {
+                self.stack.push(Expr::Float(v));
+            }
+            ConstString => if let Some(Operand::String(v)) = instr.operand {
+                self.stack.push(Expr::String(v));
+            }
+            Jmp => {
+                let pos = self.pop();
+                out.push(Stmt::Comment(format!("0x{:04x}: goto {}", instr.pos,
+                    self.render(&pos))));
+            }
+            If | While => {
+                let cond = self.pop();
+                let pos = if opcode == If { self.pop() } else { Expr::Unknown };
+                out.push(Stmt::Comment(format!("0x{:04x}: {} not {} goto {}",
+                    instr.pos, opcode.mnemonic(), self.render(&cond), self.render(&pos))));
+            }
+            Call => {
+                let id = self.pop_int();
+                let name = id.and_then(|id| proc_name(self.program, id))
+                    .map(|n| n.display().to_string())
+                    .unwrap_or_else(|| format!("proc{}", id.unwrap_or(-1)));
+                let arg_count = self.pop_int().unwrap_or(0).max(0) as usize;
+                let args = self.pop_n(arg_count);
+                self.stack.push(Expr::Call(name, args));
+            }
+            FetchProcAddress => {
+                let id = self.pop_int();
+                let name = id.and_then(|id| proc_name(self.program, id))
+                    .map(|n| n.display().to_string())
+                    .unwrap_or_else(|| "?".into());
+                self.stack.push(Expr::Var(name));
+            }
+            AToD => {
+                let v = self.return_stack.pop().unwrap_or(Expr::Unknown);
+                self.stack.push(v);
+            }
+            DToA => {
+                let v = self.pop();
+                self.return_stack.push(v);
+            }
+            PopAddress => {
+                self.return_stack.pop();
+            }
+            Swap => {
+                let a = self.pop();
+                let b = self.pop();
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+            Pop => {
+                let v = self.pop();
+                out.push(Stmt::Expr(v));
+            }
+            Dup => {
+                let v = self.pop();
+                self.stack.push(v.clone());
+                self.stack.push(v);
+            }
+            PushBase => {
+                // The argument count is pushed by the caller so it's not on the stack here.
+                // `arg_count` comes from the procedure header.
+                self.frame_start = self.stack.len();
+            }
+            PopToBase => {
+                if self.var_count.is_none() {
+                    self.var_count = Some(self.stack.len().saturating_sub(self.frame_start));
+                }
+            }
+            SetGlobal => self.global_start = Some(self.stack.len()),
+            Fetch => {
+                let id = self.pop_int();
+                self.stack.push(Expr::Var(self.var_name(id)));
+            }
+            Store => {
+                let id = self.pop_int();
+                let v = self.pop();
+                out.push(Stmt::Assign(self.var_name(id), v));
+            }
+            FetchGlobal => {
+                let id = self.pop_int();
+                self.stack.push(Expr::Var(global_name(id)));
+            }
+            StoreGlobal => {
+                let id = self.pop_int();
+                let v = self.pop();
+                out.push(Stmt::Assign(global_name(id), v));
+            }
+            FetchExternal => {
+                let name = self.pop_name();
+                self.stack.push(Expr::Var(name));
+            }
+            StoreExternal => {
+                let name = self.pop_name();
+                let v = self.pop();
+                out.push(Stmt::Assign(name, v));
+            }
+            ExportVar => {
+                let name = self.pop_name();
+                out.push(Stmt::Export(name));
+            }
+            Equal | NotEqual | LessEqual | GreaterEqual | Less | Greater | Add | Sub | Mul | Div
+            | Mod | And | Or | Bwand | Bwor | Bwxor => {
+                let op = match opcode {
+                    Equal => "==",
+                    NotEqual => "!=",
+                    LessEqual => "<=",
+                    GreaterEqual => ">=",
+                    Less => "<",
+                    Greater => ">",
+                    Add => "+",
+                    Sub => "-",
+                    Mul => "*",
+                    Div => "/",
+                    Mod => "%",
+                    And => "and",
+                    Or => "or",
+                    Bwand => "bwand",
+                    Bwor => "bwor",
+                    _ => "bwxor",
+                };
+                let r = self.pop();
+                let l = self.pop();
+                self.stack.push(Expr::Binary(op, Box::new(l), Box::new(r)));
+            }
+            Not | Negate | Bwnot => {
+                let op = match opcode {
+                    Not => "not ",
+                    Negate => "-",
+                    _ => "bwnot ",
+                };
+                let v = self.pop();
+                self.stack.push(Expr::Unary(op, Box::new(v)));
+            }
+            _ if is_return(opcode) => {
+                let frame_end = self.frame_start + self.var_count.unwrap_or(0);
+                let v = if self.stack.len() > frame_end {
+                    Some(self.pop())
+                } else {
+                    None
+                };
+                out.push(Stmt::Return(v));
+            }
+            _ => if let Some((arg_count, returns)) = opcode.signature() {
+                let args = self.pop_n(arg_count);
+                let e = Expr::Builtin(opcode.mnemonic(), args);
+                if returns {
+                    self.stack.push(e);
+                } else {
+                    out.push(Stmt::Expr(e));
+                }
+            } else {
+                out.push(Stmt::Comment(format!("0x{:04x}: unsupported instruction {}",
+                    instr.pos, opcode.mnemonic())));
+            }
+        }
+    }
+
+    fn render(&self, e: &Expr) -> String {
+        match e {
+            Expr::Int(v) => v.to_string(),
+            Expr::Float(v) => format!("{:?}", v),
+            &Expr::String(v) => if v >= 0 {
+                self.program.strings.get(v as usize)
+                    .map(|s| quote(s))
+                    .unwrap_or_else(|| format!("string_0x{:x}", v))
+            } else {
+                format!("string_0x{:x}", v)
+            }
+            Expr::Var(name) => name.clone(),
+            Expr::Unary(op, v) => format!("{}{}", op, self.render_operand(v)),
+            Expr::Binary(op, l, r) =>
+                format!("{} {} {}", self.render_operand(l), op, self.render_operand(r)),
+            Expr::Builtin(name, args) | Expr::Call(name, args) => if args.is_empty() {
+                name.clone()
+            } else {
+                let args: Vec<_> = args.iter().map(|a| self.render(a)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            Expr::Unknown => "?".into(),
+        }
+    }
+
+    fn render_operand(&self, e: &Expr) -> String {
+        let s = self.render(e);
+        if let Expr::Binary(..) | Expr::Unary(..) = e {
+            format!("({})", s)
+        } else {
+            s
+        }
+    }
+
+    fn write_block(&self, stmts: &[Stmt], indent: usize, out: &mut impl Write)
+        -> io::Result<()>
+    {
+        let pad = "   ".repeat(indent);
+        for stmt in stmts {
+            match stmt {
+                Stmt::Expr(e @ Expr::Call(..)) => writeln!(out, "{}call {};", pad, self.render(e))?,
+                Stmt::Expr(e) => writeln!(out, "{}{};", pad, self.render(e))?,
+                Stmt::Assign(name, e) => writeln!(out, "{}{} := {};", pad, name, self.render(e))?,
+                Stmt::If(cond, then, else_) => {
+                    writeln!(out, "{}if ({}) then begin", pad, self.render(cond))?;
+                    self.write_block(then, indent + 1, out)?;
+                    if else_.is_empty() {
+                        writeln!(out, "{}end", pad)?;
+                    } else {
+                        writeln!(out, "{}end else begin", pad)?;
+                        self.write_block(else_, indent + 1, out)?;
+                        writeln!(out, "{}end", pad)?;
+                    }
+                }
+                Stmt::While(cond, body) => {
+                    writeln!(out, "{}while ({}) do begin", pad, self.render(cond))?;
+                    self.write_block(body, indent + 1, out)?;
+                    writeln!(out, "{}end", pad)?;
+                }
+                Stmt::Return(Some(e)) => writeln!(out, "{}return {};", pad, self.render(e))?,
+                Stmt::Return(None) => writeln!(out, "{}return;", pad)?,
+                Stmt::Export(name) => writeln!(out, "{}export variable {};", pad, name)?,
+                Stmt::Comment(s) => writeln!(out, "{}/* {} */", pad, s)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn global_name(id: Option<i32>) -> String {
+    match id {
+        Some(id) if id >= 0 => format!("global{}", id),
+        _ => "global?".into(),
+    }
+}
+
+/// Writes SSL-like source reconstructed from the program code.
+pub fn decompile(program: &Program, out: &mut impl Write) -> io::Result<()> {
+    let code = &program.code[..];
+    let instrs = decode(code, program.code_start, code.len());
+
+    // Code ranges of procedure bodies.
+    let mut bodies: Vec<_> = program.procs.by_id.iter().enumerate()
+        .filter(|(_, p)| !p.flags.contains(ProcedureFlag::Import) && p.body_pos >= program.code_start)
+        .map(|(i, p)| (p.body_pos, i))
+        .collect();
+    bodies.sort();
+
+    // Program initialization code starts with `set_global`. It's not part of any procedure.
+    let init_start = instrs.iter().position(|i| i.is(SetGlobal));
+    // The terminating `exit_prog` is implied and not shown.
+    let init_end = init_start.map(|s| instrs[s..].iter()
+        .position(|i| i.opcode.map(is_return).unwrap_or(false)
+            || i.is(ExitProg) || i.is(StopProg))
+        .map(|e| if instrs[s + e].is(ExitProg) { s + e } else { s + e + 1 })
+        .unwrap_or(instrs.len()));
+
+    writeln!(out, "/* Decompiled from {}.int */", program.name())?;
+    writeln!(out)?;
+    for proc in &program.procs.by_id {
+        let kw = if proc.flags.contains(ProcedureFlag::Import) {
+            "import "
+        } else if proc.flags.contains(ProcedureFlag::Export) {
+            "export "
+        } else {
+            ""
+        };
+        writeln!(out, "{}procedure {}{};", kw, proc.name.display(), arg_list(proc.arg_count))?;
+    }
+
+    if let (Some(start), Some(end)) = (init_start, init_end) {
+        let mut d = Decompiler::new(program, &instrs, 0);
+        let stmts = d.block(start, end);
+        let globals = d.global_start.map(|s| d.stack.get(s..).unwrap_or(&[]).to_vec())
+            .unwrap_or_default();
+        writeln!(out)?;
+        for (i, v) in globals.iter().enumerate() {
+            writeln!(out, "variable global{} := {};", i, d.render(v))?;
+        }
+        let stmts: Vec<_> = stmts.into_iter()
+            .filter(|s| !matches!(s, Stmt::Return(None)))
+            .collect();
+        d.write_block(&stmts, 0, out)?;
+    }
+
+    for (i, &(pos, id)) in bodies.iter().enumerate() {
+        let proc = &program.procs.by_id[id];
+        let end_pos = bodies.get(i + 1).map(|&(p, _)| p).unwrap_or(code.len());
+        let start = instrs.iter().position(|i| i.pos >= pos).unwrap_or(instrs.len());
+        let mut end = instrs.iter().position(|i| i.pos >= end_pos).unwrap_or(instrs.len());
+        if let Some(init_start) = init_start {
+            if init_start >= start && init_start < end {
+                end = init_start;
+            }
+        }
+
+        let mut d = Decompiler::new(program, &instrs, proc.arg_count);
+        let mut stmts = d.block(start, end);
+        if let Some(Stmt::Return(None)) | Some(Stmt::Return(Some(Expr::Int(0)))) = stmts.last() {
+            stmts.pop();
+        }
+
+        writeln!(out)?;
+        if proc.flags.contains(ProcedureFlag::Timed) || proc.flags.contains(ProcedureFlag::Critical)
+            || proc.flags.contains(ProcedureFlag::Conditional)
+        {
+            writeln!(out, "/* flags: {:?} */", proc.flags)?;
+        }
+        writeln!(out, "procedure {}{} begin", proc.name.display(), arg_list(proc.arg_count))?;
+        let var_count = d.var_count.unwrap_or_else(|| d.stack.len().saturating_sub(d.frame_start));
+        for i in 0..var_count {
+            let init = d.stack.get(d.frame_start + i).map(|v| d.render(v))
+                .unwrap_or_else(|| "?".into());
+            writeln!(out, "   variable var{} := {};", i, init)?;
+        }
+        d.write_block(&stmts, 1, out)?;
+        writeln!(out, "end")?;
+    }
+    Ok(())
+}
+
+fn arg_list(arg_count: usize) -> String {
+    if arg_count == 0 {
+        return String::new();
+    }
+    let args: Vec<_> = (0..arg_count).map(|i| format!("variable arg{}", i)).collect();
+    format!("({})", args.join(", "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    enum Item {
+        Op(Opcode),
+        Int(i32),
+        Str(usize),
+        Addr(&'static str),
+        Label(&'static str),
+    }
+
+    use Item::*;
+
+    fn string_table(strings: &[&str]) -> Vec<u8> {
+        let mut r = Vec::new();
+        let len: usize = strings.iter().map(|s| s.len() + 3).sum();
+        r.extend_from_slice(&(len as u32).to_be_bytes());
+        for s in strings {
+            r.extend_from_slice(&(s.len() as u16 + 1).to_be_bytes());
+            r.extend_from_slice(s.as_bytes());
+            r.push(0);
+        }
+        r.extend_from_slice(&[0xff, 0xff, 0, 0]);
+        r
+    }
+
+    /// Builds program with single `start` procedure with the specified argument count and body.
+    fn program(strings: &[&str], arg_count: usize, body: &[Item]) -> Program {
+        let mut code = Vec::new();
+        for _ in 0..PROC_TABLE_START / 2 {
+            code.extend_from_slice(&(Noop8000 as u16).to_be_bytes());
+        }
+        let names = string_table(&["start"]);
+        let strings = string_table(strings);
+        let body_pos = code.len() + 4 + 24 + names.len() + strings.len();
+        code.extend_from_slice(&1u32.to_be_bytes());
+        for &v in &[6, 0, 0, 0, body_pos, arg_count] {
+            code.extend_from_slice(&(v as u32).to_be_bytes());
+        }
+        code.extend_from_slice(&names);
+        code.extend_from_slice(&strings);
+
+        let mut labels = HashMap::new();
+        let mut pos = body_pos;
+        for item in body {
+            match item {
+                Op(_) => pos += 2,
+                Int(_) | Str(_) | Addr(_) => pos += 6,
+                Label(l) => { labels.insert(*l, pos); }
+            }
+        }
+        for item in body {
+            let (op, v) = match *item {
+                Op(op) => (op, None),
+                Int(v) => (ConstLong, Some(v)),
+                Str(i) => (ConstString, Some(string_offset(&strings, i) as i32)),
+                Addr(l) => (ConstLong, Some(labels[l] as i32)),
+                Label(_) => continue,
+            };
+            code.extend_from_slice(&(op as u16).to_be_bytes());
+            if let Some(v) = v {
+                code.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        Program::new("test".into(), code.into(), Default::default()).unwrap()
+    }
+
+    fn string_offset(table: &[u8], i: usize) -> usize {
+        let mut pos = 4;
+        for _ in 0..i {
+            pos += 2 + BigEndian::read_u16(&table[pos..]) as usize;
+        }
+        pos + 2
+    }
+
+    fn test_program() -> Program {
+        program(&["hello", "bye"], 0, &[
+            Op(PushBase),
+            Int(0),
+
+            Addr("else"),
+            Int(0), Op(Fetch), Int(1), Op(Equal),
+            Op(If),
+            Str(0), Op(DisplayMsg),
+            Addr("end_if"), Op(Jmp),
+            Label("else"),
+            Str(1), Op(DisplayMsg),
+            Label("end_if"),
+
+            Label("loop"),
+            Addr("end_loop"),
+            Int(0), Op(Fetch), Int(3), Op(Less),
+            Op(If),
+            Int(0), Op(Fetch), Int(1), Op(Add), Int(0), Op(Store),
+            Addr("loop"), Op(Jmp),
+            Label("end_loop"),
+
+            Int(0), Op(DToA), Op(PopToBase), Op(PopBase), Op(AToD), Op(PopReturn),
+        ])
+    }
+
+    /// `procedure start(variable arg0, variable arg1)` storing `arg0 * 10` in the global var
+    /// `arg1`.
+    fn args_program() -> Program {
+        program(&[], 2, &[
+            Op(PushBase),
+            Int(0),
+            Int(0), Op(Fetch), Int(10), Op(Mul), Int(2), Op(Store),
+            Int(1), Op(Fetch), Int(2), Op(Fetch), Op(SetGlobalVar),
+            Int(0), Op(DToA), Op(PopToBase), Op(PopBase), Op(AToD), Op(PopReturn),
+        ])
+    }
+
+    fn to_string(f: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn disassemble_() {
+        let prg = test_program();
+        let act = to_string(|out| disassemble(&prg, out));
+        assert!(act.contains(";     0 start "));
+        assert!(act.contains(";   0x0006 \"hello\""));
+        assert!(act.contains("\n; procedure 0 start\n"));
+        assert!(act.contains("  802b  push_base\n"));
+        assert!(act.contains("  9001  const_string 0x0006  ; \"hello\"\n"));
+        assert!(act.contains("  80b8  display_msg\n"));
+    }
+
+    #[test]
+    fn decompile_() {
+        let prg = test_program();
+        let act = to_string(|out| decompile(&prg, out));
+        assert_eq!(act, "\
+/* Decompiled from test.int */
+
+procedure start;
+
+procedure start begin
+   variable var0 := 0;
+   if (var0 == 1) then begin
+      display_msg(\"hello\");
+   end else begin
+      display_msg(\"bye\");
+   end
+   while (var0 < 3) do begin
+      var0 := var0 + 1;
+   end
+end
+");
+    }
+
+    #[test]
+    fn decompile_args() {
+        let prg = args_program();
+        let act = to_string(|out| decompile(&prg, out));
+        assert_eq!(act, "\
+/* Decompiled from test.int */
+
+procedure start(variable arg0, variable arg1);
+
+procedure start(variable arg0, variable arg1) begin
+   variable var0 := 0;
+   var0 := arg0 * 10;
+   set_global_var(arg1, var0);
+end
+");
+    }
+}
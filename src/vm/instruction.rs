@@ -1,4 +1,5 @@
 mod impls;
+mod info;
 
 use enum_map_derive::Enum;
 use enum_primitive_derive::Primitive;
@@ -406,21 +407,21 @@ pub(in super) mod instructions {
         i!(ActionBeingUsed,             action_being_used),
         i!(Activateregion,              unimplemented),
         i!(Add,                         add),
-        i!(Addbutton,                   unimplemented),
-        i!(Addbuttonflag,               unimplemented),
-        i!(Addbuttongfx,                unimplemented),
-        i!(Addbuttonproc,               unimplemented),
-        i!(Addbuttonrightproc,          unimplemented),
-        i!(Addbuttontext,               unimplemented),
+        i!(Addbutton,                   addbutton),
+        i!(Addbuttonflag,               addbuttonflag),
+        i!(Addbuttongfx,                addbuttongfx),
+        i!(Addbuttonproc,               addbuttonproc),
+        i!(Addbuttonrightproc,          addbuttonrightproc),
+        i!(Addbuttontext,               addbuttontext),
         i!(Addkey,                      unimplemented),
         i!(AddMultObjsToInven,          add_mult_objs_to_inven),
         i!(Addnamedevent,               unimplemented),
         i!(Addnamedhandler,             unimplemented),
         i!(AddObjToInven,               add_obj_to_inven),
-        i!(Addregion,                   unimplemented),
-        i!(Addregionflag,               unimplemented),
-        i!(Addregionproc,               unimplemented),
-        i!(Addregionrightproc,          unimplemented),
+        i!(Addregion,                   addregion),
+        i!(Addregionflag,               addregionflag),
+        i!(Addregionproc,               addregionproc),
+        i!(Addregionrightproc,          addregionrightproc),
         i!(AddTimerEvent,               add_timer_event),
         i!(And,                         and),
         i!(Anim,                        anim),
@@ -431,9 +432,9 @@ pub(in super) mod instructions {
         i!(AnimBusy,                    unimplemented),
         i!(ArtAnim,                     unimplemented),
         i!(AToD,                        atod),
-        i!(Attack,                      unimplemented),
-        i!(Attack80dd,                  unimplemented),
-        i!(AttackSetup,                 unimplemented),
+        i!(Attack,                      attack),
+        i!(Attack80dd,                  attack),
+        i!(AttackSetup,                 attack_setup),
         i!(Bwand,                       bwand),
         i!(Bwnot,                       bwnot),
         i!(Bwor,                        bwor),
@@ -454,7 +455,7 @@ pub(in super) mod instructions {
         i!(ConstShort,                  const_int),
         i!(ConstString,                 const_string),
         i!(CreateObjectSid,             create_object_sid),
-        i!(Createwin,                   unimplemented),
+        i!(Createwin,                   createwin),
         i!(CriticalDone,                noop),
         i!(CriticalDone804b,            noop),
         i!(CriticalStart,               noop),
@@ -470,34 +471,34 @@ pub(in super) mod instructions {
         i!(CritterRmTrait,              unimplemented),
         i!(CritterSetFleeState,         unimplemented),
         i!(CritterState,                unimplemented),
-        i!(CritterStopAttacking,        unimplemented),
+        i!(CritterStopAttacking,        critter_stop_attacking),
         i!(CurMapIndex,                 cur_map_index),
         i!(DaysSinceVisited,            unimplemented),
         i!(DebugMsg,                    debug_msg),
-        i!(Deletebutton,                unimplemented),
+        i!(Deletebutton,                deletebutton),
         i!(Deletekey,                   unimplemented),
-        i!(Deleteregion,                unimplemented),
-        i!(Deletewin,                   unimplemented),
+        i!(Deleteregion,                deleteregion),
+        i!(Deletewin,                   deletewin),
         i!(DestroyMultObjs,             unimplemented),
         i!(DestroyObject,               destroy_object),
         i!(Detach,                      unimplemented),
         i!(DialogueReaction,            unimplemented),
         i!(DialogueSystemEnter,         unimplemented),
         i!(DifficultyLevel,             unimplemented),
-        i!(Display,                     unimplemented),
+        i!(Display,                     display),
         i!(Displaygfx,                  unimplemented),
         i!(DisplayMsg,                  display_msg),
         i!(Displayraw,                  unimplemented),
         i!(Div,                         div),
         i!(DoCheck,                     do_check),
-        i!(DropObj,                     unimplemented),
+        i!(DropObj,                     drop_obj),
         i!(DToA,                        dtoa),
         i!(DudeObj,                     dude_obj),
         i!(Dump,                        unimplemented),
         i!(Dup,                         dup),
         i!(Elevation,                   elevation),
         i!(EndDialogue,                 end_dialogue),
-        i!(EndgameMovie,                unimplemented),
+        i!(EndgameMovie,                endgame_movie),
         i!(EndgameSlideshow,            unimplemented),
         i!(Equal,                       equal),
         i!(Exec,                        unimplemented),
@@ -512,10 +513,10 @@ pub(in super) mod instructions {
         i!(FetchExternal,               fetch_external),
         i!(FetchGlobal,                 fetch_global),
         i!(FetchProcAddress,            unimplemented),
-        i!(Fillrect,                    unimplemented),
-        i!(Fillwin,                     unimplemented),
+        i!(Fillrect,                    fillrect),
+        i!(Fillwin,                     fillwin),
         i!(Fillwin3X3,                  unimplemented),
-        i!(FixedParam,                  unimplemented),
+        i!(FixedParam,                  fixed_param),
         i!(FloatMsg,                    float_msg),
         i!(Floor,                       unimplemented),
         i!(Fork,                        unimplemented),
@@ -540,7 +541,7 @@ pub(in super) mod instructions {
         i!(GiqOption,                   giq_option),
         i!(GiveExpPoints,               give_exp_points),
         i!(GlobalVar,                   global_var),
-        i!(Gotoxy,                      unimplemented),
+        i!(Gotoxy,                      gotoxy),
         i!(Greater,                     greater),
         i!(GreaterEqual,                greater_equal),
         is!(GsayEnd,                    gsay_end),
@@ -553,8 +554,8 @@ pub(in super) mod instructions {
         i!(Hidemouse,                   unimplemented),
         i!(HowMuch,                     unimplemented),
         i!(If,                          if_),
-        i!(InvenCmds,                   unimplemented),
-        i!(InvenUnwield,                unimplemented),
+        i!(InvenCmds,                   inven_cmds),
+        i!(InvenUnwield,                inven_unwield),
         i!(IsCritical,                  is_critical),
         i!(IsSuccess,                   is_success),
         i!(ItemCapsAdjust,              unimplemented),
@@ -570,7 +571,7 @@ pub(in super) mod instructions {
         i!(LocalVar,                    local_var),
         i!(LookupStringProc,            unimplemented),
         i!(MapVar,                      map_var),
-        i!(MarkAreaKnown,               unimplemented),
+        i!(MarkAreaKnown,               mark_area_known),
         i!(MessageStr,                  message_str),
         i!(Metarule,                    metarule),
         i!(Metarule3,                   metarule3),
@@ -608,11 +609,11 @@ pub(in super) mod instructions {
         i!(PartyAdd,                    unimplemented),
         i!(PartyMemberObj,              party_member_obj),
         i!(PartyRemove,                 unimplemented),
-        i!(PickupObj,                   unimplemented),
-        i!(PlayGmovie,                  unimplemented),
-        i!(Playmovie,                   unimplemented),
+        i!(PickupObj,                   pickup_obj),
+        i!(PlayGmovie,                  play_gmovie),
+        i!(Playmovie,                   playmovie),
         i!(Playmovierect,               unimplemented),
-        i!(PlaySfx,                     unimplemented),
+        i!(PlaySfx,                     play_sfx),
         i!(Poison,                      unimplemented),
         i!(Pop,                         pop),
         i!(PopAddress,                  unimplemented),
@@ -628,7 +629,7 @@ pub(in super) mod instructions {
         i!(PopFlagsReturnValExtern,     unimplemented),
         i!(PopReturn,                   pop_return),
         i!(PopToBase,                   pop_to_base),
-        i!(Print,                       unimplemented),
+        i!(Print,                       print),
         i!(Printrect,                   unimplemented),
         i!(ProtoData,                   unimplemented),
         i!(PushBase,                    push_base),
@@ -647,79 +648,79 @@ pub(in super) mod instructions {
         i!(RegAnimObjRunToTile,         unimplemented),
         i!(RegAnimPlaySfx,              unimplemented),
         i!(Resizewin,                   unimplemented),
-        i!(RmMultObjsFromInven,         unimplemented),
-        i!(RmObjFromInven,              unimplemented),
+        i!(RmMultObjsFromInven,         rm_mult_objs_from_inven),
+        i!(RmObjFromInven,              rm_obj_from_inven),
         i!(RmTimerEvent,                rm_timer_event),
         i!(RollDice,                    unimplemented),
         i!(RollVsSkill,                 roll_vs_skill),
         i!(RotationToTile,              rotation_to_tile),
         i!(RunningBurningGuy,           unimplemented),
         i!(Sayborder,                   unimplemented),
-        i!(Sayend,                      unimplemented),
+        is!(Sayend,                     sayend),
         i!(Saygetlastpos,               unimplemented),
-        i!(Saygotoreply,                unimplemented),
-        i!(Saymessage,                  unimplemented),
+        i!(Saygotoreply,                saygotoreply),
+        i!(Saymessage,                  saymessage),
         i!(Saymessagetimeout,           unimplemented),
-        i!(Sayoption,                   unimplemented),
+        i!(Sayoption,                   sayoption),
         i!(Sayoptioncolor,              unimplemented),
         i!(Sayoptionflags,              unimplemented),
-        i!(Sayoptionwindow,             unimplemented),
-        i!(Sayquit,                     unimplemented),
-        i!(Sayreply,                    unimplemented),
+        i!(Sayoptionwindow,             sayoptionwindow),
+        i!(Sayquit,                     sayquit),
+        i!(Sayreply,                    sayreply),
         i!(Sayreplycolor,               unimplemented),
         i!(Sayreplyflags,               unimplemented),
-        i!(Sayreplytitle,               unimplemented),
-        i!(Sayreplywindow,              unimplemented),
+        i!(Sayreplytitle,               sayreplytitle),
+        i!(Sayreplywindow,              sayreplywindow),
         i!(Sayrestart,                  unimplemented),
         i!(Sayscrolldown,               unimplemented),
         i!(Sayscrollup,                 unimplemented),
         i!(Saysetspacing,               unimplemented),
-        i!(Saystart,                    unimplemented),
-        i!(Saystartpos,                 unimplemented),
+        i!(Saystart,                    saystart),
+        i!(Saystartpos,                 saystartpos),
         i!(Scalewin,                    unimplemented),
         i!(ScriptAction,                unimplemented),
         i!(ScriptOverrides,             script_overrides),
         i!(ScrReturn,                   unimplemented),
         i!(Selectfilelist,              unimplemented),
-        i!(Selectwin,                   unimplemented),
+        i!(Selectwin,                   selectwin),
         i!(SelfObj,                     self_obj),
         i!(SetCritterStat,              unimplemented),
         i!(SetExitGrids,                unimplemented),
-        i!(Setfont,                     unimplemented),
+        i!(Setfont,                     setfont),
         i!(SetGlobal,                   set_global),
         i!(Setglobalmousefunc,          unimplemented),
         i!(SetGlobalVar,                set_global_var),
         i!(Sethighlightcolor,           unimplemented),
         i!(SetLightLevel,               set_light_level),
         i!(SetLocalVar,                 set_local_var),
-        i!(SetMapMusic,                 unimplemented),
+        i!(SetMapMusic,                 set_map_music),
         i!(SetMapStart,                 unimplemented),
         i!(SetMapVar,                   set_map_var),
         i!(SetObjVisibility,            set_obj_visibility),
         i!(Setoneoptpause,              unimplemented),
-        i!(Settextcolor,                unimplemented),
+        i!(Settextcolor,                settextcolor),
         i!(Settextflags,                unimplemented),
-        i!(SfxBuildAmbientName,         unimplemented),
-        i!(SfxBuildCharName,            unimplemented),
-        i!(SfxBuildInterfaceName,       unimplemented),
-        i!(SfxBuildItemName,            unimplemented),
-        i!(SfxBuildOpenName,            unimplemented),
-        i!(SfxBuildSceneryName,         unimplemented),
-        i!(SfxBuildWeaponName,          unimplemented),
+        i!(SfxBuildAmbientName,         sfx_build_ambient_name),
+        i!(SfxBuildCharName,            sfx_build_char_name),
+        i!(SfxBuildInterfaceName,       sfx_build_interface_name),
+        i!(SfxBuildItemName,            sfx_build_item_name),
+        i!(SfxBuildOpenName,            sfx_build_open_name),
+        i!(SfxBuildSceneryName,         sfx_build_scenery_name),
+        i!(SfxBuildWeaponName,          sfx_build_weapon_name),
         i!(Showmouse,                   unimplemented),
-        i!(Showwin,                     unimplemented),
+        i!(Showwin,                     showwin),
         i!(Signalnamed,                 unimplemented),
         i!(SkillContest,                unimplemented),
-        i!(Sounddelete,                 unimplemented),
-        i!(Soundpause,                  unimplemented),
-        i!(Soundplay,                   unimplemented),
-        i!(Soundresume,                 unimplemented),
-        i!(Soundrewind,                 unimplemented),
-        i!(Soundstop,                   unimplemented),
+        i!(Sounddelete,                 sounddelete),
+        i!(Soundpause,                  soundpause),
+        i!(Soundplay,                   soundplay),
+        i!(Soundresume,                 soundresume),
+        i!(Soundrewind,                 soundrewind),
+        i!(Soundstop,                   soundstop),
         i!(SourceObj,                   source_obj),
         i!(Spawn,                       unimplemented),
         i!(StartGdialog,                start_gdialog),
-        i!(Stopmovie,                   unimplemented),
+        i!(Stopmovie,                   stopmovie),
         i!(StopProg,                    unimplemented),
         i!(Store,                       store),
         i!(StoreExternal,               store_external),
@@ -728,7 +729,7 @@ pub(in super) mod instructions {
         i!(Swap,                        swap),
         i!(Swapa,                       swapa),
         i!(TargetObj,                   target_obj),
-        i!(TerminateCombat,             unimplemented),
+        i!(TerminateCombat,             terminate_combat),
         i!(TileContainsObjPid,          tile_contains_pid_obj),
         i!(TileContainsPidObj,          tile_contains_pid_obj),
         i!(TileDistance,                tile_distance),
@@ -744,8 +745,8 @@ pub(in super) mod instructions {
         i!(Wait,                        unimplemented),
         i!(While,                       while_),
         i!(WieldObjCritter,             unimplemented),
-        i!(WmAreaSetPos,                unimplemented),
-        i!(WorldMap,                    unimplemented),
+        i!(WmAreaSetPos,                wm_area_set_pos),
+        i!(WorldMap,                    world_map),
     ];
 }
 
@@ -1,9 +1,15 @@
 #[macro_use] mod macros;
 mod core;
 mod game;
+mod movie;
+mod sound;
+mod ui;
 
 pub use self::core::*;
 pub use self::game::*;
+pub use self::movie::*;
+pub use self::sound::*;
+pub use self::ui::*;
 
 use super::Context;
 use super::value::*;
@@ -452,4 +452,51 @@ pub fn while_(ctx: Context) -> Result<()> {
         log_a1!(ctx.prg, done);
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vm::instruction::Opcode::*;
+    use crate::vm::test::*;
+
+    #[test]
+    fn persistent_vars() {
+        let mut h = Harness::new();
+        h.eval(Asm::new()
+            .ints(&[1, 10]).op(SetGlobalVar)
+            .ints(&[2, 20]).op(SetLocalVar)
+            .ints(&[3, 30]).op(SetMapVar)).unwrap();
+        assert_eq!((h.global_vars[1], h.local_vars[2], h.map_vars[3]), (10, 20, 30));
+        assert_eq!(h.eval(Asm::new()
+                .int(1).op(GlobalVar)
+                .int(2).op(LocalVar)
+                .int(3).op(MapVar)).unwrap(),
+            vec![10.into(), 20.into(), 30.into()]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut h = Harness::new();
+        assert_eq!(h.eval(Asm::new()
+                .ints(&[7, 3]).op(Sub)
+                .ints(&[7, 3]).op(Mod)
+                .int(2).float(1.5).op(Mul)
+                .string("foo").int(1).op(Add)).unwrap(),
+            vec![4.into(), 1.into(), 3.0.into(), "foo1".into()]);
+    }
+
+    #[test]
+    fn procedure_call() {
+        let mut h = Harness::new();
+        let prg = h.load(Asm::new()
+            .proc("start", "start", 0)
+            .op(ExitProg)
+            .label("start")
+            .op(PushBase)
+            .ints(&[0, 42]).op(SetGlobalVar)
+            .int(0).ops(&[DToA, PopToBase, Swapa, PopBase, AToD, PopReturn])
+            .assemble()).unwrap();
+        h.execute_proc(prg, "start").unwrap().assert_no_suspend();
+        assert_eq!(h.global_vars[0], 42);
+    }
+}
@@ -9,11 +9,15 @@ use std::convert::{TryFrom, TryInto};
 
 use super::*;
 use crate::asset::{ExactEntityKind, Flag, Perk, Skill, Stat, Trait};
+use crate::asset::map::MapId;
 use crate::asset::proto::ProtoId;
 use crate::asset::script::ProgramId;
+use crate::game::combat::{AttackOptions, AttackRequest};
 use crate::game::dialog::Dialog;
 use crate::game::script::ScriptPid;
+use crate::game::timer::TimerEvent;
 use crate::game::world::floating_text;
+use crate::game::worldmap::MarkState;
 use crate::graphics::{EPoint, Point};
 use crate::graphics::color::*;
 use crate::graphics::font::FontKey;
@@ -38,6 +42,29 @@ enum ObjectTrait {
     ItemTotalWeight = 669,
 }
 
+/// Query of the `critter_inven_obj()` instruction.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq, Primitive)]
+#[repr(u32)]
+enum InvenQuery {
+    Worn        = 0,
+    RightHand   = 1,
+    LeftHand    = 2,
+    Count       = 0xffff_fffe,
+}
+
+/// Command of the `inven_cmds()` instruction.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq, Primitive)]
+enum InvenCmd {
+    IndexPtr = 13,
+}
+
+/// What the `mark_area_known()` instruction marks.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq, Primitive)]
+enum MarkType {
+    Town = 0,
+    Map = 1,
+}
+
 fn pop_program_id(ctx: &mut Context) -> Result<ProgramId> {
     ctx.prg.data_stack.pop()?.into_int()?
         .try_into().ok()
@@ -68,6 +95,53 @@ fn from_tile_num(ctx: &Context, tile_num: i32) -> Option<Point> {
         .map(|v| ctx.ext.world.hex_grid().from_linear_inv(v))
 }
 
+/// Removes timer events of the object's script in the current map that match the predicate.
+fn remove_timer_events(ctx: &mut Context, obj: object::Handle, f: impl Fn(&TimerEvent) -> bool) {
+    let sid = ctx.ext.world.objects().get(obj).script.map(|(sid, _)| sid);
+    if let Some(sid) = sid {
+        let map_id = ctx.ext.map_id;
+        ctx.ext.world.timer_events.remove(|e| e.map_id == map_id && e.sid == sid && f(e));
+    }
+}
+
+// op_add_obj_to_inven()
+/// Checks that the `item` can be put into the `target`'s inventory. Items owned by someone else
+/// must be removed from that inventory first.
+fn check_add_to_inven(ctx: &Context, target: object::Handle, item: object::Handle) -> bool {
+    if target == item {
+        log_error!(ctx.prg, "can't add object to its own inventory");
+        false
+    } else if ctx.ext.world.objects().owner(item).is_some() {
+        log_error!(ctx.prg, "item is already in an inventory");
+        false
+    } else {
+        true
+    }
+}
+
+/// Makes `attacker` attack `target`. Starts combat if it's not active.
+fn request_attack(ctx: &mut Context, attacker: object::Handle, target: object::Handle,
+    options: AttackOptions)
+{
+    let dead = |h| ctx.ext.world.objects().get(h).sub.as_critter()
+        .map(|c| c.is_dead())
+        .unwrap_or(true);
+    if dead(attacker) || dead(target) {
+        log_error!(ctx.prg, "attacker or target is not a live critter");
+        return;
+    }
+    let request = AttackRequest {
+        attacker,
+        target,
+        options,
+    };
+    if ctx.ext.combat.is_active() {
+        ctx.ext.combat.accept_attack_request(&request);
+    } else {
+        ctx.ext.combat.request_attack(request);
+    }
+}
+
 #[derive(Clone, Copy, Debug, Enum, Eq, Hash, Ord, PartialEq, PartialOrd, Primitive)]
 enum Metarule {
     SignalEndGame   = 13,
@@ -139,10 +213,14 @@ pub fn add_mult_objs_to_inven(ctx: Context) -> Result<()> {
     } else {
         count
     };
-    let item = ctx.prg.data_stack.pop()?.coerce_into_object()?;
-    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?;
+    let item = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
     log_a3!(ctx.prg, target, item, count);
-    log_stub!(ctx.prg);
+    if count > 0 && check_add_to_inven(&ctx, target, item) {
+        ctx.ext.world.objects_mut().add_to_inventory(target, item, count as u32);
+    }
     Ok(())
 }
 
@@ -152,7 +230,9 @@ pub fn add_obj_to_inven(ctx: Context) -> Result<()> {
     let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
         .ok_or(Error::BadValue(BadValue::Content))?;
     log_a2!(ctx.prg, target, item);
-    log_stub!(ctx.prg);
+    if check_add_to_inven(&ctx, target, item) {
+        ctx.ext.world.objects_mut().add_to_inventory(target, item, 1);
+    }
     Ok(())
 }
 
@@ -162,8 +242,20 @@ pub fn add_timer_event(ctx: Context) -> Result<()> {
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
         .ok_or(Error::BadValue(BadValue::Content))?;
 
+    let sid = ctx.ext.world.objects().get(obj).script.map(|(sid, _)| sid);
+    if let Some(sid) = sid {
+        let time = ctx.ext.world.game_time.add_decis(cmp::max(time, 0) as u32);
+        ctx.ext.world.timer_events.add(TimerEvent {
+            time,
+            map_id: ctx.ext.map_id,
+            sid,
+            fixed_param: info,
+        });
+    } else {
+        log_error!(ctx.prg, "object has no script");
+    }
+
     log_a3!(ctx.prg, obj, time, info);
-    log_stub!(ctx.prg);
 
     Ok(())
 }
@@ -183,11 +275,46 @@ pub fn anim(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+// op_attack()
+// TODO called shots, number of attacks and the result flags are ignored.
+pub fn attack(mut ctx: Context) -> Result<()> {
+    let _target_results = ctx.prg.data_stack.pop()?.into_int()?;
+    let _attacker_results = ctx.prg.data_stack.pop()?.into_int()?;
+    let max_damage = ctx.prg.data_stack.pop()?.into_int()?;
+    let min_damage = ctx.prg.data_stack.pop()?.into_int()?;
+    let to_hit_bonus = ctx.prg.data_stack.pop()?.into_int()?;
+    let _num_attacks = ctx.prg.data_stack.pop()?.into_int()?;
+    let called_shot = ctx.prg.data_stack.pop()?.into_int()?;
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let attacker = ctx.ext.self_obj.ok_or(Error::BadValue(BadValue::Content))?;
+    log_a5!(ctx.prg, target, called_shot, to_hit_bonus, min_damage, max_damage);
+
+    request_attack(&mut ctx, attacker, target, AttackOptions {
+        to_hit_bonus,
+        min_damage,
+        max_damage,
+    });
+
+    Ok(())
+}
+
+pub fn attack_setup(mut ctx: Context) -> Result<()> {
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let attacker = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a2!(ctx.prg, attacker, target);
+
+    request_attack(&mut ctx, attacker, target, AttackOptions::default());
+
+    Ok(())
+}
+
 pub fn combat_is_initialized(ctx: Context) -> Result<()> {
-    let r = false;
+    let r = ctx.ext.combat.is_active();
     ctx.prg.data_stack.push(r.into())?;
     log_r1!(ctx.prg, r);
-    log_stub!(ctx.prg);
     Ok(())
 }
 
@@ -273,14 +400,32 @@ pub fn critter_attempt_placement(ctx: Context) -> Result<()> {
 }
 
 pub fn critter_inven_obj(ctx: Context) -> Result<()> {
-    let query = ctx.prg.data_stack.pop()?.into_int()?;
-    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?;
-
-    let r = 0;
-    ctx.prg.data_stack.push(r.into())?;
+    let query = InvenQuery::from_i32(ctx.prg.data_stack.pop()?.into_int()?)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
 
+    let r = {
+        let objs = ctx.ext.world.objects();
+        let o = objs.get(obj);
+        match query {
+            InvenQuery::Worn => o.wearing(objs).into(),
+            InvenQuery::RightHand => o.in_right_hand(objs).into(),
+            InvenQuery::LeftHand => o.in_left_hand(objs).into(),
+            InvenQuery::Count => (o.inventory.items.len() as i32).into(),
+        }
+    };
     log_a2r1!(ctx.prg, obj, query, r);
-    log_stub!(ctx.prg);
+    ctx.prg.data_stack.push(r)?;
+
+    Ok(())
+}
+
+pub fn critter_stop_attacking(ctx: Context) -> Result<()> {
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a1!(ctx.prg, obj);
+    ctx.ext.combat.stop_attacking(obj);
     Ok(())
 }
 
@@ -350,6 +495,28 @@ pub fn elevation(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn drop_obj(ctx: Context) -> Result<()> {
+    let item = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a1!(ctx.prg, item);
+
+    let owner = ctx.ext.self_obj.ok_or(Error::BadValue(BadValue::Content))?;
+    let objs = ctx.ext.world.objects_mut();
+    let pos = if let Some(v) = objs.get(owner).pos {
+        v
+    } else {
+        log_error!(ctx.prg, "self_obj is not on map");
+        return Ok(());
+    };
+    if let Some(item) = objs.remove_from_inventory(owner, item, 1) {
+        objs.set_pos(item, pos);
+    } else {
+        log_error!(ctx.prg, "item is not in self_obj's inventory");
+    }
+
+    Ok(())
+}
+
 pub fn end_dialogue(ctx: Context) -> Result<()> {
     ctx.ext.dialog.take().unwrap().hide(ctx.ext.ui, ctx.ext.world);
     log_!(ctx.prg);
@@ -451,6 +618,13 @@ pub fn game_ticks(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn fixed_param(ctx: Context) -> Result<()> {
+    let r = ctx.ext.fixed_param;
+    ctx.prg.data_stack.push(Value::Int(r))?;
+    log_r1!(ctx.prg, r);
+    Ok(())
+}
+
 pub fn game_time(ctx: Context) -> Result<()> {
     let r = ctx.ext.world.game_time.as_decis();
     ctx.prg.data_stack.push(Value::Int(r as i32))?;
@@ -474,10 +648,20 @@ pub fn game_time_in_seconds(ctx: Context) -> Result<()> {
 }
 
 pub fn gdialog_barter(ctx: Context) -> Result<()> {
-    let r = 0;
+    let r = if let Some(dialog) = ctx.ext.dialog.as_mut() {
+        if ctx.ext.world.objects().get(dialog.obj).can_barter() {
+            dialog.request_barter();
+            0
+        } else {
+            log_error!(ctx.prg, "dialog critter doesn't barter");
+            -1
+        }
+    } else {
+        log_error!(ctx.prg, "not in dialog");
+        -1
+    };
     ctx.prg.data_stack.push(r.into())?;
     log_r1!(ctx.prg, r);
-    log_stub!(ctx.prg);
     Ok(())
 }
 
@@ -485,7 +669,12 @@ pub fn gdialog_set_barter_mod(ctx: Context) -> Result<()> {
     let val = ctx.prg.data_stack.pop()?.into_int()?;
 
     log_a1!(ctx.prg, val);
-    log_stub!(ctx.prg);
+
+    if let Some(dialog) = ctx.ext.dialog.as_mut() {
+        dialog.set_barter_mod(val);
+    } else {
+        log_error!(ctx.prg, "not in dialog");
+    }
     Ok(())
 }
 
@@ -699,6 +888,43 @@ pub fn item_caps_total(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn inven_cmds(ctx: Context) -> Result<()> {
+    let index = ctx.prg.data_stack.pop()?.into_int()?;
+    let cmd = ctx.prg.data_stack.pop()?.into_int()?;
+    let cmd = InvenCmd::from_i32(cmd)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    let r = match cmd {
+        InvenCmd::IndexPtr => {
+            let objs = ctx.ext.world.objects();
+            let o = objs.get(obj);
+            usize::try_from(index).ok()
+                .and_then(|i| o.inventory.items.get(i))
+                .map(|i| i.object)
+        }
+    };
+    ctx.prg.data_stack.push(r.into())?;
+
+    log_a3r1!(ctx.prg, obj, cmd, index, r);
+
+    Ok(())
+}
+
+pub fn inven_unwield(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+
+    let critter = ctx.ext.self_obj.ok_or(Error::BadValue(BadValue::Content))?;
+    let objs = ctx.ext.world.objects_mut();
+    let item = objs.get(critter).in_right_hand(objs);
+    if let Some(item) = item {
+        objs.unequip(critter, item);
+    }
+
+    Ok(())
+}
+
 pub fn is_critical(ctx: Context) -> Result<()> {
     let v = RollCheckResult::from_i32(ctx.prg.data_stack.pop()?.coerce_into_int()?);
     if v.is_none() {
@@ -735,6 +961,33 @@ pub fn jam_lock(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn mark_area_known(ctx: Context) -> Result<()> {
+    let state = ctx.prg.data_stack.pop()?.into_int()?;
+    let area = ctx.prg.data_stack.pop()?.into_int()?;
+    let kind = ctx.prg.data_stack.pop()?.into_int()?;
+    let kind = MarkType::from_i32(kind)
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    log_a3!(ctx.prg, kind, area, state);
+
+    match kind {
+        MarkType::Town => {
+            let state = MarkState::from_i32(state)
+                .ok_or(Error::BadValue(BadValue::Content))?;
+            if area < 0 || !ctx.ext.world_map.set_area_state(area as usize, state) {
+                log_error!(ctx.prg, "bad area");
+            }
+        }
+        MarkType::Map => {
+            if area < 0 || !ctx.ext.world_map.mark_map_visited(area as MapId) {
+                log_error!(ctx.prg, "bad map");
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn message_str(mut ctx: Context) -> Result<()> {
     let msg_id = ctx.prg.data_stack.pop()?.into_int()?;
     let program_id = pop_program_id(&mut ctx)?;
@@ -762,12 +1015,30 @@ pub fn metarule(ctx: Context) -> Result<()> {
             TestFirstrun    => 1.into(),
             Elevator        => 0.into(),
             PartyCount      => 0.into(),
-            AreaKnown       => 1.into(),
+            AreaKnown       => {
+                stub = false;
+                let area = arg.coerce_into_int()?;
+                usize::try_from(area).ok()
+                    .and_then(|a| ctx.ext.world_map.area_state(a))
+                    .map(|s| s != MarkState::Unknown)
+                    .unwrap_or(false)
+                    .into()
+            }
             WhoOnDrugs      => 0.into(),
             MapKnown        => 1.into(),
             IsLoadgame      => 0.into(),
-            CarCurrentTown  => 0.into(),
-            GiveCarToParty  => 0.into(),
+            CarCurrentTown  => {
+                stub = false;
+                ctx.ext.world_map.car_area()
+                    .map(|a| a as i32)
+                    .unwrap_or(-1)
+                    .into()
+            }
+            GiveCarToParty  => {
+                stub = false;
+                ctx.ext.world_map.give_car();
+                0.into()
+            }
             GiveCarGas      => 0.into(),
             SkillCheckTag   => {
                 stub = false;
@@ -776,13 +1047,30 @@ pub fn metarule(ctx: Context) -> Result<()> {
             }
             DropAllInven    => 0.into(),
             InvenUnwieldWho => 0.into(),
-            GetWorldmapXpos => 0.into(),
-            GetWorldmapYpos => 0.into(),
-            CurrentTown     => 0.into(),
+            GetWorldmapXpos => {
+                stub = false;
+                ctx.ext.world_map.pos().x.into()
+            }
+            GetWorldmapYpos => {
+                stub = false;
+                ctx.ext.world_map.pos().y.into()
+            }
+            CurrentTown     => {
+                stub = false;
+                ctx.ext.world_map.current_area().map(|a| a as i32).unwrap_or(-1).into()
+            }
             LanguageFilter  => 0.into(),
             ViolenceFilter  => 0.into(),
             WDamageType     => 0.into(),
-            CritterBarters  => 0.into(),
+            CritterBarters  => {
+                stub = false;
+                if let Some(obj) = arg.coerce_into_object()? {
+                    ctx.ext.world.objects().get(obj).can_barter().into()
+                } else {
+                    log_error!(ctx.prg, "object is null");
+                    false.into()
+                }
+            }
             CritterKillType => 0.into(),
             CarTrunkSetAnim => 0.into(),
             CarTrunkGetAnim => 0.into(),
@@ -806,7 +1094,7 @@ pub fn metarule(ctx: Context) -> Result<()> {
     Ok(())
 }
 
-pub fn metarule3(ctx: Context) -> Result<()> {
+pub fn metarule3(mut ctx: Context) -> Result<()> {
     let v3 = ctx.prg.data_stack.pop()?;
     let v2 = ctx.prg.data_stack.pop()?;
     let v1 = ctx.prg.data_stack.pop()?;
@@ -814,14 +1102,32 @@ pub fn metarule3(ctx: Context) -> Result<()> {
 
     use self::Metarule3::*;
     let mr = Metarule3::from_i32(id);
+    let mut stub = true;
     let r = if let Some(mr) = mr {
         match mr {
-            ClrFixedTimedEvents => 0,
-            MarkSubtile         => 0,
+            ClrFixedTimedEvents => {
+                stub = false;
+                if let Some(obj) = v1.clone().coerce_into_object()? {
+                    let fixed_param = v2.clone().coerce_into_int()?;
+                    remove_timer_events(&mut ctx, obj, |e| e.fixed_param == fixed_param);
+                }
+                0
+            }
+            MarkSubtile         => {
+                stub = false;
+                let pos = Point::new(v1.clone().coerce_into_int()?, v2.clone().coerce_into_int()?);
+                let radius = v3.clone().coerce_into_int()?;
+                ctx.ext.world_map.mark_subtiles(pos, radius);
+                0
+            }
             SetWmMusic          => 0,
             GetKillCount        => 0,
             MarkMapEntrance     => 0,
-            WmSubtileState      => 0,
+            WmSubtileState      => {
+                stub = false;
+                let pos = Point::new(v1.clone().coerce_into_int()?, v2.clone().coerce_into_int()?);
+                ctx.ext.world_map.subtile_state(pos).map(|s| s as i32).unwrap_or(-1)
+            }
             TileGetNextCritter  => 0,
             ArtSetBaseFidNum    => 0,
             TileSetCenter       => 0,
@@ -841,24 +1147,34 @@ pub fn metarule3(ctx: Context) -> Result<()> {
     } else {
         log_a4r1!(ctx.prg, id, v1, v2, v3, ctx.prg.data_stack.top().unwrap());
     }
-    log_stub!(ctx.prg);
+    if stub {
+        log_stub!(ctx.prg);
+    }
 
     Ok(())
 }
 
+// TODO Armor appearance is not updated when the dude's armor is moved.
 pub fn move_obj_inven_to_obj(ctx: Context) -> Result<()> {
     let dst = ctx.prg.data_stack.pop()?.coerce_into_object()?;
     let src = ctx.prg.data_stack.pop()?.coerce_into_object()?;
 
-    if src.is_none() {
-        log_error!(ctx.prg, "src object is null");
-    }
-    if dst.is_none() {
-        log_error!(ctx.prg, "dst object is null");
-    }
-
     log_a2!(ctx.prg, src, dst);
-    log_stub!(ctx.prg);
+
+    let (src, dst) = match (src, dst) {
+        (Some(src), Some(dst)) => (src, dst),
+        _ => {
+            log_error!(ctx.prg, "src or dst object is null");
+            return Ok(());
+        }
+    };
+
+    let objs = ctx.ext.world.objects_mut();
+    let items = objs.get(src).inventory.items.clone();
+    for item in items {
+        let obj = objs.remove_from_inventory(src, item.object, item.count).unwrap();
+        objs.add_to_inventory(dst, obj, item.count);
+    }
 
     Ok(())
 }
@@ -908,14 +1224,15 @@ pub fn obj_can_see_obj(ctx: Context) -> Result<()> {
 
 pub fn obj_is_carrying_obj_pid(ctx: Context) -> Result<()> {
     let pid = ctx.prg.data_stack.pop()?.into_int()?;
+    let pid = ProtoId::from_packed(pid as u32)
+        .ok_or(Error::BadValue(BadValue::Content))?;
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
         .ok_or(Error::BadValue(BadValue::Content))?;
 
-    let r = 0;
+    let r = ctx.ext.world.objects().inventory_count(obj, pid) as i32;
     ctx.prg.data_stack.push(r.into())?;
 
     log_a2r1!(ctx.prg, obj, pid, r);
-    log_stub!(ctx.prg);
 
     Ok(())
 }
@@ -1049,6 +1366,20 @@ pub fn party_member_obj(ctx: Context) -> Result<()> {
     Ok(())
 }
 
+// TODO Should animate the critter walking to and picking up the item.
+pub fn pickup_obj(ctx: Context) -> Result<()> {
+    let item = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a1!(ctx.prg, item);
+
+    let owner = ctx.ext.self_obj.ok_or(Error::BadValue(BadValue::Content))?;
+    if check_add_to_inven(&ctx, owner, item) {
+        ctx.ext.world.objects_mut().add_to_inventory(owner, item, 1);
+    }
+
+    Ok(())
+}
+
 pub fn random(ctx: Context) -> Result<()> {
     let to_incl = ctx.prg.data_stack.pop()?.into_int()?;
     let from_incl = ctx.prg.data_stack.pop()?.into_int()?;
@@ -1117,12 +1448,62 @@ pub fn reg_anim_func(ctx: Context) -> Result<()> {
     Ok(())
 }
 
-pub fn rm_timer_event(ctx: Context) -> Result<()> {
+pub fn rm_mult_objs_from_inven(ctx: Context) -> Result<()> {
+    let count = ctx.prg.data_stack.pop()?.into_int()?;
+    let item = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let owner = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+
+    let objs = ctx.ext.world.objects_mut();
+    let stack_count = objs.get(owner).inventory.items.iter()
+        .find(|i| i.object == item)
+        .map(|i| i.count)
+        .unwrap_or(0);
+    let r = cmp::min(cmp::max(count, 0) as u32, stack_count);
+    if r > 0 {
+        let removed = objs.remove_from_inventory(owner, item, r).unwrap();
+        if removed != item {
+            // The script keeps referring to the original object which is still in the inventory.
+            objs.remove(removed);
+        }
+    }
+    ctx.prg.data_stack.push((r as i32).into())?;
+
+    log_a3r1!(ctx.prg, owner, item, count, r);
+
+    Ok(())
+}
+
+pub fn rm_obj_from_inven(ctx: Context) -> Result<()> {
+    let item = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    let owner = ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))?;
+    log_a2!(ctx.prg, owner, item);
+
+    let objs = ctx.ext.world.objects_mut();
+    match objs.remove_from_inventory(owner, item, 1) {
+        Some(removed) if removed != item => {
+            // The script keeps referring to the original object which is still in the inventory.
+            objs.remove(removed);
+        }
+        Some(_) => {}
+        None => {
+            log_error!(ctx.prg, "item is not in the inventory");
+        }
+    }
+
+    Ok(())
+}
+
+pub fn rm_timer_event(mut ctx: Context) -> Result<()> {
     let obj = ctx.prg.data_stack.pop()?.coerce_into_object()?
         .ok_or(Error::BadValue(BadValue::Content))?;
 
+    remove_timer_events(&mut ctx, obj, |_| true);
+
     log_a1!(ctx.prg, obj);
-    log_stub!(ctx.prg);
 
     Ok(())
 }
@@ -1179,6 +1560,15 @@ pub fn start_gdialog(mut ctx: Context) -> Result<()> {
     Ok(())
 }
 
+pub fn terminate_combat(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+    ctx.ext.combat.request_end();
+    if let Some(obj) = ctx.ext.self_obj {
+        ctx.ext.combat.stop_attacking(obj);
+    }
+    Ok(())
+}
+
 pub fn tile_contains_pid_obj(ctx: Context) -> Result<()> {
     let pid = ctx.prg.data_stack.pop()?.into_int()?;
     let pid = ProtoId::from_packed(pid as u32)
@@ -1350,3 +1740,292 @@ pub fn tile_num_in_direction(ctx: Context) -> Result<()> {
 
     Ok(())
 }
+
+pub fn wm_area_set_pos(ctx: Context) -> Result<()> {
+    let y = ctx.prg.data_stack.pop()?.into_int()?;
+    let x = ctx.prg.data_stack.pop()?.into_int()?;
+    let area = ctx.prg.data_stack.pop()?.into_int()?;
+
+    log_a3!(ctx.prg, area, x, y);
+
+    if area < 0 || !ctx.ext.world_map.set_area_pos(area as usize, Point::new(x, y)) {
+        log_error!(ctx.prg, "bad area");
+    }
+
+    Ok(())
+}
+
+pub fn world_map(ctx: Context) -> Result<()> {
+    log_!(ctx.prg);
+    ctx.ext.world_map.request_show();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use bstring::BString;
+    use std::rc::Rc;
+    use crate::asset::message::BULLET_STR;
+    use crate::asset::proto::CritterFlag;
+    use crate::asset::script::ProgramId;
+    use crate::asset::worldmap;
+    use crate::game::dialog::Dialog;
+    use crate::game::combat::AttackOptions;
+    use crate::game::object::EquipmentSlot;
+    use crate::game::worldmap::{MarkState, WorldMap};
+    use crate::graphics::{EPoint, Point};
+    use crate::graphics::geometry::hex::{self, Direction};
+    use crate::vm::instruction::Opcode::*;
+    use crate::vm::test::*;
+
+    #[test]
+    fn objects() {
+        let mut h = Harness::new();
+        let dude_pos = EPoint::new(1, Point::new(10, 20));
+        h.new_dude(dude_pos);
+        let key = h.new_object(PID_KEY, Some(EPoint::new(1, Point::new(13, 20))));
+        h.self_obj = Some(key);
+
+        let dude_tile = h.world.hex_grid().to_linear_inv(dude_pos.point).unwrap() as i32;
+        let dist = hex::distance(Point::new(10, 20), Point::new(13, 20)) as i32;
+        assert_eq!(h.eval(Asm::new()
+                .op(DudeObj).op(ObjPid)
+                .op(SelfObj).op(ObjPid)
+                .op(DudeObj).op(TileNum)
+                .op(SelfObj).op(Elevation)
+                .op(DudeObj).op(SelfObj).op(TileDistanceObjs)).unwrap(),
+            vec![(PID_CRITTER as i32).into(), (PID_KEY as i32).into(), dude_tile.into(),
+                1.into(), dist.into()]);
+    }
+
+    #[test]
+    fn tiles() {
+        let mut h = Harness::new();
+        let pos = EPoint::new(0, Point::new(50, 60));
+        h.new_object(PID_MISC_ITEM, Some(pos));
+        let tile = h.world.hex_grid().to_linear_inv(pos.point).unwrap() as i32;
+        let expected_tile = h.world.hex_grid().go(pos.point, Direction::E, 3)
+            .and_then(|p| h.world.hex_grid().to_linear_inv(p))
+            .unwrap() as i32;
+
+        assert_eq!(h.eval(Asm::new()
+                .ints(&[tile, 0, PID_MISC_ITEM as i32]).op(TileContainsPidObj)
+                .ints(&[tile, 0, PID_KEY as i32]).op(TileContainsPidObj)
+                .ints(&[tile, 1, PID_MISC_ITEM as i32]).op(TileContainsPidObj)
+                .ints(&[tile, Direction::E as i32, 3]).op(TileNumInDirection)).unwrap(),
+            vec![1.into(), 0.into(), 0.into(), expected_tile.into()]);
+    }
+
+    #[test]
+    fn messages() {
+        let mut h = Harness::new();
+        h.set_script_messages(PROGRAM_OBJ, "{100}{}{Hello}\n{101}{}{World}\n");
+        assert_eq!(h.eval1(Asm::new()
+                .ints(&[PROGRAM_OBJ as i32, 101]).op(MessageStr)).unwrap(),
+            "World".into());
+
+        h.eval(Asm::new()
+            .ints(&[PROGRAM_OBJ as i32, 100]).op(MessageStr).op(DisplayMsg)
+            .string("direct").op(DisplayMsg)).unwrap();
+        let bullet = |s: &str| BString::from([BULLET_STR, s.as_bytes()].concat());
+        assert_eq!(h.panel_messages(), vec![bullet("Hello"), bullet("direct")]);
+    }
+
+    #[test]
+    fn combat() {
+        let mut h = Harness::new();
+        let dude = h.new_dude(EPoint::new(0, Point::new(10, 20)));
+        let critter = h.new_object(PID_CRITTER, Some(EPoint::new(0, Point::new(12, 20))));
+        h.self_obj = Some(critter);
+
+        assert_eq!(h.eval1(Asm::new().op(CombatIsInitialized)).unwrap(), 0.into());
+
+        h.eval(Asm::new()
+            .op(DudeObj).ints(&[0, 1, 10, 2, 5, 0, 0]).op(Attack)).unwrap();
+        let requests = h.combat.take_attack_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].attacker, critter);
+        assert_eq!(requests[0].target, dude);
+        assert_eq!(requests[0].options, AttackOptions {
+            to_hit_bonus: 10,
+            min_damage: 2,
+            max_damage: 5,
+        });
+
+        h.eval(Asm::new().op(SelfObj).op(DudeObj).op(AttackSetup)).unwrap();
+        assert_eq!(h.combat.take_attack_requests()[0].options, AttackOptions::default());
+
+        // Requests don't survive the map change.
+        h.eval(Asm::new().op(SelfObj).op(DudeObj).op(AttackSetup)).unwrap();
+        h.combat.clear();
+        assert!(!h.combat.has_attack_requests());
+
+        h.combat.start(&h.rpg, h.world.objects(), Some(critter), vec![dude, critter]);
+        assert_eq!(h.eval1(Asm::new().op(CombatIsInitialized)).unwrap(), 1.into());
+
+        // In combat the target is set immediately.
+        h.eval(Asm::new().op(SelfObj).op(DudeObj).op(AttackSetup)).unwrap();
+        assert!(!h.combat.has_attack_requests());
+        assert_eq!(h.combat.target(critter), Some(dude));
+
+        h.eval(Asm::new().op(SelfObj).op(CritterStopAttacking)).unwrap();
+        assert_eq!(h.combat.target(critter), None);
+
+        h.eval(Asm::new().op(TerminateCombat)).unwrap();
+        assert!(h.combat.take_end_request());
+    }
+
+    #[test]
+    fn inventory() {
+        let mut h = Harness::new();
+        let pos = |x| EPoint::new(0, Point::new(x, 20));
+        let dude = h.new_dude(pos(10));
+        let critter = h.new_object(PID_CRITTER, Some(pos(14)));
+        let item = h.new_object(PID_MISC_ITEM, Some(pos(11)));
+        let item2 = h.new_object(PID_MISC_ITEM, Some(pos(12)));
+        let weapon = h.new_object(PID_WEAPON, Some(pos(13)));
+
+        h.self_obj = Some(item);
+        h.eval(Asm::new().op(DudeObj).op(SelfObj).op(AddObjToInven)).unwrap();
+        h.self_obj = Some(item2);
+        h.eval(Asm::new().op(DudeObj).op(SelfObj).int(2).op(AddMultObjsToInven)).unwrap();
+
+        // Identical items are stacked.
+        assert!(!h.world.objects().contains(item2));
+        assert_eq!(h.world.objects().get(item).pos, None);
+        assert_eq!(h.eval(Asm::new()
+                .op(DudeObj).int(PID_MISC_ITEM as i32).op(ObjIsCarryingObjPid)
+                .op(DudeObj).ints(&[13, 0]).op(InvenCmds)
+                .op(DudeObj).ints(&[13, 1]).op(InvenCmds)).unwrap(),
+            vec![3.into(), Some(item).into(), None.into()]);
+
+        h.self_obj = Some(dude);
+        h.eval(Asm::new()
+            .op(DudeObj).ints(&[13, 0]).op(InvenCmds).op(DropObj)
+            .op(DudeObj).op(DudeObj).ints(&[13, 0]).op(InvenCmds).op(RmObjFromInven)).unwrap();
+        assert_eq!(h.world.objects().get(dude).inventory.items[0].count, 1);
+        let dropped = h.world.objects().at(pos(10)).iter()
+            .filter(|&&o| o != dude)
+            .count();
+        assert_eq!(dropped, 1);
+
+        h.self_obj = Some(weapon);
+        h.eval(Asm::new().op(DudeObj).op(SelfObj).op(AddObjToInven)).unwrap();
+        h.world.objects_mut().equip(dude, weapon, EquipmentSlot::RightHand);
+        assert_eq!(h.eval(Asm::new()
+                .op(DudeObj).int(1).op(CritterInvenObj)
+                .op(DudeObj).int(-2).op(CritterInvenObj)).unwrap(),
+            vec![Some(weapon).into(), 2.into()]);
+
+        h.self_obj = Some(dude);
+        h.eval(Asm::new().op(InvenUnwield)).unwrap();
+        assert_eq!(h.world.objects().get(dude).in_right_hand(h.world.objects()), None);
+
+        h.self_obj = Some(critter);
+        h.eval(Asm::new().op(DudeObj).op(SelfObj).op(MoveObjInvenToObj)).unwrap();
+        assert!(h.world.objects().get(dude).inventory.items.is_empty());
+        assert_eq!(h.world.objects().get(critter).inventory.items.len(), 2);
+    }
+
+    #[test]
+    fn add_to_inven_checks() {
+        let mut h = Harness::new();
+        let pos = |x| EPoint::new(0, Point::new(x, 20));
+        let dude = h.new_dude(pos(10));
+        let critter = h.new_object(PID_CRITTER, Some(pos(14)));
+        let item = h.new_object(PID_MISC_ITEM, Some(pos(11)));
+
+        h.self_obj = Some(item);
+        h.eval(Asm::new()
+            .op(DudeObj).op(SelfObj).op(AddObjToInven)
+            // Already owned by the dude, in the same or another inventory.
+            .op(DudeObj).op(SelfObj).op(AddObjToInven)
+            .op(DudeObj).op(SelfObj).int(2).op(AddMultObjsToInven)).unwrap();
+        h.self_obj = Some(critter);
+        h.eval(Asm::new()
+            .op(SelfObj).op(DudeObj).ints(&[13, 0]).op(InvenCmds).op(AddObjToInven)
+            .op(DudeObj).ints(&[13, 0]).op(InvenCmds).op(PickupObj)
+            // Into itself.
+            .op(SelfObj).op(SelfObj).op(AddObjToInven)
+            .op(SelfObj).op(SelfObj).int(1).op(AddMultObjsToInven)).unwrap();
+
+        let objs = h.world.objects();
+        assert_eq!(objs.get(dude).inventory.items.len(), 1);
+        assert_eq!(objs.get(dude).inventory.items[0].count, 1);
+        assert_eq!(objs.owner(item), Some(dude));
+        assert!(objs.get(critter).inventory.items.is_empty());
+        assert_eq!(objs.owner(critter), None);
+    }
+
+    #[test]
+    fn world_map() {
+        let mut h = Harness::new();
+        let mut db = worldmap::test::new_db();
+        db.resolve_map_ids(|name| if name == "Arroyo Temple Area" { Some(2) } else { None });
+        h.world_map = WorldMap::new(Rc::new(db));
+        h.world_map.set_pos(Point::new(173, 122));
+
+        h.eval(Asm::new()
+            .ints(&[0, 1, 1]).op(MarkAreaKnown)
+            .ints(&[1, 2, 0]).op(MarkAreaKnown)
+            .ints(&[1, 3, 0]).op(MarkAreaKnown)
+            .ints(&[1, 600, 200]).op(WmAreaSetPos)
+            .ints(&[101, 600, 40, 0]).op(Metarule3)
+            .op(WorldMap)).unwrap();
+        assert_eq!(h.world_map.area_pos(1), Some(Point::new(600, 200)));
+        assert_eq!(h.world_map.area_state(0), Some(MarkState::Visited));
+        assert!(h.world_map.take_show_request());
+
+        assert_eq!(h.eval(Asm::new()
+                .ints(&[17, 1]).op(Metarule)
+                .ints(&[44, 0]).op(Metarule)
+                .ints(&[45, 0]).op(Metarule)
+                .ints(&[46, 0]).op(Metarule)
+                .ints(&[105, 173, 122, 0]).op(Metarule3)
+                .ints(&[105, 600, 40, 0]).op(Metarule3)
+                .ints(&[105, 600, 200, 0]).op(Metarule3)).unwrap(),
+            vec![1.into(), 173.into(), 122.into(), (-1).into(), 2.into(), 1.into(), 0.into()]);
+
+        h.world_map.set_current_map("Arroyo Village");
+        assert_eq!(h.eval(Asm::new()
+                .ints(&[30, 0]).op(Metarule)
+                .ints(&[31, 0]).op(Metarule)
+                .ints(&[30, 0]).op(Metarule)).unwrap(),
+            vec![(-1).into(), 0.into(), 0.into()]);
+        assert!(h.world_map.has_car());
+    }
+
+    #[test]
+    fn barter() {
+        let mut h = Harness::new();
+        h.new_dude(EPoint::new(0, Point::new(10, 20)));
+        let critter = h.new_object(PID_CRITTER, Some(EPoint::new(0, Point::new(12, 20))));
+        h.self_obj = Some(critter);
+
+        assert_eq!(h.eval(Asm::new()
+                .int(50).op(SelfObj).op(Metarule)
+                .op(GdialogBarter)).unwrap(),
+            vec![0.into(), (-1).into()]);
+
+        h.fx.proto(PID_CRITTER).borrow_mut().sub.as_critter_mut().unwrap()
+            .flags.insert(CritterFlag::Barter);
+        h.world.objects_mut().get_mut(critter).script =
+            Some((h.sid, ProgramId::new(PROGRAM_OBJ).unwrap()));
+        h.dialog = Some(Dialog::show(&mut h.ui, &mut h.world, critter));
+        assert_eq!(h.eval(Asm::new()
+                .int(50).op(SelfObj).op(Metarule)
+                .op(GdialogBarter)).unwrap(),
+            vec![1.into(), 0.into()]);
+        assert!(h.dialog.as_mut().unwrap().start_requested_barter(&mut h.ui, &mut h.world));
+        assert!(h.dialog.as_ref().unwrap().is_bartering());
+    }
+
+    #[test]
+    fn context_params() {
+        let mut h = Harness::new();
+        h.fixed_param = 7;
+        h.map_id = 3;
+        assert_eq!(h.eval(Asm::new().op(FixedParam).op(CurMapIndex)).unwrap(),
+            vec![7.into(), 3.into()]);
+    }
+}
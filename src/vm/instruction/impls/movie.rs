@@ -0,0 +1,90 @@
+//! Movie playback instructions. The movies are played by `state::movie::MovieState` after the
+//! script procedure returns.
+
+use crate::state::movie::MovieRequest;
+
+use super::*;
+
+/// Game movies by the ID used in `play_gmovie`.
+const GAME_MOVIES: &[&str] = &[
+    "iplogo",
+    "intro",
+    "elder",
+    "vsuit",
+    "afailed",
+    "adestroy",
+    "car",
+    "cartucci",
+    "timeout",
+    "tanker",
+    "enclave",
+    "derrick",
+    "artimer1",
+    "artimer2",
+    "artimer3",
+    "artimer4",
+    "credits",
+];
+
+/// ID of the movie played after the endgame slides.
+const ENDGAME_MOVIE: usize = 16;
+
+fn game_movie_path(id: usize) -> String {
+    format!("art/cuts/{}.mve", GAME_MOVIES[id])
+}
+
+// TODO The endgame slideshow and narration are not implemented, only the final movie is played.
+pub fn endgame_movie(ctx: Context) -> Result<()> {
+    *ctx.ext.movie = Some(MovieRequest::Play(game_movie_path(ENDGAME_MOVIE)));
+    log_!(ctx.prg);
+    Ok(())
+}
+
+pub fn play_gmovie(ctx: Context) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    log_a1!(ctx.prg, id);
+    if id < 0 || id as usize >= GAME_MOVIES.len() {
+        return Err(Error::BadValue(BadValue::Content));
+    }
+    *ctx.ext.movie = Some(MovieRequest::Play(game_movie_path(id as usize)));
+    Ok(())
+}
+
+pub fn playmovie(ctx: Context) -> Result<()> {
+    let path = ctx.prg.data_stack.pop()?.coerce_into_string(ctx.prg.strings())?;
+    log_a1!(ctx.prg, path);
+    *ctx.ext.movie = Some(MovieRequest::Play(path.display().to_string()));
+    Ok(())
+}
+
+pub fn stopmovie(ctx: Context) -> Result<()> {
+    *ctx.ext.movie = Some(MovieRequest::Stop);
+    log_!(ctx.prg);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::movie::MovieRequest;
+    use crate::vm::instruction::Opcode::*;
+    use crate::vm::test::*;
+
+    #[test]
+    fn requests() {
+        let mut h = Harness::new();
+        h.eval(Asm::new().int(1).op(PlayGmovie)).unwrap();
+        assert_eq!(h.movie.take(), Some(MovieRequest::Play("art/cuts/intro.mve".into())));
+
+        h.eval(Asm::new().string("art/cuts/boil1.mve").op(Playmovie)).unwrap();
+        assert_eq!(h.movie.take(), Some(MovieRequest::Play("art/cuts/boil1.mve".into())));
+
+        h.eval(Asm::new().op(EndgameMovie)).unwrap();
+        assert_eq!(h.movie.take(), Some(MovieRequest::Play("art/cuts/credits.mve".into())));
+
+        h.eval(Asm::new().op(Stopmovie)).unwrap();
+        assert_eq!(h.movie.take(), Some(MovieRequest::Stop));
+
+        assert!(h.eval(Asm::new().int(17).op(PlayGmovie)).is_err());
+        assert_eq!(h.movie, None);
+    }
+}
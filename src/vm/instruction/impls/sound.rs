@@ -0,0 +1,244 @@
+//! Sound playback and sound effect name instructions. See `audio` module for details.
+
+use num_traits::FromPrimitive;
+
+use crate::asset::{CritterAnim, DamageKind, EntityKind, WeaponKind};
+use crate::asset::proto::SubProto;
+use crate::audio::sfx::{self, CharSfx, SceneryAction, WeaponSfx};
+
+use super::*;
+
+/// `soundplay` flag that makes the sound loop.
+const SOUND_LOOP: i32 = 0x1;
+
+fn pop_string(ctx: &mut Context) -> Result<String> {
+    let s = ctx.prg.data_stack.pop()?.coerce_into_string(ctx.prg.strings())?;
+    Ok(s.display().to_string())
+}
+
+fn pop_enum<T: FromPrimitive>(ctx: &mut Context) -> Result<T> {
+    let v = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    T::from_i32(v).ok_or(Error::BadValue(BadValue::Content))
+}
+
+fn pop_object(ctx: &mut Context) -> Result<object::Handle> {
+    ctx.prg.data_stack.pop()?.coerce_into_object()?
+        .ok_or(Error::BadValue(BadValue::Content))
+}
+
+fn push_name(ctx: &mut Context, name: String) -> Result<()> {
+    ctx.prg.data_stack.push(Rc::new(BString::from(name)).into())
+}
+
+fn sound_op(ctx: Context, f: impl FnOnce(&mut crate::audio::Audio, i32) -> bool) -> Result<()> {
+    let id = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let found = f(ctx.ext.audio, id);
+    log_a1!(ctx.prg, id);
+    if !found {
+        log_error!(ctx.prg, format!("sound {} not found", id));
+    }
+    Ok(())
+}
+
+fn script_sound_op(ctx: Context,
+    f: impl FnOnce(&mut crate::audio::mixer::Mixer, crate::audio::mixer::SoundHandle) -> bool)
+    -> Result<()>
+{
+    sound_op(ctx, |audio, id| {
+        if let Some(sound) = audio.script_sound(id) {
+            f(audio.mixer_mut(), sound)
+        } else {
+            false
+        }
+    })
+}
+
+pub fn play_sfx(mut ctx: Context) -> Result<()> {
+    let name = pop_string(&mut ctx)?;
+    ctx.ext.audio.play_sfx(&name);
+    log_a1!(ctx.prg, name);
+    Ok(())
+}
+
+pub fn set_map_music(mut ctx: Context) -> Result<()> {
+    let name = pop_string(&mut ctx)?;
+    let map_id = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    log_a2!(ctx.prg, map_id, name);
+    if map_id < 0 {
+        log_error!(ctx.prg, "bad map ID");
+        return Ok(());
+    }
+    ctx.ext.audio.set_map_music(map_id as u32, name, Some(ctx.ext.map_id));
+    Ok(())
+}
+
+pub fn sfx_build_ambient_name(mut ctx: Context) -> Result<()> {
+    let name = pop_string(&mut ctx)?;
+    let r = sfx::ambient_name(&name);
+    log_a1r1!(ctx.prg, name, r);
+    push_name(&mut ctx, r)
+}
+
+pub fn sfx_build_char_name(mut ctx: Context) -> Result<()> {
+    let extra = pop_enum::<CharSfx>(&mut ctx)?;
+    let anim = pop_enum::<CritterAnim>(&mut ctx)?;
+    let obj = pop_object(&mut ctx)?;
+
+    let fid = ctx.ext.world.objects().get(obj).fid;
+    let r = if fid.kind() == EntityKind::Critter {
+        let weapon = fid.critter().map(|c| c.weapon()).unwrap_or(WeaponKind::Unarmed);
+        ctx.ext.world.frm_db().base_name(fid)
+            .and_then(|base| sfx::char_name(base, anim, weapon, extra))
+    } else {
+        None
+    };
+    log_a3r1!(ctx.prg, obj, anim, extra, r);
+    if r.is_none() {
+        log_error!(ctx.prg, format!("can't build sound effect name for {:?}", fid));
+    }
+    push_name(&mut ctx, r.unwrap_or_default())
+}
+
+pub fn sfx_build_interface_name(mut ctx: Context) -> Result<()> {
+    let name = pop_string(&mut ctx)?;
+    let r = sfx::interface_name(&name);
+    log_a1r1!(ctx.prg, name, r);
+    push_name(&mut ctx, r)
+}
+
+pub fn sfx_build_item_name(mut ctx: Context) -> Result<()> {
+    let name = pop_string(&mut ctx)?;
+    let r = sfx::item_name(&name);
+    log_a1r1!(ctx.prg, name, r);
+    push_name(&mut ctx, r)
+}
+
+pub fn sfx_build_open_name(mut ctx: Context) -> Result<()> {
+    let action = pop_enum::<SceneryAction>(&mut ctx)?;
+    let obj = pop_object(&mut ctx)?;
+
+    let r = {
+        let objs = ctx.ext.world.objects();
+        let obj = objs.get(obj);
+        let proto = obj.proto();
+        match proto.as_ref().map(|p| &p.sub) {
+            Some(SubProto::Scenery(s)) => sfx::open_name(true, action, s.sound_id),
+            Some(SubProto::Item(i)) => sfx::open_name(false, action, i.sound_id),
+            _ => return Err(Error::BadValue(BadValue::Content)),
+        }
+    };
+    log_a2r1!(ctx.prg, obj, action, r);
+    push_name(&mut ctx, r)
+}
+
+pub fn sfx_build_scenery_name(mut ctx: Context) -> Result<()> {
+    let action = pop_enum::<SceneryAction>(&mut ctx)?;
+    let passive = ctx.prg.data_stack.pop()?.coerce_into_int()? != 0;
+    let name = pop_string(&mut ctx)?;
+    let r = sfx::scenery_name(!passive, action, &name);
+    log_a3r1!(ctx.prg, name, passive, action, r);
+    push_name(&mut ctx, r)
+}
+
+pub fn sfx_build_weapon_name(mut ctx: Context) -> Result<()> {
+    let target = ctx.prg.data_stack.pop()?.coerce_into_object()?;
+    let hit_mode = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let weapon = pop_object(&mut ctx)?;
+    let effect = pop_enum::<WeaponSfx>(&mut ctx)?;
+
+    let r = {
+        let objs = ctx.ext.world.objects();
+        let weapon = objs.get(weapon);
+        let proto = weapon.proto();
+        let weapon_proto = proto.as_ref()
+            .and_then(|p| p.sub.as_item())
+            .and_then(|i| i.sub.as_weapon())
+            .ok_or(Error::BadValue(BadValue::Content))?;
+
+        // Explosions, plasma and EMP sound the same whatever they hit.
+        let target_material = match weapon_proto.damage_kind {
+            DamageKind::Explosion | DamageKind::Plasma | DamageKind::Emp => None,
+            _ => target.and_then(|t| {
+                let target = objs.get(t);
+                let proto = target.proto()?;
+                match &proto.sub {
+                    SubProto::Item(v) => Some(v.material),
+                    SubProto::Scenery(v) => Some(v.material),
+                    SubProto::Wall(v) => Some(v.material),
+                    _ => None,
+                }
+            }),
+        };
+
+        // Odd hit modes are secondary modes of the left and right hand weapons.
+        let secondary = hit_mode == 1 || hit_mode == 3;
+        sfx::weapon_name(effect, weapon_proto.sound_id, secondary, target_material)
+    };
+    log_a4r1!(ctx.prg, effect, weapon, hit_mode, target, r);
+    push_name(&mut ctx, r)
+}
+
+pub fn sounddelete(ctx: Context) -> Result<()> {
+    sound_op(ctx, |audio, id| audio.delete_script_sound(id))
+}
+
+pub fn soundpause(ctx: Context) -> Result<()> {
+    script_sound_op(ctx, |mixer, sound| mixer.set_paused(sound, true))
+}
+
+pub fn soundplay(mut ctx: Context) -> Result<()> {
+    let flags = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let name = pop_string(&mut ctx)?;
+    let r = ctx.ext.audio.play_script_sound(&name, flags & SOUND_LOOP != 0)
+        .unwrap_or(0);
+    ctx.prg.data_stack.push(r.into())?;
+    log_a2r1!(ctx.prg, name, flags, r);
+    Ok(())
+}
+
+pub fn soundresume(ctx: Context) -> Result<()> {
+    script_sound_op(ctx, |mixer, sound| mixer.set_paused(sound, false))
+}
+
+pub fn soundrewind(ctx: Context) -> Result<()> {
+    script_sound_op(ctx, |mixer, sound| mixer.rewind(sound))
+}
+
+pub fn soundstop(ctx: Context) -> Result<()> {
+    script_sound_op(ctx, |mixer, sound| mixer.stop(sound))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vm::instruction::Opcode::*;
+    use crate::vm::test::*;
+
+    #[test]
+    fn sfx_names() {
+        let mut h = Harness::new();
+        assert_eq!(h.eval(Asm::new()
+            .string("water").op(SfxBuildAmbientName)
+            .string("ib1p1").op(SfxBuildInterfaceName)
+            .string("cmp").int(1).int(4).op(SfxBuildSceneryName)).unwrap(),
+            vec!["A WATER1".into(), "N IB1P11".into(), "SPU CMP1".into()]);
+        assert!(h.eval(Asm::new().string("cmp").int(0).int(5).op(SfxBuildSceneryName)).is_err());
+    }
+
+    #[test]
+    fn sounds() {
+        let mut h = Harness::new();
+        assert_eq!(h.eval1(Asm::new().string("missing").int(1).op(Soundplay)).unwrap(),
+            0.into());
+
+        // Unknown sounds are not fatal.
+        h.eval(Asm::new()
+            .int(1).op(Soundpause)
+            .int(1).op(Soundresume)
+            .int(1).op(Soundrewind)
+            .int(1).op(Soundstop)
+            .int(1).op(Sounddelete)
+            .string("missing").op(PlaySfx)
+            .int(3).string("07desert").op(SetMapMusic)).unwrap();
+        assert_eq!(h.audio.music(), None);
+    }
+}
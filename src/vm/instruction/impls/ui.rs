@@ -0,0 +1,455 @@
+//! Window, button, region and "say" dialog instructions. See `game::script_ui` for details.
+
+use log::*;
+use std::cmp;
+
+use crate::game::script_ui::{self, ControlKind, SayTarget, SayWindowKind};
+use crate::graphics::{Point, Rect};
+use crate::graphics::color::Rgb15;
+use crate::ui::button::Trigger;
+
+use super::*;
+
+fn pop_name(ctx: &mut Context) -> Result<Rc<BString>> {
+    ctx.prg.data_stack.pop()?.coerce_into_string(ctx.prg.strings())
+}
+
+/// Pops string that can also be `0` meaning no value.
+fn pop_opt_string(ctx: &mut Context) -> Result<Option<Rc<BString>>> {
+    let v = ctx.prg.data_stack.pop()?;
+    Ok(if v == Value::Int(0) {
+        None
+    } else {
+        Some(v.into_string(ctx.prg.strings())?)
+    })
+}
+
+/// Pops color as three floats in range [0..1].
+fn pop_color(ctx: &mut Context) -> Result<Rgb15> {
+    let mut c = [0; 3];
+    for c in c.iter_mut().rev() {
+        let v = ctx.prg.data_stack.pop()?.coerce_into_float()?;
+        *c = (v.clamp(0.0, 1.0) * 31.0).round() as u8;
+    }
+    Ok(Rgb15::new(c[0], c[1], c[2]))
+}
+
+fn pop_rect(ctx: &mut Context) -> Result<Rect> {
+    let height = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let width = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let y = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let x = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    Ok(Rect::with_size(x, y, width, height))
+}
+
+/// Pops procedure given by its index or name. Index `0` means no procedure.
+fn pop_proc(ctx: &mut Context) -> Result<Option<ProcedureId>> {
+    let v = ctx.prg.data_stack.pop()?;
+    match v {
+        Value::Int(0) => Ok(None),
+        Value::Int(id) => {
+            let id = id as ProcedureId;
+            ctx.prg.program().proc(id).ok_or(Error::BadProcedureId(id))?;
+            Ok(Some(id))
+        }
+        Value::String(_) => {
+            let name = v.into_string(ctx.prg.strings())?;
+            ctx.prg.program().proc_id(&name)
+                .map(Some)
+                .ok_or(Error::BadProcedure(name))
+        }
+        _ => Err(Error::BadValue(BadValue::Type)),
+    }
+}
+
+fn check(found: bool, what: &str, name: &bstr) -> Result<()> {
+    if found {
+        Ok(())
+    } else {
+        Err(Error::Misc(format!("{} `{}` not found", what, name.display()).into()))
+    }
+}
+
+fn check_window(found: bool) -> Result<()> {
+    if found {
+        Ok(())
+    } else {
+        Err(Error::BadState("no window selected".into()))
+    }
+}
+
+fn check_say(ok: bool) -> Result<()> {
+    if ok {
+        Ok(())
+    } else {
+        Err(Error::BadState("no say dialog started or it is already running".into()))
+    }
+}
+
+fn add_control_procs(mut ctx: Context, kind: ControlKind, triggers: &[Trigger]) -> Result<()> {
+    let mut procs = Vec::with_capacity(triggers.len());
+    for &trigger in triggers.iter().rev() {
+        procs.push((trigger, pop_proc(&mut ctx)?));
+    }
+    let name = pop_name(&mut ctx)?;
+    let found = ctx.ext.script_ui.set_control_procs(ctx.ext.ui, kind, &name, ctx.ext.sid, &procs);
+    log_a2!(ctx.prg, name, procs);
+    check(found, "control", &name)
+}
+
+fn add_control_flag(mut ctx: Context, kind: ControlKind) -> Result<()> {
+    let flag = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let name = pop_name(&mut ctx)?;
+    let flags = ctx.ext.script_ui.control_flags(kind, &name).unwrap_or(0);
+    let found = ctx.ext.script_ui.set_control_flags(kind, &name, flags | flag);
+    log_a2!(ctx.prg, name, flag);
+    check(found, "control", &name)
+}
+
+fn delete_control(mut ctx: Context, kind: ControlKind) -> Result<()> {
+    let name = pop_name(&mut ctx)?;
+    let found = ctx.ext.script_ui.delete_control(ctx.ext.ui, kind, &name);
+    log_a1!(ctx.prg, name);
+    check(found, "control", &name)
+}
+
+fn say_window(mut ctx: Context, kind: SayWindowKind) -> Result<()> {
+    let background = pop_opt_string(&mut ctx)?;
+    let rect = pop_rect(&mut ctx)?;
+    let fid = background.as_ref().and_then(|bg| script_ui::interface_fid(ctx.ext.ui, bg));
+    if background.is_some() && fid.is_none() {
+        warn!("say window background not found: {:?}", background);
+    }
+    ctx.ext.script_ui.set_say_window(kind, rect, fid);
+    log_a2!(ctx.prg, rect, background);
+    Ok(())
+}
+
+pub fn addbutton(mut ctx: Context) -> Result<()> {
+    let rect = pop_rect(&mut ctx)?;
+    let name = pop_name(&mut ctx)?;
+    let found = ctx.ext.script_ui.add_button(ctx.ext.ui, (*name).clone(), rect, ctx.ext.sid);
+    log_a2!(ctx.prg, name, rect);
+    check_window(found)
+}
+
+pub fn addbuttonflag(ctx: Context) -> Result<()> {
+    add_control_flag(ctx, ControlKind::Button)
+}
+
+pub fn addbuttongfx(mut ctx: Context) -> Result<()> {
+    let _hover = pop_opt_string(&mut ctx)?;
+    let up = pop_opt_string(&mut ctx)?;
+    let down = pop_opt_string(&mut ctx)?;
+    let name = pop_name(&mut ctx)?;
+    let up_fid = up.as_ref().and_then(|f| script_ui::interface_fid(ctx.ext.ui, f));
+    let down_fid = down.as_ref().and_then(|f| script_ui::interface_fid(ctx.ext.ui, f));
+    let found = ctx.ext.script_ui.set_button_gfx(ctx.ext.ui, &name, up_fid, down_fid);
+    log_a3!(ctx.prg, name, down, up);
+    check(found, "button", &name)
+}
+
+pub fn addbuttonproc(ctx: Context) -> Result<()> {
+    add_control_procs(ctx, ControlKind::Button,
+        &[Trigger::Enter, Trigger::Leave, Trigger::Press, Trigger::Release])
+}
+
+pub fn addbuttonrightproc(ctx: Context) -> Result<()> {
+    add_control_procs(ctx, ControlKind::Button, &[Trigger::RightPress, Trigger::RightRelease])
+}
+
+pub fn addbuttontext(mut ctx: Context) -> Result<()> {
+    let text = pop_name(&mut ctx)?;
+    let name = pop_name(&mut ctx)?;
+    let found = ctx.ext.script_ui.set_button_text(ctx.ext.ui, &name, (*text).clone());
+    log_a2!(ctx.prg, name, text);
+    check(found, "button", &name)
+}
+
+pub fn addregion(mut ctx: Context) -> Result<()> {
+    let arg_count = ctx.prg.data_stack.pop()?.into_int()?;
+    if arg_count < 3 || arg_count % 2 == 0 {
+        return Err(Error::BadValue(BadValue::Content));
+    }
+    let mut points = Vec::with_capacity(arg_count as usize / 2);
+    for _ in 0..arg_count / 2 {
+        let y = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+        let x = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+        points.push(Point::new(x, y));
+    }
+    points.reverse();
+    let name = pop_name(&mut ctx)?;
+    let found = ctx.ext.script_ui.add_region(ctx.ext.ui, (*name).clone(), &points, ctx.ext.sid);
+    log_a2!(ctx.prg, name, points);
+    check_window(found)
+}
+
+pub fn addregionflag(ctx: Context) -> Result<()> {
+    add_control_flag(ctx, ControlKind::Region)
+}
+
+pub fn addregionproc(ctx: Context) -> Result<()> {
+    add_control_procs(ctx, ControlKind::Region,
+        &[Trigger::Enter, Trigger::Leave, Trigger::Press, Trigger::Release])
+}
+
+pub fn addregionrightproc(ctx: Context) -> Result<()> {
+    add_control_procs(ctx, ControlKind::Region, &[Trigger::RightPress, Trigger::RightRelease])
+}
+
+pub fn createwin(mut ctx: Context) -> Result<()> {
+    let rect = pop_rect(&mut ctx)?;
+    let name = pop_name(&mut ctx)?;
+    ctx.ext.script_ui.create_window(ctx.ext.ui, (*name).clone(), rect);
+    log_a2!(ctx.prg, name, rect);
+    Ok(())
+}
+
+pub fn deletebutton(ctx: Context) -> Result<()> {
+    delete_control(ctx, ControlKind::Button)
+}
+
+pub fn deleteregion(ctx: Context) -> Result<()> {
+    delete_control(ctx, ControlKind::Region)
+}
+
+pub fn deletewin(mut ctx: Context) -> Result<()> {
+    let name = pop_name(&mut ctx)?;
+    let found = ctx.ext.script_ui.delete_window(ctx.ext.ui, &name);
+    log_a1!(ctx.prg, name);
+    check(found, "window", &name)
+}
+
+pub fn display(mut ctx: Context) -> Result<()> {
+    let path = pop_name(&mut ctx)?;
+    let found = if let Some(fid) = script_ui::interface_fid(ctx.ext.ui, &path) {
+        ctx.ext.script_ui.display(ctx.ext.ui, fid)
+    } else {
+        warn!("display: image not found: {}", path.display());
+        true
+    };
+    log_a1!(ctx.prg, path);
+    check_window(found)
+}
+
+pub fn fillrect(mut ctx: Context) -> Result<()> {
+    let color = pop_color(&mut ctx)?;
+    let rect = pop_rect(&mut ctx)?;
+    let found = ctx.ext.script_ui.fill(ctx.ext.ui, Some(rect), color);
+    log_a2!(ctx.prg, rect, color);
+    check_window(found)
+}
+
+pub fn fillwin(mut ctx: Context) -> Result<()> {
+    let color = pop_color(&mut ctx)?;
+    let found = ctx.ext.script_ui.fill(ctx.ext.ui, None, color);
+    log_a1!(ctx.prg, color);
+    check_window(found)
+}
+
+pub fn gotoxy(ctx: Context) -> Result<()> {
+    let y = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    let x = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    ctx.ext.script_ui.set_text_pos(Point::new(x, y));
+    log_a2!(ctx.prg, x, y);
+    Ok(())
+}
+
+pub fn print(ctx: Context) -> Result<()> {
+    let text = ctx.prg.data_stack.pop()?.coerce_into_string(ctx.prg.strings())?;
+    let found = ctx.ext.script_ui.print(ctx.ext.ui, (*text).clone());
+    log_a1!(ctx.prg, text);
+    check_window(found)
+}
+
+pub fn sayend(ctx: Context) -> Result<Option<Suspend>> {
+    let shown = ctx.ext.script_ui.say_show(ctx.ext.ui);
+    log_!(ctx.prg);
+    Ok(if shown {
+        Some(Suspend::SayEnd)
+    } else {
+        None
+    })
+}
+
+pub fn saygotoreply(mut ctx: Context) -> Result<()> {
+    let name = pop_name(&mut ctx)?;
+    let found = ctx.ext.script_ui.say_goto_reply(ctx.ext.ui, &name);
+    log_a1!(ctx.prg, name);
+    check(found, "reply", &name)
+}
+
+pub fn saymessage(mut ctx: Context) -> Result<()> {
+    let text = pop_name(&mut ctx)?;
+    let name = pop_opt_string(&mut ctx)?;
+    let ok = ctx.ext.script_ui.say_reply(name.as_ref().map(|n| (**n).clone()), (*text).clone());
+    log_a2!(ctx.prg, name, text);
+    check_say(ok)
+}
+
+pub fn sayoption(mut ctx: Context) -> Result<()> {
+    let target = ctx.prg.data_stack.pop()?;
+    let target = match target {
+        Value::String(_) => SayTarget::Reply((*target.into_string(ctx.prg.strings())?).clone()),
+        _ => {
+            ctx.prg.data_stack.push(target)?;
+            let proc_id = pop_proc(&mut ctx)?.ok_or(Error::BadValue(BadValue::Content))?;
+            SayTarget::Proc(proc_id)
+        }
+    };
+    let text = pop_name(&mut ctx)?;
+    let ok = ctx.ext.script_ui.say_option((*text).clone(), target.clone());
+    log_a2!(ctx.prg, text, target);
+    check_say(ok)
+}
+
+pub fn sayoptionwindow(ctx: Context) -> Result<()> {
+    say_window(ctx, SayWindowKind::Option)
+}
+
+pub fn sayquit(ctx: Context) -> Result<()> {
+    ctx.ext.script_ui.say_quit(ctx.ext.ui);
+    log_!(ctx.prg);
+    Ok(())
+}
+
+pub fn sayreply(mut ctx: Context) -> Result<()> {
+    let text = pop_name(&mut ctx)?;
+    let name = pop_opt_string(&mut ctx)?;
+    let ok = ctx.ext.script_ui.say_reply(name.as_ref().map(|n| (**n).clone()), (*text).clone());
+    log_a2!(ctx.prg, name, text);
+    check_say(ok)
+}
+
+pub fn sayreplytitle(mut ctx: Context) -> Result<()> {
+    let title = pop_opt_string(&mut ctx)?;
+    let ok = ctx.ext.script_ui.say_reply_title(title.as_ref().map(|t| (**t).clone()));
+    log_a1!(ctx.prg, title);
+    check_say(ok)
+}
+
+pub fn sayreplywindow(ctx: Context) -> Result<()> {
+    say_window(ctx, SayWindowKind::Reply)
+}
+
+pub fn saystart(ctx: Context) -> Result<()> {
+    let ok = ctx.ext.script_ui.say_start(ctx.ext.sid, None);
+    log_!(ctx.prg);
+    check_say(ok)
+}
+
+pub fn saystartpos(ctx: Context) -> Result<()> {
+    let pos = ctx.prg.data_stack.pop()?.into_int()?;
+    let ok = ctx.ext.script_ui.say_start(ctx.ext.sid, Some(cmp::max(pos, 0) as usize));
+    log_a1!(ctx.prg, pos);
+    check_say(ok)
+}
+
+pub fn selectwin(mut ctx: Context) -> Result<()> {
+    let name = pop_name(&mut ctx)?;
+    let found = ctx.ext.script_ui.select_window(&name);
+    log_a1!(ctx.prg, name);
+    check(found, "window", &name)
+}
+
+pub fn setfont(ctx: Context) -> Result<()> {
+    let font = ctx.prg.data_stack.pop()?.coerce_into_int()?;
+    if !ctx.ext.script_ui.set_font(ctx.ext.ui, font) {
+        warn!("setfont: font {} not found", font);
+    }
+    log_a1!(ctx.prg, font);
+    Ok(())
+}
+
+pub fn settextcolor(mut ctx: Context) -> Result<()> {
+    let color = pop_color(&mut ctx)?;
+    ctx.ext.script_ui.set_text_color(color);
+    log_a1!(ctx.prg, color);
+    Ok(())
+}
+
+pub fn showwin(ctx: Context) -> Result<()> {
+    // Windows are shown immediately on creation.
+    log_!(ctx.prg);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::game::script_ui::ControlKind;
+    use crate::graphics::Rect;
+    use crate::ui::button::Trigger;
+    use crate::vm::Suspend;
+    use crate::vm::instruction::Opcode::*;
+    use crate::vm::test::*;
+
+    #[test]
+    fn windows_and_controls() {
+        let mut h = Harness::new();
+        h.eval(Asm::new()
+            .string("win").ints(&[10, 20, 100, 50]).op(Createwin)
+            .ints(&[5, 5]).op(Gotoxy)
+            .string("hello").op(Print)
+            .string("btn").ints(&[5, 5, 20, 10]).op(Addbutton)
+            .string("btn").ints(&[0, 0, 0]).string("on_click").op(Addbuttonproc)
+            .string("btn").int(0).string("on_click").op(Addbuttonrightproc)
+            .string("rgn").ints(&[0, 0, 30, 0, 30, 40, 7]).op(Addregion)
+            .string("rgn").string("on_click").ints(&[0, 0, 0]).op(Addregionproc)
+            .op(ExitProg)
+            .proc("on_click", "on_click", 0)
+            .label("on_click")
+            .op(ExitProg)).unwrap();
+
+        let win = h.script_ui.window("win".into()).unwrap();
+        assert_eq!(h.ui.widget_base(win).borrow().rect(), Rect::with_size(10, 20, 100, 50));
+
+        let btn = h.script_ui.control(ControlKind::Button, "btn".into()).unwrap();
+        assert_eq!(h.script_ui.callback(btn, Trigger::Release), Some((h.sid, 0)));
+        assert_eq!(h.script_ui.callback(btn, Trigger::RightRelease), Some((h.sid, 0)));
+        assert_eq!(h.script_ui.callback(btn, Trigger::Press), None);
+
+        let rgn = h.script_ui.control(ControlKind::Region, "rgn".into()).unwrap();
+        assert_eq!(h.ui.widget_base(rgn).borrow().rect(), Rect::new(10, 20, 40, 60));
+        assert_eq!(h.script_ui.callback(rgn, Trigger::Enter), Some((h.sid, 0)));
+        assert_eq!(h.script_ui.callback(rgn, Trigger::Release), None);
+
+        h.eval(Asm::new()
+            .string("win").op(Selectwin)
+            .string("btn").op(Deletebutton)).unwrap();
+        assert!(h.script_ui.control(ControlKind::Button, "btn".into()).is_none());
+
+        h.eval(Asm::new().string("win").op(Deletewin)).unwrap();
+        assert!(h.script_ui.window("win".into()).is_none());
+        assert!(h.eval(Asm::new().string("hello").op(Print)).is_err());
+    }
+
+    #[test]
+    fn say_suspends() {
+        let mut h = Harness::new();
+        let prg = h.load(Asm::new()
+            .proc("talk", "talk", 0)
+            .op(ExitProg)
+            .label("talk")
+            .op(Saystart)
+            .string("r1").string("Hello").op(Sayreply)
+            .string("Next").string("r2").op(Sayoption)
+            .string("r2").string("Bye").op(Sayreply)
+            .op(Sayend)
+            .op(ExitProg)
+            .assemble()).unwrap();
+        let r = h.execute_proc(prg, "talk").unwrap();
+        assert_eq!(r.suspend, Some(Suspend::SayEnd));
+
+        // Dialog without replies doesn't suspend.
+        let prg = h.load(Asm::new()
+            .proc("talk", "talk", 0)
+            .op(ExitProg)
+            .label("talk")
+            .op(Saystart)
+            .op(Sayend)
+            .op(ExitProg)
+            .assemble()).unwrap();
+        h.script_ui.clear(&mut h.ui);
+        h.execute_proc(prg, "talk").unwrap().assert_no_suspend();
+    }
+}
@@ -0,0 +1,355 @@
+use super::Opcode;
+use super::Opcode::*;
+
+impl Opcode {
+    /// Returns instruction name as used in scripts.
+    pub fn mnemonic(self) -> String {
+        match self {
+            AToD => return "a_to_d".into(),
+            Attack | Attack80dd => return "attack_complex".into(),
+            CriticalDone804b => return "critical_done".into(),
+            CriticalStart804a => return "critical_start".into(),
+            DToA => return "d_to_a".into(),
+            Fillwin3X3 => return "fillwin3x3".into(),
+            Noop8000 => return "noop".into(),
+            Noop80d1 => return "make_daytime".into(),
+            _ => {}
+        }
+        let name = format!("{:?}", self);
+        let mut r = String::with_capacity(name.len() + 4);
+        let mut prev_lower = false;
+        for c in name.chars() {
+            if c.is_ascii_uppercase() && prev_lower {
+                r.push('_');
+            }
+            prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+            r.push(c.to_ascii_lowercase());
+        }
+        r
+    }
+
+    /// Returns argument count and whether the instruction pushes a result for instructions that
+    /// behave like regular function calls.
+    pub fn signature(self) -> Option<(usize, bool)> {
+        Some(match self {
+            | ScriptOverrides
+            | Sayend
+            | Sayquit
+            | Sayrestart
+            | Saystart
+            | Showwin
+            | Stopmovie
+            | Hidemouse
+            | Showmouse
+            | Cancelall
+            | Noop80d1
+            | EndDialogue
+            | DialogueSystemEnter
+            | WorldMap
+            | GsayStart
+            | GsayEnd
+            | GameUiDisable
+            | GameUiEnable
+            | EndgameSlideshow
+            | EndgameMovie
+            | TerminateCombat
+            | Exit
+            | Detach
+            | ExitProg
+            | StopProg
+            => (0, false),
+
+            | Saygetlastpos
+            | SelfObj
+            | SourceObj
+            | TargetObj
+            | DudeObj
+            | ObjBeingUsedWith
+            | ScriptAction
+            | GameTime
+            | GameTimeInSeconds
+            | GameTimeHour
+            | FixedParam
+            | ActionBeingUsed
+            | CurMapIndex
+            | GetMonth
+            | GetDay
+            | DaysSinceVisited
+            | CombatIsInitialized
+            | DifficultyLevel
+            | RunningBurningGuy
+            | GameUiIsDisabled
+            | CombatDifficulty
+            => (0, true),
+
+            | Saystartpos
+            | Sayreplytitle
+            | Saygotoreply
+            | Saysetspacing
+            | Sayreplyflags
+            | Sayoptionflags
+            | Saymessagetimeout
+            | Deletewin
+            | Selectwin
+            | Fillwin3X3
+            | Display
+            | Displayraw
+            | Loadpalettetable
+            | Fadein
+            | Fadeout
+            | Print
+            | Setfont
+            | Settextflags
+            | Playmovie
+            | Movieflags
+            | Deleteregion
+            | Deletebutton
+            | Refreshmouse
+            | Setglobalmousefunc
+            | Clearnamed
+            | Signalnamed
+            | Deletekey
+            | Soundpause
+            | Soundresume
+            | Soundstop
+            | Soundrewind
+            | Sounddelete
+            | Setoneoptpause
+            | GiveExpPoints
+            | ScrReturn
+            | PlaySfx
+            | DisplayMsg
+            | AnimateStandObj
+            | AnimateStandReverseObj
+            | PickupObj
+            | DropObj
+            | UseObj
+            | DialogueReaction
+            | SetLightLevel
+            | RmTimerEvent
+            | DestroyObject
+            | GameTimeAdvance
+            | PlayGmovie
+            | PartyAdd
+            | PartyRemove
+            | GdialogBarter
+            | InvenUnwield
+            | ObjLock
+            | ObjUnlock
+            | ObjOpen
+            | ObjClose
+            | GfadeOut
+            | GfadeIn
+            | JamLock
+            | GdialogSetBarterMod
+            | DebugMsg
+            | CritterStopAttacking
+            | Wait
+            | Cancel
+            | Callstart
+            | Exec
+            | Spawn
+            | Fork
+            | ExportProc
+            | Dump
+            => (1, false),
+
+            | Checkregion
+            | ObjName
+            | GetPcStat
+            | IsSuccess
+            | IsCritical
+            | HowMuch
+            | LocalVar
+            | MapVar
+            | GlobalVar
+            | ObjType
+            | ObjItemSubtype
+            | TileNum
+            | AnimBusy
+            | Elevation
+            | GameTicks
+            | TileIsVisible
+            | CritterState
+            | ObjPid
+            | GetPoison
+            | ObjIsLocked
+            | ObjIsOpen
+            | ItemCapsTotal
+            | SfxBuildAmbientName
+            | SfxBuildInterfaceName
+            | SfxBuildItemName
+            | ObjArtFid
+            | ArtAnim
+            | PartyMemberObj
+            | ObjOnScreen
+            | CritterIsFleeing
+            | LookupStringProc
+            | Floor
+            => (1, true),
+
+            | Sayreply
+            | Sayoption
+            | Saymessage
+            | Sayborder
+            | Gotoxy
+            | Addregionflag
+            | Activateregion
+            | Addbuttontext
+            | Addbuttonflag
+            | Addnamedevent
+            | Addnamedhandler
+            | Addkey
+            | SetLocalVar
+            | SetMapVar
+            | SetGlobalVar
+            | AddObjToInven
+            | RmObjFromInven
+            | WieldObjCritter
+            | SetMapMusic
+            | SetObjVisibility
+            | LoadMap
+            | CritterHeal
+            | KillCritter
+            | KillCritterType
+            | RadiationInc
+            | RadiationDec
+            | RegAnimFunc
+            | GsayReply
+            | Poison
+            | RegAnimAnimateForever
+            | CritterInjure
+            | AttackSetup
+            | UseObjOnObj
+            | MoveObjInvenToObj
+            | CritterSetFleeState
+            | CallAt
+            | CallCondition
+            | CheckArgCount
+            => (2, false),
+
+            | Soundplay
+            | Selectfilelist
+            | SfxBuildOpenName
+            | HasSkill
+            | UsingSkill
+            | Random
+            | RollDice
+            | ObjIsCarryingObjPid
+            | GetCritterStat
+            | TileDistance
+            | TileDistanceObjs
+            | ObjCanSeeObj
+            | ObjCanHearObj
+            | ProtoData
+            | MessageStr
+            | CritterInvenObj
+            | Metarule
+            | ObjCarryingPidObj
+            | ItemCapsAdjust
+            | AnimActionFrame
+            | DestroyMultObjs
+            | RotationToTile
+            => (2, true),
+
+            | Sayoptioncolor
+            | Sayreplycolor
+            | Fillwin
+            | Printrect
+            | Settextcolor
+            | Sethighlightcolor
+            | Addregionrightproc
+            | Addbuttonrightproc
+            | Mouseshape
+            | MarkAreaKnown
+            | AnimateMoveObjToTile
+            | WmAreaSetPos
+            | CritterDamage
+            | AddTimerEvent
+            | ObjSetLightLevel
+            | FloatMsg
+            | Anim
+            | RegAnimAnimate
+            | RegAnimAnimateReverse
+            | RegAnimObjMoveToObj
+            | RegAnimObjRunToObj
+            | RegAnimObjMoveToTile
+            | RegAnimObjRunToTile
+            | AddMultObjsToInven
+            | Explosion
+            | GsayMessage
+            | RegAnimPlaySfx
+            => (3, false),
+
+            | Tokenize
+            | TileContainsPidObj
+            | RollVsSkill
+            | SkillContest
+            | DoCheck
+            | ReactionInfluence
+            | MoveTo
+            | TileContainsObjPid
+            | SetCritterStat
+            | TileNumInDirection
+            | HasTrait
+            | CritterAttemptPlacement
+            | InvenCmds
+            | RmMultObjsFromInven
+            | CritterModSkill
+            | SfxBuildCharName
+            | SfxBuildSceneryName
+            => (3, true),
+
+            | Addbuttongfx
+            | SetMapStart
+            | OverrideMapStart
+            | GsayOption
+            => (4, false),
+
+            | CreateObjectSid
+            | Metarule3
+            | CritterAddTrait
+            | CritterRmTrait
+            | SfxBuildWeaponName
+            => (4, true),
+
+            | Sayreplywindow
+            | Sayoptionwindow
+            | Createwin
+            | Resizewin
+            | Scalewin
+            | Displaygfx
+            | Playmovierect
+            | Addregionproc
+            | Addbutton
+            | Addbuttonproc
+            | StartGdialog
+            | SetExitGrids
+            | GiqOption
+            => (5, false),
+
+            TileInTileRect => (5, true),
+
+            Sayscrollup | Sayscrolldown | Format => (6, false),
+
+            Fillrect => (7, false),
+
+            Attack | Attack80dd => (8, false),
+
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mnemonic_() {
+        assert_eq!(GetCritterStat.mnemonic(), "get_critter_stat");
+        assert_eq!(Metarule3.mnemonic(), "metarule3");
+        assert_eq!(AToD.mnemonic(), "a_to_d");
+        assert_eq!(Fillwin3X3.mnemonic(), "fillwin3x3");
+    }
+}
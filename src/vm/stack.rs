@@ -37,6 +37,10 @@ impl<Id: StackId> Stack<Id> {
         self.vec.last()
     }
 
+    pub fn iter(&self) -> impl Iterator<Item=&Value> {
+        self.vec.iter()
+    }
+
     pub fn push(&mut self, value: Value) -> Result<()> {
         trace!("{}: pushing {:?}", Id::VALUE, value);
         if self.len() < self.max_len {
@@ -0,0 +1,385 @@
+//! Headless environment for unit testing programs and instructions without the game data
+//! and SDL window.
+
+use bstring::BString;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use super::*;
+use crate::asset::map::test::Fixture;
+use crate::asset::message::Messages;
+use crate::asset::script::ProgramId;
+use crate::asset::script::db::ScriptDb;
+use crate::asset::worldmap;
+use crate::audio::{self, Audio};
+use crate::audio::output::NullOutput;
+use crate::game::combat::Combat;
+use crate::game::dialog::Dialog;
+use crate::game::rpg::Rpg;
+use crate::game::script::{ScriptIid, ScriptKind, Scripts};
+use crate::game::script_ui::ScriptUi;
+use crate::game::sequence::ObjSequencer;
+use crate::game::world::World;
+use crate::game::worldmap::WorldMap;
+use crate::graphics::{EPoint, Rect};
+use crate::graphics::color::GREEN;
+use crate::graphics::font::{Font, FontKey, Fonts, Glyph};
+use crate::graphics::render::TextureFactory;
+use crate::graphics::geometry::hex;
+use crate::state::movie::MovieRequest;
+use crate::ui::{self, Ui};
+use crate::ui::message_panel::MessagePanel;
+use instruction::Opcode::{self, *};
+
+pub use crate::asset::map::test::*;
+
+/// Builds program bytecode.
+///
+/// The code starts executing at the first emitted instruction when the program is run with
+/// `Vm::run()`. Procedures declared with `proc()` can be invoked with `execute_proc()`.
+#[derive(Default)]
+pub struct Asm {
+    code: Vec<u8>,
+    names: StringTable,
+    strings: StringTable,
+    procs: Vec<(usize, &'static str, usize)>,
+    labels: HashMap<&'static str, usize>,
+    /// Positions of label references in `code`.
+    fixups: Vec<(usize, &'static str)>,
+}
+
+#[derive(Default)]
+struct StringTable {
+    data: Vec<u8>,
+    offsets: HashMap<String, usize>,
+}
+
+impl StringTable {
+    fn intern(&mut self, s: &str) -> usize {
+        if let Some(&offset) = self.offsets.get(s) {
+            return offset;
+        }
+        let len = s.len() + 1;
+        self.data.write_u16::<BigEndian>(len as u16).unwrap();
+        let offset = self.data.len() + 4;
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        self.offsets.insert(s.into(), offset);
+        offset
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        if self.data.is_empty() {
+            out.write_i32::<BigEndian>(-1).unwrap();
+        } else {
+            out.write_u32::<BigEndian>(self.data.len() as u32).unwrap();
+            out.extend_from_slice(&self.data);
+            out.extend_from_slice(&[0xff, 0xff, 0, 0]);
+        }
+    }
+}
+
+impl Asm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn op(mut self, opcode: Opcode) -> Self {
+        self.code.write_u16::<BigEndian>(opcode as u16).unwrap();
+        self
+    }
+
+    pub fn ops(self, opcodes: &[Opcode]) -> Self {
+        opcodes.iter().fold(self, |asm, &op| asm.op(op))
+    }
+
+    pub fn int(self, v: i32) -> Self {
+        let mut r = self.op(ConstLong);
+        r.code.write_i32::<BigEndian>(v).unwrap();
+        r
+    }
+
+    /// Pushes each of the `values`.
+    pub fn ints(self, values: &[i32]) -> Self {
+        values.iter().fold(self, |asm, &v| asm.int(v))
+    }
+
+    pub fn float(self, v: f32) -> Self {
+        let mut r = self.op(ConstFloat);
+        r.code.write_f32::<BigEndian>(v).unwrap();
+        r
+    }
+
+    /// Pushes string from the string table.
+    pub fn string(mut self, s: &str) -> Self {
+        let offset = self.strings.intern(s);
+        let mut r = self.op(ConstString);
+        r.code.write_i32::<BigEndian>(offset as i32).unwrap();
+        r
+    }
+
+    /// Pushes string from the name table as used by the external variable instructions.
+    pub fn name(mut self, s: &str) -> Self {
+        let offset = self.names.intern(s);
+        let mut r = self.op(ConstString);
+        r.code.write_i32::<BigEndian>(offset as i32).unwrap();
+        r
+    }
+
+    /// Marks the position of the next instruction.
+    pub fn label(mut self, label: &'static str) -> Self {
+        let existing = self.labels.insert(label, self.code.len());
+        assert!(existing.is_none(), "duplicate label: {}", label);
+        self
+    }
+
+    /// Pushes the code address of the `label`.
+    pub fn addr(self, label: &'static str) -> Self {
+        let mut r = self.op(ConstLong);
+        r.fixups.push((r.code.len(), label));
+        r.code.write_i32::<BigEndian>(0).unwrap();
+        r
+    }
+
+    /// Declares procedure with body starting at the `label`.
+    pub fn proc(mut self, name: &str, label: &'static str, arg_count: usize) -> Self {
+        let name = self.names.intern(name);
+        self.procs.push((name, label, arg_count));
+        self
+    }
+
+    pub fn assemble(self) -> Vec<u8> {
+        let mut tables = Vec::new();
+        tables.write_u32::<BigEndian>(self.procs.len() as u32).unwrap();
+        let mut names = Vec::new();
+        self.names.write(&mut names);
+        let mut strings = Vec::new();
+        self.strings.write(&mut strings);
+        let code_start = PROC_TABLE_START + 4 + self.procs.len() * 24 + names.len()
+            + strings.len();
+
+        let label = |l| self.labels.get(l).map(|&p| (code_start + p) as u32)
+            .unwrap_or_else(|| panic!("undefined label: {}", l));
+        for &(name, body, arg_count) in &self.procs {
+            for &v in &[name as u32, 0, 0, 0, label(body), arg_count as u32] {
+                tables.write_u32::<BigEndian>(v).unwrap();
+            }
+        }
+        tables.extend_from_slice(&names);
+        tables.extend_from_slice(&strings);
+
+        let mut code = self.code.clone();
+        for &(pos, l) in &self.fixups {
+            (&mut code[pos..pos + 4]).write_u32::<BigEndian>(label(l)).unwrap();
+        }
+
+        // Same entry point layout as the compiler emits.
+        let mut r = Asm::new()
+            .int(code_start as i32)
+            .op(Jmp)
+            .code;
+        while r.len() < 24 {
+            r.write_u16::<BigEndian>(Noop8000 as u16).unwrap();
+        }
+        r.write_u16::<BigEndian>(Pop as u16).unwrap();
+        r.write_u16::<BigEndian>(PopFlagsExit as u16).unwrap();
+        while r.len() < PROC_TABLE_START {
+            r.write_u16::<BigEndian>(Noop8000 as u16).unwrap();
+        }
+        r.extend_from_slice(&tables);
+        r.extend_from_slice(&code);
+        r
+    }
+}
+
+/// Owns everything `Context` refers to. Game data comes from in-memory fixture files.
+pub struct Harness {
+    pub fx: Fixture,
+    pub vm: Vm,
+    pub ui: Ui,
+    pub message_panel: ui::Handle,
+    pub world: World,
+    pub obj_sequencer: ObjSequencer,
+    pub dialog: Option<Dialog>,
+    pub script_ui: ScriptUi,
+    pub audio: Audio,
+    pub movie: Option<MovieRequest>,
+    pub world_map: WorldMap,
+    pub combat: Combat,
+    pub script_db: ScriptDb,
+    pub scripts: Scripts,
+    pub rpg: Rpg,
+    pub local_vars: Vec<i32>,
+    pub map_vars: Vec<i32>,
+    pub global_vars: Vec<i32>,
+    pub external_vars: HashMap<Rc<BString>, Option<Value>>,
+    pub self_obj: Option<object::Handle>,
+    pub source_obj: Option<object::Handle>,
+    pub target_obj: Option<object::Handle>,
+    pub sid: ScriptIid,
+    pub fixed_param: i32,
+    pub skill: Option<crate::asset::Skill>,
+    pub map_id: crate::asset::map::MapId,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let fx = Fixture::new(&format!("vm-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)));
+
+        // Blank fixed width glyphs are enough for the layout code.
+        let tf = TextureFactory::new_in_memory();
+        let glyphs = || (0..256)
+            .map(|_| Glyph {
+                width: 5,
+                height: 10,
+                texture: tf.new_texture(5, 10, vec![0; 50].into()),
+            })
+            .collect();
+        let mut fonts = Fonts::new();
+        for id in 0..16 {
+            for &key in &[FontKey::antialiased(id), FontKey::non_antialiased(id)] {
+                fonts.insert(key, Font {
+                    height: 10,
+                    horz_spacing: 0,
+                    vert_spacing: 0,
+                    glyphs: glyphs(),
+                });
+            }
+        }
+        let fonts = Rc::new(fonts);
+
+        let mut ui = Ui::new(fx.frm_db.clone(), fonts.clone(), 640, 480);
+        let win = ui.new_window(Rect::with_size(0, 0, 640, 480), None);
+        let message_panel = ui.new_widget(win, Rect::with_size(0, 0, 200, 100), None, None,
+            MessagePanel::new(fonts.clone(), FontKey::antialiased(1), GREEN));
+        let world = World::new(fx.proto_db.clone(), fx.frm_db.clone(),
+            Messages::read(&mut &b""[..]).unwrap(), hex::TileGrid::default(),
+            Rect::with_size(0, 0, 640, 380), Instant::now(), fonts);
+        let script_db = ScriptDb::new(fx.fs.clone(), "english").unwrap();
+        let scripts = fx.new_scripts();
+        let rpg = fx.new_rpg();
+        let audio = Audio::new(fx.fs.clone(), Box::new(NullOutput::new(audio::SAMPLE_RATE)));
+
+        Self {
+            fx,
+            vm: Vm::default(),
+            ui,
+            message_panel,
+            world,
+            obj_sequencer: ObjSequencer::new(Instant::now()),
+            dialog: None,
+            script_ui: ScriptUi::new(),
+            audio,
+            movie: None,
+            world_map: WorldMap::new(Rc::new(worldmap::test::new_db())),
+            combat: Combat::new(),
+            script_db,
+            scripts,
+            rpg,
+            local_vars: vec![0; 10],
+            map_vars: vec![0; 10],
+            global_vars: vec![0; 10],
+            external_vars: HashMap::new(),
+            self_obj: None,
+            source_obj: None,
+            target_obj: None,
+            sid: ScriptIid::new(ScriptKind::System, 0),
+            fixed_param: 0,
+            skill: None,
+            map_id: 0,
+        }
+    }
+
+    /// Calls `f` with the VM and a context built from the harness state.
+    pub fn with_context<R>(&mut self, f: impl FnOnce(&mut Vm, &mut Context) -> R) -> R {
+        let mut ctx = Context {
+            local_vars: &mut self.local_vars,
+            map_vars: &mut self.map_vars,
+            global_vars: &mut self.global_vars,
+            external_vars: &mut self.external_vars,
+            sid: self.sid,
+            self_obj: self.self_obj,
+            fixed_param: self.fixed_param,
+            source_obj: self.source_obj,
+            target_obj: self.target_obj,
+            skill: self.skill,
+            ui: &mut self.ui,
+            world: &mut self.world,
+            obj_sequencer: &mut self.obj_sequencer,
+            dialog: &mut self.dialog,
+            script_ui: &mut self.script_ui,
+            audio: &mut self.audio,
+            movie: &mut self.movie,
+            world_map: &mut self.world_map,
+            combat: &mut self.combat,
+            message_panel: self.message_panel,
+            script_db: &mut self.script_db,
+            new_scripts: NewScripts::new(&self.scripts),
+            proto_db: &self.fx.proto_db,
+            map_id: self.map_id,
+            rpg: &mut self.rpg,
+        };
+        f(&mut self.vm, &mut ctx)
+    }
+
+    /// Loads program and runs its initialization code.
+    pub fn load(&mut self, code: Vec<u8>) -> Result<Handle> {
+        let program = Rc::new(self.vm.load("test".into(), code.into())?);
+        let h = self.vm.insert(program);
+        self.with_context(|vm, ctx| vm.run(h, ctx))?.assert_no_suspend();
+        Ok(h)
+    }
+
+    pub fn execute_proc(&mut self, program: Handle, name: &str) -> Result<InvocationResult> {
+        let proc_id = self.vm.program_state(program).program()
+            .proc_id(&Rc::new(name.into()))
+            .ok_or_else(|| Error::BadProcedure(Rc::new(name.into())))?;
+        self.with_context(|vm, ctx| vm.program_state_mut(program).execute_proc(proc_id, ctx))
+    }
+
+    /// Runs the code and returns the values left on the data stack with strings resolved.
+    pub fn eval(&mut self, asm: Asm) -> Result<Vec<Value>> {
+        let h = self.load(asm.op(ExitProg).assemble())?;
+        let prg = self.vm.program_state(h);
+        prg.data_stack.iter()
+            .map(|v| v.clone().resolved(prg.strings()))
+            .collect()
+    }
+
+    /// Like `eval()` but expects exactly one value.
+    pub fn eval1(&mut self, asm: Asm) -> Result<Value> {
+        let mut r = self.eval(asm)?;
+        assert_eq!(r.len(), 1, "{:?}", r);
+        Ok(r.pop().unwrap())
+    }
+
+    pub fn new_object(&mut self, pid: u32, pos: Option<EPoint>) -> object::Handle {
+        let proto = self.fx.proto(pid);
+        let fid = proto.borrow().fid;
+        self.world.new_object(fid, Some(proto), pos, &self.rpg)
+    }
+
+    pub fn new_dude(&mut self, pos: EPoint) -> object::Handle {
+        let proto = self.fx.proto(PID_CRITTER);
+        let fid = proto.borrow().fid;
+        self.world.insert_dude_obj(object::Object::new(fid, Some(proto), Some(pos),
+            object::SubObject::Critter(Default::default())))
+    }
+
+    /// Sets messages of the program (`PROGRAM_MAP` or `PROGRAM_OBJ`) in the `.msg` file format.
+    pub fn set_script_messages(&mut self, program_id: u32, msgs: &str) {
+        let name = self.script_db.info(ProgramId::new(program_id).unwrap()).unwrap().name.clone();
+        self.fx.write_file(&format!("text/english/dialog/{}.msg", name), msgs.as_bytes());
+    }
+
+    /// Messages pushed to the message panel.
+    pub fn panel_messages(&self) -> Vec<BString> {
+        self.ui.widget_ref::<MessagePanel>(self.message_panel).messages()
+            .map(|m| m.into())
+            .collect()
+    }
+}